@@ -0,0 +1,332 @@
+//! The thumbnail grid: every file in the gallery as a cell, moved through
+//! with the keyboard and opened with Enter or a click.
+//!
+//! Geometry lives here rather than in the view so a key press and what is
+//! drawn always agree. Cells sit in rows as wide as the grid allows, the
+//! column count following the width the scrollable last reported, and a step
+//! up or down is one row's worth of cells. The view builds only the rows
+//! near the visible band and pads the rest with space, so a folder of
+//! thousands lays out as cheaply as a screenful.
+//!
+//! Thumbnails are asked for by the same band. Requests go out a few at a
+//! time and each one arriving frees a slot for the next, so scrolling fast
+//! never queues a decode for every cell passed over.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use iced::keyboard::{
+    self,
+    key::{self, Physical},
+};
+use iced::widget::{operation, scrollable};
+use iced::{Size, Task};
+
+use crate::{
+    app::Message,
+    config::{Config, GRID_CELL_MAX, GRID_CELL_MIN},
+    gallery::{Gallery, Sort},
+    tasks,
+    thumbnails::{self, Thumbnail, ThumbnailCache},
+};
+
+pub const GRID_SCROLL: &str = "gallery_grid";
+pub const GRID_PADDING: f32 = 12.0;
+pub const GRID_GAP: f32 = 8.0;
+/// Room under each picture for the file name.
+pub const GRID_LABEL_HEIGHT: f32 = 20.0;
+
+/// Rows beyond the visible band that are built and given thumbnails.
+const LOOKAHEAD_ROWS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Left,
+    Right,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+}
+
+#[derive(Debug, Clone)]
+pub enum GridMsg {
+    Toggle,
+    Step(Step),
+    OpenSelected,
+    Open(usize),
+    Select(usize),
+    SetCellSize(f32),
+    Sort(Sort),
+    Scrolled(scrollable::Viewport),
+    Resized(Size),
+    ThumbnailReady(PathBuf, Option<Thumbnail>),
+}
+
+pub struct GridState {
+    pub open: bool,
+    pub selected: usize,
+    pub thumbnails: ThumbnailCache,
+    viewport: Size,
+    scroll_y: f32,
+}
+
+impl Default for GridState {
+    fn default() -> Self {
+        Self {
+            open: false,
+            selected: 0,
+            thumbnails: ThumbnailCache::default(),
+            viewport: Size::new(800.0, 600.0),
+            scroll_y: 0.0,
+        }
+    }
+}
+
+/// The height of a cell: its picture plus the label under it.
+pub fn row_height(cell: f32) -> f32 {
+    cell + GRID_LABEL_HEIGHT
+}
+
+fn columns(width: f32, cell: f32) -> usize {
+    ((width - 2.0 * GRID_PADDING + GRID_GAP) / (cell + GRID_GAP))
+        .floor()
+        .max(1.0) as usize
+}
+
+/// Where `step` moves the selection from `index`. Down from the row above a
+/// shorter last row lands on its final cell rather than going nowhere.
+fn step(index: usize, len: usize, columns: usize, page_rows: usize, step: Step) -> usize {
+    let last = len.saturating_sub(1);
+    let down = |rows: usize| {
+        if index / columns < last / columns {
+            (index + rows * columns).min(last)
+        } else {
+            index
+        }
+    };
+    let up = |rows: usize| index - rows.min(index / columns) * columns;
+    match step {
+        Step::Left => index.saturating_sub(1),
+        Step::Right => (index + 1).min(last),
+        Step::Up => up(1),
+        Step::Down => down(1),
+        Step::PageUp => up(page_rows),
+        Step::PageDown => down(page_rows),
+        Step::First => 0,
+        Step::Last => last,
+    }
+}
+
+impl GridState {
+    pub fn columns(&self, cell: f32) -> usize {
+        columns(self.viewport.width, cell)
+    }
+
+    /// The rows worth building: those on screen plus a few either side.
+    pub fn visible_rows(&self, cell: f32, len: usize) -> Range<usize> {
+        let pitch = row_height(cell) + GRID_GAP;
+        let rows = len.div_ceil(self.columns(cell));
+        let top = (self.scroll_y - GRID_PADDING) / pitch;
+        let bottom = (self.scroll_y + self.viewport.height - GRID_PADDING) / pitch;
+        let end = (bottom.ceil().max(0.0) as usize + LOOKAHEAD_ROWS).min(rows);
+        let start = (top.floor().max(0.0) as usize).saturating_sub(LOOKAHEAD_ROWS);
+        start.min(end)..end
+    }
+
+    fn page_rows(&self, cell: f32) -> usize {
+        ((self.viewport.height / (row_height(cell) + GRID_GAP)).floor() as usize).max(1)
+    }
+
+    /// Scrolls just far enough to bring the selected cell into view.
+    fn reveal(&mut self, cell: f32) -> Task<Message> {
+        let row = self.selected / self.columns(cell);
+        let top = GRID_PADDING + row as f32 * (row_height(cell) + GRID_GAP);
+        let bottom = top + row_height(cell);
+        let y = if top < self.scroll_y {
+            top - GRID_PADDING
+        } else if bottom > self.scroll_y + self.viewport.height {
+            bottom + GRID_PADDING - self.viewport.height
+        } else {
+            return Task::none();
+        };
+        self.scroll_y = y.max(0.0);
+        operation::scroll_to(
+            GRID_SCROLL,
+            scrollable::AbsoluteOffset {
+                x: 0.0,
+                y: self.scroll_y,
+            },
+        )
+    }
+
+    /// Catches up with files another program changed: their thumbnails are
+    /// made again and the selection stays on `selected` if it is still there.
+    pub fn relist(
+        &mut self,
+        gallery: &Gallery,
+        selected: Option<&Path>,
+        changed: &[PathBuf],
+        cell: f32,
+    ) -> Task<Message> {
+        for path in changed {
+            self.thumbnails.forget(path);
+        }
+        let last = gallery.len().saturating_sub(1);
+        self.selected = selected
+            .and_then(|s| gallery.paths().iter().position(|p| p == s))
+            .unwrap_or(self.selected)
+            .min(last);
+        self.open &= gallery.len() > 0;
+        self.request(gallery, cell)
+    }
+
+    /// Starts generating thumbnails for the visible band, as far as the
+    /// worker slots allow.
+    fn request(&mut self, gallery: &Gallery, cell: f32) -> Task<Message> {
+        if !self.open {
+            return Task::none();
+        }
+        let paths = gallery.paths();
+        let columns = self.columns(cell);
+        let rows = self.visible_rows(cell, paths.len());
+        let cells = (rows.start * columns).min(paths.len())..(rows.end * columns).min(paths.len());
+        let start = self.thumbnails.want(&paths[cells], thumbnails::workers());
+        Task::batch(start.into_iter().map(tasks::load_thumbnail))
+    }
+}
+
+/// The grid's own keys, fixed rather than bound, since they mirror the
+/// arrows and Enter of any file browser.
+pub fn key_message(physical_key: &Physical, modifiers: keyboard::Modifiers) -> Option<GridMsg> {
+    if modifiers.control() || modifiers.alt() || modifiers.logo() {
+        return None;
+    }
+    let Physical::Code(code) = physical_key else {
+        return None;
+    };
+    Some(match code {
+        key::Code::ArrowLeft => GridMsg::Step(Step::Left),
+        key::Code::ArrowRight => GridMsg::Step(Step::Right),
+        key::Code::ArrowUp => GridMsg::Step(Step::Up),
+        key::Code::ArrowDown => GridMsg::Step(Step::Down),
+        key::Code::PageUp => GridMsg::Step(Step::PageUp),
+        key::Code::PageDown => GridMsg::Step(Step::PageDown),
+        key::Code::Home => GridMsg::Step(Step::First),
+        key::Code::End => GridMsg::Step(Step::Last),
+        key::Code::Enter | key::Code::NumpadEnter => GridMsg::OpenSelected,
+        key::Code::Escape => GridMsg::Toggle,
+        _ => return None,
+    })
+}
+
+pub fn update(
+    state: &mut GridState,
+    gallery: &mut Gallery,
+    config: &mut Config,
+    msg: GridMsg,
+) -> Task<Message> {
+    let cell = config.grid_cell_size;
+    match msg {
+        GridMsg::Toggle => {
+            state.open = !state.open && gallery.len() > 0;
+            if state.open {
+                state.selected = gallery.position();
+                state.scroll_y = 0.0;
+                return Task::batch([state.reveal(cell), state.request(gallery, cell)]);
+            }
+        }
+        GridMsg::Step(s) => {
+            let columns = state.columns(cell);
+            let page = state.page_rows(cell);
+            state.selected = step(state.selected, gallery.len(), columns, page, s);
+            return Task::batch([state.reveal(cell), state.request(gallery, cell)]);
+        }
+        GridMsg::OpenSelected => {
+            return update(state, gallery, config, GridMsg::Open(state.selected));
+        }
+        GridMsg::Open(index) => {
+            if let Some(path) = gallery.paths().get(index) {
+                state.open = false;
+                state.selected = index;
+                return Task::done(Message::MediaSelected(path.clone()));
+            }
+        }
+        GridMsg::Select(index) => {
+            state.selected = index.min(gallery.len().saturating_sub(1));
+            return Task::batch([state.reveal(cell), state.request(gallery, cell)]);
+        }
+        GridMsg::SetCellSize(size) => {
+            config.grid_cell_size = size.round().clamp(GRID_CELL_MIN, GRID_CELL_MAX);
+            let cell = config.grid_cell_size;
+            return Task::batch([state.reveal(cell), state.request(gallery, cell)]);
+        }
+        GridMsg::Sort(sort) => {
+            config.gallery_sort = sort;
+            // The files come back in the new order as a listing, which keeps
+            // the selection.
+            gallery.set_sort(sort);
+            if let Some(request) = gallery.take_request() {
+                return tasks::list_folder(request);
+            }
+        }
+        GridMsg::Scrolled(viewport) => {
+            state.scroll_y = viewport.absolute_offset().y;
+            state.viewport = viewport.bounds().size();
+            return state.request(gallery, cell);
+        }
+        GridMsg::Resized(size) => {
+            state.viewport = size;
+            return state.request(gallery, cell);
+        }
+        GridMsg::ThumbnailReady(path, thumbnail) => {
+            state.thumbnails.insert(path, thumbnail);
+            return state.request(gallery, cell);
+        }
+    }
+    Task::none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_stay_in_bounds_and_reach_a_short_last_row() {
+        // Ten cells in rows of four: 0-3, 4-7, 8-9.
+        let at = |index, s| step(index, 10, 4, 2, s);
+        assert_eq!(at(0, Step::Left), 0);
+        assert_eq!(at(9, Step::Right), 9);
+        assert_eq!(at(1, Step::Up), 1);
+        assert_eq!(at(5, Step::Up), 1);
+        assert_eq!(at(1, Step::Down), 5);
+        assert_eq!(at(6, Step::Down), 9);
+        assert_eq!(at(9, Step::Down), 9);
+        assert_eq!(at(9, Step::PageUp), 1);
+        assert_eq!(at(2, Step::PageDown), 9);
+        assert_eq!(at(4, Step::Last), 9);
+        assert_eq!(step(0, 0, 4, 2, Step::Down), 0);
+    }
+
+    #[test]
+    fn builds_only_rows_near_the_view() {
+        let cell = 100.0;
+        let pitch = row_height(cell) + GRID_GAP;
+        let mut state = GridState {
+            viewport: Size::new(4.0 * (cell + GRID_GAP) + 2.0 * GRID_PADDING, 3.0 * pitch),
+            ..GridState::default()
+        };
+        assert_eq!(state.columns(cell), 4);
+        assert_eq!(state.visible_rows(cell, 1000), 0..3 + LOOKAHEAD_ROWS);
+
+        state.scroll_y = GRID_PADDING + 50.0 * pitch;
+        assert_eq!(
+            state.visible_rows(cell, 1000),
+            50 - LOOKAHEAD_ROWS..53 + LOOKAHEAD_ROWS
+        );
+        // Scrolled past the end of a shorter folder, there is nothing to build.
+        assert_eq!(state.visible_rows(cell, 10), 3..3);
+    }
+}
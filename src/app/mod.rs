@@ -8,11 +8,14 @@
 //! committed version needs writing to disk.
 
 mod edit;
+pub mod grid;
 mod transport;
 
 pub use edit::{EditMsg, EditState, Tool};
+pub use grid::{GridMsg, GridState};
 pub use transport::{TransportMsg, TransportState};
 
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -22,26 +25,37 @@ use iced::time::every;
 use iced::{
     Color, Element, Event, Rectangle, Subscription, Task, Theme, event,
     keyboard::{self, key::Physical},
-    widget::column,
+    widget::{column, row},
     window::{self, Mode},
 };
 
 use crate::{
     components::{
-        bottom_bar,
+        bottom_bar, folder_tree, grid as grid_view,
         notifications::{Notification, NotificationEntry},
         preferences,
         preferences::{PreferenceMessage, PreferenceOutcome},
         timeline_bar, viewer,
     },
-    config::{Config, UI_SCALE_DEFAULT, UI_SCALE_MAX, UI_SCALE_MIN, UI_SCALE_STEP},
-    gallery::Gallery,
+    config::{
+        Config, GRID_CELL_DEFAULT, GRID_CELL_STEP, UI_SCALE_DEFAULT, UI_SCALE_MAX, UI_SCALE_MIN,
+        UI_SCALE_STEP,
+    },
+    gallery::{Gallery, Listing, MAX_ENTRIES},
     keybinds::Action,
-    styles, tasks,
+    styles, tasks, watch,
     wgpu::{
-        media::image_data::{ImageId, MediaData},
+        media::archive,
+        media::exr::ExrView,
+        media::fits::{FitsView, Stretch},
+        media::image_data::{ImageData, ImageId, MediaData},
+        media::layers::LayerView,
+        media::raw::RawSettings,
+        media::svg::SvgDetail,
+        media::texture::TextureView,
+        media::tonemap::{EXPOSURE_MAX, EXPOSURE_MIN, ToneOperator, Window},
         passes::checkerboard::CheckerboardUniforms,
-        view_program::{Histogram, ViewProgram, hash_modifiers_for_histogram},
+        view_program::{Histogram, ViewProgram, ViewSnapshot, hash_modifiers_for_histogram},
     },
 };
 
@@ -52,6 +66,17 @@ pub struct App {
     loading: Option<String>,
     load_generation: u64,
     pending_media: Option<PathBuf>,
+    /// The view to put back when the load in flight is the current file
+    /// read again after another program rewrote it.
+    reload: Option<ViewSnapshot>,
+    /// The load generation whose preview is on screen, so its view carries
+    /// over to the full decode.
+    previewed: Option<u64>,
+    render_generation: u64,
+    rendering: Option<Render>,
+    pending_render: Option<Render>,
+    /// The layer of a layered document the info panel's controls act on.
+    selected_layer: usize,
     focus_scale: bool,
     config: Config,
     config_dirty: bool,
@@ -64,8 +89,17 @@ pub struct App {
     notifications: Vec<NotificationEntry>,
     export_progress: Option<f32>,
     edit: EditState,
+    grid: GridState,
     histogram: Option<HistogramResult>,
     histogram_inflight: Option<(ImageId, u64)>,
+    /// An SVG detail raster is being rendered.
+    svg_rasterizing: bool,
+    /// The view moved on while it was, so another is wanted once it lands.
+    svg_stale: bool,
+    /// The size last picked for an SVG export, reused by the export key.
+    svg_export_scale: u32,
+    /// Folders folded shut in the folder tree.
+    collapsed_folders: HashSet<PathBuf>,
 }
 
 impl App {
@@ -79,15 +113,23 @@ impl App {
         program.mipmap_zoom_out = config.mipmap_zoom_out;
         program.smooth_zoom_in = config.smooth_zoom_in;
         program.loop_animations = config.loop_animations;
+        program.keep_color_profile = config.keep_color_profile;
+        program.tone.operator = config.tone_operator;
         styles::set_radius(config.rounded);
         let transport = TransportState::from_config(&config);
         Self {
             program,
-            gallery: Gallery::default(),
+            gallery: Gallery::empty(config.gallery_sort, config.recursive),
             mode: Mode::Windowed,
             loading: None,
             load_generation: 0,
             pending_media: None,
+            reload: None,
+            previewed: None,
+            render_generation: 0,
+            rendering: None,
+            pending_render: None,
+            selected_layer: 0,
             focus_scale: false,
             config,
             config_dirty: false,
@@ -100,12 +142,31 @@ impl App {
             notifications: Vec::new(),
             export_progress: None,
             edit: EditState::default(),
+            grid: GridState::default(),
             histogram: None,
             histogram_inflight: None,
+            svg_rasterizing: false,
+            svg_stale: false,
+            svg_export_scale: 1,
+            collapsed_folders: HashSet::new(),
         }
     }
 }
 
+/// Another view of the open document, rendered in the background and swapped
+/// in for the current image.
+#[derive(Debug, Clone, PartialEq)]
+enum Render {
+    Exr(ExrView),
+    Page(usize),
+    /// Every page of an icon side by side.
+    Sheet,
+    Fits(FitsView),
+    Layers(LayerView),
+    Texture(TextureView),
+    Raw(RawSettings),
+}
+
 #[derive(Debug, Clone)]
 pub struct HistogramResult {
     pub image_id: ImageId,
@@ -126,14 +187,45 @@ pub enum Message {
     Previous,
     SelectMedia,
     MediaSelected(PathBuf),
+    /// Play the current file's numbered run as an animation at this frame rate.
+    PlaySequence(u32),
+    MediaPreview(u64, Box<ImageData>),
     MediaLoaded(u64, MediaData),
     MediaFailed(u64, String),
+    SelectExrChannel(usize, Option<usize>),
+    SetExrView(ExrView),
+    SelectFitsHdu(usize),
+    SetFitsStretch(Stretch),
+    SetFitsView(FitsView),
+    SelectLayer(usize),
+    SetLayerVisible(usize, bool),
+    SetLayerOpacity(usize, f32),
+    SoloLayer(Option<usize>),
+    NextPage,
+    PreviousPage,
+    SelectPage(usize),
+    ShowPageSheet,
+    SetTextureView(TextureView),
+    SetRawSettings(RawSettings),
+    ImageRendered(u64, Result<Box<ImageData>, String>),
+    SvgDetailRendered(Option<Arc<SvgDetail>>),
     ToggleFullscreen,
     ToggleInfoColumn,
     ToggleInfoSection(&'static str),
     TogglePreferences,
     OpenAbout,
     OpenUrl(&'static str),
+    ClearThumbnailCache,
+    FolderChanged(Vec<PathBuf>),
+    /// The folder watch cannot say file by file what changed, so the
+    /// folder is listed again.
+    RelistFolder,
+    FolderListed(Listing),
+    ToggleSubfolders,
+    ParentFolder,
+    ToggleFolder(PathBuf),
+    /// Go to the file at this place in the gallery, from the folder tree.
+    JumpToFolder(usize),
     Preference(PreferenceMessage),
     ClipboardLoaded(MediaData),
     CursorMoved(Vec2),
@@ -162,8 +254,16 @@ pub enum Message {
     DismissNotification(usize),
     NotificationTick(Instant),
     Edit(EditMsg),
+    Grid(GridMsg),
+    SetToneOperator(ToneOperator),
+    SetExposure(f32),
+    SetWindow(Window),
     ExportImage,
     ExportFrame,
+    ExportPages,
+    ExportLayer,
+    /// Export an SVG at this multiple of its native size.
+    ExportSvg(u32),
     ExportProgress(f32),
     ExportDone(Result<String, String>),
     HistogramReady(Box<HistogramResult>),
@@ -177,6 +277,12 @@ impl From<EditMsg> for Message {
     }
 }
 
+impl From<GridMsg> for Message {
+    fn from(msg: GridMsg) -> Self {
+        Message::Grid(msg)
+    }
+}
+
 impl From<TransportMsg> for Message {
     fn from(msg: TransportMsg) -> Self {
         Message::Transport(msg)
@@ -187,17 +293,28 @@ impl App {
     pub fn new(path: Option<PathBuf>, config: Config) -> (Self, Task<Message>) {
         let effective_path = path.or_else(|| {
             if config.remember_last {
-                config.last_media.as_ref().filter(|p| p.exists()).cloned()
+                config
+                    .last_media
+                    .as_ref()
+                    .filter(|p| p.exists() || archive::split(p).is_some())
+                    .cloned()
             } else {
                 None
             }
         });
         let mut app = Self::from_config(config);
         if let Some(p) = effective_path {
-            app.gallery = Gallery::new(&p);
-            app.loading = Some(Gallery::filename(&p));
+            app.gallery = Gallery::new(&p, app.config.gallery_sort, app.config.recursive);
+            app.loading = Some(app.gallery.filename(&p));
             app.load_generation = 1;
-            return (app, tasks::load_media(p, 1));
+            let list = app.list_gallery();
+            return (
+                app,
+                Task::batch([
+                    tasks::load_media(p, 1, app.config.embedded_preview_only),
+                    list,
+                ]),
+            );
         }
         (app, Task::none())
     }
@@ -211,18 +328,22 @@ impl App {
             }
             Message::ScaleUp(cursor) => {
                 self.program.scale_up(cursor);
+                return self.refine_svg();
             }
             Message::ScaleDown(cursor) => {
                 self.program.scale_down(cursor);
+                return self.refine_svg();
             }
             Message::RotateCw => {
                 if self.gallery.current().is_some() {
                     self.program.rotate();
+                    return self.refine_svg();
                 }
             }
             Message::RotateCcw => {
                 if self.gallery.current().is_some() {
                     self.program.rotate_ccw();
+                    return self.refine_svg();
                 }
             }
             Message::Fit => {
@@ -230,58 +351,89 @@ impl App {
                     self.program.set_fit_active(false);
                 } else {
                     self.program.fit();
+                    return self.refine_svg();
                 }
             }
             Message::BoundsChanged(bounds) => {
                 self.program.set_bounds(bounds);
+                return self.refine_svg();
             }
             Message::Scale(scale) => {
                 let center = self.program.viewport_center();
                 self.program.set_scale(scale, center);
+                return self.refine_svg();
             }
-            Message::Next => {
-                if let Some(p) = self.gallery.next() {
-                    return Task::done(Message::MediaSelected(p.clone()));
-                }
-            }
-            Message::Previous => {
-                if let Some(p) = self.gallery.previous() {
-                    return Task::done(Message::MediaSelected(p.clone()));
-                }
-            }
+            Message::Next => return self.step_gallery(!self.reading_right_to_left()),
+            Message::Previous => return self.step_gallery(self.reading_right_to_left()),
             Message::SelectMedia => return tasks::select_media(),
             Message::MediaSelected(path) => {
-                if let Some(p) = self.gallery.set(path) {
+                if let Some(p) = self.gallery.set(path).cloned() {
+                    self.reload = None;
                     let inflight = self.loading.is_some();
-                    self.loading = Some(Gallery::filename(p));
+                    self.loading = Some(self.gallery.filename(&p));
                     self.program.release_image_pixels();
+                    let list = self.list_gallery();
                     if inflight {
-                        self.pending_media = Some(p.clone());
-                        return Task::none();
+                        self.pending_media = Some(p);
+                        return list;
                     }
                     self.load_generation = self.load_generation.wrapping_add(1);
-                    return tasks::load_media(p.clone(), self.load_generation);
+                    return Task::batch([self.load(p), list]);
+                }
+            }
+            Message::PlaySequence(fps) => {
+                if self.loading.is_none()
+                    && let Some(run) = self.gallery.sequence()
+                {
+                    let paths = run.to_vec();
+                    self.loading = Some(format!("{} frames", paths.len()));
+                    self.load_generation = self.load_generation.wrapping_add(1);
+                    return tasks::load_sequence(paths, fps, self.load_generation);
+                }
+            }
+            Message::MediaPreview(generation, data) => {
+                // A reload keeps the old pixels up until the full decode lands.
+                if generation == self.load_generation
+                    && self.pending_media.is_none()
+                    && self.reload.is_none()
+                {
+                    self.apply_media(MediaData::Image(data));
+                    self.previewed = Some(generation);
                 }
             }
             Message::MediaLoaded(generation, media) => {
                 if generation == self.load_generation {
                     if let Some(p) = self.pending_media.take() {
                         self.load_generation = self.load_generation.wrapping_add(1);
-                        return tasks::load_media(p, self.load_generation);
+                        return self.load(p);
                     }
                     self.loading = None;
+                    let reload = self.reload.take();
+                    let preview = (self.previewed.take() == Some(generation))
+                        .then(|| self.program.snapshot());
                     self.apply_media(media);
+                    if let Some(view) = reload {
+                        self.program.restore(view);
+                    } else if let Some(view) = preview {
+                        self.program.carry_over(view);
+                    }
                     if self.config.remember_last {
                         self.config.last_media = self.gallery.current().cloned();
                         self.config_dirty = true;
                     }
-                    return self.maybe_request_histogram();
+                    return Task::batch([self.maybe_request_histogram(), self.refine_svg()]);
                 }
             }
             Message::ClipboardLoaded(media) => {
                 self.loading = None;
                 self.apply_media(media);
-                return self.maybe_request_histogram();
+                return Task::batch([self.maybe_request_histogram(), self.refine_svg()]);
+            }
+            Message::Grid(msg) => {
+                if matches!(msg, GridMsg::SetCellSize(_) | GridMsg::Sort(_)) {
+                    self.config_dirty = true;
+                }
+                return grid::update(&mut self.grid, &mut self.gallery, &mut self.config, msg);
             }
             Message::Transport(msg) => {
                 if matches!(msg, TransportMsg::CommitVolume | TransportMsg::ToggleMute) {
@@ -296,16 +448,127 @@ impl App {
                 return Task::batch([task, self.maybe_request_histogram()]);
             }
             Message::MediaFailed(generation, err) => {
-                let notify = Task::done(Message::Notify(Notification::error(err)));
-                if generation == self.load_generation {
+                let current = generation == self.load_generation;
+                // Mid-save, a rewrite can fail to read; the next one will
+                // land, so the old picture stays without a word.
+                let notify = if current && self.reload.is_some() {
+                    Task::none()
+                } else {
+                    Task::done(Message::Notify(Notification::error(err)))
+                };
+                if current {
+                    // A reload queued behind this one keeps the view.
                     if let Some(p) = self.pending_media.take() {
                         self.load_generation = self.load_generation.wrapping_add(1);
-                        return Task::batch([notify, tasks::load_media(p, self.load_generation)]);
+                        return Task::batch([notify, self.load(p)]);
                     }
                     self.loading = None;
+                    self.reload = None;
                 }
                 return notify;
             }
+            Message::SelectExrChannel(layer, channel) => {
+                if let Some(selection) = self.program.exr() {
+                    let view = selection.document.view_of(layer, channel);
+                    return self.render(Render::Exr(view));
+                }
+            }
+            Message::SetExrView(view) => return self.render(Render::Exr(view)),
+            Message::SelectFitsHdu(hdu) => {
+                if let Some(selection) = self.program.fits()
+                    && selection
+                        .document
+                        .hdus
+                        .get(hdu)
+                        .is_some_and(|h| h.is_image())
+                {
+                    let view = FitsView {
+                        hdu,
+                        plane: 0,
+                        ..selection.view
+                    };
+                    return self.render(Render::Fits(view));
+                }
+            }
+            Message::SetFitsStretch(stretch) => {
+                if let Some(selection) = self.program.fits() {
+                    let view = selection.with_stretch(stretch);
+                    return self.render(Render::Fits(view));
+                }
+            }
+            Message::SetFitsView(view) => return self.render(Render::Fits(view)),
+            Message::SelectLayer(layer) => self.selected_layer = layer,
+            Message::SetLayerVisible(layer, visible) => {
+                if let Some(view) = self.layer_target() {
+                    return self.render(Render::Layers(view.with_visible(layer, visible)));
+                }
+            }
+            Message::SetLayerOpacity(layer, opacity) => {
+                if let Some(view) = self.layer_target() {
+                    return self.render(Render::Layers(view.with_opacity(layer, opacity)));
+                }
+            }
+            Message::SoloLayer(solo) => {
+                if let Some(view) = self.layer_target() {
+                    return self.render(Render::Layers(view.with_solo(solo)));
+                }
+            }
+            Message::NextPage => {
+                let next = self.page_target().map(|i| i + 1);
+                if let Some(index) = next.filter(|&i| i < self.page_count()) {
+                    return self.render(Render::Page(index));
+                }
+            }
+            Message::PreviousPage => {
+                if let Some(index) = self.page_target().and_then(|i| i.checked_sub(1)) {
+                    return self.render(Render::Page(index));
+                }
+            }
+            Message::SelectPage(index) => return self.render(Render::Page(index)),
+            Message::ShowPageSheet => return self.render(Render::Sheet),
+            Message::SetTextureView(mut view) => {
+                if let Some(selection) = self.program.texture() {
+                    // A volume's smaller levels have fewer slices.
+                    let slices = selection.document.level_size(view.level).2;
+                    view.slice = view.slice.min(slices - 1);
+                }
+                return self.render(Render::Texture(view));
+            }
+            Message::SetRawSettings(settings) => return self.render(Render::Raw(settings)),
+            Message::ImageRendered(generation, result) => {
+                if generation != self.render_generation {
+                    return Task::none();
+                }
+                self.rendering = None;
+                match result {
+                    Ok(data) => {
+                        let sheet = data.pages().is_some_and(|p| p.sheet);
+                        self.program.replace_image(*data);
+                        // The sheet is for comparing entries pixel for pixel.
+                        if sheet {
+                            let center = self.program.viewport_center();
+                            self.program.set_scale(1.0, center);
+                        }
+                    }
+                    Err(e) => {
+                        self.pending_render = None;
+                        return Task::done(Message::Notify(Notification::error(e)));
+                    }
+                }
+                if let Some(request) = self.pending_render.take() {
+                    return self.render(request);
+                }
+                return self.maybe_request_histogram();
+            }
+            Message::SvgDetailRendered(detail) => {
+                self.svg_rasterizing = false;
+                if let Some(detail) = detail {
+                    self.program.add_svg_detail(detail);
+                }
+                if std::mem::take(&mut self.svg_stale) {
+                    return self.refine_svg();
+                }
+            }
             Message::ToggleEditPanel => {
                 self.config.show_edit = !self.config.show_edit;
                 self.config_dirty = true;
@@ -345,6 +608,56 @@ impl App {
             Message::OpenUrl(url) => {
                 return tasks::open_url(url);
             }
+            Message::FolderChanged(changed) => return self.folder_changed(changed),
+            Message::RelistFolder => {
+                return self.relist_gallery(|gallery| {
+                    gallery.relist();
+                });
+            }
+            Message::FolderListed(listing) => {
+                let truncated = listing.truncated;
+                let mut used = false;
+                let task = self.relist_gallery(|gallery| {
+                    used = gallery.listed(listing);
+                });
+                if used && truncated {
+                    let note = Notification::warning(format!(
+                        "Listed the first {MAX_ENTRIES} entries of this folder"
+                    ));
+                    return Task::batch([task, Task::done(Message::Notify(note))]);
+                }
+                return task;
+            }
+            Message::ToggleSubfolders => {
+                self.config.recursive = !self.config.recursive;
+                self.config_dirty = true;
+                let recursive = self.config.recursive;
+                return self.relist_gallery(|gallery| {
+                    gallery.set_recursive(recursive);
+                });
+            }
+            Message::ParentFolder => {
+                return self.relist_gallery(|gallery| {
+                    gallery.open_parent();
+                });
+            }
+            Message::ToggleFolder(path) => {
+                if !self.collapsed_folders.remove(&path) {
+                    self.collapsed_folders.insert(path);
+                }
+            }
+            Message::JumpToFolder(index) => {
+                if self.grid.open {
+                    return Task::done(GridMsg::Select(index).into());
+                }
+                if let Some(path) = self.gallery.paths().get(index) {
+                    return Task::done(Message::MediaSelected(path.clone()));
+                }
+            }
+            Message::ClearThumbnailCache => {
+                self.grid.thumbnails.clear();
+                return tasks::clear_thumbnail_cache();
+            }
             Message::Preference(msg) => {
                 let Some(pending) = self.editing_config.as_mut() else {
                     return Task::none();
@@ -357,11 +670,13 @@ impl App {
                         let dec = self.config.decorations != pending.decorations;
                         let aot = self.config.always_on_top != pending.always_on_top;
                         let mipmap_changed = self.config.mipmap_zoom_out != pending.mipmap_zoom_out;
+                        let orient_changed = self.config.auto_orient != pending.auto_orient;
                         self.config = pending;
                         self.config.save();
                         self.config_dirty = false;
                         self.program.mipmap_zoom_out = self.config.mipmap_zoom_out;
                         self.program.smooth_zoom_in = self.config.smooth_zoom_in;
+                        self.program.keep_color_profile = self.config.keep_color_profile;
                         self.program
                             .set_loop_animations(self.config.loop_animations);
                         if self.program.show_checkerboard {
@@ -369,6 +684,9 @@ impl App {
                                 checker_uniforms_from_theme(&self.config.theme);
                         }
                         self.program.show_pixel_grid = self.config.show_pixel_grid;
+                        if orient_changed {
+                            self.apply_orientation();
+                        }
                         if mipmap_changed {
                             self.notifications
                                 .push(NotificationEntry::new(Notification::warning(
@@ -412,7 +730,10 @@ impl App {
             Message::PanStarted => {
                 self.program.set_panning(true);
             }
-            Message::PanEnded => self.program.set_panning(false),
+            Message::PanEnded => {
+                self.program.set_panning(false);
+                return self.refine_svg();
+            }
             Message::CopyColor => {
                 if let Some([r, g, b, _]) = self.picked_color {
                     return tasks::copy_text(format!("#{r:02X}{g:02X}{b:02X}"));
@@ -437,8 +758,12 @@ impl App {
                 }
             }
             Message::OpenFileLocation => {
-                if let Some(path) = self.gallery.current() {
-                    return tasks::open_file_location(path.clone());
+                if let Some(path) = self
+                    .gallery
+                    .archive()
+                    .or(self.gallery.current().map(|p| p.as_path()))
+                {
+                    return tasks::open_file_location(path.to_path_buf());
                 }
             }
             Message::Exit => {
@@ -487,12 +812,29 @@ impl App {
                 let task = edit::update(&mut self.edit, &mut self.program, timed, msg);
                 return Task::batch([task, self.maybe_request_histogram()]);
             }
+            Message::SetToneOperator(operator) => {
+                self.program.tone.operator = operator;
+                self.config.tone_operator = operator;
+                self.config_dirty = true;
+            }
+            Message::SetExposure(ev) => {
+                self.program.tone.exposure = ev.clamp(EXPOSURE_MIN, EXPOSURE_MAX);
+            }
+            Message::SetWindow(window) => {
+                self.program.window = Window {
+                    width: window.width.max(Window::MIN_WIDTH),
+                    ..window
+                };
+            }
             Message::ExportImage => {
                 #[cfg(feature = "av")]
                 if let Some(data) = self.transport.video_export_data(&self.program) {
                     let suggested = self.suggested_export_name("mp4");
                     return tasks::export_image(data, suggested);
                 }
+                if self.program.svg().is_some() {
+                    return Task::done(Message::ExportSvg(self.svg_export_scale));
+                }
                 if let Some(data) = self.program.export_data() {
                     let ext = if data.is_animated() { "gif" } else { "png" };
                     let suggested = self.suggested_export_name(ext);
@@ -505,6 +847,40 @@ impl App {
                     return tasks::export_image(data, suggested);
                 }
             }
+            Message::ExportPages => {
+                if let (Some(pages), Some(data)) =
+                    (self.program.pages(), self.program.export_frame_data())
+                {
+                    let suggested = self.suggested_export_name("tiff");
+                    return tasks::export_pages(data, Arc::clone(&pages.source), suggested);
+                }
+            }
+            Message::ExportLayer => {
+                if let (Some(selection), Some(view), Some(data)) = (
+                    self.program.layers(),
+                    self.layer_target(),
+                    self.program.export_frame_data(),
+                ) && self.selected_layer < view.visible.len()
+                {
+                    let suggested = self.suggested_export_name("png");
+                    let view = view.with_solo(Some(self.selected_layer));
+                    return tasks::export_layer(
+                        data,
+                        Arc::clone(&selection.document),
+                        view,
+                        suggested,
+                    );
+                }
+            }
+            Message::ExportSvg(scale) => {
+                self.svg_export_scale = scale;
+                if let (Some(document), Some(data)) =
+                    (self.program.svg(), self.program.export_frame_data())
+                {
+                    let suggested = self.suggested_export_name("png");
+                    return tasks::export_svg(data, Arc::clone(document), scale, suggested);
+                }
+            }
             Message::ExportProgress(p) => {
                 self.export_progress = Some(p);
             }
@@ -569,6 +945,88 @@ impl App {
         )
     }
 
+    /// Renders another view of the current document in the background. While
+    /// one render is running only the latest request is kept, so dragging a
+    /// range or holding a page key does not queue up a render per step.
+    fn render(&mut self, request: Render) -> Task<Message> {
+        if self.rendering.is_some() {
+            self.pending_render = Some(request);
+            return Task::none();
+        }
+        let generation = self.render_generation;
+        let task = match &request {
+            Render::Exr(view) => match self.program.exr() {
+                Some(selection) if selection.view != *view => {
+                    tasks::render_exr(Arc::clone(&selection.document), *view, generation)
+                }
+                _ => return Task::none(),
+            },
+            Render::Page(index) => match self.program.pages() {
+                Some(pages) if (pages.index != *index || pages.sheet) && *index < pages.count() => {
+                    tasks::load_page(Arc::clone(&pages.source), *index, generation)
+                }
+                _ => return Task::none(),
+            },
+            Render::Sheet => match self.program.pages() {
+                Some(pages) if !pages.sheet => {
+                    tasks::load_sheet(Arc::clone(&pages.source), pages.index, generation)
+                }
+                _ => return Task::none(),
+            },
+            Render::Fits(view) => match self.program.fits() {
+                Some(selection) if selection.view != *view => {
+                    tasks::render_fits(selection.clone(), *view, generation)
+                }
+                _ => return Task::none(),
+            },
+            Render::Texture(view) => match self.program.texture() {
+                Some(selection) if selection.view != *view => {
+                    tasks::render_texture(Arc::clone(&selection.document), *view, generation)
+                }
+                _ => return Task::none(),
+            },
+            Render::Raw(settings) => match self.program.raw() {
+                Some(selection) if selection.settings != *settings => {
+                    tasks::render_raw(Arc::clone(&selection.document), *settings, generation)
+                }
+                _ => return Task::none(),
+            },
+            Render::Layers(view) => match self.program.layers() {
+                Some(selection) if selection.view != *view => {
+                    tasks::render_layers(Arc::clone(&selection.document), view.clone(), generation)
+                }
+                _ => return Task::none(),
+            },
+        };
+        self.rendering = Some(request);
+        task
+    }
+
+    /// The page the view is on or headed to, so repeated page keys keep
+    /// advancing while a page is still loading.
+    fn page_target(&self) -> Option<usize> {
+        let pages = self.program.pages()?;
+        match self.pending_render.as_ref().or(self.rendering.as_ref()) {
+            Some(Render::Page(index)) => Some(*index),
+            _ => Some(pages.index),
+        }
+    }
+
+    /// The layer view on screen or headed there, so toggling several layers
+    /// in quick succession builds on each toggle rather than on the last
+    /// finished composite.
+    fn layer_target(&self) -> Option<LayerView> {
+        let selection = self.program.layers()?;
+        match self.pending_render.as_ref().or(self.rendering.as_ref()) {
+            Some(Render::Layers(view)) => Some(view.clone()),
+            _ => Some(selection.view.clone()),
+        }
+    }
+
+    fn page_count(&self) -> usize {
+        self.program.pages().map_or(0, |p| p.count())
+    }
+
     fn trim_handles(&self) -> Option<timeline_bar::TrimHandles> {
         let duration = self.transport.media_timing(&self.program)?.duration;
         if duration.is_zero() {
@@ -599,12 +1057,108 @@ impl App {
             .unwrap_or_else(|| format!("export.{ext}"))
     }
 
+    /// Whether Next and Previous trade places: in an archive, when manga
+    /// reading order is on.
+    fn reading_right_to_left(&self) -> bool {
+        self.config.right_to_left && self.gallery.archive().is_some()
+    }
+
+    /// Loads `path` under the current load generation.
+    fn load(&self, path: PathBuf) -> Task<Message> {
+        tasks::load_media(
+            path,
+            self.load_generation,
+            self.config.embedded_preview_only,
+        )
+    }
+
+    /// Brings the gallery and grid up to date with files another program
+    /// changed. When the file on screen went, the one that took its place
+    /// opens; when it was rewritten, it is read again under the same view.
+    fn folder_changed(&mut self, changed: Vec<PathBuf>) -> Task<Message> {
+        let selected = self.gallery.paths().get(self.grid.selected).cloned();
+        let before = self.gallery.current().cloned();
+        let rewritten = self.gallery.sync(&changed);
+        let grid = self.grid.relist(
+            &self.gallery,
+            selected.as_deref(),
+            &changed,
+            self.config.grid_cell_size,
+        );
+        let load = match self.gallery.current().cloned() {
+            Some(p) if Some(&p) != before.as_ref() => Task::done(Message::MediaSelected(p)),
+            // Read again once the load in flight lands. A reload in flight
+            // keeps its view; a first load has none on screen to keep.
+            Some(p) if rewritten && self.loading.is_some() => {
+                self.pending_media = Some(p);
+                Task::none()
+            }
+            Some(p) if rewritten => {
+                self.reload = Some(self.program.snapshot());
+                self.loading = Some(self.gallery.filename(&p));
+                self.load_generation = self.load_generation.wrapping_add(1);
+                self.load(p)
+            }
+            _ => Task::none(),
+        };
+        Task::batch([grid, load])
+    }
+
+    /// Lists the gallery again through `relist`, keeping the grid's
+    /// selection and the file on screen where they still are. A folder that
+    /// has to be read again is read in the background.
+    fn relist_gallery(&mut self, relist: impl FnOnce(&mut Gallery)) -> Task<Message> {
+        let selected = self.gallery.paths().get(self.grid.selected).cloned();
+        let before = self.gallery.current().cloned();
+        relist(&mut self.gallery);
+        let list = self.list_gallery();
+        let grid = self.grid.relist(
+            &self.gallery,
+            selected.as_deref(),
+            &[],
+            self.config.grid_cell_size,
+        );
+        let load = match self.gallery.current() {
+            Some(p) if Some(p) != before.as_ref() => Task::done(Message::MediaSelected(p.clone())),
+            _ => Task::none(),
+        };
+        Task::batch([grid, load, list])
+    }
+
+    /// Reads the gallery's folder in the background when it asked for that.
+    fn list_gallery(&mut self) -> Task<Message> {
+        match self.gallery.take_request() {
+            Some(request) => tasks::list_folder(request),
+            None => Task::none(),
+        }
+    }
+
+    fn step_gallery(&mut self, forward: bool) -> Task<Message> {
+        let next = if forward {
+            self.gallery.next()
+        } else {
+            self.gallery.previous()
+        };
+        match next {
+            Some(p) => Task::done(Message::MediaSelected(p.clone())),
+            None => Task::none(),
+        }
+    }
+
     fn apply_media(&mut self, media: MediaData) {
         self.histogram = None;
         self.histogram_inflight = None;
+        self.render_generation = self.render_generation.wrapping_add(1);
+        self.rendering = None;
+        self.pending_render = None;
+        self.selected_layer = 0;
+        self.svg_stale = false;
         self.transport.clear_video();
         match media {
-            MediaData::Image(data) => self.program.set_image(*data),
+            MediaData::Image(data) => {
+                self.program.set_image(*data);
+                self.apply_orientation();
+            }
             MediaData::Animation(anim) => self.program.set_animation(anim),
             #[cfg(feature = "av")]
             MediaData::Video(info) => self.transport.attach_video(*info, &mut self.program),
@@ -614,6 +1168,34 @@ impl App {
         self.program.fit();
     }
 
+    /// Asks for a sharper raster of an SVG's visible region when the view has
+    /// outgrown what is on hand. One renders at a time; a view change in the
+    /// meantime asks again once it lands.
+    fn refine_svg(&mut self) -> Task<Message> {
+        let Some((document, request)) = self.program.svg_detail_request(self.scale_factor()) else {
+            return Task::none();
+        };
+        if self.svg_rasterizing {
+            self.svg_stale = true;
+            return Task::none();
+        }
+        self.svg_rasterizing = true;
+        tasks::rasterize_svg(document, request)
+    }
+
+    fn apply_orientation(&mut self) {
+        let Some(upright) = self.program.exif().and_then(|e| e.display_transform()) else {
+            return;
+        };
+        let (turns, mirror) = if self.config.auto_orient {
+            upright
+        } else {
+            (0, false)
+        };
+        self.program.set_mirror(mirror);
+        self.program.set_base_rotation(turns);
+    }
+
     fn handle_event(&mut self, event: Event) -> Task<Message> {
         match event {
             Event::Mouse(iced::mouse::Event::ButtonReleased(iced::mouse::Button::Left))
@@ -629,6 +1211,12 @@ impl App {
             Event::Window(window::Event::FileDropped(path)) => {
                 Task::done(Message::MediaSelected(path))
             }
+            Event::Window(window::Event::Resized(size)) => grid::update(
+                &mut self.grid,
+                &self.gallery,
+                &mut self.config,
+                GridMsg::Resized(size),
+            ),
             Event::Keyboard(keyboard::Event::KeyPressed {
                 physical_key,
                 modifiers,
@@ -650,9 +1238,43 @@ impl App {
             };
         }
 
-        match self.config.keymap.resolve(&physical_key, &modifiers) {
+        let action = self.config.keymap.resolve(&physical_key, &modifiers);
+        if self.grid.open {
+            if let Some(msg) = grid::key_message(&physical_key, modifiers) {
+                return Task::done(msg.into());
+            }
+            // Only what makes sense over the grid; the rest acts on a viewer
+            // that is not showing.
+            let cell = self.config.grid_cell_size;
+            match action {
+                Some(Action::ZoomIn) => {
+                    return Task::done(GridMsg::SetCellSize(cell + GRID_CELL_STEP).into());
+                }
+                Some(Action::ZoomOut) => {
+                    return Task::done(GridMsg::SetCellSize(cell - GRID_CELL_STEP).into());
+                }
+                Some(Action::ZoomFit) => {
+                    return Task::done(GridMsg::SetCellSize(GRID_CELL_DEFAULT).into());
+                }
+                Some(
+                    Action::ToggleGrid
+                    | Action::ToggleSubfolders
+                    | Action::ToggleFullscreen
+                    | Action::ToggleBottomBar
+                    | Action::OpenMedia
+                    | Action::UiScaleUp
+                    | Action::UiScaleDown
+                    | Action::UiScaleReset,
+                ) => {}
+                _ => return Task::none(),
+            }
+        }
+
+        match action {
             Some(Action::Next) => Task::done(Message::Next),
             Some(Action::Previous) => Task::done(Message::Previous),
+            Some(Action::NextPage) => Task::done(Message::NextPage),
+            Some(Action::PreviousPage) => Task::done(Message::PreviousPage),
             Some(Action::ToggleFullscreen) => Task::done(Message::ToggleFullscreen),
             Some(Action::FocusScale) => {
                 self.focus_scale = true;
@@ -697,6 +1319,8 @@ impl App {
             Some(Action::ToggleCheckerboard) => Task::done(Message::ToggleCheckerboard),
             Some(Action::TogglePixelGrid) => Task::done(Message::TogglePixelGrid),
             Some(Action::ToggleBottomBar) => Task::done(Message::ToggleBottomBar),
+            Some(Action::ToggleGrid) => Task::done(GridMsg::Toggle.into()),
+            Some(Action::ToggleSubfolders) => Task::done(Message::ToggleSubfolders),
             Some(Action::OpenMedia) => Task::done(Message::SelectMedia),
             Some(Action::CopyImage) => Task::done(Message::CopyImage),
             Some(Action::ExportImage) => Task::done(Message::ExportImage),
@@ -747,6 +1371,16 @@ impl App {
         #[cfg(feature = "av")]
         let video_panel = self.transport.video_panel();
 
+        if self.grid.open {
+            return self.with_folder_tree(grid_view::view(
+                &self.grid,
+                &self.gallery,
+                self.config.grid_cell_size,
+                self.config.show_bottom_bar,
+                &self.config.keymap,
+            ));
+        }
+
         let histogram = self.histogram.as_ref().map(|h| &h.data);
 
         let mut col = column![];
@@ -771,6 +1405,8 @@ impl App {
             histogram,
             context_menu: self.context_menu.map(|p| iced::Point::new(p.x, p.y)),
             timing: self.transport.media_timing(&self.program),
+            hide_patient_info: self.config.hide_patient_info,
+            selected_layer: self.selected_layer,
             #[cfg(feature = "av")]
             video_panel,
         }));
@@ -801,20 +1437,36 @@ impl App {
                 self.config.show_edit,
                 self.program.show_checkerboard,
                 self.gallery.current().is_some(),
+                self.program.svg().is_some(),
                 self.transport.playback_active(&self.program),
                 self.program.fit_active(),
+                self.program.pages().map(|p| (p.index, p.count())),
+                self.program.display_tone(),
                 self.export_progress,
                 &self.config.keymap,
             ));
         }
 
-        col.into()
+        self.with_folder_tree(col.into())
+    }
+
+    /// Puts the folder tree beside `content` while the gallery spans
+    /// subfolders.
+    fn with_folder_tree<'a>(&'a self, content: Element<'a, Message>) -> Element<'a, Message> {
+        if !self.gallery.recursive() || self.gallery.folder().is_none() {
+            return content;
+        }
+        row![
+            folder_tree::view(&self.gallery, &self.collapsed_folders, &self.config.theme),
+            content,
+        ]
+        .into()
     }
 
     pub fn title(&self) -> String {
         self.gallery
             .current()
-            .map(|p| p.to_string_lossy().into_owned())
+            .map(|p| self.gallery.display_path(p))
             .unwrap_or_else(|| "Bloom".into())
     }
 
@@ -845,6 +1497,13 @@ impl App {
             subs.push(every(delay).map(|t| TransportMsg::Tick(t).into()));
         }
 
+        if let Some(folder) = self.gallery.folder() {
+            subs.push(Subscription::run_with(
+                (folder.to_path_buf(), self.gallery.recursive()),
+                watch::changes,
+            ));
+        }
+
         if self.config_dirty {
             subs.push(every(Duration::from_secs(1)).map(|_| Message::SaveConfig));
         }
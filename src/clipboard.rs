@@ -1,6 +1,9 @@
 use std::path::PathBuf;
 
-use crate::{gallery::SUPPORTED, wgpu::media::image_data::ImageData};
+use crate::{
+    gallery::SUPPORTED,
+    wgpu::media::{image_data::ImageData, sniff},
+};
 
 pub enum ClipboardImage {
     Pixels(Box<ImageData>),
@@ -27,7 +30,7 @@ pub fn read() -> Option<ClipboardImage> {
                 .and_then(|e| e.to_str())
                 .unwrap_or("")
                 .to_ascii_lowercase();
-            if SUPPORTED.contains(&ext.as_str()) {
+            if SUPPORTED.contains(&ext.as_str()) || sniff::sniff(&path).is_some() {
                 return Some(ClipboardImage::Path(path));
             }
         }
@@ -2,20 +2,28 @@ use iced::alignment::Vertical;
 use iced::widget::progress_bar;
 use iced::widget::svg::Handle;
 use iced::widget::tooltip::Position;
-use iced::widget::{Column, Space, column, container, row, svg};
+use iced::widget::{Column, Space, column, container, row, svg, text};
 use iced::window::Mode;
-use iced::{Border, Element, Length};
+use iced::{Border, Element, Font, Length};
 
-use crate::app::Message;
+use crate::app::{GridMsg, Message};
+use crate::components::viewer::export_menu_item;
 use crate::keybinds::{Action, Keymap};
 use crate::styles::{
     BAR_HEIGHT, BUTTON_SIZE, PAD, bar_style, icon_button_style, panel_divider_style, svg_style,
 };
 use crate::ui::{svg_button, svg_button_toggle, with_tooltip, with_tooltip_key};
-use crate::widgets::menu::{menu_item, menu_item_enabled, menu_separator, styled_menu};
+use crate::wgpu::media::tonemap::{EXPOSURE_MAX, EXPOSURE_MIN, Tone, ToneOperator, Window};
+use crate::widgets::menu::{
+    SubMenuSide, menu_item, menu_item_enabled, menu_separator, styled_menu,
+};
 use crate::widgets::menu_button::{MenuAlign, MenuButton};
+use crate::widgets::number_entry::NumberEntry;
+use crate::widgets::option_picker::OptionPicker;
 use crate::widgets::scale_entry::ScaleEntry;
 
+const TONE_PICKER_WIDTH: f32 = 88.0;
+
 #[allow(clippy::too_many_arguments)]
 pub fn view<'a>(
     mode: Mode,
@@ -26,8 +34,11 @@ pub fn view<'a>(
     show_edit: bool,
     show_checkerboard: bool,
     has_image: bool,
+    is_svg: bool,
     is_animation: bool,
     fit_active: bool,
+    page: Option<(usize, usize)>,
+    tone: Option<Tone>,
     export_progress: Option<f32>,
     keymap: &Keymap,
 ) -> Element<'a, Message> {
@@ -110,6 +121,87 @@ pub fn view<'a>(
     .spacing(2)
     .align_y(Vertical::Center);
 
+    let left_buttons = match page {
+        Some((index, count)) => left_buttons
+            .push(Space::new().width(PAD))
+            .push(with_tooltip_key(
+                svg_button(
+                    include_bytes!("../../assets/icons/left.svg"),
+                    Message::PreviousPage,
+                ),
+                "Previous page",
+                Position::Top,
+                keymap,
+                Action::PreviousPage,
+            ))
+            .push(with_tooltip(
+                text(format!("{} / {count}", index + 1))
+                    .size(12)
+                    .font(Font::MONOSPACE),
+                "Page",
+                Position::Top,
+            ))
+            .push(with_tooltip_key(
+                svg_button(
+                    include_bytes!("../../assets/icons/right.svg"),
+                    Message::NextPage,
+                ),
+                "Next page",
+                Position::Top,
+                keymap,
+                Action::NextPage,
+            )),
+        None => left_buttons,
+    };
+
+    let left_buttons = match tone {
+        Some(Tone::Map(tone)) => left_buttons
+            .push(Space::new().width(PAD))
+            .push(with_tooltip(
+                OptionPicker::new(ToneOperator::ALL, tone.operator, Message::SetToneOperator)
+                    .width(Length::Fixed(TONE_PICKER_WIDTH)),
+                "Tonemap",
+                Position::Top,
+            ))
+            .push(with_tooltip(
+                NumberEntry::new(tone.exposure, Message::SetExposure)
+                    .range(EXPOSURE_MIN, EXPOSURE_MAX)
+                    .step(0.1)
+                    .drag_per_px(0.05)
+                    .suffix(" EV"),
+                "Exposure",
+                Position::Top,
+            )),
+        Some(Tone::Window(window)) => {
+            // Whole units for CT-like ranges, tenths for data that spans a few.
+            let step = if window.width >= 10.0 { 1.0 } else { 0.1 };
+            let drag = (window.width / 200.0).max(0.01);
+            left_buttons
+                .push(Space::new().width(PAD))
+                .push(with_tooltip(
+                    NumberEntry::new(window.center, move |center| {
+                        Message::SetWindow(Window { center, ..window })
+                    })
+                    .range(f32::MIN, f32::MAX)
+                    .step(step)
+                    .drag_per_px(drag),
+                    "Window level",
+                    Position::Top,
+                ))
+                .push(with_tooltip(
+                    NumberEntry::new(window.width, move |width| {
+                        Message::SetWindow(Window { width, ..window })
+                    })
+                    .range(Window::MIN_WIDTH, f32::MAX)
+                    .step(step)
+                    .drag_per_px(drag),
+                    "Window width",
+                    Position::Top,
+                ))
+        }
+        None => left_buttons,
+    };
+
     let right_buttons = row![
         with_tooltip_key(
             svg_button_toggle(
@@ -161,6 +253,16 @@ pub fn view<'a>(
             keymap,
             Action::OpenMedia,
         ),
+        with_tooltip_key(
+            svg_button(
+                include_bytes!("../../assets/icons/grid.svg"),
+                GridMsg::Toggle.into()
+            ),
+            "Thumbnail grid",
+            Position::Top,
+            keymap,
+            Action::ToggleGrid,
+        ),
         with_tooltip(
             MenuButton::new(
                 svg(Handle::from_memory(include_bytes!(
@@ -173,8 +275,9 @@ pub fn view<'a>(
                     column![
                         menu_item("Preferences", Message::TogglePreferences),
                         menu_separator(),
-                        menu_item_enabled("Export", Message::ExportImage, has_image),
+                        export_menu_item(is_svg, has_image, SubMenuSide::Left),
                         menu_item_enabled("Export frame", Message::ExportFrame, is_animation),
+                        menu_item_enabled("Export all pages", Message::ExportPages, page.is_some()),
                     ]
                     .push(menu_separator())
                     .push(menu_item("About", Message::OpenAbout))
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use iced::alignment::Vertical;
+use iced::widget::scrollable::{Direction, Scrollbar};
+use iced::widget::svg::Handle;
+use iced::widget::tooltip::Position;
+use iced::widget::{Column, Space, button, column, container, row, scrollable, svg, text};
+use iced::{Element, Font, Length, Padding, Theme};
+
+use crate::app::Message;
+use crate::gallery::Gallery;
+use crate::styles::{
+    FOLDER_TREE_WIDTH, INFO_HEADER_LABEL_SIZE, INFO_ROW_FONT_SIZE, PAD, bar_style, muted_text,
+    plain_icon_button_style, pref_nav_button_style, svg_style,
+};
+use crate::ui::{svg_button_plain, with_tooltip};
+
+const INDENT: f32 = 12.0;
+const CARET_SIZE: f32 = 14.0;
+
+/// The folders of a recursive gallery as a tree. A click on a folder jumps
+/// to its first file, the caret beside it folds away the folders under it.
+pub fn view<'a>(
+    gallery: &'a Gallery,
+    collapsed: &HashSet<PathBuf>,
+    theme: &Theme,
+) -> Element<'a, Message> {
+    let muted = muted_text(theme);
+    let current = gallery.current().and_then(|p| p.parent());
+    let folders = gallery.folders();
+
+    let mut header = row![
+        text("Folders")
+            .size(INFO_HEADER_LABEL_SIZE)
+            .color(muted)
+            .font(Font::MONOSPACE),
+        Space::new().width(Length::Fill),
+    ]
+    .align_y(Vertical::Center);
+    if gallery.folder().and_then(Path::parent).is_some() {
+        header = header.push(with_tooltip(
+            svg_button_plain(
+                include_bytes!("../../assets/icons/up.svg"),
+                Message::ParentFolder,
+            ),
+            "Parent folder",
+            Position::Bottom,
+        ));
+    }
+
+    let mut rows = Column::new();
+    // The depth of a folded folder while its subtree is being passed over.
+    let mut folded: Option<usize> = None;
+    for (i, folder) in folders.iter().enumerate() {
+        if folded.is_some_and(|depth| folder.depth > depth) {
+            continue;
+        }
+        let closed = collapsed.contains(&folder.path);
+        folded = closed.then_some(folder.depth);
+        let has_children = folders.get(i + 1).is_some_and(|f| f.depth > folder.depth);
+
+        let caret: Element<'a, Message> = if has_children {
+            let icon: &'static [u8] = if closed {
+                include_bytes!("../../assets/icons/right.svg")
+            } else {
+                include_bytes!("../../assets/icons/down.svg")
+            };
+            button(
+                svg(Handle::from_memory(icon))
+                    .style(svg_style)
+                    .width(CARET_SIZE)
+                    .height(CARET_SIZE),
+            )
+            .padding(0)
+            .style(plain_icon_button_style)
+            .on_press(Message::ToggleFolder(folder.path.clone()))
+            .into()
+        } else {
+            Space::new().width(CARET_SIZE).into()
+        };
+
+        let name = folder
+            .path
+            .file_name()
+            .unwrap_or(folder.path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let count = (folder.files > 0).then(|| folder.files.to_string());
+        let label = button(
+            row![
+                container(
+                    text(name)
+                        .size(INFO_ROW_FONT_SIZE)
+                        .wrapping(text::Wrapping::None)
+                )
+                .width(Length::Fill)
+                .clip(true),
+                text(count.unwrap_or_default())
+                    .size(INFO_ROW_FONT_SIZE)
+                    .color(muted)
+                    .font(Font::MONOSPACE),
+            ]
+            .spacing(PAD)
+            .align_y(Vertical::Center),
+        )
+        .on_press(Message::JumpToFolder(folder.first))
+        .padding([1.0, PAD])
+        .width(Length::Fill)
+        .style(pref_nav_button_style(
+            current == Some(folder.path.as_path()),
+        ));
+
+        rows = rows.push(
+            row![
+                Space::new().width(folder.depth as f32 * INDENT),
+                caret,
+                label
+            ]
+            .align_y(Vertical::Center),
+        );
+    }
+
+    let pad = PAD * 2.0;
+    let content = column![header, rows].spacing(PAD).padding(Padding {
+        top: PAD,
+        right: pad,
+        bottom: pad,
+        left: pad,
+    });
+
+    container(
+        scrollable(content)
+            .width(Length::Fill)
+            .direction(Direction::Vertical(
+                Scrollbar::new().width(4).scroller_width(4),
+            )),
+    )
+    .style(bar_style)
+    .height(Length::Fill)
+    .width(Length::Fixed(FOLDER_TREE_WIDTH))
+    .into()
+}
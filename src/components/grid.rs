@@ -0,0 +1,200 @@
+use iced::alignment::Vertical;
+use iced::widget::image::{self, FilterMethod};
+use iced::widget::scrollable::{Direction, Scrollbar};
+use iced::widget::tooltip::Position;
+use iced::widget::{Column, Row, Space, button, column, container, row, scrollable, text};
+use iced::{ContentFit, Element, Font, Length, Theme};
+
+use crate::app::Message;
+use crate::app::grid::{
+    GRID_GAP, GRID_LABEL_HEIGHT, GRID_PADDING, GRID_SCROLL, GridMsg, GridState, row_height,
+};
+use crate::config::{GRID_CELL_MAX, GRID_CELL_MIN, GRID_CELL_STEP};
+use crate::gallery::{Gallery, Sort, SortKey};
+use crate::keybinds::{Action, Keymap};
+use crate::styles::{BAR_HEIGHT, PAD, bar_style, grid_cell_style, muted_text, panel_divider_style};
+use crate::thumbnails::Thumbnail;
+use crate::ui::{svg_button, svg_button_active, svg_button_toggle, with_tooltip, with_tooltip_key};
+use crate::widgets::option_picker::OptionPicker;
+use crate::widgets::value_slider::{Fmt, ValueSlider};
+
+const CELL_PAD: f32 = 4.0;
+const LABEL_SIZE: f32 = 11.0;
+const SIZE_SLIDER_WIDTH: f32 = 140.0;
+const SORT_PICKER_WIDTH: f32 = 96.0;
+
+pub fn view<'a>(
+    state: &'a GridState,
+    gallery: &'a Gallery,
+    cell: f32,
+    show_bar: bool,
+    keymap: &Keymap,
+) -> Element<'a, Message> {
+    let paths = gallery.paths();
+    let columns = state.columns(cell);
+    let rows = state.visible_rows(cell, paths.len());
+    let pitch = row_height(cell) + GRID_GAP;
+    let total_rows = paths.len().div_ceil(columns);
+
+    let mut body = Column::new().push(Space::new().height(rows.start as f32 * pitch));
+    for r in rows.clone() {
+        let mut cells = Row::new().spacing(GRID_GAP);
+        for index in r * columns..((r + 1) * columns).min(paths.len()) {
+            let path = &paths[index];
+            let thumbnail = state.thumbnails.get(path);
+            cells = cells.push(cell_view(
+                index,
+                gallery.filename(path),
+                thumbnail,
+                index == state.selected,
+                cell,
+            ));
+        }
+        body = body.push(container(cells).height(pitch));
+    }
+    body = body.push(Space::new().height((total_rows - rows.end) as f32 * pitch));
+
+    let grid = scrollable(container(body).padding(GRID_PADDING).width(Length::Fill))
+        .id(GRID_SCROLL)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .direction(Direction::Vertical(
+            Scrollbar::new().width(4).scroller_width(4),
+        ))
+        .on_scroll(|viewport| GridMsg::Scrolled(viewport).into());
+
+    let mut col = column![grid];
+    if show_bar {
+        col = col.push(bar(state, gallery, cell, keymap));
+    }
+    col.into()
+}
+
+fn cell_view<'a>(
+    index: usize,
+    name: String,
+    thumbnail: Option<Option<&Thumbnail>>,
+    selected: bool,
+    cell: f32,
+) -> Element<'a, Message> {
+    let picture: Element<'a, Message> = match thumbnail {
+        Some(Some(t)) => image::Image::new(t.handle.clone())
+            .content_fit(ContentFit::Contain)
+            .filter_method(FilterMethod::Linear)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into(),
+        // Unreadable, or not decoded yet: the name below says what it is.
+        _ => Space::new().into(),
+    };
+    let label = container(
+        text(name)
+            .size(LABEL_SIZE)
+            .style(move |theme: &Theme| text::Style {
+                color: (!selected).then(|| muted_text(theme)),
+            }),
+    )
+    .height(GRID_LABEL_HEIGHT - CELL_PAD)
+    .align_y(Vertical::Bottom)
+    .clip(true);
+
+    button(column![
+        container(picture)
+            .center(Length::Fill)
+            .width(Length::Fill)
+            .height(Length::Fill),
+        label,
+    ])
+    .width(cell)
+    .height(row_height(cell))
+    .padding(CELL_PAD)
+    .style(grid_cell_style(selected))
+    .on_press(GridMsg::Open(index).into())
+    .into()
+}
+
+fn bar<'a>(
+    state: &'a GridState,
+    gallery: &'a Gallery,
+    cell: f32,
+    keymap: &Keymap,
+) -> Element<'a, Message> {
+    let count = text(format!("{} / {}", state.selected + 1, gallery.len()))
+        .size(12)
+        .font(Font::MONOSPACE);
+    let size = container(
+        ValueSlider::new(cell, GRID_CELL_MIN..=GRID_CELL_MAX, |size| {
+            GridMsg::SetCellSize(size).into()
+        })
+        .step(GRID_CELL_STEP)
+        .format(Fmt::num(0).suffix(" px")),
+    )
+    .width(SIZE_SLIDER_WIDTH);
+
+    let sort = gallery.sort();
+    let key = OptionPicker::new(SortKey::ALL, sort.key, move |key| {
+        GridMsg::Sort(Sort { key, ..sort }).into()
+    })
+    .width(Length::Fixed(SORT_PICKER_WIDTH));
+    let flip = GridMsg::Sort(Sort {
+        descending: !sort.descending,
+        ..sort
+    });
+    let (direction, direction_tooltip): (&'static [u8], &str) = if sort.descending {
+        (
+            include_bytes!("../../assets/icons/sort-descending.svg"),
+            "Descending",
+        )
+    } else {
+        (
+            include_bytes!("../../assets/icons/sort-ascending.svg"),
+            "Ascending",
+        )
+    };
+
+    let content = row![
+        with_tooltip(count, "Selected", Position::Top),
+        Space::new().width(Length::Fill),
+        with_tooltip(key, "Sort by", Position::Top),
+        with_tooltip(
+            svg_button(direction, flip.into()),
+            direction_tooltip,
+            Position::Top
+        ),
+        with_tooltip_key(
+            svg_button_toggle(
+                include_bytes!("../../assets/icons/folder.svg"),
+                Message::ToggleSubfolders,
+                gallery.recursive(),
+            ),
+            "Include subfolders",
+            Position::Top,
+            keymap,
+            Action::ToggleSubfolders,
+        ),
+        with_tooltip(size, "Thumbnail size", Position::Top),
+        with_tooltip_key(
+            svg_button_active(
+                include_bytes!("../../assets/icons/grid.svg"),
+                GridMsg::Toggle.into()
+            ),
+            "Thumbnail grid",
+            Position::Top,
+            keymap,
+            Action::ToggleGrid,
+        ),
+    ]
+    .height(BAR_HEIGHT)
+    .width(Length::Fill)
+    .align_y(Vertical::Center)
+    .spacing(PAD);
+
+    container(column![
+        container(Space::new().height(2.0))
+            .width(Length::Fill)
+            .style(panel_divider_style),
+        container(content).padding([0.0, PAD]),
+    ])
+    .style(bar_style)
+    .into()
+}
@@ -8,7 +8,7 @@ use iced::widget::image::{self, FilterMethod};
 use iced::widget::scrollable::{Direction, Scrollbar};
 use iced::widget::svg::Handle;
 use iced::widget::tooltip::Position;
-use iced::widget::{Space, button, column, container, row, scrollable, stack, svg, text};
+use iced::widget::{Space, button, column, container, row, scrollable, stack, svg, text, toggler};
 use iced::{Color, Element, Font, Length, Padding, Point, Rectangle, Renderer, Theme, mouse};
 
 use crate::app::Message;
@@ -17,13 +17,26 @@ use crate::styles::{
     INFO_CHANNEL_COL_WIDTH, INFO_HEADER_LABEL_SIZE, INFO_HISTOGRAM_HEIGHT, INFO_PANEL_WIDTH,
     INFO_ROW_FONT_SIZE, INFO_SECTION_GAP, INFO_SECTION_SPACING, PAD, RULE_HEIGHT, bar_style,
     color_swatch_style, info_section_header_style, muted_text, panel_divider_style,
-    svg_color_style,
+    pref_nav_button_style, svg_color_style,
 };
 use crate::ui::{format_duration, with_tooltip_delay};
+use crate::wgpu::media::dicom::DicomInfo;
+use crate::wgpu::media::exr::{ChannelMode, ExrSelection, ExrView};
+use crate::wgpu::media::fits::{self, FitsSelection, FitsView, Stretch};
+use crate::wgpu::media::layers::LayerSelection;
+use crate::wgpu::media::pages::Pages;
+use crate::wgpu::media::raw::{Demosaic, RawSelection, RawSettings, WhiteBalance};
+use crate::wgpu::media::sniff;
+use crate::wgpu::media::texture::{FACES, TextureSelection, TextureView};
+use crate::wgpu::media::tonemap::Window;
 use crate::wgpu::view_program::{Histogram as HistogramData, ViewProgram};
 use crate::widgets::histogram::Histogram;
+use crate::widgets::number_entry::NumberEntry;
+use crate::widgets::option_picker::OptionPicker;
+use crate::widgets::value_slider::{Fmt, ValueSlider};
 
 const FILENAME_MAX_CHARS: usize = 18;
+const RANGE_ENTRY_WIDTH: f32 = 60.0;
 
 #[cfg(feature = "av")]
 pub struct VideoPanel<'a> {
@@ -227,6 +240,534 @@ fn aspect_ratio_str(w: u32, h: u32) -> String {
     format!("{}:{}", w / d, h / d)
 }
 
+fn list_item<'a>(
+    label: String,
+    detail: String,
+    indent: f32,
+    active: bool,
+    muted: Color,
+    msg: Message,
+) -> Element<'a, Message> {
+    button(
+        row![
+            Space::new().width(indent),
+            text(label)
+                .size(INFO_ROW_FONT_SIZE)
+                .font(Font::MONOSPACE)
+                .width(Length::Fill),
+            text(detail)
+                .size(INFO_ROW_FONT_SIZE)
+                .color(muted)
+                .font(Font::MONOSPACE),
+        ]
+        .align_y(Vertical::Center),
+    )
+    .on_press(msg)
+    .padding([1.0, PAD])
+    .width(Length::Fill)
+    .style(pref_nav_button_style(active))
+    .into()
+}
+
+/// One row per page with its size and compression. Reduced-resolution pages
+/// are marked, since a scanner's thumbnail otherwise looks like a real page.
+/// Icon entries show their bit depth, under a row for the sheet of all sizes.
+fn page_rows<'a>(pages: &Pages, muted: Color) -> Vec<Element<'a, Message>> {
+    let mut rows: Vec<Element<'a, Message>> = Vec::new();
+    if pages.source.is_icon() {
+        rows.push(list_item(
+            "All sizes".to_string(),
+            pages.count().to_string(),
+            0.0,
+            pages.sheet,
+            muted,
+            Message::ShowPageSheet,
+        ));
+    }
+    rows.extend(pages.source.pages().iter().enumerate().map(|(i, page)| {
+        let detail = match page.bit_depth {
+            Some(bits) => format!("{bits}-bit {}", page.compression),
+            None if page.reduced => format!("{} (thumb)", page.compression),
+            None => page.compression.clone(),
+        };
+        list_item(
+            format!("{:>2}  {} x {}", i + 1, page.width, page.height),
+            detail,
+            0.0,
+            i == pages.index && !pages.sheet,
+            muted,
+            Message::SelectPage(i),
+        )
+    }));
+    rows
+}
+
+/// How many images the container holds, then the primary image's depth and
+/// auxiliary images, which are listed but never shown.
+#[cfg(feature = "heif")]
+fn heif_rows<'a>(
+    info: &crate::wgpu::media::heif::HeifInfo,
+    muted: Color,
+) -> Vec<Element<'a, Message>> {
+    let images = match info.images {
+        n if info.sequence => format!("Sequence of {n}"),
+        n if n > 1 => format!("Burst of {n}"),
+        n => n.to_string(),
+    };
+    let mut rows = vec![
+        row_item("Container", info.container, muted),
+        row_item("Images", images, muted),
+    ];
+    rows.extend(info.auxiliary.iter().map(|item| {
+        row![
+            text(item.kind.clone())
+                .size(INFO_ROW_FONT_SIZE)
+                .color(muted)
+                .font(Font::MONOSPACE)
+                .width(Length::Fill),
+            text(format!("{} x {}", item.width, item.height))
+                .size(INFO_ROW_FONT_SIZE)
+                .font(Font::MONOSPACE)
+                .align_x(Horizontal::Right),
+        ]
+        .into()
+    }));
+    rows
+}
+
+/// How the texture is encoded, then a control for each of mip level, array
+/// layer, cubemap face and depth slice that it has more than one of.
+fn texture_rows<'a>(selection: &TextureSelection, muted: Color) -> Vec<Element<'a, Message>> {
+    let doc = &selection.document;
+    let view = selection.view;
+    let (width, height, depth) = doc.level_size(view.level);
+    let size = if depth > 1 {
+        format!("{width} x {height} x {depth}")
+    } else {
+        format!("{width} x {height}")
+    };
+    let mut rows = vec![
+        row_item("Container", doc.kind, muted),
+        row_item("Format", doc.format.clone(), muted),
+        row_item("Supercomp.", doc.supercompression.clone(), muted),
+        row_item("Alpha", doc.alpha, muted),
+        row_item("Level size", size, muted),
+    ];
+
+    let slider = |count: u32, current: u32, set: fn(TextureView, u32) -> TextureView| {
+        ValueSlider::new(current as f32, 0.0..=(count - 1) as f32, move |v| {
+            Message::SetTextureView(set(view, (v.round() as u32).min(count - 1)))
+        })
+        .step(1.0)
+        .format(Fmt::num(0))
+    };
+    if doc.levels > 1 {
+        let level = slider(doc.levels, view.level, |view, level| TextureView {
+            level,
+            ..view
+        });
+        rows.push(control_row("Mip level", level, muted));
+    }
+    if doc.layers > 1 {
+        let layer = slider(doc.layers, view.layer, |view, layer| TextureView {
+            layer,
+            ..view
+        });
+        rows.push(control_row("Layer", layer, muted));
+    }
+    if doc.faces > 1 {
+        rows.push(
+            OptionPicker::new(&FACES, view.face, move |face| {
+                Message::SetTextureView(TextureView { face, ..view })
+            })
+            .into(),
+        );
+    }
+    if depth > 1 {
+        let slice = slider(depth, view.slice, |view, slice| TextureView {
+            slice,
+            ..view
+        });
+        rows.push(control_row("Slice", slice, muted));
+    }
+    rows
+}
+
+fn raw_rows<'a>(selection: &RawSelection, muted: Color) -> Vec<Element<'a, Message>> {
+    let settings = selection.settings;
+    let set = move |f: fn(&mut RawSettings, f32)| {
+        move |v| {
+            let mut settings = settings;
+            f(&mut settings, v);
+            Message::SetRawSettings(settings)
+        }
+    };
+    let mut rows = vec![
+        OptionPicker::new(
+            WhiteBalance::ALL,
+            settings.white_balance,
+            move |white_balance| {
+                Message::SetRawSettings(RawSettings {
+                    white_balance,
+                    ..settings
+                })
+            },
+        )
+        .into(),
+    ];
+    if settings.white_balance == WhiteBalance::Custom {
+        rows.push(control_row(
+            "Temperature",
+            ValueSlider::new(
+                settings.kelvin,
+                RawSettings::KELVIN_RANGE,
+                set(|s, v| s.kelvin = v),
+            )
+            .step(50.0)
+            .format(Fmt::num(0).suffix(" K")),
+            muted,
+        ));
+    }
+    rows.push(control_row(
+        "Exposure",
+        ValueSlider::new(
+            settings.exposure,
+            RawSettings::EXPOSURE_RANGE,
+            set(|s, v| s.exposure = v),
+        )
+        .step(0.1)
+        .format(Fmt::signed(1).suffix(" EV")),
+        muted,
+    ));
+    rows.push(control_row(
+        "Highlights",
+        ValueSlider::new(
+            settings.highlights * 100.0,
+            0.0..=100.0,
+            set(|s, v| s.highlights = v / 100.0),
+        )
+        .step(1.0)
+        .format(Fmt::num(0).suffix("%")),
+        muted,
+    ));
+    rows.push(
+        OptionPicker::new(Demosaic::ALL, settings.demosaic, move |demosaic| {
+            Message::SetRawSettings(RawSettings {
+                demosaic,
+                ..settings
+            })
+        })
+        .into(),
+    );
+    rows.push(control_row(
+        "Camera crop",
+        toggler(settings.camera_crop).on_toggle(move |camera_crop| {
+            Message::SetRawSettings(RawSettings {
+                camera_crop,
+                ..settings
+            })
+        }),
+        muted,
+    ));
+    rows
+}
+
+/// The file's VOI windows and the full range, as one-click presets.
+fn window_rows<'a>(info: &DicomInfo, current: Window, muted: Color) -> Vec<Element<'a, Message>> {
+    info.presets
+        .iter()
+        .map(|(name, window)| {
+            list_item(
+                name.clone(),
+                format!("{} / {}", window.center.round(), window.width.round()),
+                0.0,
+                *window == current,
+                muted,
+                Message::SetWindow(*window),
+            )
+        })
+        .collect()
+}
+
+fn tag_rows<'a>(info: &DicomInfo, hide_patient: bool, muted: Color) -> Vec<Element<'a, Message>> {
+    info.tags
+        .iter()
+        .map(|tag| {
+            let value = if tag.identifying && hide_patient {
+                "(hidden)".to_string()
+            } else {
+                tag.value.clone()
+            };
+            column![
+                text(format!(
+                    "({:04X},{:04X}) {}",
+                    tag.tag.0, tag.tag.1, tag.name
+                ))
+                .size(INFO_ROW_FONT_SIZE)
+                .color(muted)
+                .font(Font::MONOSPACE),
+                text(value).size(INFO_ROW_FONT_SIZE).font(Font::MONOSPACE),
+            ]
+            .into()
+        })
+        .collect()
+}
+
+/// Every HDU with its shape, then the stretch of the one on screen and, for a
+/// cube, which plane.
+fn fits_rows<'a>(selection: &FitsSelection, muted: Color) -> Vec<Element<'a, Message>> {
+    let view = selection.view;
+    let mut rows = Vec::new();
+    let hdus = &selection.document.hdus;
+    if hdus.len() > 1 {
+        for (i, hdu) in hdus.iter().enumerate() {
+            let shape = if hdu.is_image() {
+                let dims: Vec<String> = hdu.axes.iter().map(usize::to_string).collect();
+                dims.join(" x ")
+            } else {
+                "no image".to_string()
+            };
+            rows.push(list_item(
+                format!("{i:>2}  {}", hdu.name),
+                shape,
+                0.0,
+                i == view.hdu,
+                muted,
+                Message::SelectFitsHdu(i),
+            ));
+        }
+    }
+
+    rows.push(OptionPicker::new(Stretch::ALL, view.stretch, Message::SetFitsStretch).into());
+    let (black, white) = view.points;
+    let step = ((white - black).abs() / 200.0).max(1e-4);
+    let entry = |value: f32, set: fn(FitsView, f32) -> FitsView| {
+        NumberEntry::new(value, move |v| Message::SetFitsView(set(view, v)))
+            .range(f32::MIN, f32::MAX)
+            .step(0.01)
+            .drag_per_px(step)
+            .width(RANGE_ENTRY_WIDTH)
+    };
+    rows.push(
+        row![
+            text("Points")
+                .size(INFO_ROW_FONT_SIZE)
+                .color(muted)
+                .font(Font::MONOSPACE)
+                .width(Length::Fill),
+            entry(black, |view, black| FitsView {
+                points: (black, view.points.1),
+                ..view
+            }),
+            entry(white, |view, white| FitsView {
+                points: (view.points.0, white),
+                ..view
+            }),
+        ]
+        .spacing(PAD)
+        .align_y(Vertical::Center)
+        .into(),
+    );
+
+    let planes = selection.hdu().planes();
+    if planes > 1 {
+        rows.push(
+            row![
+                text("Plane")
+                    .size(INFO_ROW_FONT_SIZE)
+                    .color(muted)
+                    .font(Font::MONOSPACE)
+                    .width(Length::Fill),
+                ValueSlider::new(view.plane as f32 + 1.0, 1.0..=planes as f32, move |p| {
+                    Message::SetFitsView(FitsView {
+                        plane: (p.round() as usize).clamp(1, planes) - 1,
+                        ..view
+                    })
+                })
+                .step(1.0)
+                .format(Fmt::num(0)),
+            ]
+            .spacing(PAD)
+            .align_y(Vertical::Center)
+            .into(),
+        );
+    }
+    rows
+}
+
+fn card_rows<'a>(selection: &FitsSelection) -> Vec<Element<'a, Message>> {
+    selection
+        .hdu()
+        .cards
+        .iter()
+        .map(|card| {
+            text(card.clone())
+                .size(INFO_ROW_FONT_SIZE)
+                .font(Font::MONOSPACE)
+                .into()
+        })
+        .collect()
+}
+
+/// Every layer of the EXR, with the selected one opened up into its channels.
+/// A channel on its own gets its display mode and range underneath.
+fn exr_rows<'a>(selection: &ExrSelection, muted: Color) -> Vec<Element<'a, Message>> {
+    let view = selection.view;
+    let mut rows = Vec::new();
+    for (i, layer) in selection.document.layers.iter().enumerate() {
+        let selected = i == view.layer;
+        rows.push(list_item(
+            layer.label().to_string(),
+            layer.channels.len().to_string(),
+            0.0,
+            selected && view.channel.is_none(),
+            muted,
+            Message::SelectExrChannel(i, None),
+        ));
+        if !selected {
+            continue;
+        }
+        for (c, channel) in layer.channels.iter().enumerate() {
+            rows.push(list_item(
+                channel.name.clone(),
+                if channel.is_half() { "half" } else { "float" }.to_string(),
+                PAD * 2.0,
+                view.channel == Some(c),
+                muted,
+                Message::SelectExrChannel(i, Some(c)),
+            ));
+        }
+    }
+
+    if view.channel.is_some() {
+        rows.push(
+            OptionPicker::new(ChannelMode::ALL, view.mode, move |mode| {
+                Message::SetExrView(ExrView { mode, ..view })
+            })
+            .into(),
+        );
+        if view.mode != ChannelMode::Ids {
+            let step = ((view.range.1 - view.range.0).abs() / 200.0).max(1e-4);
+            let entry = |value: f32, set: fn(ExrView, f32) -> ExrView| {
+                NumberEntry::new(value, move |v| Message::SetExrView(set(view, v)))
+                    .range(f32::MIN, f32::MAX)
+                    .step(0.01)
+                    .drag_per_px(step)
+                    .width(RANGE_ENTRY_WIDTH)
+            };
+            rows.push(
+                row![
+                    text("Range")
+                        .size(INFO_ROW_FONT_SIZE)
+                        .color(muted)
+                        .font(Font::MONOSPACE)
+                        .width(Length::Fill),
+                    entry(view.range.0, |view, lo| ExrView {
+                        range: (lo, view.range.1),
+                        ..view
+                    }),
+                    entry(view.range.1, |view, hi| ExrView {
+                        range: (view.range.0, hi),
+                        ..view
+                    }),
+                ]
+                .spacing(PAD)
+                .align_y(Vertical::Center)
+                .into(),
+            );
+        }
+    }
+    rows
+}
+
+fn control_row<'a>(
+    label: &'a str,
+    control: impl Into<Element<'a, Message>>,
+    muted: Color,
+) -> Element<'a, Message> {
+    row![
+        text(label)
+            .size(INFO_ROW_FONT_SIZE)
+            .color(muted)
+            .font(Font::MONOSPACE)
+            .width(Length::Fill),
+        control.into(),
+    ]
+    .spacing(PAD)
+    .align_y(Vertical::Center)
+    .into()
+}
+
+/// The layer tree, indented by group, then visibility, solo and opacity for
+/// the selected layer.
+fn layer_rows<'a>(
+    selection: &LayerSelection,
+    selected: usize,
+    muted: Color,
+) -> Vec<Element<'a, Message>> {
+    let view = &selection.view;
+    let mut rows: Vec<Element<'a, Message>> = selection
+        .document
+        .layers
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            let detail = if view.solo == Some(i) {
+                "solo".to_string()
+            } else if !view.visible[i] {
+                "hidden".to_string()
+            } else if view.opacity[i] < 1.0 {
+                format!("{:.0}%", view.opacity[i] * 100.0)
+            } else if layer.group {
+                "group".to_string()
+            } else {
+                String::new()
+            };
+            list_item(
+                layer.name.clone(),
+                detail,
+                layer.depth as f32 * PAD * 2.0,
+                i == selected,
+                muted,
+                Message::SelectLayer(i),
+            )
+        })
+        .collect();
+
+    if selected >= view.visible.len() {
+        return rows;
+    }
+    rows.push(control_row(
+        "Visible",
+        toggler(view.visible[selected]).on_toggle(move |v| Message::SetLayerVisible(selected, v)),
+        muted,
+    ));
+    rows.push(control_row(
+        "Solo",
+        toggler(view.solo == Some(selected))
+            .on_toggle(move |on| Message::SoloLayer(on.then_some(selected))),
+        muted,
+    ));
+    rows.push(control_row(
+        "Opacity",
+        ValueSlider::new(view.opacity[selected] * 100.0, 0.0..=100.0, move |v| {
+            Message::SetLayerOpacity(selected, v / 100.0)
+        })
+        .step(1.0)
+        .format(Fmt::num(0).suffix("%")),
+        muted,
+    ));
+    rows.push(list_item(
+        "Export layer".to_string(),
+        String::new(),
+        0.0,
+        false,
+        muted,
+        Message::ExportLayer,
+    ));
+    rows
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn view<'a>(
     path: Option<&'a Path>,
@@ -236,6 +777,8 @@ pub fn view<'a>(
     info_collapsed: &HashSet<String>,
     pixel_preview_size: u32,
     histogram: Option<&'a HistogramData>,
+    hide_patient_info: bool,
+    selected_layer: usize,
     #[cfg(feature = "av")] video: Option<VideoPanel<'a>>,
 ) -> Element<'a, Message> {
     let palette = theme.extended_palette();
@@ -271,26 +814,50 @@ pub fn view<'a>(
 
     let mut file_rows: Vec<Element<'a, Message>> = Vec::new();
     if let Some(p) = path {
-        if let Some(name) = p.file_name().and_then(|n| n.to_str()) {
+        let name = gallery.filename(p);
+        if !name.is_empty() {
             let filename_row = row_item(
                 "Filename",
-                truncate_filename(name, FILENAME_MAX_CHARS),
+                truncate_filename(&name, FILENAME_MAX_CHARS),
                 muted,
             );
-            file_rows.push(if let Some(path_str) = p.to_str() {
-                with_tooltip_delay(filename_row, path_str, Position::Right, Duration::ZERO)
-            } else {
-                filename_row
-            });
+            file_rows.push(with_tooltip_delay(
+                filename_row,
+                gallery.display_path(p),
+                Position::Right,
+                Duration::ZERO,
+            ));
         }
-        if let Some(ext) = p.extension().and_then(|e| e.to_str()) {
-            file_rows.push(row_item("Format", ext.to_ascii_uppercase(), muted));
+        let named = sniff::named_extension(p);
+        let detected = gallery
+            .format()
+            .filter(|_| gallery.current().is_some_and(|c| c == p));
+        match detected {
+            Some(found) => {
+                file_rows.push(row_item("Format", found.to_ascii_uppercase(), muted));
+                if !sniff::agrees(found, &named) {
+                    let label = if named.is_empty() {
+                        "none".to_string()
+                    } else {
+                        format!("{} (mismatch)", named.to_ascii_uppercase())
+                    };
+                    file_rows.push(row_item("Extension", label, muted));
+                }
+            }
+            None if !named.is_empty() => {
+                file_rows.push(row_item("Format", named.to_ascii_uppercase(), muted));
+            }
+            None => {}
         }
     }
     let count = gallery.len();
     if count > 0 {
         file_rows.push(row_item(
-            "In folder",
+            if gallery.archive().is_some() {
+                "In archive"
+            } else {
+                "In folder"
+            },
             format!("{} / {}", gallery.position() + 1, count),
             muted,
         ));
@@ -302,6 +869,12 @@ pub fn view<'a>(
         image_rows.push(row_item("Dimensions", format!("{} x {}", w, h), muted));
         image_rows.push(row_item("Aspect ratio", aspect_ratio_str(w, h), muted));
     }
+    if program.embedded_preview() {
+        image_rows.push(row_item("Showing", "Embedded preview", muted));
+    }
+    if program.streamed() {
+        image_rows.push(row_item("Showing", "Streamed, exports as PNG", muted));
+    }
     image_rows.push(row_item(
         "Scale",
         format!("{:.0}%", program.scale() * 100.0),
@@ -358,6 +931,51 @@ pub fn view<'a>(
     }
     push_section(&mut rows, "IMAGE", false, image_rows);
 
+    if let Some(selection) = program.exr() {
+        push_section(&mut rows, "LAYERS", true, exr_rows(selection, muted));
+    }
+
+    if let Some(selection) = program.layers() {
+        let layers = layer_rows(selection, selected_layer, muted);
+        push_section(&mut rows, "LAYERS", true, layers);
+    }
+
+    if let Some(selection) = program.fits() {
+        push_section(&mut rows, "FITS", true, fits_rows(selection, muted));
+        push_section(&mut rows, "HEADER", false, card_rows(selection));
+    }
+
+    if let Some(selection) = program.texture() {
+        push_section(&mut rows, "TEXTURE", true, texture_rows(selection, muted));
+    }
+
+    if let Some(selection) = program.raw() {
+        push_section(&mut rows, "RAW", true, raw_rows(selection, muted));
+    }
+
+    #[cfg(feature = "heif")]
+    if let Some(info) = program.heif() {
+        push_section(&mut rows, info.container, true, heif_rows(info, muted));
+    }
+
+    if let Some(pages) = program.pages() {
+        let title = if pages.source.is_icon() {
+            "SIZES"
+        } else {
+            "PAGES"
+        };
+        push_section(&mut rows, title, true, page_rows(pages, muted));
+    }
+
+    if let Some(info) = program.dicom() {
+        if info.window.is_some() {
+            let window = window_rows(info, program.window, muted);
+            push_section(&mut rows, "WINDOW", true, window);
+        }
+        let tags = tag_rows(info, hide_patient_info, muted);
+        push_section(&mut rows, "TAGS", false, tags);
+    }
+
     #[cfg(feature = "av")]
     if let Some(v) = &video {
         let m = v.meta;
@@ -470,6 +1088,20 @@ pub fn view<'a>(
         ));
         cursor_rows.push(row_item("Pixel", format!("({}, {})", px, py), muted));
         cursor_rows.push(row_item("UV", format!("({:.3}, {:.3})", uv.x, uv.y), muted));
+        if let Some(selection) = program.fits() {
+            if let Some(v) = selection.value_at(px, py) {
+                let value = if v.is_finite() {
+                    v.to_string()
+                } else {
+                    "blank".to_string()
+                };
+                cursor_rows.push(row_item("Value", value, muted));
+            }
+            if let Some((ra, dec)) = selection.world_at(px, py) {
+                cursor_rows.push(row_item("RA", fits::format_ra(ra), muted));
+                cursor_rows.push(row_item("Dec", fits::format_dec(dec), muted));
+            }
+        }
     }
     push_section(&mut rows, "CURSOR", cursor_has_content, cursor_rows);
 
@@ -1,5 +1,7 @@
 pub mod bottom_bar;
 pub mod edit_panel;
+pub mod folder_tree;
+pub mod grid;
 pub mod info_panel;
 pub mod modifier_stack;
 pub mod notifications;
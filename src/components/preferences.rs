@@ -67,6 +67,11 @@ pub enum PreferenceMessage {
     SetLoopAnimations(bool),
     SetLoopVideo(bool),
     SetRememberLast(bool),
+    SetAutoOrient(bool),
+    SetKeepColorProfile(bool),
+    SetHidePatientInfo(bool),
+    SetRightToLeft(bool),
+    SetEmbeddedPreviewOnly(bool),
     SetMipmapZoomOut(bool),
     SetSmoothZoomIn(bool),
     SetPixelGrid(bool),
@@ -179,6 +184,26 @@ pub fn update(
             pending.remember_last = v;
             PreferenceOutcome::Open
         }
+        PreferenceMessage::SetAutoOrient(v) => {
+            pending.auto_orient = v;
+            PreferenceOutcome::Open
+        }
+        PreferenceMessage::SetKeepColorProfile(v) => {
+            pending.keep_color_profile = v;
+            PreferenceOutcome::Open
+        }
+        PreferenceMessage::SetHidePatientInfo(v) => {
+            pending.hide_patient_info = v;
+            PreferenceOutcome::Open
+        }
+        PreferenceMessage::SetRightToLeft(v) => {
+            pending.right_to_left = v;
+            PreferenceOutcome::Open
+        }
+        PreferenceMessage::SetEmbeddedPreviewOnly(v) => {
+            pending.embedded_preview_only = v;
+            PreferenceOutcome::Open
+        }
         PreferenceMessage::SetMipmapZoomOut(v) => {
             pending.mipmap_zoom_out = v;
             PreferenceOutcome::Open
@@ -235,6 +260,11 @@ pub fn update(
             pending.loop_animations = d.loop_animations;
             pending.loop_video = d.loop_video;
             pending.remember_last = d.remember_last;
+            pending.auto_orient = d.auto_orient;
+            pending.keep_color_profile = d.keep_color_profile;
+            pending.hide_patient_info = d.hide_patient_info;
+            pending.right_to_left = d.right_to_left;
+            pending.embedded_preview_only = d.embedded_preview_only;
             pending.mipmap_zoom_out = d.mipmap_zoom_out;
             pending.smooth_zoom_in = d.smooth_zoom_in;
             pending.show_pixel_grid = d.show_pixel_grid;
@@ -574,14 +604,66 @@ fn rendering_pane<'a>(pending: &'a Config, theme: &Theme) -> Element<'a, Message
         ),
     ];
 
-    let files = vec![setting(
-        "Remember last media",
-        "Open the last viewed file when no file is passed on launch",
-        toggler(pending.remember_last)
-            .on_toggle(|v| Message::Preference(PreferenceMessage::SetRememberLast(v)))
-            .into(),
-        theme,
-    )];
+    let files = vec![
+        setting(
+            "Remember last media",
+            "Open the last viewed file when no file is passed on launch",
+            toggler(pending.remember_last)
+                .on_toggle(|v| Message::Preference(PreferenceMessage::SetRememberLast(v)))
+                .into(),
+            theme,
+        ),
+        setting(
+            "Auto-orient photos",
+            "Rotate and mirror images as their EXIF orientation tag says the camera was held",
+            toggler(pending.auto_orient)
+                .on_toggle(|v| Message::Preference(PreferenceMessage::SetAutoOrient(v)))
+                .into(),
+            theme,
+        ),
+        setting(
+            "Keep color profile on export",
+            "Convert PNG and JPEG exports back into the source's ICC profile and embed it, instead of writing sRGB",
+            toggler(pending.keep_color_profile)
+                .on_toggle(|v| Message::Preference(PreferenceMessage::SetKeepColorProfile(v)))
+                .into(),
+            theme,
+        ),
+        setting(
+            "Hide patient information",
+            "Blank names, IDs, birth dates and other identifying DICOM tags in the info panel",
+            toggler(pending.hide_patient_info)
+                .on_toggle(|v| Message::Preference(PreferenceMessage::SetHidePatientInfo(v)))
+                .into(),
+            theme,
+        ),
+        setting(
+            "Read archives right to left",
+            "In ZIP and CBZ archives, the left arrow key and button turn to the next page, as manga reads",
+            toggler(pending.right_to_left)
+                .on_toggle(|v| Message::Preference(PreferenceMessage::SetRightToLeft(v)))
+                .into(),
+            theme,
+        ),
+        setting(
+            "Embedded preview only",
+            "Open RAW files on the JPEG preview the camera stored in them and skip developing the sensor data, for fast culling",
+            toggler(pending.embedded_preview_only)
+                .on_toggle(|v| Message::Preference(PreferenceMessage::SetEmbeddedPreviewOnly(v)))
+                .into(),
+            theme,
+        ),
+        setting(
+            "Thumbnail cache",
+            "Grid thumbnails are kept in the user cache folder and shared with file managers; delete the ones Bloom wrote",
+            button(text("Clear").size(12))
+                .style(plain_icon_button_style)
+                .on_press(Message::ClearThumbnailCache)
+                .padding([4.0, 8.0])
+                .into(),
+            theme,
+        ),
+    ];
 
     let quality = vec![
         setting(
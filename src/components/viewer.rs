@@ -32,11 +32,57 @@ use crate::{
         crop_overlay::CropOverlay,
         draw_overlay::DrawOverlay,
         loading_spinner::Circular,
-        menu::{menu_item, menu_item_enabled, menu_separator, styled_menu},
+        menu::{SubMenuSide, menu_item, menu_item_enabled, menu_separator, styled_menu, sub_menu},
         text_overlay::TextOverlay,
     },
 };
 
+/// The sizes an SVG can be exported at, as multiples of its native size.
+const SVG_EXPORT_SCALES: [(u32, &str); 4] = [
+    (1, "Native size"),
+    (2, "2× size"),
+    (4, "4× size"),
+    (8, "8× size"),
+];
+
+/// The Export entry of a menu. An SVG has no one size to export at, so for
+/// one it opens onto the sizes to pick from.
+pub fn export_menu_item<'a>(svg: bool, enabled: bool, side: SubMenuSide) -> Element<'a, Message> {
+    if !svg {
+        return menu_item_enabled("Export", Message::ExportImage, enabled);
+    }
+    let sizes = SVG_EXPORT_SCALES
+        .iter()
+        .fold(column![], |col, &(scale, label)| {
+            col.push(menu_item(label, Message::ExportSvg(scale)))
+        });
+    sub_menu("Export", styled_menu(sizes, 140))
+        .side(side)
+        .into()
+}
+
+/// The frame rates a numbered sequence can be played at.
+const SEQUENCE_RATES: [(u32, &str); 5] = [
+    (12, "12 fps"),
+    (24, "24 fps"),
+    (25, "25 fps"),
+    (30, "30 fps"),
+    (60, "60 fps"),
+];
+
+/// The Play as Sequence entry, opening onto the frame rates to play at.
+fn sequence_menu_item<'a>(enabled: bool) -> Element<'a, Message> {
+    if !enabled {
+        return menu_item_enabled("Play as Sequence", Message::Noop, false);
+    }
+    let rates = SEQUENCE_RATES.iter().fold(column![], |col, &(fps, label)| {
+        col.push(menu_item(label, Message::PlaySequence(fps)))
+    });
+    sub_menu("Play as Sequence", styled_menu(rates, 100))
+        .side(SubMenuSide::Right)
+        .into()
+}
+
 pub struct ViewerCtx<'a> {
     pub program: ViewProgram,
     pub loading: Option<&'a str>,
@@ -58,6 +104,8 @@ pub struct ViewerCtx<'a> {
     pub histogram: Option<&'a Histogram>,
     pub context_menu: Option<Point>,
     pub timing: Option<MediaTiming>,
+    pub hide_patient_info: bool,
+    pub selected_layer: usize,
     #[cfg(feature = "av")]
     pub video_panel: Option<info_panel::VideoPanel<'a>>,
 }
@@ -189,7 +237,19 @@ pub fn view(ctx: ViewerCtx<'_>) -> Element<'_, Message> {
             menu_item_enabled("Copy Image", Message::CopyImage, has_media),
             menu_item_enabled("Copy File Path", Message::CopyPath, has_media),
             menu_separator(),
-            menu_item_enabled("Export", Message::ExportImage, has_media),
+            export_menu_item(ctx.program.svg().is_some(), has_media, SubMenuSide::Right),
+            menu_item_enabled(
+                "Export All Pages",
+                Message::ExportPages,
+                ctx.program.pages().is_some()
+            ),
+            menu_item_enabled(
+                "Export Layer",
+                Message::ExportLayer,
+                ctx.program.layers().is_some()
+            ),
+            menu_separator(),
+            sequence_menu_item(ctx.gallery.sequence().is_some()),
             menu_separator(),
             menu_item(bottom_bar_label, Message::ToggleBottomBar),
         ],
@@ -218,6 +278,8 @@ pub fn view(ctx: ViewerCtx<'_>) -> Element<'_, Message> {
             ctx.info_collapsed,
             ctx.pixel_preview_size,
             ctx.histogram,
+            ctx.hide_patient_info,
+            ctx.selected_layer,
             #[cfg(feature = "av")]
             ctx.video_panel,
         ));
@@ -11,7 +11,9 @@ use std::path::PathBuf;
 use iced::Theme;
 use serde::{Deserialize, Serialize};
 
+use crate::gallery::{Sort, SortKey};
 use crate::keybinds::{Keymap, KeymapFile};
+use crate::wgpu::media::tonemap::ToneOperator;
 
 pub const UI_SCALE_MIN: f32 = 0.5;
 pub const UI_SCALE_MAX: f32 = 3.0;
@@ -24,6 +26,11 @@ pub const PIXEL_PREVIEW_SIZE_OPTIONS: &[u32] = &[3, 5, 7, 9, 11, 13, 15];
 pub const VOLUME_MAX: f32 = 2.0;
 pub const VOLUME_DEFAULT: f32 = 1.0;
 
+pub const GRID_CELL_MIN: f32 = 96.0;
+pub const GRID_CELL_MAX: f32 = 384.0;
+pub const GRID_CELL_STEP: f32 = 32.0;
+pub const GRID_CELL_DEFAULT: f32 = 160.0;
+
 pub const ALL_THEMES: &[Theme] = &[
     Theme::Light,
     Theme::Dark,
@@ -67,12 +74,22 @@ pub struct Config {
     pub last_media: Option<PathBuf>,
     pub mipmap_zoom_out: bool,
     pub smooth_zoom_in: bool,
+    pub auto_orient: bool,
+    pub keep_color_profile: bool,
+    pub tone_operator: ToneOperator,
+    pub hide_patient_info: bool,
+    pub right_to_left: bool,
+    pub embedded_preview_only: bool,
     pub keymap: Keymap,
     pub info_collapsed: HashSet<String>,
     pub ui_scale: f32,
     pub pixel_preview_size: u32,
     pub volume: f32,
     pub muted: bool,
+    pub grid_cell_size: f32,
+    pub gallery_sort: Sort,
+    /// Whether the gallery lists the folders under the one opened as well.
+    pub recursive: bool,
 }
 
 impl Default for Config {
@@ -94,12 +111,21 @@ impl Default for Config {
             last_media: None,
             mipmap_zoom_out: true,
             smooth_zoom_in: false,
+            auto_orient: true,
+            keep_color_profile: false,
+            tone_operator: ToneOperator::default(),
+            hide_patient_info: false,
+            right_to_left: false,
+            embedded_preview_only: false,
             keymap: Keymap::default(),
             info_collapsed: HashSet::new(),
             ui_scale: UI_SCALE_DEFAULT,
             pixel_preview_size: PIXEL_PREVIEW_SIZE_DEFAULT,
             volume: VOLUME_DEFAULT,
             muted: false,
+            grid_cell_size: GRID_CELL_DEFAULT,
+            gallery_sort: Sort::default(),
+            recursive: false,
         }
     }
 }
@@ -135,6 +161,18 @@ struct ConfigFile {
     mipmap_zoom_out: bool,
     #[serde(default)]
     smooth_zoom_in: bool,
+    #[serde(default = "default_true")]
+    auto_orient: bool,
+    #[serde(default)]
+    keep_color_profile: bool,
+    #[serde(default)]
+    tone_operator: String,
+    #[serde(default)]
+    hide_patient_info: bool,
+    #[serde(default)]
+    right_to_left: bool,
+    #[serde(default)]
+    embedded_preview_only: bool,
     #[serde(default)]
     keybinds: KeymapFile,
     #[serde(default)]
@@ -147,6 +185,14 @@ struct ConfigFile {
     volume: f32,
     #[serde(default)]
     muted: bool,
+    #[serde(default = "default_grid_cell")]
+    grid_cell_size: f32,
+    #[serde(default)]
+    sort_by: String,
+    #[serde(default)]
+    sort_descending: bool,
+    #[serde(default)]
+    recursive: bool,
 }
 
 fn default_true() -> bool {
@@ -165,6 +211,10 @@ fn default_volume() -> f32 {
     VOLUME_DEFAULT
 }
 
+fn default_grid_cell() -> f32 {
+    GRID_CELL_DEFAULT
+}
+
 impl From<&Config> for ConfigFile {
     fn from(c: &Config) -> Self {
         let mut info_collapsed: Vec<String> = c.info_collapsed.iter().cloned().collect();
@@ -186,12 +236,22 @@ impl From<&Config> for ConfigFile {
             last_media: c.last_media.clone(),
             mipmap_zoom_out: c.mipmap_zoom_out,
             smooth_zoom_in: c.smooth_zoom_in,
+            auto_orient: c.auto_orient,
+            keep_color_profile: c.keep_color_profile,
+            tone_operator: c.tone_operator.name().to_string(),
+            hide_patient_info: c.hide_patient_info,
+            right_to_left: c.right_to_left,
+            embedded_preview_only: c.embedded_preview_only,
             keybinds: KeymapFile::from(&c.keymap),
             info_collapsed,
             ui_scale: c.ui_scale,
             pixel_preview_size: c.pixel_preview_size,
             volume: c.volume,
             muted: c.muted,
+            grid_cell_size: c.grid_cell_size,
+            sort_by: c.gallery_sort.key.name().to_string(),
+            sort_descending: c.gallery_sort.descending,
+            recursive: c.recursive,
         }
     }
 }
@@ -221,12 +281,24 @@ impl From<ConfigFile> for Config {
             last_media: f.last_media,
             mipmap_zoom_out: f.mipmap_zoom_out,
             smooth_zoom_in: f.smooth_zoom_in,
+            auto_orient: f.auto_orient,
+            keep_color_profile: f.keep_color_profile,
+            tone_operator: ToneOperator::from_name(&f.tone_operator).unwrap_or_default(),
+            hide_patient_info: f.hide_patient_info,
+            right_to_left: f.right_to_left,
+            embedded_preview_only: f.embedded_preview_only,
             keymap: Keymap::from(f.keybinds),
             info_collapsed: f.info_collapsed.into_iter().collect(),
             ui_scale,
             pixel_preview_size,
             volume: f.volume.clamp(0.0, VOLUME_MAX),
             muted: f.muted,
+            grid_cell_size: f.grid_cell_size.clamp(GRID_CELL_MIN, GRID_CELL_MAX),
+            gallery_sort: Sort {
+                key: SortKey::from_name(&f.sort_by).unwrap_or_default(),
+                descending: f.sort_descending,
+            },
+            recursive: f.recursive,
         }
     }
 }
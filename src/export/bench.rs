@@ -53,6 +53,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels: Arc::clone(pixels),
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -61,6 +62,9 @@ mod tests {
             height: H,
             modifiers: modifiers.to_vec(),
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
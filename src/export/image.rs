@@ -1,45 +1,101 @@
 use std::io::{BufWriter, Write};
 use std::path::Path;
 
+use image::ImageEncoder;
+
+use crate::modifiers::cpu::Texel;
 use crate::modifiers::drawing_raster::LayerView;
 use crate::modifiers::text_raster::TextRaster;
+use crate::wgpu::media::icc::{self, FromSrgb};
+
+use super::raster::{BandSource, ExportCtx, render_into, render_strips};
+use super::{ExportData, FrameRun, Geom, ctx_with, process_export_frame};
+
+/// Output tagged with the source's ICC profile: pixels leave sRGB through
+/// `to_profile` and the profile itself is embedded alongside them.
+struct Tagging<'a> {
+    icc: &'a [u8],
+    to_profile: FromSrgb,
+}
+
+impl<'a> Tagging<'a> {
+    fn new(profile: Option<&'a [u8]>) -> Option<Self> {
+        let icc = profile?;
+        Some(Self {
+            icc,
+            to_profile: icc::from_srgb(icc)?,
+        })
+    }
 
-use super::raster::{ExportCtx, render_into, render_strips};
-use super::{ExportData, ExportFrame, Geom, ctx_with, process_frame};
+    fn write_iccp<W: Write>(&self, writer: &mut png::Writer<W>) -> Result<(), String> {
+        let name = icc::profile_name(self.icc)
+            .filter(|n| n.is_ascii() && (1..80).contains(&n.len()))
+            .unwrap_or_else(|| "ICC Profile".to_string());
+        let mut body = name.into_bytes();
+        body.extend_from_slice(&[0, 0]);
+        let mut z = flate2::write::ZlibEncoder::new(body, flate2::Compression::default());
+        z.write_all(self.icc).map_err(|e| e.to_string())?;
+        let body = z.finish().map_err(|e| e.to_string())?;
+        writer
+            .write_chunk(png::chunk::iCCP, &body)
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn write_strip(
+    stream: &mut impl Write,
+    tagging: Option<&Tagging>,
+    buf: &[u8],
+) -> Result<(), String> {
+    match tagging {
+        Some(t) => stream.write_all(&t.to_profile.convert(buf)),
+        None => stream.write_all(buf),
+    }
+    .map_err(|e| e.to_string())
+}
 
 pub(super) fn encode_png(
     ctx: &ExportCtx,
+    profile: Option<&[u8]>,
     path: &Path,
     progress: &impl Fn(f32),
 ) -> Result<(), String> {
+    let tagging = Tagging::new(profile);
     let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
     let mut enc = png::Encoder::new(BufWriter::new(file), ctx.out_w(), ctx.out_h());
     enc.set_color(png::ColorType::Rgba);
     enc.set_depth(png::BitDepth::Eight);
     let mut writer = enc.write_header().map_err(|e| e.to_string())?;
+    if let Some(t) = &tagging {
+        t.write_iccp(&mut writer)?;
+    }
     let mut stream = writer.stream_writer().map_err(|e| e.to_string())?;
 
     render_strips(
         ctx,
-        |buf| stream.write_all(buf).map_err(|e| e.to_string()),
+        |buf| write_strip(&mut stream, tagging.as_ref(), buf),
         progress,
     )
 }
 
-pub(super) fn encode_png_streaming(
+pub(super) fn encode_png_streaming<T: Texel>(
     geom: &Geom,
     data: &ExportData,
     text_layers: &[Option<TextRaster>],
     drawing_layers: &[Option<LayerView<'_>>],
-    pixels: &[u8],
+    source: impl BandSource<T>,
     path: &Path,
     progress: &impl Fn(f32),
 ) -> Result<(), String> {
+    let tagging = Tagging::new(data.profile.as_deref().map(Vec::as_slice));
     let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
     let mut enc = png::Encoder::new(BufWriter::new(file), geom.out_w, geom.out_h);
     enc.set_color(png::ColorType::Rgba);
     enc.set_depth(png::BitDepth::Eight);
     let mut writer = enc.write_header().map_err(|e| e.to_string())?;
+    if let Some(t) = &tagging {
+        t.write_iccp(&mut writer)?;
+    }
     let mut stream = writer.stream_writer().map_err(|e| e.to_string())?;
 
     super::raster::stream_bands(
@@ -47,8 +103,8 @@ pub(super) fn encode_png_streaming(
         data,
         text_layers,
         drawing_layers,
-        pixels,
-        |buf| stream.write_all(buf).map_err(|e| e.to_string()),
+        source,
+        |buf| write_strip(&mut stream, tagging.as_ref(), buf),
         progress,
     )
 }
@@ -76,14 +132,17 @@ pub(super) fn encode_rgba(
 
 pub(super) fn encode_jpeg(
     ctx: &ExportCtx,
+    profile: Option<&[u8]>,
     path: &Path,
     progress: &impl Fn(f32),
 ) -> Result<(), String> {
+    let tagging = Tagging::new(profile);
     let mut rgb = Vec::with_capacity(ctx.out_w() as usize * ctx.out_h() as usize * 3);
     render_strips(
         ctx,
         |buf| {
-            for p in buf.chunks_exact(4) {
+            let converted = tagging.as_ref().map(|t| t.to_profile.convert(buf));
+            for p in converted.as_deref().unwrap_or(buf).chunks_exact(4) {
                 let a = p[3] as f32 / 255.0;
                 let blend = |c: u8| (c as f32 * a + 255.0 * (1.0 - a)).round() as u8;
                 rgb.push(blend(p[0]));
@@ -95,9 +154,83 @@ pub(super) fn encode_jpeg(
         progress,
     )?;
 
-    image::RgbImage::from_raw(ctx.out_w(), ctx.out_h(), rgb)
-        .ok_or_else(|| "Failed to create image buffer.".to_string())?
-        .save(path)
+    let Some(t) = tagging else {
+        return image::RgbImage::from_raw(ctx.out_w(), ctx.out_h(), rgb)
+            .ok_or_else(|| "Failed to create image buffer.".to_string())?
+            .save(path)
+            .map_err(|e| e.to_string());
+    };
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut enc = image::codecs::jpeg::JpegEncoder::new(BufWriter::new(file));
+    enc.set_icc_profile(t.icc.to_vec())
+        .map_err(|e| e.to_string())?;
+    enc.write_image(
+        &rgb,
+        ctx.out_w(),
+        ctx.out_h(),
+        image::ExtendedColorType::Rgb8,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// The sizes Windows asks an icon for, up to the largest ICO can hold.
+const ICO_SIZES: [u32; 7] = [16, 24, 32, 48, 64, 128, 256];
+
+/// A multi-size icon: the image centered on a transparent square, then
+/// scaled to every standard size that does not enlarge it, plus its own size
+/// when that fits in an ICO.
+pub(super) fn encode_ico(
+    ctx: &ExportCtx,
+    path: &Path,
+    progress: &impl Fn(f32),
+) -> Result<(), String> {
+    let (w, h) = (ctx.out_w(), ctx.out_h());
+    let mut rgba = Vec::with_capacity(w as usize * h as usize * 4);
+    render_strips(
+        ctx,
+        |buf| {
+            rgba.extend_from_slice(buf);
+            Ok(())
+        },
+        progress,
+    )?;
+    let picture = image::RgbaImage::from_raw(w, h, rgba)
+        .ok_or_else(|| "Failed to create image buffer.".to_string())?;
+
+    let side = w.max(h);
+    let mut square = image::RgbaImage::new(side, side);
+    image::imageops::replace(
+        &mut square,
+        &picture,
+        ((side - w) / 2) as i64,
+        ((side - h) / 2) as i64,
+    );
+
+    let mut sizes: Vec<u32> = ICO_SIZES.into_iter().filter(|&s| s <= side).collect();
+    if side <= 256 && !sizes.contains(&side) {
+        sizes.push(side);
+    }
+    let frames = sizes
+        .into_iter()
+        .map(|size| {
+            let scaled = if size == side {
+                square.clone()
+            } else {
+                image::imageops::resize(&square, size, size, image::imageops::FilterType::Lanczos3)
+            };
+            image::codecs::ico::IcoFrame::as_png(
+                scaled.as_raw(),
+                size,
+                size,
+                image::ExtendedColorType::Rgba8,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    image::codecs::ico::IcoEncoder::new(BufWriter::new(file))
+        .encode_images(&frames)
         .map_err(|e| e.to_string())
 }
 
@@ -105,7 +238,7 @@ pub(super) fn encode_jpeg(
 pub(super) fn encode_apng(
     geom: &Geom,
     data: &ExportData,
-    frames: &[ExportFrame],
+    frames: &FrameRun<'_>,
     text_layers: &[Option<TextRaster>],
     drawing_layers: &[Option<LayerView<'_>>],
     path: &Path,
@@ -118,18 +251,27 @@ pub(super) fn encode_apng(
     enc.set_animated(frames.len() as u32, 0)
         .map_err(|e| e.to_string())?;
     let mut writer = enc.write_header().map_err(|e| e.to_string())?;
+    let tagging = Tagging::new(data.profile.as_deref().map(Vec::as_slice));
+    if let Some(t) = &tagging {
+        t.write_iccp(&mut writer)?;
+    }
 
     let mut buf = vec![0u8; geom.out_w as usize * geom.out_h as usize * 4];
     let n = frames.len();
-    for (i, fr) in frames.iter().enumerate() {
-        let processed = process_frame(data, text_layers, drawing_layers, &fr.pixels)?;
+    for i in 0..n {
+        let fr = frames.frame(i)?;
+        let processed = process_export_frame(data, text_layers, drawing_layers, &fr)?;
         let fctx = ctx_with(geom, &processed);
         render_into(&mut buf, &fctx);
         let ms = (fr.delay.as_millis().min(u16::MAX as u128) as u16).max(1);
         writer
             .set_frame_delay(ms, 1000)
             .map_err(|e| e.to_string())?;
-        writer.write_image_data(&buf).map_err(|e| e.to_string())?;
+        match &tagging {
+            Some(t) => writer.write_image_data(&t.to_profile.convert(&buf)),
+            None => writer.write_image_data(&buf),
+        }
+        .map_err(|e| e.to_string())?;
         progress((i + 1) as f32 / n as f32);
     }
 
@@ -140,7 +282,7 @@ pub(super) fn encode_apng(
 pub(super) fn encode_gif(
     geom: &Geom,
     data: &ExportData,
-    frames: &[ExportFrame],
+    frames: &FrameRun<'_>,
     text_layers: &[Option<TextRaster>],
     drawing_layers: &[Option<LayerView<'_>>],
     path: &Path,
@@ -163,8 +305,9 @@ pub(super) fn encode_gif(
 
     let mut buf = vec![0u8; geom.out_w as usize * geom.out_h as usize * 4];
     let n = frames.len();
-    for (i, fr) in frames.iter().enumerate() {
-        let processed = process_frame(data, text_layers, drawing_layers, &fr.pixels)?;
+    for i in 0..n {
+        let fr = frames.frame(i)?;
+        let processed = process_export_frame(data, text_layers, drawing_layers, &fr)?;
         let fctx = ctx_with(geom, &processed);
         render_into(&mut buf, &fctx);
         let mut frame =
@@ -5,6 +5,25 @@
 //! back to rendering the full frame.
 //!
 //! Video frames and the JPEG and raw RGBA encoders still buffer whole frames.
+//!
+//! A frame may carry the source's 16-bit or float samples beside its RGBA8
+//! pixels. The chain then runs on those and quantizes once at the end, so the
+//! file gets the same smooth result the preview shows rather than a chain run
+//! on already-rounded pixels. A scene-linear source is tonemapped there instead,
+//! with the operator and exposure the view had when the export started, and a
+//! DICOM's values go through the view's window the same way.
+//!
+//! A multi-page source can also be written whole, as one TIFF. Each page is
+//! decoded in turn and run through the same stack as the page on screen, so
+//! only one page is held in memory at a time.
+//!
+//! An image sequence is never held whole either. Its frames are read from disk
+//! one at a time as the encoder reaches them, and only those inside the trim.
+//!
+//! Nor is an image too big to hold. It exports through the streaming path
+//! alone, as a PNG, decoding its file again band by band as the encoder walks
+//! down it; a chain or turn that cannot go band by band, or any other format,
+//! is refused.
 
 #[cfg(test)]
 mod bench;
@@ -15,16 +34,26 @@ mod raster;
 #[cfg(feature = "av")]
 mod video;
 
+use std::io::BufWriter;
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
+use rayon::prelude::*;
+use tiff::encoder::{Compression, TiffEncoder, colortype};
+
 use crate::modifiers::drawing_raster::{self, DrawingRaster, LayerView};
 use crate::modifiers::plan::{ImageSpec, chain_output_spec, plan_modifiers};
 use crate::modifiers::text_raster::{self, TextRaster};
-use crate::modifiers::{Modifier, cpu};
+use crate::modifiers::{Modifier, cpu, cpu::Texel};
+use crate::wgpu::media::pages::PageSource;
+use crate::wgpu::media::samples::DeepPixels;
+use crate::wgpu::media::sequence::Sequence;
+use crate::wgpu::media::stream::StreamSource;
+use crate::wgpu::media::tonemap::Tone;
 
-use raster::{ExportCtx, render_into};
+use raster::{ExportCtx, StreamedRows, render_into};
 
 fn layer_views(layers: &[Option<DrawingRaster>]) -> Vec<Option<LayerView<'_>>> {
     layers
@@ -33,8 +62,10 @@ fn layer_views(layers: &[Option<DrawingRaster>]) -> Vec<Option<LayerView<'_>>> {
         .collect()
 }
 
+#[derive(Clone)]
 pub struct ExportFrame {
     pub pixels: Arc<Vec<u8>>,
+    pub deep: Option<Arc<DeepPixels>>,
     pub delay: Duration,
 }
 
@@ -43,10 +74,51 @@ pub enum ExportSource {
         frames: Vec<ExportFrame>,
         still_index: usize,
     },
+    /// A numbered image sequence, read frame by frame while encoding.
+    Sequence {
+        sequence: Arc<Sequence>,
+        still_index: usize,
+    },
+    /// An image too big to hold, decoded again band by band. See stream.rs.
+    Stream(Arc<StreamSource>),
     #[cfg(feature = "av")]
     Video(VideoExportInfo),
 }
 
+/// The frames an encoder walks, with the trim already applied.
+pub(super) enum FrameRun<'a> {
+    Held(&'a [ExportFrame]),
+    Sequence(&'a Sequence, Range<usize>),
+}
+
+impl FrameRun<'_> {
+    pub(super) fn len(&self) -> usize {
+        match self {
+            FrameRun::Held(frames) => frames.len(),
+            FrameRun::Sequence(_, range) => range.len(),
+        }
+    }
+
+    pub(super) fn frame(&self, index: usize) -> Result<ExportFrame, String> {
+        match self {
+            FrameRun::Held(frames) => frames
+                .get(index)
+                .cloned()
+                .ok_or_else(|| "No frame available.".to_string()),
+            FrameRun::Sequence(sequence, range) => {
+                let data = sequence
+                    .read(range.start + index)
+                    .map_err(|e| e.to_string())?;
+                Ok(ExportFrame {
+                    pixels: data.pixels_snapshot(),
+                    deep: data.deep_snapshot(),
+                    delay: sequence.delay(),
+                })
+            }
+        }
+    }
+}
+
 #[cfg(feature = "av")]
 pub struct VideoExportInfo {
     pub path: std::path::PathBuf,
@@ -60,12 +132,32 @@ pub struct ExportData {
     pub height: u32,
     pub modifiers: Vec<Modifier>,
     pub rotation: u8,
+    pub mirror: bool,
+    /// ICC profile to convert the output into and embed, instead of writing
+    /// untagged sRGB. Honored by the PNG, APNG and JPEG encoders.
+    pub profile: Option<Arc<Vec<u8>>>,
+    /// Set for a scene-linear or windowed source: the view's tonemap or
+    /// window, applied after the chain in place of plain quantization.
+    pub tone: Option<Tone>,
     pub trim: Option<(Duration, Duration)>,
 }
 
 impl ExportData {
+    /// Whether this is an image too large to hold, which exports only as a
+    /// PNG written as its rows decode.
+    pub fn is_streamed(&self) -> bool {
+        matches!(self.source, ExportSource::Stream(_))
+    }
+
+    /// Why an image too large to hold cannot be exported as it stands, told
+    /// before a file is picked.
+    pub fn stream_refusal(&self) -> Option<&'static str> {
+        (self.is_streamed() && (!can_stream_bands(self) || geom_of(self).rotation != 0))
+            .then_some(STREAM_UNBANDABLE)
+    }
+
     pub fn is_animated(&self) -> bool {
-        matches!(&self.source, ExportSource::Frames { frames, .. } if self.trimmed(frames).len() > 1)
+        self.frames().is_ok_and(|(run, _)| run.len() > 1)
     }
 
     pub fn is_video(&self) -> bool {
@@ -79,33 +171,55 @@ impl ExportData {
         }
     }
 
-    fn in_memory(&self) -> Result<(&[ExportFrame], usize), String> {
-        match &self.source {
+    /// The frames inside the trim, and which of them a still export takes.
+    fn frames(&self) -> Result<(FrameRun<'_>, usize), String> {
+        let (run, still_index) = match &self.source {
             ExportSource::Frames {
                 frames,
                 still_index,
-            } => Ok((frames, *still_index)),
+            } => {
+                let (offset, len) = self.trim_bounds(frames);
+                (
+                    FrameRun::Held(&frames[offset..offset + len]),
+                    still_index.saturating_sub(offset),
+                )
+            }
+            ExportSource::Sequence {
+                sequence,
+                still_index,
+            } => {
+                let delays = std::iter::repeat_n(sequence.delay(), sequence.frame_count());
+                let (offset, len) = self.trim_span(delays, sequence.frame_count());
+                (
+                    FrameRun::Sequence(sequence, offset..offset + len),
+                    still_index.saturating_sub(offset),
+                )
+            }
+            ExportSource::Stream(_) => return Err(STREAM_PNG_ONLY.to_string()),
             #[cfg(feature = "av")]
             ExportSource::Video(_) => {
-                Err("Video frames are not available for this format.".to_string())
+                return Err("Video frames are not available for this format.".to_string());
             }
-        }
+        };
+        let still_index = still_index.min(run.len().saturating_sub(1));
+        Ok((run, still_index))
     }
 
-    fn trimmed<'a>(&self, frames: &'a [ExportFrame]) -> &'a [ExportFrame] {
-        let (offset, len) = self.trim_bounds(frames);
-        &frames[offset..offset + len]
+    fn trim_bounds(&self, frames: &[ExportFrame]) -> (usize, usize) {
+        self.trim_span(frames.iter().map(|f| f.delay), frames.len())
     }
 
-    fn trim_bounds(&self, frames: &[ExportFrame]) -> (usize, usize) {
+    /// The first frame and frame count the trim keeps of `count` frames
+    /// lasting `delays`.
+    fn trim_span(&self, delays: impl Iterator<Item = Duration>, count: usize) -> (usize, usize) {
         let Some((start, end)) = self.trim else {
-            return (0, frames.len());
+            return (0, count);
         };
-        let mut first = frames.len();
+        let mut first = count;
         let mut last = 0usize;
         let mut clock = Duration::ZERO;
-        for (i, f) in frames.iter().enumerate() {
-            let frame_end = clock + f.delay;
+        for (i, delay) in delays.enumerate() {
+            let frame_end = clock + delay;
             if frame_end > start && clock < end {
                 first = first.min(i);
                 last = i;
@@ -113,7 +227,7 @@ impl ExportData {
             clock = frame_end;
         }
         if first > last {
-            return (0, frames.len().min(1));
+            return (0, count.min(1));
         }
         (first, last - first + 1)
     }
@@ -130,6 +244,7 @@ struct Geom {
     out_w: u32,
     out_h: u32,
     rotation: u8,
+    mirror: bool,
 }
 
 fn geom_of(data: &ExportData) -> Geom {
@@ -156,6 +271,7 @@ fn geom_of(data: &ExportData) -> Geom {
         out_w,
         out_h,
         rotation: data.rotation,
+        mirror: data.mirror,
     }
 }
 
@@ -166,31 +282,83 @@ fn ctx_with<'a>(geom: &Geom, processed: &'a [u8]) -> ExportCtx<'a> {
     }
 }
 
-fn ensure_available(pixels: &[u8], w: u32, h: u32) -> Result<(), String> {
+fn ensure_available<T>(pixels: &[T], w: u32, h: u32) -> Result<(), String> {
     if pixels.len() < w as usize * h as usize * 4 {
-        Err("Image pixels are no longer available. Try reloading the image.".to_string())
+        Err("Image pixels are not in memory. Try reloading it.".to_string())
     } else {
         Ok(())
     }
 }
 
-fn process_frame(
+fn process_frame<T: Texel>(
     data: &ExportData,
     text_layers: &[Option<TextRaster>],
     drawing_layers: &[Option<LayerView<'_>>],
-    pixels: &[u8],
+    pixels: &[T],
 ) -> Result<Vec<u8>, String> {
     ensure_available(pixels, data.width, data.height)?;
-    Ok(cpu::render_full(
+    let rendered = cpu::render_full(
         &data.modifiers,
         text_layers,
         drawing_layers,
         pixels,
         data.width,
         data.height,
-    ))
+    );
+    Ok(quantize(data.tone, rendered))
+}
+
+/// The last step before an encoder: the tonemap or window when the source
+/// needs one, plain quantization for anything else.
+fn quantize<T: Texel>(tone: Option<Tone>, rendered: Vec<T>) -> Vec<u8> {
+    match tone {
+        Some(tone) => {
+            let linear: Vec<f32> = rendered.into_par_iter().map(T::to_unit).collect();
+            tone.to_rgba8(&linear)
+        }
+        None => T::quantize(rendered),
+    }
+}
+
+fn process_export_frame(
+    data: &ExportData,
+    text_layers: &[Option<TextRaster>],
+    drawing_layers: &[Option<LayerView<'_>>],
+    frame: &ExportFrame,
+) -> Result<Vec<u8>, String> {
+    match frame.deep.as_deref() {
+        Some(DeepPixels::U16(s)) => process_frame(data, text_layers, drawing_layers, s),
+        Some(DeepPixels::F16(s)) => process_frame(data, text_layers, drawing_layers, s),
+        Some(DeepPixels::F32(s)) => process_frame(data, text_layers, drawing_layers, s),
+        None => process_frame(data, text_layers, drawing_layers, &frame.pixels),
+    }
 }
 
+fn stream_png<T: Texel>(
+    data: &ExportData,
+    geom: &Geom,
+    text_layers: &[Option<TextRaster>],
+    drawing_layers: &[Option<LayerView<'_>>],
+    pixels: &[T],
+    path: &Path,
+    progress: &impl Fn(f32),
+) -> Result<(), String> {
+    ensure_available(pixels, data.width, data.height)?;
+    image::encode_png_streaming(
+        geom,
+        data,
+        text_layers,
+        drawing_layers,
+        pixels,
+        path,
+        progress,
+    )
+}
+
+const STREAM_PNG_ONLY: &str = "An image too large to hold exports only as PNG.";
+const STREAM_UNBANDABLE: &str =
+    "An image too large to hold cannot be exported turned or with these modifiers.";
+
 fn can_stream_bands(data: &ExportData) -> bool {
     data.rotation.is_multiple_of(2)
         && cpu::plan_is_bandable(
@@ -204,16 +372,9 @@ pub fn render_still_rgba(data: &ExportData) -> Result<(u32, u32, Vec<u8>), Strin
     let drawing_rasters = drawing_raster::build_layers(&data.modifiers, data.width, data.height);
     let drawing_layers = layer_views(&drawing_rasters);
     let geom = geom_of(data);
-    let (all_frames, still_index) = data.in_memory()?;
-    let (offset, len) = data.trim_bounds(all_frames);
-    let frames = &all_frames[offset..offset + len];
-    let still_index = still_index
-        .saturating_sub(offset)
-        .min(len.saturating_sub(1));
-    let still = frames
-        .get(still_index)
-        .ok_or_else(|| "No frame available.".to_string())?;
-    let processed = process_frame(data, &text_layers, &drawing_layers, &still.pixels)?;
+    let (frames, still_index) = data.frames()?;
+    let still = frames.frame(still_index)?;
+    let processed = process_export_frame(data, &text_layers, &drawing_layers, &still)?;
     let ctx = ctx_with(&geom, &processed);
     let mut rgba = vec![0u8; geom.out_w as usize * geom.out_h as usize * 4];
     render_into(&mut rgba, &ctx);
@@ -234,12 +395,6 @@ pub fn do_export(data: ExportData, path: &Path, progress: impl Fn(f32)) -> Resul
     let drawing_rasters = drawing_raster::build_layers(&data.modifiers, data.width, data.height);
     let drawing_layers = layer_views(&drawing_rasters);
     let geom = geom_of(&data);
-    let (all_frames, still_index) = data.in_memory()?;
-    let (offset, len) = data.trim_bounds(all_frames);
-    let frames = &all_frames[offset..offset + len];
-    let still_index = still_index
-        .saturating_sub(offset)
-        .min(len.saturating_sub(1));
 
     let ext = path
         .extension()
@@ -247,11 +402,38 @@ pub fn do_export(data: ExportData, path: &Path, progress: impl Fn(f32)) -> Resul
         .unwrap_or("png")
         .to_ascii_lowercase();
 
+    if let ExportSource::Stream(source) = &data.source {
+        if ext != "png" {
+            return Err(STREAM_PNG_ONLY.to_string());
+        }
+        // Rows arrive top to bottom only, so the output must take them in
+        // that order.
+        if let Some(refusal) = data.stream_refusal() {
+            return Err(refusal.to_string());
+        }
+        let result = image::encode_png_streaming(
+            &geom,
+            &data,
+            &text_layers,
+            &drawing_layers,
+            StreamedRows::new(source.bands(), source.width),
+            path,
+            &progress,
+        );
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(path);
+            return Err(e);
+        }
+        return Ok(export_name(path));
+    }
+
+    let (frames, still_index) = data.frames()?;
+
     match ext.as_str() {
         "gif" => image::encode_gif(
             &geom,
             &data,
-            frames,
+            &frames,
             &text_layers,
             &drawing_layers,
             path,
@@ -260,34 +442,62 @@ pub fn do_export(data: ExportData, path: &Path, progress: impl Fn(f32)) -> Resul
         "apng" => image::encode_apng(
             &geom,
             &data,
-            frames,
+            &frames,
             &text_layers,
             &drawing_layers,
             path,
             &progress,
         )?,
         _ => {
-            let still = frames
-                .get(still_index)
-                .ok_or_else(|| "No frame available.".to_string())?;
+            let still = frames.frame(still_index)?;
 
             if ext == "png" && can_stream_bands(&data) {
-                ensure_available(&still.pixels, data.width, data.height)?;
-                image::encode_png_streaming(
-                    &geom,
-                    &data,
-                    &text_layers,
-                    &drawing_layers,
-                    &still.pixels,
-                    path,
-                    &progress,
-                )?;
+                match still.deep.as_deref() {
+                    Some(DeepPixels::U16(s)) => stream_png(
+                        &data,
+                        &geom,
+                        &text_layers,
+                        &drawing_layers,
+                        s,
+                        path,
+                        &progress,
+                    )?,
+                    Some(DeepPixels::F16(s)) => stream_png(
+                        &data,
+                        &geom,
+                        &text_layers,
+                        &drawing_layers,
+                        s,
+                        path,
+                        &progress,
+                    )?,
+                    Some(DeepPixels::F32(s)) => stream_png(
+                        &data,
+                        &geom,
+                        &text_layers,
+                        &drawing_layers,
+                        s,
+                        path,
+                        &progress,
+                    )?,
+                    None => stream_png(
+                        &data,
+                        &geom,
+                        &text_layers,
+                        &drawing_layers,
+                        &still.pixels,
+                        path,
+                        &progress,
+                    )?,
+                }
             } else {
-                let processed = process_frame(&data, &text_layers, &drawing_layers, &still.pixels)?;
+                let processed = process_export_frame(&data, &text_layers, &drawing_layers, &still)?;
                 let ctx = ctx_with(&geom, &processed);
+                let profile = data.profile.as_deref().map(Vec::as_slice);
                 match ext.as_str() {
-                    "jpg" | "jpeg" => image::encode_jpeg(&ctx, path, &progress)?,
-                    "png" => image::encode_png(&ctx, path, &progress)?,
+                    "jpg" | "jpeg" => image::encode_jpeg(&ctx, profile, path, &progress)?,
+                    "png" => image::encode_png(&ctx, profile, path, &progress)?,
+                    "ico" => image::encode_ico(&ctx, path, &progress)?,
                     _ => image::encode_rgba(&ctx, path, &progress)?,
                 }
             }
@@ -297,6 +507,62 @@ pub fn do_export(data: ExportData, path: &Path, progress: impl Fn(f32)) -> Resul
     Ok(export_name(path))
 }
 
+/// Writes every page of `pages` into one multi-page TIFF. Each page takes the
+/// place of `data`'s source, keeping its modifiers, rotation and tonemap.
+pub fn do_export_pages(
+    data: ExportData,
+    pages: &Arc<PageSource>,
+    path: &Path,
+    progress: impl Fn(f32),
+) -> Result<String, String> {
+    let result = write_pages(&data, pages, path, &progress);
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result.map(|()| export_name(path))
+}
+
+fn write_pages(
+    data: &ExportData,
+    pages: &Arc<PageSource>,
+    path: &Path,
+    progress: &impl Fn(f32),
+) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut tiff = TiffEncoder::new(BufWriter::new(file))
+        .map_err(|e| e.to_string())?
+        .with_compression(Compression::Lzw);
+    let count = pages.count();
+    for index in 0..count {
+        let page = pages
+            .load(index)
+            .map_err(|e| format!("Page {}: {e}", index + 1))?;
+        let page_data = ExportData {
+            source: ExportSource::Frames {
+                frames: vec![ExportFrame {
+                    pixels: page.pixels_snapshot(),
+                    deep: page.deep_snapshot(),
+                    delay: Duration::ZERO,
+                }],
+                still_index: 0,
+            },
+            width: page.width,
+            height: page.height,
+            modifiers: data.modifiers.clone(),
+            rotation: data.rotation,
+            mirror: data.mirror,
+            profile: None,
+            tone: data.tone.filter(|_| page.scene_linear),
+            trim: None,
+        };
+        let (w, h, rgba) = render_still_rgba(&page_data)?;
+        tiff.write_image::<colortype::RGBA8>(w, h, &rgba)
+            .map_err(|e| e.to_string())?;
+        progress((index + 1) as f32 / count as f32);
+    }
+    Ok(())
+}
+
 fn export_name(path: &Path) -> String {
     path.file_name()
         .map(|n| n.to_string_lossy().into_owned())
@@ -311,6 +577,7 @@ mod trim_tests {
         (0..10)
             .map(|_| ExportFrame {
                 pixels: Arc::new(vec![0u8; 4]),
+                deep: None,
                 delay: Duration::from_millis(100),
             })
             .collect()
@@ -326,6 +593,9 @@ mod trim_tests {
             height: 1,
             modifiers: Vec::new(),
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim,
         }
     }
@@ -334,7 +604,6 @@ mod trim_tests {
         let data = data_with(trim, 0);
         let frames = match &data.source {
             ExportSource::Frames { frames, .. } => frames,
-            #[cfg(feature = "av")]
             _ => unreachable!(),
         };
         data.trim_bounds(frames)
@@ -398,6 +667,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels: Arc::new(vec![0u8; (w * h * 4) as usize]),
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -408,6 +678,9 @@ mod tests {
                 radius: 3.0,
             }))],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
@@ -422,6 +695,44 @@ mod tests {
         assert_eq!((geom.out_w, geom.out_h), (w, h));
     }
 
+    #[test]
+    fn scene_linear_frames_export_through_the_view_tonemap() {
+        use crate::wgpu::media::tonemap::{ToneOperator, Tonemap};
+
+        let linear = vec![0.5, 2.0, 8.0, 1.0];
+        let frame = ExportFrame {
+            pixels: Arc::new(vec![0, 0, 0, 255]),
+            deep: Some(Arc::new(DeepPixels::F32(linear.clone()))),
+            delay: Duration::ZERO,
+        };
+        let tone = Tone::Map(Tonemap {
+            operator: ToneOperator::Hable,
+            exposure: -1.0,
+        });
+        let mut data = ExportData {
+            source: ExportSource::Frames {
+                frames: Vec::new(),
+                still_index: 0,
+            },
+            width: 1,
+            height: 1,
+            modifiers: Vec::new(),
+            rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: Some(tone),
+            trim: None,
+        };
+
+        let out = process_export_frame(&data, &[], &[], &frame).unwrap();
+        assert_eq!(out, tone.to_rgba8(&linear));
+        assert!(out[0] < out[1] && out[1] < out[2], "highlights kept apart");
+
+        data.tone = None;
+        let out = process_export_frame(&data, &[], &[], &frame).unwrap();
+        assert_eq!(&out[1..3], &[255, 255], "without a tonemap they clip");
+    }
+
     fn assert_streamed_png_matches_buffered(
         label: &str,
         mut modifiers: Vec<Modifier>,
@@ -453,6 +764,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels: Arc::new(px.clone()),
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -461,6 +773,9 @@ mod tests {
             height: h,
             modifiers,
             rotation,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
         assert!(
@@ -474,11 +789,11 @@ mod tests {
         let a = dir.join(format!("{label}-stream.png"));
         let b = dir.join(format!("{label}-buffer.png"));
 
-        image::encode_png_streaming(&geom, &data, &[], &[], &px, &a, &|_| {}).unwrap();
+        image::encode_png_streaming(&geom, &data, &[], &[], px.as_slice(), &a, &|_| {}).unwrap();
 
         let processed = process_frame(&data, &[], &[], &px).unwrap();
         let ctx = ctx_with(&geom, &processed);
-        image::encode_png(&ctx, &b, &|_| {}).unwrap();
+        image::encode_png(&ctx, None, &b, &|_| {}).unwrap();
 
         let (ba, bb) = (std::fs::read(&a).unwrap(), std::fs::read(&b).unwrap());
         let _ = std::fs::remove_file(&a);
@@ -490,6 +805,44 @@ mod tests {
             ba.len(),
             bb.len()
         );
+
+        // A source too big to hold only reads downwards.
+        if rotation == 0 {
+            let c = dir.join(format!("{label}-rows.png"));
+            let rows = streamed_rows(&px, w);
+            image::encode_png_streaming(&geom, &data, &[], &[], rows, &c, &|_| {}).unwrap();
+            let bc = std::fs::read(&c).unwrap();
+            let _ = std::fs::remove_file(&c);
+            assert!(
+                bc == bb,
+                "{label}: PNG from streamed rows differs from buffered PNG"
+            );
+        }
+    }
+
+    /// `px` arriving the way a streamed image's bands do, seven rows at a time.
+    fn streamed_rows(px: &[u8], w: u32) -> StreamedRows {
+        use crate::wgpu::media::stream::Band;
+
+        let stride = w as usize * 4;
+        let bands: Vec<Band> = px
+            .chunks(stride * 7)
+            .enumerate()
+            .map(|(i, rows)| Band {
+                y: i as u32 * 7,
+                rows: (rows.len() / stride) as u32,
+                pixels: rows.to_vec(),
+            })
+            .collect();
+        let (tx, rx) = std::sync::mpsc::sync_channel(2);
+        std::thread::spawn(move || {
+            for band in bands {
+                if tx.send(Ok(band)).is_err() {
+                    return;
+                }
+            }
+        });
+        StreamedRows::new(rx, w)
     }
 
     #[test]
@@ -573,6 +926,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels: Arc::new(vec![0u8; 16 * 16 * 4]),
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -581,6 +935,9 @@ mod tests {
             height: 16,
             modifiers: mods,
             rotation,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
@@ -608,6 +965,87 @@ mod tests {
         assert!(can_stream_bands(&mk(blur, 0)), "plain blur should stream");
     }
 
+    #[test]
+    fn every_page_exports_with_the_view_rotation() {
+        let dir = std::env::temp_dir();
+        let src = dir.join(format!(
+            "bloom-test-export-pages-{}-src.tiff",
+            std::process::id()
+        ));
+        let out = dir.join(format!(
+            "bloom-test-export-pages-{}-out.tiff",
+            std::process::id()
+        ));
+        {
+            let mut tiff = TiffEncoder::new(std::fs::File::create(&src).unwrap()).unwrap();
+            tiff.write_image::<colortype::RGBA8>(2, 1, &[9; 8]).unwrap();
+            tiff.write_image::<colortype::RGBA8>(3, 1, &[7; 12])
+                .unwrap();
+        }
+        let pages = Arc::new(PageSource::tiff(&src).expect("two pages"));
+        let data = ExportData {
+            source: ExportSource::Frames {
+                frames: Vec::new(),
+                still_index: 0,
+            },
+            width: 2,
+            height: 1,
+            modifiers: Vec::new(),
+            rotation: 1,
+            mirror: false,
+            profile: None,
+            tone: None,
+            trim: None,
+        };
+        do_export_pages(data, &pages, &out, |_| {}).unwrap();
+
+        let written = PageSource::tiff(&out).expect("output keeps both pages");
+        let _ = std::fs::remove_file(&src);
+        let _ = std::fs::remove_file(&out);
+        let sizes: Vec<_> = written
+            .pages()
+            .iter()
+            .map(|p| (p.width, p.height, p.compression.as_str()))
+            .collect();
+        assert_eq!(sizes, [(1, 2, "LZW"), (1, 3, "LZW")]);
+    }
+
+    #[test]
+    fn mirror_flips_columns_before_rotating() {
+        let (a, b) = ([1u8, 0, 0, 255], [2u8, 0, 0, 255]);
+        let mk = |rotation: u8| ExportData {
+            source: ExportSource::Frames {
+                frames: vec![ExportFrame {
+                    pixels: Arc::new([a, b].concat()),
+                    deep: None,
+                    delay: Duration::ZERO,
+                }],
+                still_index: 0,
+            },
+            width: 2,
+            height: 1,
+            modifiers: Vec::new(),
+            rotation,
+            mirror: true,
+            profile: None,
+            tone: None,
+            trim: None,
+        };
+
+        let (w, h, rgba) = render_still_rgba(&mk(0)).unwrap();
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(rgba, [b, a].concat());
+        assert!(can_stream_bands(&mk(0)), "a mirror keeps rows intact");
+
+        let (w, h, rgba) = render_still_rgba(&mk(1)).unwrap();
+        assert_eq!((w, h), (1, 2));
+        assert_eq!(
+            rgba,
+            [b, a].concat(),
+            "transverse puts the right edge on top"
+        );
+    }
+
     fn resize_data(
         w: u32,
         h: u32,
@@ -627,6 +1065,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels: Arc::new(vec![0u8; (w * h * 4) as usize]),
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -635,6 +1074,9 @@ mod tests {
             height: h,
             modifiers,
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         }
     }
@@ -710,6 +1152,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels,
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -718,6 +1161,9 @@ mod tests {
             height: h,
             modifiers: vec![Modifier::new(ModifierKind::Text(text))],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
@@ -749,6 +1195,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels,
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -757,6 +1204,9 @@ mod tests {
             height: h,
             modifiers: vec![Modifier::new(ModifierKind::Drawing(drawing))],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
@@ -797,6 +1247,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels,
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -808,6 +1259,9 @@ mod tests {
                 Modifier::new(ModifierKind::ChromaticAberration(ca)),
             ],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
@@ -854,6 +1308,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels,
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -864,6 +1319,9 @@ mod tests {
                 radius: 4.0,
             }))],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
@@ -911,6 +1369,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels: gradient(w, h),
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -926,6 +1385,9 @@ mod tests {
                 Modifier::new(ModifierKind::Posterize(Posterize { levels: 6 })),
             ],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
 
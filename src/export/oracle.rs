@@ -74,6 +74,7 @@ mod tests {
             source: ExportSource::Frames {
                 frames: vec![ExportFrame {
                     pixels: source_pixels(W, H),
+                    deep: None,
                     delay: Duration::ZERO,
                 }],
                 still_index: 0,
@@ -82,6 +83,9 @@ mod tests {
             height: H,
             modifiers,
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
         render_still_rgba(&data).expect("oracle render should succeed")
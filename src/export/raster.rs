@@ -4,11 +4,93 @@
 //! Band height comes from the chain's accumulated apron, at least 4x it so the
 //! overlap stays a small fraction of the work, clamped to a sane range. Peak
 //! memory is one band instead of three full frames.
+//!
+//! A high-precision source is rendered at its own precision and each band is
+//! quantized to RGBA8 only once its chain has run.
+//!
+//! The source need not be in memory either. Bands ask a BandSource for the
+//! rows they read, top to bottom, so an image too big to hold decodes again
+//! from its file as the export walks down it and only the rows the current
+//! band needs are kept.
+
+use std::sync::mpsc::Receiver;
 
 use rayon::prelude::*;
 
+use crate::modifiers::cpu::Texel;
+use crate::wgpu::media::stream::Band;
+
 use super::Geom;
 
+/// Where stream_bands reads the source's rows from.
+pub(super) trait BandSource<T> {
+    /// Rows `lo..hi` of the source or more, with the row they start at.
+    fn rows(&mut self, lo: u32, hi: u32) -> Result<(&[T], u32), String>;
+
+    /// Whether the rows are decoded as they are asked for, so asking for
+    /// all of them would hold the whole image after all.
+    fn streamed(&self) -> bool {
+        false
+    }
+}
+
+impl<T> BandSource<T> for &[T] {
+    fn rows(&mut self, _lo: u32, _hi: u32) -> Result<(&[T], u32), String> {
+        Ok((*self, 0))
+    }
+}
+
+/// The rows of a streamed image, decoded from the top as bands reach them.
+/// Rows above the band being asked for are dropped, so asks must go down.
+pub(super) struct StreamedRows {
+    bands: Receiver<Result<Band, String>>,
+    stride: usize,
+    rows: Vec<u8>,
+    y0: u32,
+}
+
+impl StreamedRows {
+    /// Takes the bands of an image `width` pixels wide, as StreamSource::bands
+    /// sends them.
+    pub(super) fn new(bands: Receiver<Result<Band, String>>, width: u32) -> Self {
+        Self {
+            bands,
+            stride: width as usize * 4,
+            rows: Vec::new(),
+            y0: 0,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.y0 + (self.rows.len() / self.stride) as u32
+    }
+}
+
+impl BandSource<u8> for StreamedRows {
+    fn rows(&mut self, lo: u32, hi: u32) -> Result<(&[u8], u32), String> {
+        let drop = lo.saturating_sub(self.y0).min(self.end() - self.y0);
+        self.rows.drain(..drop as usize * self.stride);
+        self.y0 += drop;
+        while self.end() < hi {
+            match self.bands.recv() {
+                Ok(Ok(band)) => {
+                    if band.y != self.end() {
+                        return Err(format!("streamed rows skipped to {}", band.y));
+                    }
+                    self.rows.extend_from_slice(&band.pixels);
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Err("the streamed image ended early".to_string()),
+            }
+        }
+        Ok((&self.rows, self.y0))
+    }
+
+    fn streamed(&self) -> bool {
+        true
+    }
+}
+
 const STRIP_HEIGHT: u32 = 64;
 
 fn band_height(apron_rows: u32, out_h: u32) -> u32 {
@@ -62,12 +144,12 @@ pub(super) fn render_strips(
 }
 
 #[allow(clippy::too_many_arguments)]
-pub(super) fn stream_bands(
+pub(super) fn stream_bands<T: Texel>(
     geom: &Geom,
     data: &super::ExportData,
     text_layers: &[Option<crate::modifiers::text_raster::TextRaster>],
     drawing_layers: &[Option<crate::modifiers::drawing_raster::LayerView<'_>>],
-    pixels: &[u8],
+    mut source: impl BandSource<T>,
     mut sink: impl FnMut(&[u8]) -> Result<(), String>,
     progress: &impl Fn(f32),
 ) -> Result<(), String> {
@@ -95,15 +177,32 @@ pub(super) fn stream_bands(
         let (py0, py1) = (a.min(geom.img_h), b.min(geom.img_h));
 
         let band = if py1 > py0 {
-            crate::modifiers::cpu::render_band(
+            let rows = crate::modifiers::cpu::band_source_rows(
                 &data.modifiers,
-                text_layers,
-                drawing_layers,
-                pixels,
                 data.width,
                 data.height,
                 py0,
                 py1,
+            );
+            let (lo, hi) = match rows {
+                Some(rows) => rows,
+                None if source.streamed() => return Err(super::STREAM_UNBANDABLE.to_string()),
+                None => (0, data.height),
+            };
+            let (rows, rows_y0) = source.rows(lo, hi)?;
+            super::quantize(
+                data.tone,
+                crate::modifiers::cpu::render_band_rows(
+                    &data.modifiers,
+                    text_layers,
+                    drawing_layers,
+                    rows,
+                    rows_y0,
+                    data.width,
+                    data.height,
+                    py0,
+                    py1,
+                ),
             )
         } else {
             Vec::new()
@@ -145,6 +244,7 @@ fn fill_row_from_band(row: &mut [u8], oy: u32, ctx: &BandCtx) {
             2 => (g.cw - 1 - ox, g.ch - 1 - oy),
             _ => (ox, oy),
         };
+        let cx = if g.mirror { g.cw - 1 - cx } else { cx };
 
         let fx = g.cx0 + cx;
         let fy = g.cy0 + cy;
@@ -181,6 +281,7 @@ fn fill_row(row: &mut [u8], oy: u32, ctx: &ExportCtx) {
             3 => (g.cw - 1 - oy, ox),
             _ => unreachable!(),
         };
+        let cx = if g.mirror { g.cw - 1 - cx } else { cx };
 
         let fx = g.cx0 + cx;
         let fy = g.cy0 + cy;
@@ -477,6 +477,9 @@ mod tests {
             height: info.height,
             modifiers: vec![],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
         let t = std::time::Instant::now();
@@ -535,6 +538,9 @@ mod tests {
                 height: 24.0,
             }))],
             rotation: 1,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
         do_export(data, &output, |_| {}).expect("export");
@@ -573,6 +579,9 @@ mod tests {
                 exposure: -10.0,
             }))],
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: None,
         };
         do_export(data, &modified, |_| {}).expect("export with modifier");
@@ -640,6 +649,9 @@ mod tests {
             height: info.height,
             modifiers: Vec::new(),
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: Some((Duration::from_secs(1), Duration::from_secs(3))),
         };
 
@@ -719,6 +731,9 @@ mod tests {
             height: info.height,
             modifiers: Vec::new(),
             rotation: 0,
+            mirror: false,
+            profile: None,
+            tone: None,
             trim: Some((Duration::from_secs(2), Duration::from_secs(3))),
         };
         do_export(data, &output, |_| {}).expect("trimmed export");
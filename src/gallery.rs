@@ -1,9 +1,17 @@
 use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
     fmt,
     fs::read_dir,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering as AtomicOrdering},
+    time::UNIX_EPOCH,
 };
 
+use rayon::prelude::*;
+
+use crate::wgpu::media::{archive, exif_data::ExifData, sequence, sniff};
+
 pub const SUPPORTED: &[&str] = &[
     "jpg",
     "jpeg",
@@ -43,6 +51,8 @@ pub const SUPPORTED: &[&str] = &[
     "heic",
     #[cfg(feature = "heif")]
     "heif",
+    #[cfg(feature = "heif")]
+    "avif",
     #[cfg(feature = "av")]
     "mp4",
     #[cfg(feature = "av")]
@@ -104,80 +114,748 @@ pub const SUPPORTED: &[&str] = &[
     "epsf",
 ];
 
+/// How many levels below the folder it opened in a recursive gallery reads.
+const MAX_DEPTH: usize = 8;
+/// How many directory entries a listing reads before it stops, so opening a
+/// recursive gallery at the top of a huge tree still ends.
+pub const MAX_ENTRIES: usize = 100_000;
+
+/// Tells listings apart, so only the last one asked for is used.
+static LISTINGS: AtomicU64 = AtomicU64::new(0);
+
+fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| SUPPORTED.iter().any(|&s| s.eq_ignore_ascii_case(ext)))
+}
+
+/// Whether `path` belongs in the listing of `folder`: a file, or a link to
+/// one, that opens by name, or when it lies in `folder` itself rather than
+/// below it, by content. See `list`.
+fn listable(path: &Path, folder: Option<&Path>) -> bool {
+    path.is_file()
+        && (is_supported(path)
+            || (folder.is_some() && path.parent() == folder && sniff::sniff(path).is_some()))
+}
+
+/// Orders names the way people count: `page2` before `page10`. Runs of digits
+/// compare by value, everything else without regard to case.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let run = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let (na, nb) = (&a[..run(a)], &b[..run(b)]);
+            let (ta, tb) = (na.trim_start_matches('0'), nb.trim_start_matches('0'));
+            let ordering = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+            a = &a[na.len()..];
+            b = &b[nb.len()..];
+            ordering
+        } else {
+            a = &a[ca.len_utf8()..];
+            b = &b[cb.len_utf8()..];
+            ca.to_lowercase().cmp(cb.to_lowercase())
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Modified,
+    Size,
+    Captured,
+    Type,
+    Dimensions,
+}
+
+impl SortKey {
+    pub const ALL: &[(SortKey, &str)] = &[
+        (SortKey::Name, "Name"),
+        (SortKey::Modified, "Modified"),
+        (SortKey::Size, "Size"),
+        (SortKey::Captured, "Date taken"),
+        (SortKey::Type, "Type"),
+        (SortKey::Dimensions, "Dimensions"),
+    ];
+
+    pub fn name(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(key, _)| *key == self)
+            .map_or("", |(_, name)| name)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(key, _)| *key)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Number(u128),
+    Text(String),
+}
+
+/// What `path` sorts on under `key`. None when the file does not say, such
+/// as a photo without EXIF under Captured or a format whose header the image
+/// crate cannot read under Dimensions. Name has no value of its own: every
+/// key falls back to the name.
+fn sort_value(path: &Path, key: SortKey) -> Option<SortValue> {
+    match key {
+        SortKey::Name => None,
+        SortKey::Modified => {
+            let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+            let since = modified.duration_since(UNIX_EPOCH).ok()?;
+            Some(SortValue::Number(since.as_nanos()))
+        }
+        SortKey::Size => Some(SortValue::Number(
+            std::fs::metadata(path).ok()?.len().into(),
+        )),
+        // EXIF writes dates as `YYYY:MM:DD HH:MM:SS`, which sorts as text.
+        SortKey::Captured => {
+            let exif = ExifData::read(path);
+            exif.captured.or(exif.datetime).map(SortValue::Text)
+        }
+        SortKey::Type => sniff::sniff(path)
+            .map(str::to_string)
+            .or_else(|| Some(path.extension()?.to_str()?.to_ascii_lowercase()))
+            .map(SortValue::Text),
+        SortKey::Dimensions => {
+            let (width, height) = image::ImageReader::open(path)
+                .ok()?
+                .with_guessed_format()
+                .ok()?
+                .into_dimensions()
+                .ok()?;
+            Some(SortValue::Number(width as u128 * height as u128))
+        }
+    }
+}
+
+/// What a file sorts on: its value under the sort's key and its name.
+type SortKeys = (Option<SortValue>, String);
+
+fn sort_keys(path: &Path, key: SortKey) -> SortKeys {
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    (sort_value(path, key), name)
+}
+
+/// Orders two files by `sort`. Ties break on the natural order of the names,
+/// in the same direction; files without a value go last either way.
+fn sort_cmp((va, na): &SortKeys, (vb, nb): &SortKeys, sort: Sort) -> Ordering {
+    let direct = |ordering: Ordering| {
+        if sort.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    };
+    let by_value = match (va, vb) {
+        (Some(a), Some(b)) => direct(a.cmp(b)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+    by_value.then_with(|| direct(natural_cmp(na, nb)))
+}
+
+/// Orders `paths` by `sort`. See `sort_cmp`.
+fn sort_paths(paths: Vec<PathBuf>, sort: Sort) -> Vec<PathBuf> {
+    // Reading EXIF or headers opens every file, so do it across the pool.
+    let mut keyed: Vec<(SortKeys, PathBuf)> = paths
+        .into_par_iter()
+        .map(|p| (sort_keys(&p, sort.key), p))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| sort_cmp(a, b, sort));
+    keyed.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Walks `folder`, and with `recursive` the folders under it down to
+/// MAX_DEPTH, handing each file found to `file` along with how deep it lies.
+/// Hidden folders are passed over, and a folder reached twice through links
+/// is read once, so a link back up cannot loop. Returns the folders read,
+/// `folder` first, and whether MAX_ENTRIES cut the walk short.
+fn walk(
+    folder: &Path,
+    recursive: bool,
+    mut file: impl FnMut(PathBuf, usize),
+) -> (Vec<PathBuf>, bool) {
+    let mut dirs = Vec::new();
+    let mut seen = HashSet::new();
+    let mut entries = 0;
+    let mut pending = vec![(folder.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        if recursive && !std::fs::canonicalize(&dir).is_ok_and(|real| seen.insert(real)) {
+            continue;
+        }
+        for entry in read_dir(&dir).into_iter().flatten().flatten() {
+            entries += 1;
+            if entries > MAX_ENTRIES {
+                dirs.push(dir);
+                return (dirs, true);
+            }
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            let (is_file, is_dir) = if file_type.is_symlink() {
+                (path.is_file(), path.is_dir())
+            } else {
+                (file_type.is_file(), file_type.is_dir())
+            };
+            if is_file {
+                file(path, depth);
+            } else if is_dir
+                && recursive
+                && depth < MAX_DEPTH
+                && !entry.file_name().to_string_lossy().starts_with('.')
+            {
+                pending.push((path, depth + 1));
+            }
+        }
+        dirs.push(dir);
+    }
+    (dirs, false)
+}
+
+/// The media files in `folder`, and with `recursive`, in the folders under
+/// it, what those that were sniffed turned out to be, and whether
+/// MAX_ENTRIES cut the walk short. See `walk`. Files without a known
+/// extension are sniffed in `folder` itself but not below it, where opening
+/// every stray file would drag the walk out.
+fn list(folder: &Path, recursive: bool) -> (Vec<PathBuf>, Formats, bool) {
+    let mut paths = Vec::new();
+    let mut formats = Formats::new();
+    let (_, truncated) = walk(folder, recursive, |path, depth| {
+        if is_supported(&path) {
+            paths.push(path);
+        } else if depth == 0
+            && let Some(format) = sniff::sniff(&path)
+        {
+            formats.insert(path.clone(), Some(format));
+            paths.push(path);
+        }
+    });
+    (paths, formats, truncated)
+}
+
+/// What files' content was sniffed as, None where it was not recognised.
+type Formats = HashMap<PathBuf, Option<&'static str>>;
+
+/// The folders a recursive gallery of `folder` reads, `folder` first.
+pub fn subfolders(folder: &Path) -> Vec<PathBuf> {
+    walk(folder, true, |_, _| {}).0
+}
+
+/// A listing of a gallery's folder still to be read, which can take a while
+/// for a big tree and so runs off the UI thread. So does putting the files
+/// already listed in a new order, since most sorts open every file.
+pub struct ListRequest {
+    folder: PathBuf,
+    recursive: bool,
+    sort: Sort,
+    generation: u64,
+    /// The files to put in order, when the folder need not be read again.
+    paths: Option<Vec<PathBuf>>,
+}
+
+impl ListRequest {
+    /// Reads the folder, unless its files were handed over, and puts them in
+    /// order.
+    pub fn run(self) -> Listing {
+        let (paths, formats, truncated) = match self.paths {
+            Some(paths) => (paths, Formats::new(), false),
+            None => list(&self.folder, self.recursive),
+        };
+        Listing {
+            paths: arrange(paths, self.sort),
+            formats,
+            sort: self.sort,
+            generation: self.generation,
+            truncated,
+        }
+    }
+}
+
+/// The files a ListRequest found, for Gallery::listed.
+#[derive(Debug, Clone)]
+pub struct Listing {
+    paths: Vec<PathBuf>,
+    formats: Formats,
+    sort: Sort,
+    generation: u64,
+    /// Whether the walk stopped at MAX_ENTRIES with more left to read.
+    pub truncated: bool,
+}
+
+/// Orders folders as a tree lists them: each before everything under it,
+/// siblings in natural order.
+fn tree_cmp(a: &Path, b: &Path) -> Ordering {
+    let (mut a, mut b) = (a.components(), b.components());
+    loop {
+        let (ca, cb) = match (a.next(), b.next()) {
+            (Some(ca), Some(cb)) => (ca.as_os_str(), cb.as_os_str()),
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+        };
+        let ordering =
+            natural_cmp(&ca.to_string_lossy(), &cb.to_string_lossy()).then_with(|| ca.cmp(cb));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// A folder of the gallery, as the folder tree shows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Folder {
+    pub path: PathBuf,
+    /// Levels below the folder the gallery opened in.
+    pub depth: usize,
+    /// Where its files start in the listing. Those of the folders under it
+    /// follow, so this is also where its whole subtree starts.
+    pub first: usize,
+    /// Its own files, not counting those under it.
+    pub files: usize,
+}
+
+fn parent_of(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+/// Puts `paths`, all somewhere under `root`, in order: folder by folder as
+/// the tree reads, and within each folder by `sort`.
+fn arrange(paths: Vec<PathBuf>, sort: Sort) -> Vec<PathBuf> {
+    let mut groups: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        let dir = parent_of(&path).to_path_buf();
+        groups.entry(dir).or_default().push(path);
+    }
+    let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = groups.into_iter().collect();
+    groups.sort_by(|(a, _), (b, _)| tree_cmp(a, b));
+    groups
+        .into_iter()
+        .flat_map(|(_, files)| sort_paths(files, sort))
+        .collect()
+}
+
+/// The folders of an arranged listing, including those between `root` and
+/// the ones with files that hold nothing but other folders.
+fn folders_of(paths: &[PathBuf], root: &Path) -> Vec<Folder> {
+    let mut folders: Vec<Folder> = Vec::new();
+    let mut known = HashSet::new();
+    for (first, path) in paths.iter().enumerate() {
+        let dir = path.parent().unwrap_or(root);
+        if let Some(folder) = folders.last_mut().filter(|f| f.path == dir) {
+            folder.files += 1;
+            continue;
+        }
+        // Every folder from the root down to this one, the last being itself.
+        let below = dir.strip_prefix(root).unwrap_or(Path::new(""));
+        let mut at = root.to_path_buf();
+        let steps = std::iter::once(None).chain(below.components().map(Some));
+        for (depth, part) in steps.enumerate() {
+            if let Some(part) = part {
+                at.push(part);
+            }
+            if known.insert(at.clone()) {
+                folders.push(Folder {
+                    path: at.clone(),
+                    depth,
+                    first,
+                    files: 0,
+                });
+            }
+        }
+        if let Some(folder) = folders.last_mut() {
+            folder.files = 1;
+        }
+    }
+    folders
+}
+
 #[derive(Default)]
 pub struct Gallery {
     paths: Vec<PathBuf>,
     index: usize,
     file_size: Option<u64>,
+    format: Option<&'static str>,
+    /// The ZIP or CBZ being browsed, whose entries stand in for files.
+    archive: Option<PathBuf>,
+    /// The numbered run the current file is a frame of. See sequence.rs.
+    sequence: Option<Vec<PathBuf>>,
+    sort: Sort,
+    /// The folder listed, which other programs may change under it.
+    folder: Option<PathBuf>,
+    /// Whether the folders under `folder` are listed too.
+    recursive: bool,
+    folders: Vec<Folder>,
+    /// The listing of `folder` waited for, and the request for it until
+    /// that is taken to be run.
+    listing: Option<u64>,
+    request: Option<ListRequest>,
+    /// Whether the listing waited for only puts the files already listed in
+    /// order, rather than reading the folder again.
+    sorting: bool,
+    /// What the files were sniffed as, by the listing or when one was first
+    /// current, so stepping back and forth reads each header once.
+    formats: Formats,
 }
 
 impl Gallery {
-    pub fn new(file_path: &Path) -> Self {
-        let parent: &Path = match file_path.parent() {
-            Some(p) => p,
-            None => return Self::default(),
-        };
-
-        let mut paths: Vec<PathBuf> = read_dir(parent)
-            .into_iter()
-            .flatten()
-            .filter_map(|entry| entry.ok())
-            .filter_map(|entry| {
-                let name = entry.file_name();
-                let ext = Path::new(&name).extension()?.to_str()?;
-                if !SUPPORTED.iter().any(|&s| s.eq_ignore_ascii_case(ext)) {
-                    return None;
-                }
-                let file_type = entry.file_type().ok()?;
-                let is_file = if file_type.is_symlink() {
-                    entry.path().is_file()
-                } else {
-                    file_type.is_file()
-                };
-                is_file.then(|| entry.path())
-            })
-            .collect();
+    /// No files yet, but folders opened from it list in `sort` order, and
+    /// with `recursive`, along with the folders under them.
+    pub fn empty(sort: Sort, recursive: bool) -> Self {
+        Self {
+            sort,
+            recursive,
+            ..Self::default()
+        }
+    }
 
-        paths.sort_unstable();
+    pub fn new(file_path: &Path, sort: Sort, recursive: bool) -> Self {
+        if archive::is_archive(file_path) {
+            return Self::in_archive(file_path.to_path_buf(), None, sort, recursive);
+        }
+        if let Some((archive, name)) = archive::split(file_path) {
+            return Self::in_archive(archive.to_path_buf(), Some(&name), sort, recursive);
+        }
 
-        let index = paths.iter().position(|p| p == file_path).unwrap_or(0);
+        match file_path.parent() {
+            Some(parent) => Self::in_folder(parent, file_path, sort, recursive),
+            None => Self::empty(sort, recursive),
+        }
+    }
 
-        let file_size = std::fs::metadata(file_path).ok().map(|m| m.len());
-        Self {
+    /// `folder` opened on `file_path`, which stands alone until the listing
+    /// asked for here comes back through `listed`.
+    fn in_folder(folder: &Path, file_path: &Path, sort: Sort, recursive: bool) -> Self {
+        let paths = vec![file_path.to_path_buf()];
+        let folders = folders_of(&paths, folder);
+        let mut gallery = Self {
             paths,
-            index,
-            file_size,
+            sort,
+            folder: Some(folder.to_path_buf()),
+            recursive,
+            folders,
+            ..Self::default()
+        };
+        gallery.relist();
+        gallery.refresh_file_info();
+        gallery
+    }
+
+    /// The listing this gallery is waiting on, to be run in the background.
+    /// Taken once: None afterwards until the folder has to be read again.
+    pub fn take_request(&mut self) -> Option<ListRequest> {
+        self.request.take()
+    }
+
+    /// Takes in the files `listing` found, staying on the current file, or
+    /// going to the first one when it is not among them. A listing that was
+    /// asked for before the latest one is dropped. Returns whether it was
+    /// used.
+    pub fn listed(&mut self, listing: Listing) -> bool {
+        if self.listing != Some(listing.generation) {
+            return false;
         }
+        self.listing = None;
+        // A folder read again may hold other files under the same names.
+        if !std::mem::take(&mut self.sorting) {
+            self.formats = listing.formats;
+        }
+        let current = self.current().cloned();
+        self.paths = listing.paths;
+        self.index = current
+            .and_then(|c| self.paths.iter().position(|p| *p == c))
+            .unwrap_or(0);
+        self.refresh_folders();
+        self.refresh_file_info();
+        // The sort changed while the folder was read.
+        if listing.sort != self.sort {
+            self.resort();
+        }
+        true
+    }
+
+    /// An archive's entries in natural order, opened on `entry` or the first.
+    /// Pages read in order, so `sort` waits for the next folder.
+    fn in_archive(archive: PathBuf, entry: Option<&str>, sort: Sort, recursive: bool) -> Self {
+        let mut names = archive::entries(&archive);
+        names.sort_by(|a, b| natural_cmp(a, b));
+        let index = entry
+            .and_then(|e| names.iter().position(|n| n == e))
+            .unwrap_or(0);
+        let mut gallery = Self {
+            paths: names.iter().map(|n| archive.join(n)).collect(),
+            index,
+            archive: Some(archive),
+            sort,
+            recursive,
+            ..Self::default()
+        };
+        gallery.refresh_file_info();
+        gallery
     }
 
-    pub fn filename(path: &Path) -> String {
+    /// The name to show for `path`: its file name, or for an entry of the
+    /// archive being browsed, `archive.zip › entry`.
+    pub fn filename(&self, path: &Path) -> String {
+        if let Some((archive, entry)) = self.entry_of(path) {
+            let name = archive.file_name().unwrap_or_default().to_string_lossy();
+            return format!("{name} › {entry}");
+        }
         path.file_name()
             .map(|n| n.to_string_lossy().into_owned())
             .unwrap_or_default()
     }
 
-    fn refresh_file_size(&mut self) {
-        self.file_size = self
-            .current()
-            .and_then(|p| std::fs::metadata(p).ok())
-            .map(|m| m.len());
+    /// The full path to show for `path`, with an archive entry written as
+    /// `/path/to/archive.zip › entry`.
+    pub fn display_path(&self, path: &Path) -> String {
+        match self.entry_of(path) {
+            Some((archive, entry)) => format!("{} › {entry}", archive.display()),
+            None => path.to_string_lossy().into_owned(),
+        }
+    }
+
+    fn entry_of<'a>(&'a self, path: &'a Path) -> Option<(&'a Path, String)> {
+        let archive = self.archive.as_deref()?;
+        let entry = path.strip_prefix(archive).ok()?;
+        Some((archive, archive::entry_name(entry)))
+    }
+
+    /// The archive being browsed, when the gallery is one.
+    pub fn archive(&self) -> Option<&Path> {
+        self.archive.as_deref()
+    }
+
+    fn refresh_file_info(&mut self) {
+        let current = self.current().cloned();
+        let current = current.as_deref();
+        let (file_size, format) = match current.and_then(|p| self.entry_of(p)) {
+            Some((archive, name)) => (
+                archive::entry_size(archive, &name),
+                archive::sniff(archive, &name),
+            ),
+            None => (
+                current
+                    .and_then(|p| std::fs::metadata(p).ok())
+                    .map(|m| m.len()),
+                current.and_then(|p| {
+                    *self
+                        .formats
+                        .entry(p.to_path_buf())
+                        .or_insert_with(|| sniff::sniff(p))
+                }),
+            ),
+        };
+        let sequence = current.and_then(|p| sequence::run_of(p, &self.paths));
+        self.file_size = file_size;
+        self.format = format;
+        self.sequence = sequence;
     }
 
     pub fn set(&mut self, file_path: PathBuf) -> Option<&PathBuf> {
         if let Some(index) = self.paths.iter().position(|p| p == &file_path) {
             self.index = index;
         } else {
-            *self = Gallery::new(&file_path);
+            *self = Gallery::new(&file_path, self.sort, self.recursive);
             return self.current();
         }
-        self.refresh_file_size();
+        self.refresh_file_info();
         self.current()
     }
 
+    pub fn sort(&self) -> Sort {
+        self.sort
+    }
+
+    /// Asks for the files to be reordered by `sort`. They keep their order
+    /// until the request comes back through `listed`, which stays on the
+    /// current file.
+    pub fn set_sort(&mut self, sort: Sort) {
+        self.sort = sort;
+        if self.archive.is_none() {
+            self.resort();
+        }
+    }
+
+    /// Asks for the files listed to be put in order again, unless the folder
+    /// is being read, whose listing is sorted on arrival.
+    fn resort(&mut self) {
+        let Some(folder) = self.folder.clone() else {
+            return;
+        };
+        if self.listing.is_some() && !self.sorting {
+            return;
+        }
+        let generation = LISTINGS.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        self.listing = Some(generation);
+        self.sorting = true;
+        self.request = Some(ListRequest {
+            folder,
+            recursive: self.recursive,
+            sort: self.sort,
+            generation,
+            paths: Some(self.paths.clone()),
+        });
+    }
+
+    fn refresh_folders(&mut self) {
+        if let Some(root) = &self.folder {
+            self.folders = folders_of(&self.paths, root);
+        }
+    }
+
+    pub fn recursive(&self) -> bool {
+        self.recursive
+    }
+
+    /// Lists the folders under this one as well, or stops. Either way the
+    /// current file stays current: going flat lists just the folder it is in.
+    pub fn set_recursive(&mut self, recursive: bool) {
+        self.recursive = recursive;
+        if self.archive.is_some() {
+            return;
+        }
+        let Some(current) = self.current().cloned() else {
+            return;
+        };
+        let folder = match (&self.folder, recursive) {
+            (Some(folder), true) => folder.clone(),
+            _ => match current.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return,
+            },
+        };
+        self.folder = Some(folder);
+        self.relist();
+    }
+
+    /// Asks for the folder to be listed again from scratch. Until it is, the
+    /// files listed before stay; see `listed`.
+    pub fn relist(&mut self) {
+        let Some(folder) = self.folder.clone() else {
+            return;
+        };
+        let generation = LISTINGS.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        self.listing = Some(generation);
+        self.sorting = false;
+        self.request = Some(ListRequest {
+            folder,
+            recursive: self.recursive,
+            sort: self.sort,
+            generation,
+            paths: None,
+        });
+    }
+
+    /// Lists the folder above this one instead, with everything under it.
+    /// False when there is none.
+    pub fn open_parent(&mut self) -> bool {
+        let Some(parent) = self.folder.as_deref().and_then(Path::parent) else {
+            return false;
+        };
+        self.folder = Some(parent.to_path_buf());
+        self.relist();
+        true
+    }
+
+    /// The folders listed, in tree order: just the one unless recursive.
+    pub fn folders(&self) -> &[Folder] {
+        &self.folders
+    }
+
+    /// The folder listed, when the gallery is one rather than an archive.
+    pub fn folder(&self) -> Option<&Path> {
+        self.folder.as_deref()
+    }
+
+    /// Catches the listing up with `changed`, files of the folder that were
+    /// written, added or removed since it was read. Stays on the current
+    /// file, or when that went, on the one after it. Returns whether the
+    /// current file was rewritten and should be read again.
+    pub fn sync(&mut self, changed: &[PathBuf]) -> bool {
+        let mut moved = false;
+        for path in changed {
+            self.formats.remove(path);
+            let listed = self.paths.iter().position(|p| p == path);
+            match (listed, listable(path, self.folder.as_deref())) {
+                (None, true) => {
+                    let i = self.place_of(path);
+                    self.paths.insert(i, path.clone());
+                    if i <= self.index && self.paths.len() > 1 {
+                        self.index += 1;
+                    }
+                    moved = true;
+                }
+                (Some(i), false) => {
+                    self.paths.remove(i);
+                    if i < self.index {
+                        self.index -= 1;
+                    }
+                    moved = true;
+                }
+                _ => {}
+            }
+        }
+        self.index = self.index.min(self.paths.len().saturating_sub(1));
+        if moved {
+            self.refresh_folders();
+            // An order worked out before these changes would undo them.
+            if self.sorting {
+                self.resort();
+            }
+        }
+        self.refresh_file_info();
+        self.current().is_some_and(|c| changed.contains(c))
+    }
+
+    /// Where `path`, not yet listed, goes in the listing: among the files of
+    /// its folder, where the sort puts it. Both searches are binary, so only
+    /// the few files they land on are read for what they sort on, rather
+    /// than the whole folder.
+    fn place_of(&self, path: &Path) -> usize {
+        let dir = parent_of(path);
+        let lo = self
+            .paths
+            .partition_point(|p| tree_cmp(parent_of(p), dir) == Ordering::Less);
+        let hi = self
+            .paths
+            .partition_point(|p| tree_cmp(parent_of(p), dir) != Ordering::Greater);
+        let keys = sort_keys(path, self.sort.key);
+        lo + self.paths[lo..hi.max(lo)].partition_point(|p| {
+            sort_cmp(&sort_keys(p, self.sort.key), &keys, self.sort) != Ordering::Greater
+        })
+    }
+
     pub fn next(&mut self) -> Option<&PathBuf> {
         if !self.paths.is_empty() {
             self.index = (self.index + 1) % self.paths.len();
-            self.refresh_file_size();
+            self.refresh_file_info();
         }
         self.current()
     }
@@ -185,7 +863,7 @@ impl Gallery {
     pub fn previous(&mut self) -> Option<&PathBuf> {
         if !self.paths.is_empty() {
             self.index = (self.index + self.paths.len() - 1) % self.paths.len();
-            self.refresh_file_size();
+            self.refresh_file_info();
         }
         self.current()
     }
@@ -194,10 +872,24 @@ impl Gallery {
         self.file_size
     }
 
+    /// The current file's format as read from its content, when recognised.
+    pub fn format(&self) -> Option<&'static str> {
+        self.format
+    }
+
+    /// The files of the numbered run the current file belongs to, in order.
+    pub fn sequence(&self) -> Option<&[PathBuf]> {
+        self.sequence.as_deref()
+    }
+
     pub fn current(&self) -> Option<&PathBuf> {
         self.paths.get(self.index)
     }
 
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
     pub fn position(&self) -> usize {
         self.index
     }
@@ -240,6 +932,19 @@ mod tests {
         dir
     }
 
+    /// Runs the listing `gallery` asked for, as the app does off the UI
+    /// thread.
+    fn settle(gallery: &mut Gallery) {
+        if let Some(request) = gallery.take_request() {
+            assert!(gallery.listed(request.run()));
+        }
+    }
+
+    fn listed(mut gallery: Gallery) -> Gallery {
+        settle(&mut gallery);
+        gallery
+    }
+
     fn names(gallery: &Gallery) -> Vec<String> {
         gallery
             .paths
@@ -262,12 +967,35 @@ mod tests {
         std::fs::write(dir.join("noext"), b"x").unwrap();
         std::fs::create_dir(dir.join("d.jpg")).unwrap();
 
-        let gallery = Gallery::new(&dir.join("a.jpg"));
+        let gallery = listed(Gallery::new(&dir.join("a.jpg"), Sort::default(), false));
 
         assert_eq!(names(&gallery), vec!["a.jpg", "b.PNG"]);
         let _ = std::fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn misnamed_and_extensionless_images_are_listed_by_content() {
+        let dir = fixture("sniff");
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        std::fs::write(dir.join("a.jpg"), b"x").unwrap();
+        std::fs::write(dir.join("download"), png).unwrap();
+        std::fs::write(dir.join("photo.bin"), png).unwrap();
+        std::fs::write(dir.join("notes.txt"), b"just text").unwrap();
+
+        let mut gallery = listed(Gallery::new(&dir.join("download"), Sort::default(), false));
+
+        assert_eq!(names(&gallery), vec!["a.jpg", "download", "photo.bin"]);
+        assert_eq!(gallery.format(), Some("png"));
+
+        // Stepping reuses what the listing sniffed, until the file changes.
+        std::fs::write(dir.join("photo.bin"), b"\xff\xd8\xff\xe0").unwrap();
+        gallery.next();
+        assert_eq!(gallery.format(), Some("png"));
+        gallery.sync(&[dir.join("photo.bin")]);
+        assert_eq!(gallery.format(), Some("jpg"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[cfg(unix)]
     #[test]
     fn a_symlinked_image_is_listed_like_a_real_one() {
@@ -276,12 +1004,240 @@ mod tests {
         std::os::unix::fs::symlink(dir.join("real.jpg"), dir.join("linked.jpg")).unwrap();
         std::os::unix::fs::symlink(dir.join("missing.jpg"), dir.join("dangling.jpg")).unwrap();
 
-        let gallery = Gallery::new(&dir.join("real.jpg"));
+        let gallery = listed(Gallery::new(&dir.join("real.jpg"), Sort::default(), false));
 
         assert_eq!(names(&gallery), vec!["linked.jpg", "real.jpg"]);
         let _ = std::fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn numbers_in_names_sort_by_value() {
+        let mut names = vec![
+            "page10.jpg",
+            "Page2.jpg",
+            "page1.jpg",
+            "page02b.jpg",
+            "cover.jpg",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec![
+                "cover.jpg",
+                "page1.jpg",
+                "Page2.jpg",
+                "page02b.jpg",
+                "page10.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn sorting_reorders_and_stays_on_the_current_file() {
+        let dir = fixture("sort");
+        std::fs::write(dir.join("a10.png"), b"x").unwrap();
+        std::fs::write(dir.join("a2.png"), b"xxx").unwrap();
+        std::fs::write(dir.join("b.jpg"), b"xx").unwrap();
+
+        let mut gallery = listed(Gallery::new(&dir.join("b.jpg"), Sort::default(), false));
+        assert_eq!(names(&gallery), vec!["a2.png", "a10.png", "b.jpg"]);
+
+        let by = |key, descending| Sort { key, descending };
+        gallery.set_sort(by(SortKey::Size, false));
+        // The new order is worked out in the background.
+        assert_eq!(names(&gallery), vec!["a2.png", "a10.png", "b.jpg"]);
+        settle(&mut gallery);
+        assert_eq!(names(&gallery), vec!["a10.png", "b.jpg", "a2.png"]);
+        assert_eq!(gallery.position(), 1);
+        gallery.set_sort(by(SortKey::Type, true));
+        settle(&mut gallery);
+        assert_eq!(names(&gallery), vec!["a10.png", "a2.png", "b.jpg"]);
+        assert_eq!(gallery.position(), 2);
+        // None of them has a header to measure, so the names decide.
+        gallery.set_sort(by(SortKey::Dimensions, true));
+        settle(&mut gallery);
+        assert_eq!(names(&gallery), vec!["b.jpg", "a10.png", "a2.png"]);
+
+        // A file added while a new order is worked out is not lost to it.
+        gallery.set_sort(by(SortKey::Name, false));
+        let stale = gallery.take_request().unwrap();
+        std::fs::write(dir.join("a1.png"), b"x").unwrap();
+        gallery.sync(&[dir.join("a1.png")]);
+        assert!(!gallery.listed(stale.run()));
+        settle(&mut gallery);
+        assert_eq!(
+            names(&gallery),
+            vec!["a1.png", "a2.png", "a10.png", "b.jpg"]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_changed_on_disk_join_and_leave_the_listing() {
+        let dir = fixture("sync");
+        for name in ["a.png", "b.png", "c.png"] {
+            std::fs::write(dir.join(name), b"x").unwrap();
+        }
+        let mut gallery = listed(Gallery::new(&dir.join("b.png"), Sort::default(), false));
+
+        std::fs::remove_file(dir.join("a.png")).unwrap();
+        std::fs::write(dir.join("d.png"), b"x").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"x").unwrap();
+        let changed = ["a.png", "d.png", "notes.txt"].map(|n| dir.join(n));
+        assert!(!gallery.sync(&changed));
+        assert_eq!(names(&gallery), vec!["b.png", "c.png", "d.png"]);
+        assert_eq!(gallery.position(), 0);
+
+        assert!(gallery.sync(&[dir.join("b.png")]));
+
+        std::fs::remove_file(dir.join("b.png")).unwrap();
+        assert!(!gallery.sync(&[dir.join("b.png")]));
+        assert_eq!(gallery.current(), Some(&dir.join("c.png")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_added_on_disk_sort_into_place_and_sniff_only_in_the_folder_opened() {
+        let dir = fixture("sync-sorted");
+        for (name, size) in [("a.png", 1), ("c.png", 3), ("sub/e.png", 5)] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, vec![b'x'; size]).unwrap();
+        }
+        let sort = Sort {
+            key: SortKey::Size,
+            descending: true,
+        };
+        let mut gallery = listed(Gallery::new(&dir.join("a.png"), sort, true));
+        assert_eq!(names(&gallery), vec!["c.png", "a.png", "e.png"]);
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        std::fs::write(dir.join("b.png"), b"xx").unwrap();
+        std::fs::write(dir.join("download"), png).unwrap();
+        std::fs::write(dir.join("sub/download"), png).unwrap();
+        std::fs::write(dir.join("sub/d.png"), b"xxxxxxx").unwrap();
+        let changed = ["b.png", "download", "sub/download", "sub/d.png"].map(|n| dir.join(n));
+        assert!(!gallery.sync(&changed));
+        assert_eq!(
+            names(&gallery),
+            vec!["download", "c.png", "b.png", "a.png", "d.png", "e.png"]
+        );
+        assert_eq!(gallery.current(), Some(&dir.join("a.png")));
+        assert_eq!(gallery.folders()[1].first, 4);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn subfolders_list_as_a_tree_and_next_crosses_them() {
+        let dir = fixture("recursive");
+        for name in [
+            "top.png",
+            "100CANON/b.jpg",
+            "100CANON/a.jpg",
+            "DCIM/10/c.jpg",
+        ] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"x").unwrap();
+        }
+        std::fs::create_dir_all(dir.join(".cache")).unwrap();
+        std::fs::write(dir.join(".cache/hidden.png"), b"x").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("100CANON/loop")).unwrap();
+
+        let mut gallery = listed(Gallery::new(
+            &dir.join("100CANON/b.jpg"),
+            Sort::default(),
+            false,
+        ));
+        assert_eq!(names(&gallery), vec!["a.jpg", "b.jpg"]);
+        assert_eq!(gallery.folders().len(), 1);
+
+        gallery.set_recursive(true);
+        assert!(gallery.open_parent());
+        settle(&mut gallery);
+        assert_eq!(gallery.current(), Some(&dir.join("100CANON/b.jpg")));
+        assert_eq!(names(&gallery), vec!["top.png", "a.jpg", "b.jpg", "c.jpg"]);
+        let tree: Vec<_> = gallery
+            .folders()
+            .iter()
+            .map(|f| {
+                (
+                    f.path.strip_prefix(&dir).unwrap(),
+                    f.depth,
+                    f.first,
+                    f.files,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                (Path::new(""), 0, 0, 1),
+                (Path::new("100CANON"), 1, 1, 2),
+                (Path::new("DCIM"), 1, 3, 0),
+                (Path::new("DCIM/10"), 2, 3, 1),
+            ]
+        );
+        assert_eq!(gallery.next(), Some(&dir.join("DCIM/10/c.jpg")));
+
+        gallery.set_recursive(false);
+        settle(&mut gallery);
+        assert_eq!(names(&gallery), vec!["c.jpg"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_listing_waits_in_the_background_and_only_the_latest_is_used() {
+        let dir = fixture("background");
+        for name in ["a.png", "b.png", "sub/c.png"] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"x").unwrap();
+        }
+        std::fs::write(dir.join("sub/download"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let mut gallery = Gallery::new(&dir.join("b.png"), Sort::default(), true);
+        assert_eq!(names(&gallery), vec!["b.png"]);
+        let stale = gallery.take_request().unwrap();
+        assert!(gallery.take_request().is_none());
+
+        gallery.relist();
+        assert!(!gallery.listed(stale.run()));
+        settle(&mut gallery);
+        // Below the folder opened, only known extensions are listed.
+        assert_eq!(names(&gallery), vec!["a.png", "b.png", "c.png"]);
+        assert_eq!(gallery.position(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn an_archive_is_browsed_like_a_folder() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let dir = fixture("archive");
+        let book = dir.join("book.cbz");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&book).unwrap());
+        for name in ["p10.png", "p2.png", "notes.txt", "__MACOSX/._p2.png"] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(b"\x89PNG\r\n\x1a\n").unwrap();
+        }
+        zip.finish().unwrap();
+
+        let mut gallery = Gallery::new(&book, Sort::default(), false);
+        assert_eq!(names(&gallery), vec!["p2.png", "p10.png"]);
+        assert_eq!(gallery.archive(), Some(book.as_path()));
+        assert_eq!(gallery.format(), Some("png"));
+        let next = gallery.next().cloned().unwrap();
+        assert_eq!(next, book.join("p10.png"));
+        assert_eq!(gallery.filename(&next), "book.cbz › p10.png");
+
+        let reopened = Gallery::new(&next, Sort::default(), false);
+        assert_eq!(reopened.position(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[cfg(unix)]
     #[test]
     fn opening_a_symlink_lands_on_that_symlink() {
@@ -289,7 +1245,11 @@ mod tests {
         std::fs::write(dir.join("a_real.jpg"), b"x").unwrap();
         std::os::unix::fs::symlink(dir.join("a_real.jpg"), dir.join("z_link.jpg")).unwrap();
 
-        let gallery = Gallery::new(&dir.join("z_link.jpg"));
+        let gallery = listed(Gallery::new(
+            &dir.join("z_link.jpg"),
+            Sort::default(),
+            false,
+        ));
 
         assert_eq!(gallery.len(), 2);
         assert_eq!(gallery.position(), 1);
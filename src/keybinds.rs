@@ -18,6 +18,8 @@ use serde::{Deserialize, Serialize};
 pub enum Action {
     Next,
     Previous,
+    NextPage,
+    PreviousPage,
     ToggleFullscreen,
     FocusScale,
     PasteFromClipboard,
@@ -47,6 +49,8 @@ pub enum Action {
     ToggleCheckerboard,
     TogglePixelGrid,
     ToggleBottomBar,
+    ToggleGrid,
+    ToggleSubfolders,
     OpenMedia,
     CopyImage,
     ExportImage,
@@ -85,6 +89,8 @@ impl Action {
         match self {
             Self::Next => "Next image".into(),
             Self::Previous => "Previous image".into(),
+            Self::NextPage => "Next page".into(),
+            Self::PreviousPage => "Previous page".into(),
             Self::ToggleFullscreen => "Toggle fullscreen".into(),
             Self::FocusScale => "Focus zoom entry".into(),
             Self::PasteFromClipboard => "Paste from clipboard".into(),
@@ -114,6 +120,8 @@ impl Action {
             Self::ToggleCheckerboard => "Toggle checkerboard".into(),
             Self::TogglePixelGrid => "Toggle pixel grid".into(),
             Self::ToggleBottomBar => "Toggle bottom bar".into(),
+            Self::ToggleGrid => "Toggle thumbnail grid".into(),
+            Self::ToggleSubfolders => "Toggle subfolders".into(),
             Self::OpenMedia => "Open media".into(),
             Self::CopyImage => "Copy image".into(),
             Self::ExportImage => "Export image".into(),
@@ -124,6 +132,8 @@ impl Action {
         match self {
             Self::Next => "Go to the next file in the folder",
             Self::Previous => "Go to the previous file in the folder",
+            Self::NextPage => "Go to the next page of a multi-page file",
+            Self::PreviousPage => "Go to the previous page of a multi-page file",
             Self::ToggleFullscreen => "Switch between windowed and fullscreen mode",
             Self::FocusScale => "Focus the zoom percentage entry field",
             Self::PasteFromClipboard => "Load an image from the clipboard",
@@ -153,6 +163,8 @@ impl Action {
             Self::ToggleCheckerboard => "Show or hide the checkerboard background",
             Self::TogglePixelGrid => "Show or hide the pixel grid",
             Self::ToggleBottomBar => "Show or hide the bottom toolbar",
+            Self::ToggleGrid => "Browse the folder as a grid of thumbnails",
+            Self::ToggleSubfolders => "Browse the folders under the current one as well",
             Self::OpenMedia => "Open a media file from disk",
             Self::CopyImage => "Copy the current image to the clipboard",
             Self::ExportImage => "Export the current image to a file",
@@ -163,11 +175,15 @@ impl Action {
         match self {
             Self::Next
             | Self::Previous
+            | Self::NextPage
+            | Self::PreviousPage
             | Self::ToggleFullscreen
             | Self::PasteFromClipboard
             | Self::OpenMedia
             | Self::CopyImage
-            | Self::ExportImage => KeyCategory::Navigation,
+            | Self::ExportImage
+            | Self::ToggleGrid
+            | Self::ToggleSubfolders => KeyCategory::Navigation,
             Self::RotateCw
             | Self::RotateCcw
             | Self::ZoomIn
@@ -206,7 +222,11 @@ impl Action {
         &[
             Action::Next,
             Action::Previous,
+            Action::NextPage,
+            Action::PreviousPage,
             Action::ToggleFullscreen,
+            Action::ToggleGrid,
+            Action::ToggleSubfolders,
             Action::OpenMedia,
             Action::CopyImage,
             Action::PasteFromClipboard,
@@ -456,6 +476,8 @@ impl Default for Keymap {
         let mut m = HashMap::new();
         m.insert(Action::Next, n(key::Code::ArrowRight));
         m.insert(Action::Previous, n(key::Code::ArrowLeft));
+        m.insert(Action::NextPage, n(key::Code::PageDown));
+        m.insert(Action::PreviousPage, n(key::Code::PageUp));
         m.insert(Action::ToggleFullscreen, n(key::Code::KeyF));
         m.insert(Action::FocusScale, n(key::Code::KeyZ));
         m.insert(Action::PasteFromClipboard, c(key::Code::KeyV));
@@ -492,6 +514,16 @@ impl Default for Keymap {
         m.insert(Action::ToggleCheckerboard, n(key::Code::KeyB));
         m.insert(Action::TogglePixelGrid, n(key::Code::KeyG));
         m.insert(Action::ToggleBottomBar, n(key::Code::KeyH));
+        m.insert(Action::ToggleGrid, n(key::Code::KeyV));
+        m.insert(
+            Action::ToggleSubfolders,
+            KeyBinding {
+                ctrl: false,
+                shift: true,
+                alt: false,
+                code: key::Code::KeyV,
+            },
+        );
         m.insert(Action::OpenMedia, c(key::Code::KeyO));
         m.insert(Action::CopyImage, c(key::Code::KeyC));
         m.insert(Action::ExportImage, c(key::Code::KeyE));
@@ -552,6 +584,8 @@ impl Keymap {
 pub(crate) struct KeymapFile {
     pub next: Option<String>,
     pub previous: Option<String>,
+    pub next_page: Option<String>,
+    pub previous_page: Option<String>,
     pub toggle_fullscreen: Option<String>,
     pub focus_scale: Option<String>,
     pub paste_from_clipboard: Option<String>,
@@ -589,6 +623,8 @@ pub(crate) struct KeymapFile {
     pub toggle_checkerboard: Option<String>,
     pub toggle_pixel_grid: Option<String>,
     pub toggle_bottom_bar: Option<String>,
+    pub toggle_grid: Option<String>,
+    pub toggle_subfolders: Option<String>,
     pub open_media: Option<String>,
     pub copy_image: Option<String>,
     pub export_image: Option<String>,
@@ -607,6 +643,8 @@ impl From<&Keymap> for KeymapFile {
         Self {
             next: bind(Action::Next),
             previous: bind(Action::Previous),
+            next_page: bind(Action::NextPage),
+            previous_page: bind(Action::PreviousPage),
             toggle_fullscreen: bind(Action::ToggleFullscreen),
             focus_scale: bind(Action::FocusScale),
             paste_from_clipboard: bind(Action::PasteFromClipboard),
@@ -644,6 +682,8 @@ impl From<&Keymap> for KeymapFile {
             toggle_checkerboard: bind(Action::ToggleCheckerboard),
             toggle_pixel_grid: bind(Action::TogglePixelGrid),
             toggle_bottom_bar: bind(Action::ToggleBottomBar),
+            toggle_grid: bind(Action::ToggleGrid),
+            toggle_subfolders: bind(Action::ToggleSubfolders),
             open_media: bind(Action::OpenMedia),
             copy_image: bind(Action::CopyImage),
             export_image: bind(Action::ExportImage),
@@ -664,6 +704,8 @@ impl From<KeymapFile> for Keymap {
         let bindings = [
             resolve(f.next, Action::Next),
             resolve(f.previous, Action::Previous),
+            resolve(f.next_page, Action::NextPage),
+            resolve(f.previous_page, Action::PreviousPage),
             resolve(f.toggle_fullscreen, Action::ToggleFullscreen),
             resolve(f.focus_scale, Action::FocusScale),
             resolve(f.paste_from_clipboard, Action::PasteFromClipboard),
@@ -701,6 +743,8 @@ impl From<KeymapFile> for Keymap {
             resolve(f.toggle_checkerboard, Action::ToggleCheckerboard),
             resolve(f.toggle_pixel_grid, Action::TogglePixelGrid),
             resolve(f.toggle_bottom_bar, Action::ToggleBottomBar),
+            resolve(f.toggle_grid, Action::ToggleGrid),
+            resolve(f.toggle_subfolders, Action::ToggleSubfolders),
             resolve(f.open_media, Action::OpenMedia),
             resolve(f.copy_image, Action::CopyImage),
             resolve(f.export_image, Action::ExportImage),
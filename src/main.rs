@@ -11,7 +11,9 @@ mod keybinds;
 mod modifiers;
 mod styles;
 mod tasks;
+mod thumbnails;
 mod ui;
+mod watch;
 mod wgpu;
 mod widgets;
 use std::{env, path::PathBuf};
@@ -35,7 +35,18 @@
 //! Resize alone happened to work under the old code, because its band origin is
 //! always aligned and its apron is its whole reach -- which is why the bug
 //! survived until a chain put a blur next to a resize.
-
+//!
+//! Every kernel is generic over Texel. The u8 instance is the original code,
+//! rounding and clamping at the end of each stage. The u16, f16 and f32
+//! instances are how a high-precision source gets through a chain in its own
+//! storage: no stage rounds to 8 bits, so levels or exposure pushed hard on a
+//! smooth gradient do not open it into steps, and the one quantization happens
+//! in Texel::quantize, where the encoders take the result. Pointwise stages
+//! clamp only alpha: the integer texels saturate on store anyway, and the float
+//! ones keep HDR values above 1.0 for the tonemap that follows the chain.
+
+use bytemuck::Pod;
+use half::f16;
 use rayon::prelude::*;
 
 use crate::modifiers::drawing_raster::LayerView;
@@ -45,6 +56,119 @@ use crate::modifiers::roi::{StepClass, step_class_for};
 use crate::modifiers::text_raster::TextRaster;
 use crate::modifiers::{Modifier, ModifierKind, StageTransform, motion_blur_samples};
 
+/// A channel type the kernels run on.
+pub(crate) trait Texel: Pod + Default + PartialEq + Send + Sync {
+    /// The value on the scale kernels accumulate in: 0..=255 for u8, the unit
+    /// range for f32. Weighted sums are linear, so only the round trip through
+    /// from_acc has to agree.
+    fn to_acc(self) -> f32;
+    fn from_acc(a: f32) -> Self;
+    fn to_unit(self) -> f32;
+    fn from_unit(v: f32) -> Self;
+
+    fn to_byte(self) -> u8 {
+        u8::from_unit(self.to_unit().clamp(0.0, 1.0))
+    }
+
+    /// The rendered buffer as RGBA8, for the encoders.
+    fn quantize(v: Vec<Self>) -> Vec<u8> {
+        v.into_par_iter().map(Self::to_byte).collect()
+    }
+}
+
+impl Texel for u8 {
+    fn to_acc(self) -> f32 {
+        self as f32
+    }
+
+    fn from_acc(a: f32) -> Self {
+        a.round().clamp(0.0, 255.0) as u8
+    }
+
+    fn to_unit(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn from_unit(v: f32) -> Self {
+        (v * 255.0).round() as u8
+    }
+
+    fn to_byte(self) -> u8 {
+        self
+    }
+
+    fn quantize(v: Vec<Self>) -> Vec<u8> {
+        v
+    }
+}
+
+impl Texel for f32 {
+    fn to_acc(self) -> f32 {
+        self
+    }
+
+    fn from_acc(a: f32) -> Self {
+        a
+    }
+
+    fn to_unit(self) -> f32 {
+        self
+    }
+
+    fn from_unit(v: f32) -> Self {
+        v
+    }
+}
+
+impl Texel for u16 {
+    fn to_acc(self) -> f32 {
+        self as f32
+    }
+
+    fn from_acc(a: f32) -> Self {
+        a.round().clamp(0.0, 65535.0) as u16
+    }
+
+    fn to_unit(self) -> f32 {
+        self as f32 / 65535.0
+    }
+
+    fn from_unit(v: f32) -> Self {
+        (v * 65535.0).round() as u16
+    }
+}
+
+impl Texel for f16 {
+    fn to_acc(self) -> f32 {
+        self.to_f32()
+    }
+
+    fn from_acc(a: f32) -> Self {
+        f16::from_f32(a)
+    }
+
+    fn to_unit(self) -> f32 {
+        self.to_f32()
+    }
+
+    fn from_unit(v: f32) -> Self {
+        f16::from_f32(v)
+    }
+}
+
+fn load<T: Texel>(p: &[T]) -> [f32; 4] {
+    [
+        p[0].to_unit(),
+        p[1].to_unit(),
+        p[2].to_unit(),
+        p[3].to_unit(),
+    ]
+}
+
+fn store<T: Texel>(c: [f32; 4]) -> [T; 4] {
+    c.map(T::from_unit)
+}
+
 fn rows_needed(
     class: StepClass,
     y0: u32,
@@ -129,17 +253,58 @@ pub(crate) fn plan_is_bandable(source: ImageSpec, plan: &[PlanItem]) -> bool {
     })
 }
 
+/// The source rows render_band reads to produce output rows `y0..y1`.
+pub(crate) fn band_source_rows(
+    modifiers: &[Modifier],
+    img_w: u32,
+    img_h: u32,
+    y0: u32,
+    y1: u32,
+) -> Option<(u32, u32)> {
+    let plan = plan_modifiers(modifiers);
+    let specs = infer_specs(ImageSpec::new(img_w, img_h), &plan);
+    let (lo, hi) = source_rows_for_band(&plan, &specs, y0, y1)?;
+    Some((lo, hi.min(img_h).max(lo)))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render_band<T: Texel>(
+    modifiers: &[Modifier],
+    text_layers: &[Option<TextRaster>],
+    drawing_layers: &[Option<LayerView<'_>>],
+    pixels: &[T],
+    img_w: u32,
+    img_h: u32,
+    y0: u32,
+    y1: u32,
+) -> Vec<T> {
+    render_band_rows(
+        modifiers,
+        text_layers,
+        drawing_layers,
+        pixels,
+        0,
+        img_w,
+        img_h,
+        y0,
+        y1,
+    )
+}
+
+/// render_band over only some of the source: `rows` are the source's rows
+/// from `rows_y0` down, which must cover what band_source_rows asks for.
 #[allow(clippy::too_many_arguments)]
-pub(crate) fn render_band(
+pub(crate) fn render_band_rows<T: Texel>(
     modifiers: &[Modifier],
     text_layers: &[Option<TextRaster>],
     drawing_layers: &[Option<LayerView<'_>>],
-    pixels: &[u8],
+    rows: &[T],
+    rows_y0: u32,
     img_w: u32,
     img_h: u32,
     y0: u32,
     y1: u32,
-) -> Vec<u8> {
+) -> Vec<T> {
     let plan = plan_modifiers(modifiers);
     let specs = infer_specs(ImageSpec::new(img_w, img_h), &plan);
     let out_spec = specs
@@ -149,20 +314,20 @@ pub(crate) fn render_band(
 
     let Some((src_lo, src_hi)) = source_rows_for_band(&plan, &specs, y0, y1) else {
         debug_assert!(false, "render_band called on a chain that cannot be banded");
-        return vec![0u8; row_bytes * (y1 - y0) as usize];
+        return vec![T::default(); row_bytes * (y1 - y0) as usize];
     };
     let src_hi = src_hi.min(img_h).max(src_lo);
     let band_h = src_hi - src_lo;
     if band_h == 0 {
-        return vec![0u8; row_bytes * (y1 - y0) as usize];
+        return vec![T::default(); row_bytes * (y1 - y0) as usize];
     }
 
     let stride = img_w as usize * 4;
-    let start = src_lo as usize * stride;
-    let end = (src_hi as usize * stride).min(pixels.len());
-    let mut cur = vec![0u8; band_h as usize * stride];
+    let start = src_lo.saturating_sub(rows_y0) as usize * stride;
+    let end = (src_hi.saturating_sub(rows_y0) as usize * stride).min(rows.len());
+    let mut cur = vec![T::default(); band_h as usize * stride];
     if start < end {
-        cur[..end - start].copy_from_slice(&pixels[start..end]);
+        cur[..end - start].copy_from_slice(&rows[start..end]);
     }
 
     let mut cur_h = band_h;
@@ -204,7 +369,7 @@ pub(crate) fn render_band(
         }
     }
 
-    let mut out = vec![0u8; row_bytes * (y1 - y0) as usize];
+    let mut out = vec![T::default(); row_bytes * (y1 - y0) as usize];
     for (i, dst) in out.chunks_mut(row_bytes).enumerate() {
         let want = y0 + i as u32;
         if want < y_off {
@@ -229,16 +394,16 @@ fn raster_to_doc(src: ImageSpec, stage_in: ImageSpec) -> (f32, f32) {
     )
 }
 
-pub(crate) fn render_full(
+pub(crate) fn render_full<T: Texel>(
     modifiers: &[Modifier],
     text_layers: &[Option<TextRaster>],
     drawing_layers: &[Option<LayerView<'_>>],
-    pixels: &[u8],
+    pixels: &[T],
     img_w: u32,
     img_h: u32,
-) -> Vec<u8> {
+) -> Vec<T> {
     let n = img_w as usize * img_h as usize * 4;
-    let mut cur = vec![0u8; n];
+    let mut cur = vec![T::default(); n];
     let copy = n.min(pixels.len());
     cur[..copy].copy_from_slice(&pixels[..copy]);
 
@@ -280,7 +445,7 @@ pub(crate) fn render_full(
                     }
                 }
                 ModifierKind::PixelSort(ps) => {
-                    cur = crate::modifiers::pixel_sort::pixel_sort_cpu(
+                    cur = crate::modifiers::pixel_sort::pixel_sort_texels(
                         &cur,
                         w,
                         h,
@@ -321,11 +486,11 @@ pub(crate) fn render_full(
 }
 
 #[allow(clippy::too_many_arguments)]
-fn apply_stage_banded(
+fn apply_stage_banded<T: Texel>(
     item: &PlanItem,
     spec: &crate::modifiers::plan::StageSpec,
     class: StepClass,
-    mut cur: Vec<u8>,
+    mut cur: Vec<T>,
     w: u32,
     h: u32,
     y_off: u32,
@@ -333,7 +498,7 @@ fn apply_stage_banded(
     text_layers: &[Option<TextRaster>],
     drawing_layers: &[Option<LayerView<'_>>],
     to_doc: (f32, f32),
-) -> Vec<u8> {
+) -> Vec<T> {
     let _ = class;
     let (wu, hu) = (w as usize, h as usize);
     match item {
@@ -359,7 +524,7 @@ fn apply_stage_banded(
                 }
             }
             ModifierKind::PixelSort(ps) => {
-                cur = crate::modifiers::pixel_sort::pixel_sort_cpu(
+                cur = crate::modifiers::pixel_sort::pixel_sort_texels(
                     &cur,
                     wu,
                     hu,
@@ -397,8 +562,8 @@ fn apply_stage_banded(
     cur
 }
 
-fn apply_pointwise_band(
-    buf: &mut [u8],
+fn apply_pointwise_band<T: Texel>(
+    buf: &mut [T],
     img_w: u32,
     full_h: u32,
     y_off: u32,
@@ -410,34 +575,36 @@ fn apply_pointwise_band(
         for x in 0..w {
             let o = x * 4;
             let u = (x as f32 + 0.5) / img_w as f32;
-            let mut c = pixel_to_f32(&row[o..o + 4]);
+            let mut c = load(&row[o..o + 4]);
             for m in segment {
                 c = m.kind.apply_cpu(img_w, full_h, [u, v], c);
             }
-            row[o..o + 4].copy_from_slice(&f32_to_pixel(c.map(|v| v.clamp(0.0, 1.0))));
+            c[3] = c[3].clamp(0.0, 1.0);
+            row[o..o + 4].copy_from_slice(&store(c));
         }
     });
 }
 
-fn apply_pointwise_segment(buf: &mut [u8], img_w: u32, img_h: u32, segment: &[&Modifier]) {
+fn apply_pointwise_segment<T: Texel>(buf: &mut [T], img_w: u32, img_h: u32, segment: &[&Modifier]) {
     let w = img_w as usize;
     buf.par_chunks_mut(w * 4).enumerate().for_each(|(y, row)| {
         let v = (y as f32 + 0.5) / img_h as f32;
         for x in 0..w {
             let o = x * 4;
             let u = (x as f32 + 0.5) / img_w as f32;
-            let mut c = pixel_to_f32(&row[o..o + 4]);
+            let mut c = load(&row[o..o + 4]);
             for m in segment {
                 c = m.kind.apply_cpu(img_w, img_h, [u, v], c);
             }
-            row[o..o + 4].copy_from_slice(&f32_to_pixel(c.map(|v| v.clamp(0.0, 1.0))));
+            c[3] = c[3].clamp(0.0, 1.0);
+            row[o..o + 4].copy_from_slice(&store(c));
         }
     });
 }
 
 pub(crate) const MAX_DIRECT_RADIUS: f32 = 64.0;
 
-fn blur_full(buf: &mut [u8], w: usize, h: usize, radius: f32) {
+fn blur_full<T: Texel>(buf: &mut [T], w: usize, h: usize, radius: f32) {
     if radius <= 0.0 || w == 0 || h == 0 {
         return;
     }
@@ -472,7 +639,7 @@ fn blur_full(buf: &mut [u8], w: usize, h: usize, radius: f32) {
     buf.copy_from_slice(&up);
 }
 
-fn blur_direct(buf: &mut [u8], w: usize, h: usize, radius: f32) {
+fn blur_direct<T: Texel>(buf: &mut [T], w: usize, h: usize, radius: f32) {
     let r = radius.ceil() as i32;
     if r <= 0 || w == 0 || h == 0 {
         return;
@@ -486,7 +653,7 @@ fn blur_direct(buf: &mut [u8], w: usize, h: usize, radius: f32) {
 
     let stride_h = w * 4;
     buf.par_chunks_mut(stride_h).for_each_init(
-        || (vec![0u8; stride_h], vec![0.0f32; stride_h]),
+        || (vec![T::default(); stride_h], vec![0.0f32; stride_h]),
         |(row_copy, acc), row| {
             row_copy.copy_from_slice(row);
             acc.iter_mut().for_each(|v| *v = 0.0);
@@ -499,7 +666,7 @@ fn blur_direct(buf: &mut [u8], w: usize, h: usize, radius: f32) {
                 for x in 0..lo.min(w) {
                     let sx = (x as isize + off).clamp(0, w as isize - 1) as usize;
                     for c in 0..4 {
-                        acc[x * 4 + c] += row_copy[sx * 4 + c] as f32 * k;
+                        acc[x * 4 + c] += row_copy[sx * 4 + c].to_acc() * k;
                     }
                 }
                 if hi > lo {
@@ -507,19 +674,19 @@ fn blur_direct(buf: &mut [u8], w: usize, h: usize, radius: f32) {
                     let dst = &mut acc[lo * 4..hi * 4];
                     let src = &row_copy[src_start..src_start + (hi - lo) * 4];
                     for (a, &p) in dst.iter_mut().zip(src.iter()) {
-                        *a += p as f32 * k;
+                        *a += p.to_acc() * k;
                     }
                 }
                 for x in hi.max(lo)..w {
                     let sx = (x as isize + off).clamp(0, w as isize - 1) as usize;
                     for c in 0..4 {
-                        acc[x * 4 + c] += row_copy[sx * 4 + c] as f32 * k;
+                        acc[x * 4 + c] += row_copy[sx * 4 + c].to_acc() * k;
                     }
                 }
             }
 
             for (o, &a) in row.iter_mut().zip(acc.iter()) {
-                *o = (a + 0.5).clamp(0.0, 255.0) as u8;
+                *o = T::from_acc(a);
             }
         },
     );
@@ -534,20 +701,27 @@ fn blur_direct(buf: &mut [u8], w: usize, h: usize, radius: f32) {
                 let sy = (y as i32 - r + ki as i32).clamp(0, h as i32 - 1) as usize;
                 let src_row = &scratch[sy * stride..sy * stride + stride];
                 for (a, &p) in acc_row.iter_mut().zip(src_row.iter()) {
-                    *a += p as f32 * k;
+                    *a += p.to_acc() * k;
                 }
             }
             for (o, &a) in out_row.iter_mut().zip(acc_row.iter()) {
-                *o = (a + 0.5).clamp(0.0, 255.0) as u8;
+                *o = T::from_acc(a);
             }
         },
     );
 }
 
-fn copy_rect(src: &[u8], src_w: usize, x: usize, y: usize, out_w: usize, rows: usize) -> Vec<u8> {
+fn copy_rect<T: Texel>(
+    src: &[T],
+    src_w: usize,
+    x: usize,
+    y: usize,
+    out_w: usize,
+    rows: usize,
+) -> Vec<T> {
     let row = out_w * 4;
     let stride = src_w * 4;
-    let mut dst = vec![0u8; row * rows];
+    let mut dst = vec![T::default(); row * rows];
     for r in 0..rows {
         let s = (y + r) * stride + x * 4;
         let Some(chunk) = src.get(s..s + row) else {
@@ -558,10 +732,10 @@ fn copy_rect(src: &[u8], src_w: usize, x: usize, y: usize, out_w: usize, rows: u
     dst
 }
 
-fn chromatic_aberration_full(src: &[u8], img_w: u32, img_h: u32, amount: f32) -> Vec<u8> {
+fn chromatic_aberration_full<T: Texel>(src: &[T], img_w: u32, img_h: u32, amount: f32) -> Vec<T> {
     let w = img_w as usize;
     let scale = amount / img_w as f32;
-    let mut out = vec![0u8; src.len()];
+    let mut out = vec![T::default(); src.len()];
     out.par_chunks_mut(w * 4).enumerate().for_each(|(y, row)| {
         let v = (y as f32 + 0.5) / img_h as f32;
         for x in 0..w {
@@ -590,19 +764,25 @@ fn chromatic_aberration_full(src: &[u8], img_w: u32, img_h: u32, amount: f32) ->
                 b_uv[1] * img_h as f32,
             );
             let o = x * 4;
-            row[o..o + 4].copy_from_slice(&f32_to_pixel([cr[0], cg[1], cb[2], cg[3]]));
+            row[o..o + 4].copy_from_slice(&store([cr[0], cg[1], cb[2], cg[3]]));
         }
     });
     out
 }
 
-fn motion_blur_full(src: &[u8], img_w: u32, img_h: u32, angle: f32, distance: f32) -> Vec<u8> {
+fn motion_blur_full<T: Texel>(
+    src: &[T],
+    img_w: u32,
+    img_h: u32,
+    angle: f32,
+    distance: f32,
+) -> Vec<T> {
     let w = img_w as usize;
     let rad = angle.to_radians();
     let du = rad.cos() * distance;
     let dv = rad.sin() * distance;
     let n = motion_blur_samples(distance) as i32;
-    let mut out = vec![0u8; src.len()];
+    let mut out = vec![T::default(); src.len()];
     out.par_chunks_mut(w * 4).enumerate().for_each(|(y, row)| {
         let cy = y as f32 + 0.5;
         for x in 0..w {
@@ -618,7 +798,7 @@ fn motion_blur_full(src: &[u8], img_w: u32, img_h: u32, angle: f32, distance: f3
             }
             let inv = 1.0 / n as f32;
             let o = x * 4;
-            row[o..o + 4].copy_from_slice(&f32_to_pixel([
+            row[o..o + 4].copy_from_slice(&store([
                 acc[0] * inv,
                 acc[1] * inv,
                 acc[2] * inv,
@@ -629,7 +809,7 @@ fn motion_blur_full(src: &[u8], img_w: u32, img_h: u32, angle: f32, distance: f3
     out
 }
 
-fn sample_bilinear(pixels: &[u8], w: u32, h: u32, fx: f32, fy: f32) -> [f32; 4] {
+fn sample_bilinear<T: Texel>(pixels: &[T], w: u32, h: u32, fx: f32, fy: f32) -> [f32; 4] {
     let px = fx - 0.5;
     let py = fy - 0.5;
     let x0 = px.floor();
@@ -641,12 +821,7 @@ fn sample_bilinear(pixels: &[u8], w: u32, h: u32, fx: f32, fy: f32) -> [f32; 4]
         let cy = (y.max(0.0) as u32).min(h - 1);
         let base = (cy as usize * w as usize + cx as usize) * 4;
         match pixels.get(base..base + 4) {
-            Some(p) => [
-                p[0] as f32 / 255.0,
-                p[1] as f32 / 255.0,
-                p[2] as f32 / 255.0,
-                p[3] as f32 / 255.0,
-            ],
+            Some(p) => load(p),
             None => [0.0; 4],
         }
     };
@@ -663,22 +838,22 @@ fn sample_bilinear(pixels: &[u8], w: u32, h: u32, fx: f32, fy: f32) -> [f32; 4]
     o
 }
 
-fn drawing_full(buf: &mut [u8], img_w: u32, raster: &LayerView<'_>, to_doc: (f32, f32)) {
+fn drawing_full<T: Texel>(buf: &mut [T], img_w: u32, raster: &LayerView<'_>, to_doc: (f32, f32)) {
     let w = img_w as usize;
     buf.par_chunks_mut(w * 4).enumerate().for_each(|(y, row)| {
         let fy = (y as f32 + 0.5) * to_doc.1;
         for x in 0..w {
             if let Some(src) = raster.sample((x as f32 + 0.5) * to_doc.0, fy) {
                 let o = x * 4;
-                let dst = pixel_to_f32(&row[o..o + 4]);
-                row[o..o + 4].copy_from_slice(&f32_to_pixel(blend_over(dst, src)));
+                let dst = load(&row[o..o + 4]);
+                row[o..o + 4].copy_from_slice(&store(blend_over(dst, src)));
             }
         }
     });
 }
 
-fn drawing_band(
-    buf: &mut [u8],
+fn drawing_band<T: Texel>(
+    buf: &mut [T],
     img_w: u32,
     _h: u32,
     y_off: u32,
@@ -691,15 +866,15 @@ fn drawing_band(
         for x in 0..w {
             if let Some(src) = raster.sample((x as f32 + 0.5) * to_doc.0, fy) {
                 let o = x * 4;
-                let dst = pixel_to_f32(&row[o..o + 4]);
-                row[o..o + 4].copy_from_slice(&f32_to_pixel(blend_over(dst, src)));
+                let dst = load(&row[o..o + 4]);
+                row[o..o + 4].copy_from_slice(&store(blend_over(dst, src)));
             }
         }
     });
 }
 
-fn text_band(
-    buf: &mut [u8],
+fn text_band<T: Texel>(
+    buf: &mut [T],
     img_w: u32,
     _h: u32,
     y_off: u32,
@@ -712,14 +887,20 @@ fn text_band(
         for x in 0..w {
             if let Some(src) = raster.sample((x as f32 + 0.5) * to_doc.0, fy) {
                 let o = x * 4;
-                let dst = pixel_to_f32(&row[o..o + 4]);
-                row[o..o + 4].copy_from_slice(&f32_to_pixel(blend_over(dst, src)));
+                let dst = load(&row[o..o + 4]);
+                row[o..o + 4].copy_from_slice(&store(blend_over(dst, src)));
             }
         }
     });
 }
 
-fn text_full(buf: &mut [u8], img_w: u32, img_h: u32, raster: &TextRaster, to_doc: (f32, f32)) {
+fn text_full<T: Texel>(
+    buf: &mut [T],
+    img_w: u32,
+    img_h: u32,
+    raster: &TextRaster,
+    to_doc: (f32, f32),
+) {
     let w = img_w as usize;
     let _ = img_h;
     buf.par_chunks_mut(w * 4).enumerate().for_each(|(y, row)| {
@@ -727,8 +908,8 @@ fn text_full(buf: &mut [u8], img_w: u32, img_h: u32, raster: &TextRaster, to_doc
         for x in 0..w {
             if let Some(src) = raster.sample((x as f32 + 0.5) * to_doc.0, fy) {
                 let o = x * 4;
-                let dst = pixel_to_f32(&row[o..o + 4]);
-                row[o..o + 4].copy_from_slice(&f32_to_pixel(blend_over(dst, src)));
+                let dst = load(&row[o..o + 4]);
+                row[o..o + 4].copy_from_slice(&store(blend_over(dst, src)));
             }
         }
     });
@@ -780,14 +961,14 @@ fn lanczos3(x: f32) -> f32 {
     (px.sin() / px) * ((px / A).sin() / (px / A))
 }
 
-pub(crate) fn resample(
-    src: &[u8],
+pub(crate) fn resample<T: Texel>(
+    src: &[T],
     src_w: u32,
     src_h: u32,
     dst_w: u32,
     dst_h: u32,
     filter: ResizeFilter,
-) -> Vec<u8> {
+) -> Vec<T> {
     if (src_w, src_h) == (dst_w, dst_h) {
         return src.to_vec();
     }
@@ -847,60 +1028,58 @@ pub(crate) fn resample(
             .collect()
     };
 
-    let one_axis =
-        |input: &[u8], in_w: u32, in_h: u32, out_len: u32, horizontal: bool| -> Vec<u8> {
-            let (out_w, out_h) = if horizontal {
-                (out_len, in_h)
-            } else {
-                (in_w, out_len)
-            };
-            let src_len = if horizontal { in_w } else { in_h };
-            let taps = build_taps(out_len, src_len);
-            let in_stride = in_w as usize * 4;
-
-            let mut out = vec![0u8; out_w as usize * out_h as usize * 4];
-            if horizontal {
-                out.par_chunks_mut(out_w as usize * 4)
-                    .enumerate()
-                    .for_each(|(row, out_row)| {
-                        let in_row = &input[row * in_stride..(row + 1) * in_stride];
-                        for (col, tap) in taps.iter().enumerate() {
-                            let mut acc = [0.0f32; 4];
-                            for (i, &wt) in tap.weights.iter().enumerate() {
-                                let base = (tap.start as usize + i).min(src_len as usize - 1) * 4;
-                                for c in 0..4 {
-                                    acc[c] += in_row[base + c] as f32 * wt;
-                                }
-                            }
+    let one_axis = |input: &[T], in_w: u32, in_h: u32, out_len: u32, horizontal: bool| -> Vec<T> {
+        let (out_w, out_h) = if horizontal {
+            (out_len, in_h)
+        } else {
+            (in_w, out_len)
+        };
+        let src_len = if horizontal { in_w } else { in_h };
+        let taps = build_taps(out_len, src_len);
+        let in_stride = in_w as usize * 4;
+
+        let mut out = vec![T::default(); out_w as usize * out_h as usize * 4];
+        if horizontal {
+            out.par_chunks_mut(out_w as usize * 4)
+                .enumerate()
+                .for_each(|(row, out_row)| {
+                    let in_row = &input[row * in_stride..(row + 1) * in_stride];
+                    for (col, tap) in taps.iter().enumerate() {
+                        let mut acc = [0.0f32; 4];
+                        for (i, &wt) in tap.weights.iter().enumerate() {
+                            let base = (tap.start as usize + i).min(src_len as usize - 1) * 4;
                             for c in 0..4 {
-                                out_row[col * 4 + c] =
-                                    (acc[c] / tap.norm).round().clamp(0.0, 255.0) as u8;
+                                acc[c] += in_row[base + c].to_acc() * wt;
                             }
                         }
-                    });
-            } else {
-                let row_floats = out_w as usize * 4;
-                out.par_chunks_mut(row_floats)
-                    .zip(taps.par_iter())
-                    .for_each_init(
-                        || vec![0.0f32; row_floats],
-                        |acc_row, (out_row, tap)| {
-                            acc_row.iter_mut().for_each(|v| *v = 0.0);
-                            for (i, &wt) in tap.weights.iter().enumerate() {
-                                let sy = (tap.start as usize + i).min(src_len as usize - 1);
-                                let in_row = &input[sy * in_stride..sy * in_stride + row_floats];
-                                for (a, &p) in acc_row.iter_mut().zip(in_row.iter()) {
-                                    *a += p as f32 * wt;
-                                }
-                            }
-                            for (o, &a) in out_row.iter_mut().zip(acc_row.iter()) {
-                                *o = (a / tap.norm).round().clamp(0.0, 255.0) as u8;
+                        for c in 0..4 {
+                            out_row[col * 4 + c] = T::from_acc(acc[c] / tap.norm);
+                        }
+                    }
+                });
+        } else {
+            let row_floats = out_w as usize * 4;
+            out.par_chunks_mut(row_floats)
+                .zip(taps.par_iter())
+                .for_each_init(
+                    || vec![0.0f32; row_floats],
+                    |acc_row, (out_row, tap)| {
+                        acc_row.iter_mut().for_each(|v| *v = 0.0);
+                        for (i, &wt) in tap.weights.iter().enumerate() {
+                            let sy = (tap.start as usize + i).min(src_len as usize - 1);
+                            let in_row = &input[sy * in_stride..sy * in_stride + row_floats];
+                            for (a, &p) in acc_row.iter_mut().zip(in_row.iter()) {
+                                *a += p.to_acc() * wt;
                             }
-                        },
-                    );
-            }
-            out
-        };
+                        }
+                        for (o, &a) in out_row.iter_mut().zip(acc_row.iter()) {
+                            *o = T::from_acc(a / tap.norm);
+                        }
+                    },
+                );
+        }
+        out
+    };
 
     let mid = one_axis(src, src_w, src_h, dst_w, true);
     one_axis(&mid, dst_w, src_h, dst_h, false)
@@ -914,14 +1093,14 @@ pub(crate) struct VBand {
     pub full_out_h: u32,
 }
 
-pub(crate) fn resample_band(
-    src: &[u8],
+pub(crate) fn resample_band<T: Texel>(
+    src: &[T],
     src_w: u32,
     src_h: u32,
     dst_w: u32,
     band: &VBand,
     filter: ResizeFilter,
-) -> Vec<u8> {
+) -> Vec<T> {
     let mid = if src_w == dst_w {
         src.to_vec()
     } else {
@@ -937,7 +1116,7 @@ pub(crate) fn resample_band(
     } * inv;
 
     let row_bytes = dst_w as usize * 4;
-    let mut out = vec![0u8; row_bytes * band.out_len as usize];
+    let mut out = vec![T::default(); row_bytes * band.out_len as usize];
     let in_rows = src_h as usize;
 
     out.par_chunks_mut(row_bytes)
@@ -972,13 +1151,13 @@ pub(crate) fn resample_band(
                 let local = (s as i64 - band.src_base as i64).clamp(0, in_rows as i64 - 1) as usize;
                 let in_row = &mid[local * row_bytes..local * row_bytes + row_bytes];
                 for (a, &p) in acc.iter_mut().zip(in_row.iter()) {
-                    *a += p as f32 * wt;
+                    *a += p.to_acc() * wt;
                 }
                 sum += wt;
             }
             let norm = if sum.abs() < 1e-6 { 1.0 } else { sum };
             for (o, &a) in out_row.iter_mut().zip(acc.iter()) {
-                *o = (a / norm).round().clamp(0.0, 255.0) as u8;
+                *o = T::from_acc(a / norm);
             }
         });
 
@@ -994,17 +1173,12 @@ pub(crate) fn f32_to_pixel(c: [f32; 4]) -> [u8; 4] {
     ]
 }
 
-pub(crate) fn sample_pixel(pixels: &[u8], w: u32, h: u32, u: f32, v: f32) -> [f32; 4] {
+pub(crate) fn sample_pixel<T: Texel>(pixels: &[T], w: u32, h: u32, u: f32, v: f32) -> [f32; 4] {
     let x = (u * w as f32).clamp(0.0, w as f32 - 1.0) as usize;
     let y = (v * h as f32).clamp(0.0, h as f32 - 1.0) as usize;
     let base = (y * w as usize + x) * 4;
     match pixels.get(base..base + 4) {
-        Some(p) => [
-            p[0] as f32 / 255.0,
-            p[1] as f32 / 255.0,
-            p[2] as f32 / 255.0,
-            p[3] as f32 / 255.0,
-        ],
+        Some(p) => load(p),
         None => [0.0; 4],
     }
 }
@@ -1216,6 +1390,44 @@ mod pointwise_tests {
     }
 }
 
+#[cfg(test)]
+mod texel_tests {
+    use super::*;
+    use crate::modifiers::kinds::Exposure;
+    use crate::modifiers::{Modifier, ModifierKind};
+
+    fn distinct(px: &[u8]) -> usize {
+        let mut seen = [false; 256];
+        px.chunks_exact(4).for_each(|p| seen[p[0] as usize] = true);
+        seen.iter().filter(|&&s| s).count()
+    }
+
+    #[test]
+    fn sixteen_bit_source_does_not_band_under_exposure() {
+        let w = 256;
+        let chain = vec![Modifier::new(ModifierKind::Exposure(Exposure {
+            exposure: 4.0,
+        }))];
+        let deep: Vec<u16> = (0..w)
+            .flat_map(|x| {
+                let v = (x * 16) as u16;
+                [v, v, v, 65535]
+            })
+            .collect();
+        let shallow: Vec<u8> = deep.iter().map(|&v| v.to_byte()).collect();
+
+        let from_deep = u16::quantize(render_full(&chain, &[], &[], &deep, w, 1));
+        let from_shallow = render_full(&chain, &[], &[], &shallow, w, 1);
+
+        assert!(distinct(&from_shallow) <= 17, "8 bits leave only 17 steps");
+        assert!(
+            distinct(&from_deep) > 200,
+            "16 bits should keep the gradient smooth, got {} levels",
+            distinct(&from_deep)
+        );
+    }
+}
+
 #[cfg(test)]
 mod scaled_blur_tests {
     use super::*;
@@ -7,6 +7,8 @@
 
 use rayon::prelude::*;
 
+use crate::modifiers::cpu::Texel;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SortAxis {
     Horizontal { reverse: bool },
@@ -116,12 +118,19 @@ pub fn key_cutoff(threshold: f32) -> u8 {
     (threshold * 255.0 + 0.5).clamp(0.0, 255.0) as u8
 }
 
-type Rgba = [u8; 4];
+type Rgba<T> = [T; 4];
+
+/// The key is always taken at 8 bits, whatever the buffer holds. That is the
+/// effect's definition (256 buckets, the GPU pass sorts the same way), and it
+/// only decides where a pixel goes; the pixel itself moves at full precision.
+fn key<T: Texel>(px: &Rgba<T>) -> u8 {
+    key_of(&px.map(T::to_byte))
+}
 
-fn counting_sort_run(run: &mut [Rgba], reverse: bool, sorted: &mut Vec<Rgba>) {
+fn counting_sort_run<T: Texel>(run: &mut [Rgba<T>], reverse: bool, sorted: &mut Vec<Rgba<T>>) {
     let mut count = [0u32; 256];
     for px in run.iter() {
-        count[key_of(px) as usize] += 1;
+        count[key(px) as usize] += 1;
     }
     let mut offset = [0u32; 256];
     let mut acc = 0u32;
@@ -132,9 +141,9 @@ fn counting_sort_run(run: &mut [Rgba], reverse: bool, sorted: &mut Vec<Rgba>) {
 
     let n = run.len() as u32;
     sorted.clear();
-    sorted.resize(run.len(), Rgba::default());
+    sorted.resize(run.len(), [T::default(); 4]);
     for px in run.iter() {
-        let k = key_of(px) as usize;
+        let k = key(px) as usize;
         let dst = offset[k];
         offset[k] += 1;
         let rank = if reverse { n - 1 - dst } else { dst };
@@ -143,24 +152,24 @@ fn counting_sort_run(run: &mut [Rgba], reverse: bool, sorted: &mut Vec<Rgba>) {
     run.copy_from_slice(sorted);
 }
 
-fn sort_row(row: &mut [Rgba], cutoff: u8, reverse: bool, sorted: &mut Vec<Rgba>) {
+fn sort_row<T: Texel>(row: &mut [Rgba<T>], cutoff: u8, reverse: bool, sorted: &mut Vec<Rgba<T>>) {
     let n = row.len();
     let mut i = 0;
     while i < n {
-        if key_of(&row[i]) <= cutoff {
+        if key(&row[i]) <= cutoff {
             i += 1;
             continue;
         }
         let start = i;
-        while i < n && key_of(&row[i]) > cutoff {
+        while i < n && key(&row[i]) > cutoff {
             i += 1;
         }
         counting_sort_run(&mut row[start..i], reverse, sorted);
     }
 }
 
-fn transpose(src: &[Rgba], width: usize, height: usize) -> Vec<Rgba> {
-    let mut out = vec![Rgba::default(); src.len()];
+fn transpose<T: Texel>(src: &[Rgba<T>], width: usize, height: usize) -> Vec<Rgba<T>> {
+    let mut out = vec![[T::default(); 4]; src.len()];
     out.par_chunks_mut(height).enumerate().for_each(|(x, col)| {
         for (y, dst) in col.iter_mut().enumerate() {
             *dst = src[y * width + x];
@@ -176,6 +185,16 @@ pub fn pixel_sort_cpu(
     threshold: f32,
     angle: f32,
 ) -> Vec<u8> {
+    pixel_sort_texels(src, width, height, threshold, angle)
+}
+
+pub(crate) fn pixel_sort_texels<T: Texel>(
+    src: &[T],
+    width: usize,
+    height: usize,
+    threshold: f32,
+    angle: f32,
+) -> Vec<T> {
     let mut out = src.to_vec();
     if width == 0 || height == 0 {
         return out;
@@ -183,14 +202,14 @@ pub fn pixel_sort_cpu(
     let cutoff = key_cutoff(threshold);
     match SortMode::from_angle(angle) {
         SortMode::Cardinal(SortAxis::Horizontal { reverse }) => {
-            let px: &mut [Rgba] = bytemuck::cast_slice_mut(&mut out);
+            let px: &mut [Rgba<T>] = bytemuck::cast_slice_mut(&mut out);
             px.par_chunks_mut(width)
                 .for_each_init(Vec::new, |scratch, row| {
                     sort_row(row, cutoff, reverse, scratch);
                 });
         }
         SortMode::Cardinal(SortAxis::Vertical { reverse }) => {
-            let px: &[Rgba] = bytemuck::cast_slice(&out);
+            let px: &[Rgba<T>] = bytemuck::cast_slice(&out);
             let mut t = transpose(px, width, height);
             t.par_chunks_mut(height)
                 .for_each_init(Vec::new, |scratch, col| {
@@ -200,9 +219,9 @@ pub fn pixel_sort_cpu(
             out.copy_from_slice(bytemuck::cast_slice(&back));
         }
         SortMode::Diagonal { dx, dy } => {
-            let px: &mut [Rgba] = bytemuck::cast_slice_mut(&mut out);
-            let mut gather: Vec<Rgba> = Vec::new();
-            let mut scratch: Vec<Rgba> = Vec::new();
+            let px: &mut [Rgba<T>] = bytemuck::cast_slice_mut(&mut out);
+            let mut gather: Vec<Rgba<T>> = Vec::new();
+            let mut scratch: Vec<Rgba<T>> = Vec::new();
             for_each_diagonal_line(width, height, dx, dy, |idx| {
                 gather.clear();
                 gather.extend(idx.iter().map(|&i| px[i]));
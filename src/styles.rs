@@ -15,6 +15,7 @@ pub const RADIUS: f32 = 6.0;
 pub const INFO_PANEL_WIDTH: f32 = 220.0;
 pub const RULE_HEIGHT: f32 = 2.0;
 pub const EDIT_PANEL_WIDTH: f32 = 240.0;
+pub const FOLDER_TREE_WIDTH: f32 = 200.0;
 pub const TOAST_WIDTH: f32 = 300.0;
 pub const PREF_SIDEBAR_WIDTH: f32 = 160.0;
 pub const PREF_CONTENT_MAX_WIDTH: f32 = 600.0;
@@ -200,6 +201,34 @@ pub fn pref_nav_button_style(active: bool) -> impl Fn(&Theme, button::Status) ->
     }
 }
 
+pub fn grid_cell_style(selected: bool) -> impl Fn(&Theme, button::Status) -> button::Style {
+    move |theme: &Theme, status: button::Status| {
+        let palette = theme.extended_palette();
+        let background = match status {
+            button::Status::Hovered | button::Status::Pressed => {
+                Some(Background::Color(palette.background.weak.color))
+            }
+            _ if selected => Some(Background::Color(palette.background.weak.color)),
+            _ => None,
+        };
+        let border = if selected {
+            Border {
+                color: palette.primary.base.color,
+                width: 2.0,
+                radius: radius().into(),
+            }
+        } else {
+            iced::border::rounded(radius())
+        };
+        button::Style {
+            background,
+            border,
+            text_color: palette.background.base.text,
+            ..Default::default()
+        }
+    }
+}
+
 pub fn pref_section_rule_style(theme: &Theme) -> rule::Style {
     rule::Style {
         color: theme.extended_palette().primary.base.color,
@@ -7,30 +7,93 @@
 //! previous load finishes, so a result whose generation no longer matches is
 //! dropped rather than displayed over the newer selection.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::SinkExt;
 use iced::window::{self, Level, Mode};
 use image::ImageError;
 
-use crate::app::{HistogramResult, Message};
-use crate::export::{ExportData, do_export, render_still_rgba};
+use crate::app::{GridMsg, HistogramResult, Message};
+use crate::export::{
+    ExportData, ExportFrame, ExportSource, do_export, do_export_pages, render_still_rgba,
+};
 use crate::{
     clipboard::{self, ClipboardImage},
-    gallery::SUPPORTED,
-    modifiers::Modifier,
+    components::notifications::Notification,
+    gallery::{ListRequest, SUPPORTED},
+    modifiers::kinds::{Resize, ResizeFilter, ResizeMode},
+    modifiers::{Modifier, ModifierKind},
+    thumbnails,
+    wgpu::media::archive::ARCHIVE_EXTENSIONS,
+    wgpu::media::exr::{ExrDocument, ExrView},
+    wgpu::media::fits::{FitsSelection, FitsView},
     wgpu::media::image_data::{ImageData, ImageId, MediaData},
+    wgpu::media::layers::{LayerDocument, LayerView},
+    wgpu::media::pages::PageSource,
+    wgpu::media::raw::{RawDocument, RawSettings},
+    wgpu::media::sequence,
+    wgpu::media::svg::{DetailRequest, SvgDocument},
+    wgpu::media::texture::{TextureDocument, TextureView},
     wgpu::view_program::compute_subsampled_histogram,
 };
 
-pub fn load_media(path: PathBuf, generation: u64) -> iced::Task<Message> {
-    iced::Task::future(async move {
+/// Loads `path`, sending a preview first when the format has one and it
+/// arrives before the full decode. With `preview_only`, a RAW that carries a
+/// preview loads as just that.
+pub fn load_media(path: PathBuf, generation: u64, preview_only: bool) -> iced::Task<Message> {
+    let (mut tx, rx) = futures::channel::mpsc::channel(2);
+
+    tokio::spawn(async move {
         let filename = path
             .file_name()
             .map(|n| n.to_string_lossy().into_owned())
             .unwrap_or_default();
-        match tokio::task::spawn_blocking(move || ImageData::load_media(&path)).await {
+        if preview_only {
+            let embedded_path = path.clone();
+            let embedded = tokio::task::spawn_blocking(move || {
+                ImageData::load_embedded_preview(&embedded_path)
+            })
+            .await;
+            if let Ok(Some(preview)) = embedded {
+                let media = MediaData::Image(Box::new(preview));
+                let _ = tx.send(Message::MediaLoaded(generation, media)).await;
+                return;
+            }
+        }
+        let preview_path = path.clone();
+        let full = tokio::task::spawn_blocking(move || ImageData::load_media(&path));
+        let preview =
+            tokio::task::spawn_blocking(move || ImageData::load_preview(&preview_path)).await;
+        if let Ok(Some(preview)) = preview
+            && !full.is_finished()
+        {
+            let _ = tx
+                .send(Message::MediaPreview(generation, Box::new(preview)))
+                .await;
+        }
+        let msg = match full.await {
+            Ok(Ok(media)) => Message::MediaLoaded(generation, media),
+            Ok(Err(e)) => Message::MediaFailed(generation, friendly_error(&e, &filename)),
+            Err(_) => Message::MediaFailed(generation, "load thread panicked".to_string()),
+        };
+        let _ = tx.send(msg).await;
+    });
+
+    iced::Task::stream(rx)
+}
+
+/// Opens `paths` as a sequence at `fps`. Only the first frame decodes here;
+/// the animation fetches the rest as it plays.
+pub fn load_sequence(paths: Vec<PathBuf>, fps: u32, generation: u64) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let filename = paths
+            .first()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match tokio::task::spawn_blocking(move || sequence::load(paths, fps)).await {
             Ok(Ok(media)) => Message::MediaLoaded(generation, media),
             Ok(Err(e)) => Message::MediaFailed(generation, friendly_error(&e, &filename)),
             Err(_) => Message::MediaFailed(generation, "load thread panicked".to_string()),
@@ -38,6 +101,143 @@ pub fn load_media(path: PathBuf, generation: u64) -> iced::Task<Message> {
     })
 }
 
+/// Generates the grid thumbnail for `path`.
+pub fn load_thumbnail(path: PathBuf) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let source = path.clone();
+        let thumbnail = tokio::task::spawn_blocking(move || thumbnails::generate(&source))
+            .await
+            .ok()
+            .flatten();
+        GridMsg::ThumbnailReady(path, thumbnail).into()
+    })
+}
+
+/// Reads a gallery's folder, which for a big tree can take a while.
+pub fn list_folder(request: ListRequest) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        match tokio::task::spawn_blocking(move || request.run()).await {
+            Ok(listing) => Message::FolderListed(listing),
+            Err(_) => Message::Notify(Notification::error("Could not list the folder")),
+        }
+    })
+}
+
+/// Deletes the thumbnails on disk and reports how many went.
+pub fn clear_thumbnail_cache() -> iced::Task<Message> {
+    iced::Task::future(async {
+        let result = tokio::task::spawn_blocking(thumbnails::disk::clear)
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        Message::Notify(match result {
+            Ok(n) => Notification::info(format!("Cleared {n} cached thumbnails")),
+            Err(e) => Notification::error(format!("Could not clear thumbnails: {e}")),
+        })
+    })
+}
+
+pub fn render_exr(
+    document: Arc<ExrDocument>,
+    view: ExrView,
+    generation: u64,
+) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let result = tokio::task::spawn_blocking(move || document.render(view))
+            .await
+            .map(Box::new)
+            .map_err(|_| "render thread panicked".to_string());
+        Message::ImageRendered(generation, result)
+    })
+}
+
+pub fn render_fits(
+    selection: FitsSelection,
+    view: FitsView,
+    generation: u64,
+) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let result = match tokio::task::spawn_blocking(move || selection.render(view)).await {
+            Ok(Ok(data)) => Ok(Box::new(data)),
+            Ok(Err(e)) => Err(format!("FITS HDU {}: {e}", view.hdu)),
+            Err(_) => Err("render thread panicked".to_string()),
+        };
+        Message::ImageRendered(generation, result)
+    })
+}
+
+pub fn render_layers(
+    document: Arc<LayerDocument>,
+    view: LayerView,
+    generation: u64,
+) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let result = tokio::task::spawn_blocking(move || document.render(view))
+            .await
+            .map(Box::new)
+            .map_err(|_| "render thread panicked".to_string());
+        Message::ImageRendered(generation, result)
+    })
+}
+
+pub fn render_texture(
+    document: Arc<TextureDocument>,
+    view: TextureView,
+    generation: u64,
+) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let result = match tokio::task::spawn_blocking(move || document.render(view)).await {
+            Ok(Ok(data)) => Ok(Box::new(data)),
+            Ok(Err(e)) => Err(format!("Mip level {}: {e}", view.level)),
+            Err(_) => Err("texture decoder panicked".to_string()),
+        };
+        Message::ImageRendered(generation, result)
+    })
+}
+
+pub fn render_raw(
+    document: Arc<RawDocument>,
+    settings: RawSettings,
+    generation: u64,
+) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let result = match tokio::task::spawn_blocking(move || document.render(settings)).await {
+            Ok(Ok(data)) => Ok(Box::new(data)),
+            Ok(Err(e)) => Err(format!("RAW develop: {e}")),
+            Err(_) => Err("RAW develop panicked".to_string()),
+        };
+        Message::ImageRendered(generation, result)
+    })
+}
+
+pub fn rasterize_svg(document: Arc<SvgDocument>, request: DetailRequest) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let detail = tokio::task::spawn_blocking(move || document.render_detail(request)).await;
+        Message::SvgDetailRendered(detail.ok().and_then(Result::ok).map(Arc::new))
+    })
+}
+
+pub fn load_page(source: Arc<PageSource>, index: usize, generation: u64) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let result = match tokio::task::spawn_blocking(move || source.load(index)).await {
+            Ok(Ok(data)) => Ok(Box::new(data)),
+            Ok(Err(e)) => Err(format!("Page {}: {e}", index + 1)),
+            Err(_) => Err("page decoder panicked".to_string()),
+        };
+        Message::ImageRendered(generation, result)
+    })
+}
+
+pub fn load_sheet(source: Arc<PageSource>, index: usize, generation: u64) -> iced::Task<Message> {
+    iced::Task::future(async move {
+        let result = match tokio::task::spawn_blocking(move || source.load_sheet(index)).await {
+            Ok(Ok(data)) => Ok(Box::new(data)),
+            Ok(Err(e)) => Err(format!("All sizes: {e}")),
+            Err(_) => Err("page decoder panicked".to_string()),
+        };
+        Message::ImageRendered(generation, result)
+    })
+}
+
 pub fn load_from_clipboard() -> iced::Task<Message> {
     iced::Task::future(async move {
         match tokio::task::spawn_blocking(clipboard::read).await {
@@ -52,8 +252,14 @@ pub fn load_from_clipboard() -> iced::Task<Message> {
 
 pub fn select_media() -> iced::Task<Message> {
     iced::Task::future(async {
+        let media: Vec<&str> = SUPPORTED
+            .iter()
+            .chain(ARCHIVE_EXTENSIONS)
+            .copied()
+            .collect();
         let handle = rfd::AsyncFileDialog::new()
-            .add_filter("Media", SUPPORTED)
+            .add_filter("Media", &media)
+            .add_filter("All files", &["*"])
             .pick_file()
             .await;
         match handle {
@@ -64,30 +270,139 @@ pub fn select_media() -> iced::Task<Message> {
 }
 
 pub fn export_image(data: ExportData, suggested_name: String) -> iced::Task<Message> {
-    let (mut tx, rx) = futures::channel::mpsc::channel(64);
+    if let Some(refusal) = data.stream_refusal() {
+        return iced::Task::done(Message::Notify(Notification::error(refusal)));
+    }
+    let mut dialog = rfd::AsyncFileDialog::new();
+    if data.is_streamed() {
+        dialog = dialog.add_filter("PNG Image", &["png"]);
+    } else if data.is_video() {
+        dialog = dialog
+            .add_filter("MP4 Video", &["mp4"])
+            .add_filter("Matroska Video", &["mkv"])
+            .add_filter("QuickTime Video", &["mov"]);
+    } else {
+        if data.is_animated() {
+            dialog = dialog
+                .add_filter("GIF Animation", &["gif"])
+                .add_filter("Animated PNG", &["apng"]);
+        }
+        dialog = dialog
+            .add_filter("PNG Image", &["png"])
+            .add_filter("JPEG Image", &["jpg", "jpeg"])
+            .add_filter("WebP Image", &["webp"]);
+        if !data.is_animated() {
+            dialog = dialog.add_filter("Windows Icon", &["ico"]);
+        }
+    }
+    run_export(
+        dialog.set_file_name(&suggested_name),
+        move |path, progress| do_export(data, path, progress),
+    )
+}
 
-    let animated = data.is_animated();
-    let video = data.is_video();
+/// Writes every page of a multi-page file into one TIFF, each with the edits
+/// of the page on screen.
+pub fn export_pages(
+    data: ExportData,
+    source: Arc<PageSource>,
+    suggested_name: String,
+) -> iced::Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .add_filter("TIFF Image", &["tiff", "tif"])
+        .set_file_name(&suggested_name);
+    run_export(dialog, move |path, progress| {
+        do_export_pages(data, &source, path, progress)
+    })
+}
 
-    tokio::spawn(async move {
-        let mut dialog = rfd::AsyncFileDialog::new();
-        if video {
-            dialog = dialog
-                .add_filter("MP4 Video", &["mp4"])
-                .add_filter("Matroska Video", &["mkv"])
-                .add_filter("QuickTime Video", &["mov"]);
+/// Exports what `view` composites, such as one layer soloed, with the edits
+/// of the image on screen.
+pub fn export_layer(
+    data: ExportData,
+    document: Arc<LayerDocument>,
+    view: LayerView,
+    suggested_name: String,
+) -> iced::Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .add_filter("PNG Image", &["png"])
+        .add_filter("WebP Image", &["webp"])
+        .set_file_name(&suggested_name);
+    run_export(dialog, move |path, progress| {
+        let image = document.render(view);
+        let data = ExportData {
+            source: ExportSource::Frames {
+                frames: vec![ExportFrame {
+                    pixels: image.pixels_snapshot(),
+                    deep: None,
+                    delay: Duration::ZERO,
+                }],
+                still_index: 0,
+            },
+            ..data
+        };
+        do_export(data, path, progress)
+    })
+}
+
+/// Exports an SVG at `scale` times its native size. An unedited document is
+/// drawn at that size from the vector; an edited one runs its stack on the
+/// native raster, as the view does, and is resized after.
+pub fn export_svg(
+    data: ExportData,
+    document: Arc<SvgDocument>,
+    scale: u32,
+    suggested_name: String,
+) -> iced::Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .add_filter("PNG Image", &["png"])
+        .add_filter("JPEG Image", &["jpg", "jpeg"])
+        .add_filter("WebP Image", &["webp"])
+        .add_filter("Windows Icon", &["ico"])
+        .set_file_name(&suggested_name);
+    run_export(dialog, move |path, progress| {
+        let data = if data.modifiers.iter().any(|m| m.has_visible_effect()) {
+            let mut modifiers = data.modifiers;
+            if scale != 1 {
+                let percent = scale as f32 * 100.0;
+                modifiers.push(Modifier::new(ModifierKind::Resize(Resize {
+                    mode: ResizeMode::Percent,
+                    width: percent,
+                    height: percent,
+                    filter: ResizeFilter::Lanczos,
+                    lock_aspect: true,
+                })));
+            }
+            ExportData { modifiers, ..data }
         } else {
-            if animated {
-                dialog = dialog
-                    .add_filter("GIF Animation", &["gif"])
-                    .add_filter("Animated PNG", &["apng"]);
+            let (width, height) = (document.width * scale, document.height * scale);
+            let pixels = document.render(width, height).map_err(|e| e.to_string())?;
+            ExportData {
+                source: ExportSource::Frames {
+                    frames: vec![ExportFrame {
+                        pixels: Arc::new(pixels),
+                        deep: None,
+                        delay: Duration::ZERO,
+                    }],
+                    still_index: 0,
+                },
+                width,
+                height,
+                ..data
             }
-            dialog = dialog
-                .add_filter("PNG Image", &["png"])
-                .add_filter("JPEG Image", &["jpg", "jpeg"])
-                .add_filter("WebP Image", &["webp"]);
-        }
-        let handle = dialog.set_file_name(&suggested_name).save_file().await;
+        };
+        do_export(data, path, progress)
+    })
+}
+
+fn run_export(
+    dialog: rfd::AsyncFileDialog,
+    export: impl FnOnce(&Path, &dyn Fn(f32)) -> Result<String, String> + Send + 'static,
+) -> iced::Task<Message> {
+    let (mut tx, rx) = futures::channel::mpsc::channel(64);
+
+    tokio::spawn(async move {
+        let handle = dialog.save_file().await;
 
         let Some(handle) = handle else { return };
         let path = handle.path().to_path_buf();
@@ -98,7 +413,7 @@ pub fn export_image(data: ExportData, suggested_name: String) -> iced::Task<Mess
         let (done_tx, mut done_rx) = tokio::sync::oneshot::channel::<Result<String, String>>();
 
         std::thread::spawn(move || {
-            let result = do_export(data, &path, |p| {
+            let result = export(&path, &|p| {
                 let _ = progress_tx.blocking_send(p);
             });
             let _ = done_tx.send(result);
@@ -0,0 +1,495 @@
+//! The on-disk thumbnail cache, shared with other programs through the
+//! freedesktop thumbnail specification.
+//!
+//! A thumbnail lives at `thumbnails/large/<md5 of the file URI>.png` in the
+//! user cache directory, so one a file manager made is picked up here and
+//! ours are picked up there. The PNG's text chunks carry the source's URI,
+//! modification time and size, and a thumbnail whose source has changed
+//! since fails that check and is made again. Files that could not be
+//! pictured are recorded the same way under `fail/bloom`, so a folder of
+//! unreadable files is not decoded again on every visit.
+//!
+//! Reading one of our thumbnails bumps its modification time, which makes
+//! the oldest of them the least recently used; those go first once ours pass
+//! DISK_BUDGET_BYTES. An index in our failure directory names the thumbnails
+//! we wrote, so trimming looks only at those, and only thumbnails whose
+//! `Software` text chunk names Bloom are touched, trimmed or cleared, so
+//! another program's stay as they are. The larger sizes other programs keep
+//! are read when ours is missing but never written or trimmed.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+
+const DISK_BUDGET_BYTES: u64 = 512 * 1024 * 1024;
+/// Writes between checks of the cache's size.
+const TRIM_EVERY: usize = 64;
+/// The size directory written to, then those read from when it misses.
+const SIZES: &[&str] = &["large", "x-large", "xx-large"];
+
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+/// Held while the index is added to or rewritten, so a trim on one worker
+/// does not drop a name another just wrote.
+static INDEX: Mutex<()> = Mutex::new(());
+
+/// A file as the cache identifies it.
+pub struct Source {
+    uri: String,
+    mtime: u64,
+    size: u64,
+    /// The thumbnail's file name, shared by every size directory.
+    name: String,
+}
+
+impl Source {
+    /// None for anything but a plain file, such as an entry in an archive.
+    pub fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok().filter(|m| m.is_file())?;
+        let mtime = meta
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        let uri = file_uri(&std::path::absolute(path).ok()?);
+        Some(Self {
+            name: format!("{:x}.png", md5::compute(uri.as_bytes())),
+            uri,
+            mtime,
+            size: meta.len(),
+        })
+    }
+}
+
+pub enum Cached {
+    Image(RgbaImage),
+    Failed,
+}
+
+fn root() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("thumbnails"))
+}
+
+fn fail_dir(root: &Path) -> PathBuf {
+    root.join("fail").join("bloom")
+}
+
+/// The names of the thumbnails we wrote to the shared size directory, one a
+/// line. It sits with our failures, where no other program writes.
+fn index_file(root: &Path) -> PathBuf {
+    fail_dir(root).join("index")
+}
+
+/// `path` as a file URI, escaped as GLib does so the hash matches other
+/// programs'.
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    if cfg!(windows) {
+        uri.push('/');
+    }
+    for &b in path.as_os_str().as_encoded_bytes() {
+        let b = if cfg!(windows) && b == b'\\' { b'/' } else { b };
+        if b.is_ascii_alphanumeric() || b"-_.!~*'()/&=:@+$,".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{b:02X}"));
+        }
+    }
+    uri
+}
+
+/// A fresh thumbnail of `source`, or the record that it failed, when the
+/// cache holds either.
+pub fn load(source: &Source) -> Option<Cached> {
+    load_from(&root()?, source)
+}
+
+fn load_from(root: &Path, source: &Source) -> Option<Cached> {
+    if read(&fail_dir(root).join(&source.name), source).is_some() {
+        return Some(Cached::Failed);
+    }
+    SIZES.iter().find_map(|dir| {
+        let file = root.join(dir).join(&source.name);
+        let (image, ours) = read(&file, source)?;
+        if ours {
+            touch(&file);
+        }
+        Some(Cached::Image(image))
+    })
+}
+
+/// Records `image` as the thumbnail of `source`, or that it has none.
+pub fn store(source: &Source, image: Option<&RgbaImage>) {
+    if let Some(root) = root() {
+        let _ = store_in(&root, source, image);
+        if WRITES
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(TRIM_EVERY)
+        {
+            let _ = trim(&root, DISK_BUDGET_BYTES);
+        }
+    }
+}
+
+fn store_in(root: &Path, source: &Source, image: Option<&RgbaImage>) -> io::Result<()> {
+    let placeholder;
+    let (dir, image) = match image {
+        Some(image) => (root.join(SIZES[0]), image),
+        None => {
+            placeholder = RgbaImage::new(1, 1);
+            (fail_dir(root), &placeholder)
+        }
+    };
+    create_private_dir(&dir)?;
+    // Written aside and renamed into place, so a reader never sees half.
+    let tmp = dir.join(format!("{}.{}.tmp", source.name, std::process::id()));
+    if let Err(e) = encode(&tmp, source, image) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, dir.join(&source.name))?;
+    if dir != fail_dir(root) {
+        remember(root, &source.name)?;
+    }
+    Ok(())
+}
+
+/// Adds `name` to the index of thumbnails we wrote.
+fn remember(root: &Path, name: &str) -> io::Result<()> {
+    let _lock = INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    create_private_dir(&fail_dir(root))?;
+    let mut options = File::options();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    writeln!(options.open(index_file(root))?, "{name}")
+}
+
+/// Deletes the thumbnails Bloom wrote and its record of failures, returning
+/// how many files went. Thumbnails other programs made are left alone.
+pub fn clear() -> io::Result<usize> {
+    match root() {
+        Some(root) => clear_in(&root),
+        None => Ok(0),
+    }
+}
+
+fn clear_in(root: &Path) -> io::Result<usize> {
+    let _ = fs::remove_file(index_file(root));
+    let mut removed = 0;
+    // The failure directory is Bloom's own; the size directory is shared.
+    for (dir, shared) in [(root.join(SIZES[0]), true), (fail_dir(root), false)] {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_file())
+                && (!shared || ours(&path))
+                && fs::remove_file(&path).is_ok()
+            {
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Deletes the least recently used of the thumbnails the index names until
+/// the rest fit in `budget`, and writes back the index of those left. Only
+/// a file about to go is opened, to check that another program has not
+/// since written its own thumbnail under the same name.
+fn trim(root: &Path, budget: u64) -> io::Result<()> {
+    let _lock = INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = root.join(SIZES[0]);
+    let index = fs::read_to_string(index_file(root))?;
+    let names: HashSet<&str> = index.lines().collect();
+    let mut files: Vec<(SystemTime, u64, &str)> = names
+        .into_iter()
+        .filter_map(|name| {
+            let meta = fs::metadata(dir.join(name)).ok().filter(|m| m.is_file())?;
+            Some((meta.modified().unwrap_or(UNIX_EPOCH), meta.len(), name))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    files.sort_unstable_by_key(|(used, _, _)| *used);
+    let mut kept = String::new();
+    for (_, len, name) in files {
+        let path = dir.join(name);
+        if total > budget && (!ours(&path) || fs::remove_file(&path).is_ok()) {
+            total -= len;
+            continue;
+        }
+        kept.push_str(name);
+        kept.push('\n');
+    }
+    open_private(&index_file(root))?.write_all(kept.as_bytes())
+}
+
+fn touch(file: &Path) {
+    if let Ok(f) = File::options().write(true).open(file) {
+        let _ = f.set_modified(SystemTime::now());
+    }
+}
+
+/// Whether `file` is a thumbnail Bloom wrote. Only the chunks before the
+/// image data are read, which is where `encode` puts its text.
+fn ours(file: &Path) -> bool {
+    let Ok(f) = File::open(file) else {
+        return false;
+    };
+    png::Decoder::new(io::BufReader::new(f))
+        .read_info()
+        .is_ok_and(|reader| by_bloom(reader.info()))
+}
+
+fn by_bloom(info: &png::Info) -> bool {
+    info.uncompressed_latin1_text
+        .iter()
+        .any(|chunk| chunk.keyword == "Software" && chunk.text == "Bloom")
+}
+
+/// The thumbnail in `file`, if it is one of `source` as it is now, and
+/// whether Bloom wrote it.
+fn read(file: &Path, source: &Source) -> Option<(RgbaImage, bool)> {
+    let mut decoder = png::Decoder::new(File::open(file).ok()?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().ok()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).ok()?;
+    buf.truncate(frame.buffer_size());
+
+    let text = |key: &str| {
+        reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.as_str())
+    };
+    // Some writers store the time with a fraction.
+    let mtime = text("Thumb::MTime").and_then(|m| m.parse::<f64>().ok());
+    let size_matches =
+        text("Thumb::Size").is_none_or(|s| s.parse::<u64>().ok() == Some(source.size));
+    if text("Thumb::URI") != Some(source.uri.as_str())
+        || mtime.map(|m| m as u64) != Some(source.mtime)
+        || !size_matches
+    {
+        return None;
+    }
+
+    let rgba = match frame.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+    let ours = by_bloom(reader.info());
+    Some((RgbaImage::from_raw(frame.width, frame.height, rgba)?, ours))
+}
+
+fn encode(file: &Path, source: &Source, image: &RgbaImage) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(open_private(file)?),
+        image.width(),
+        image.height(),
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (key, value) in [
+        ("Thumb::URI", source.uri.clone()),
+        ("Thumb::MTime", source.mtime.to_string()),
+        ("Thumb::Size", source.size.to_string()),
+        ("Software", "Bloom".to_string()),
+    ] {
+        encoder
+            .add_text_chunk(key.to_string(), value)
+            .map_err(Error::other)?;
+    }
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    writer
+        .write_image_data(image.as_raw())
+        .map_err(Error::other)?;
+    writer.finish().map_err(Error::other)
+}
+
+// The spec asks for the cache to be private to the user.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+fn open_private(file: &Path) -> io::Result<File> {
+    let mut options = File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bloom_thumbnail_cache_{label}_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn names_thumbnails_as_the_spec_does() {
+        // The specification's own example.
+        let uri = "file:///home/jens/photos/me.png";
+        assert_eq!(
+            format!("{:x}", md5::compute(uri.as_bytes())),
+            "c6ee772d9e49320e97ec29a7eb5b1697"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            file_uri(Path::new("/photos/a b/ü#1.jpg")),
+            "file:///photos/a%20b/%C3%BC%231.jpg"
+        );
+    }
+
+    #[test]
+    fn round_trips_and_goes_stale_when_the_source_changes() {
+        let dir = fixture("round_trip");
+        let file = dir.join("photo.png");
+        fs::write(&file, b"not really a png").unwrap();
+        let root = dir.join("cache");
+        let mut source = Source::of(&file).unwrap();
+
+        let image = RgbaImage::from_pixel(3, 2, image::Rgba([10, 20, 30, 255]));
+        store_in(&root, &source, Some(&image)).unwrap();
+        match load_from(&root, &source) {
+            Some(Cached::Image(cached)) => assert_eq!(cached, image),
+            _ => panic!("thumbnail not read back"),
+        }
+
+        source.mtime += 1;
+        assert!(load_from(&root, &source).is_none());
+        store_in(&root, &source, None).unwrap();
+        assert!(matches!(load_from(&root, &source), Some(Cached::Failed)));
+
+        assert_eq!(clear_in(&root).unwrap(), 2);
+        assert!(load_from(&root, &source).is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn trims_least_recently_used_first() {
+        let dir = fixture("trim");
+        let file = dir.join("photo.png");
+        fs::write(&file, b"not really a png").unwrap();
+        let source = Source::of(&file).unwrap();
+        let image = RgbaImage::from_pixel(8, 8, image::Rgba([1, 2, 3, 255]));
+        let root = dir.join("cache");
+        let cache = root.join(SIZES[0]);
+        fs::create_dir_all(&cache).unwrap();
+        // Another program's thumbnail, older than all of ours, written over
+        // one the index still names.
+        fs::write(cache.join("foreign.png"), [0u8; 100]).unwrap();
+        let mut len = 0;
+        for (i, name) in ["foreign.png", "old.png", "mid.png", "new.png"]
+            .iter()
+            .enumerate()
+        {
+            let file = cache.join(name);
+            if i > 0 {
+                encode(&file, &source, &image).unwrap();
+                len = fs::metadata(&file).unwrap().len();
+            }
+            remember(&root, name).unwrap();
+            let when = UNIX_EPOCH + std::time::Duration::from_secs(1_000 * (i as u64 + 1));
+            File::options()
+                .write(true)
+                .open(&file)
+                .unwrap()
+                .set_modified(when)
+                .unwrap();
+        }
+        trim(&root, len * 5 / 2).unwrap();
+        assert!(cache.join("foreign.png").exists());
+        assert!(!cache.join("old.png").exists());
+        assert!(cache.join("mid.png").exists());
+        assert!(cache.join("new.png").exists());
+        let index = fs::read_to_string(index_file(&root)).unwrap();
+        let mut left: Vec<_> = index.lines().collect();
+        left.sort_unstable();
+        assert_eq!(left, ["mid.png", "new.png"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn clearing_leaves_other_programs_thumbnails() {
+        let dir = fixture("clear_foreign");
+        let file = dir.join("photo.png");
+        fs::write(&file, b"not really a png").unwrap();
+        let root = dir.join("cache");
+        let source = Source::of(&file).unwrap();
+        store_in(&root, &source, Some(&RgbaImage::new(2, 2))).unwrap();
+        let foreign = root.join(SIZES[0]).join("foreign.png");
+        fs::write(&foreign, [0u8; 100]).unwrap();
+
+        assert_eq!(clear_in(&root).unwrap(), 1);
+        assert!(foreign.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reading_leaves_other_programs_thumbnails_as_they_were() {
+        let dir = fixture("touch_foreign");
+        let file = dir.join("photo.png");
+        fs::write(&file, b"not really a png").unwrap();
+        let root = dir.join("cache");
+        let source = Source::of(&file).unwrap();
+        let large = root.join(SIZES[0]);
+        fs::create_dir_all(&large).unwrap();
+        let thumbnail = large.join(&source.name);
+        let mut encoder = png::Encoder::new(File::create(&thumbnail).unwrap(), 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        for (key, value) in [
+            ("Thumb::URI", source.uri.clone()),
+            ("Thumb::MTime", source.mtime.to_string()),
+        ] {
+            encoder.add_text_chunk(key.to_string(), value).unwrap();
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0; 4]).unwrap();
+        writer.finish().unwrap();
+        let when = UNIX_EPOCH + std::time::Duration::from_secs(1_000);
+        File::options()
+            .write(true)
+            .open(&thumbnail)
+            .unwrap()
+            .set_modified(when)
+            .unwrap();
+
+        assert!(matches!(load_from(&root, &source), Some(Cached::Image(_))));
+        assert_eq!(fs::metadata(&thumbnail).unwrap().modified().unwrap(), when);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,301 @@
+//! Thumbnails for the gallery grid, decoded off the UI thread and kept under a
+//! memory budget.
+//!
+//! A thumbnail is whatever the file can show quickest: an embedded preview
+//! when the format carries a big enough one, otherwise the full decode scaled
+//! down. Animations show the frame they open on and videos their first frame.
+//! A file that fails to decode, or an image too large to hold in memory,
+//! records a failure so the grid shows its name instead of asking again.
+//!
+//! The cache is keyed by path and evicts the entries shown least recently
+//! once their pixels pass the budget. An evicted cell is simply generated
+//! again when it scrolls back into view, so a folder of thousands costs no
+//! more than the budget however far it is browsed.
+//!
+//! Behind it sits the shared cache on disk (see `disk`), checked before any
+//! decode and filled after one, so a folder of RAW files or videos is slow
+//! only the first time any program thumbnails it.
+
+pub mod disk;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use iced::widget::image::Handle;
+use image::{ImageBuffer, Rgba, RgbaImage, imageops};
+
+use crate::wgpu::media::image_data::{ImageData, MediaData};
+
+/// The longest side of a thumbnail, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+const BUDGET_BYTES: usize = 128 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub handle: Handle,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Thumbnail {
+    fn size_bytes(&self) -> usize {
+        self.width as usize * self.height as usize * 4
+    }
+}
+
+/// The thumbnail of `path`, from the disk cache when it holds a fresh one and
+/// decoded otherwise. None when it cannot be pictured.
+pub fn generate(path: &Path) -> Option<Thumbnail> {
+    let source = disk::Source::of(path);
+    let cached = source.as_ref().and_then(disk::load);
+    let image = match cached {
+        Some(disk::Cached::Image(image)) => shrink(image),
+        Some(disk::Cached::Failed) => return None,
+        None => {
+            let image = render(path);
+            if let Some(source) = &source {
+                disk::store(source, image.as_ref());
+            }
+            image?
+        }
+    };
+    Some(Thumbnail {
+        width: image.width(),
+        height: image.height(),
+        handle: Handle::from_rgba(image.width(), image.height(), image.into_raw()),
+    })
+}
+
+/// Decodes `path` and scales it down, turned the way it displays.
+fn render(path: &Path) -> Option<RgbaImage> {
+    let image = ImageData::load_preview(path)
+        .filter(|p| p.width.max(p.height) >= THUMBNAIL_SIZE / 2)
+        .map(Arc::new)
+        .or_else(|| decode(path))?;
+    if !image.pixels_available() {
+        return None;
+    }
+    let pixels = image.pixels_snapshot();
+    let full =
+        ImageBuffer::<Rgba<u8>, &[u8]>::from_raw(image.width, image.height, pixels.as_slice())?;
+    let (width, height) = fit_within(image.width, image.height, THUMBNAIL_SIZE);
+    Some(orient(
+        imageops::thumbnail(&full, width, height),
+        image.exif.display_transform(),
+    ))
+}
+
+/// Another program's larger thumbnail brought down to ours.
+fn shrink(image: RgbaImage) -> RgbaImage {
+    let (width, height) = fit_within(image.width(), image.height(), THUMBNAIL_SIZE);
+    if (width, height) == image.dimensions() {
+        image
+    } else {
+        imageops::thumbnail(&image, width, height)
+    }
+}
+
+fn decode(path: &Path) -> Option<Arc<ImageData>> {
+    match ImageData::load_media(path).ok()? {
+        MediaData::Image(image) => Some(Arc::new(*image)),
+        MediaData::Animation(animation) => Some(Arc::clone(animation.current_image())),
+        #[cfg(feature = "av")]
+        MediaData::Video(info) => {
+            crate::wgpu::media::video::poster_frame(&info.path, THUMBNAIL_SIZE)
+                .ok()
+                .map(Arc::new)
+        }
+    }
+}
+
+/// Scales `width` x `height` down to fit a `max` square, never up.
+fn fit_within(width: u32, height: u32, max: u32) -> (u32, u32) {
+    let long = width.max(height);
+    if long <= max {
+        return (width, height);
+    }
+    let scale = |side: u32| ((side as u64 * max as u64 / long as u64) as u32).max(1);
+    (scale(width), scale(height))
+}
+
+fn orient(image: RgbaImage, transform: Option<(u8, bool)>) -> RgbaImage {
+    let Some((turns, mirror)) = transform else {
+        return image;
+    };
+    let image = if mirror {
+        imageops::flip_horizontal(&image)
+    } else {
+        image
+    };
+    match turns {
+        1 => imageops::rotate90(&image),
+        2 => imageops::rotate180(&image),
+        3 => imageops::rotate270(&image),
+        _ => image,
+    }
+}
+
+/// How many thumbnails decode at once.
+pub fn workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .clamp(2, 8)
+}
+
+struct Entry {
+    /// None when the file could not be pictured.
+    thumbnail: Option<Thumbnail>,
+    used: u64,
+}
+
+pub struct ThumbnailCache {
+    entries: HashMap<PathBuf, Entry>,
+    pending: HashSet<PathBuf>,
+    bytes: usize,
+    budget: usize,
+    clock: u64,
+}
+
+impl Default for ThumbnailCache {
+    fn default() -> Self {
+        Self::with_budget(BUDGET_BYTES)
+    }
+}
+
+impl ThumbnailCache {
+    pub fn with_budget(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            pending: HashSet::new(),
+            bytes: 0,
+            budget,
+            clock: 0,
+        }
+    }
+
+    /// The thumbnail for `path`: None while it is unknown, Some(None) once it
+    /// has failed.
+    pub fn get(&self, path: &Path) -> Option<Option<&Thumbnail>> {
+        self.entries.get(path).map(|e| e.thumbnail.as_ref())
+    }
+
+    /// Marks `paths` as on screen and returns those that still need
+    /// generating, keeping no more than `workers` in flight.
+    pub fn want<'a>(
+        &mut self,
+        paths: impl IntoIterator<Item = &'a PathBuf>,
+        workers: usize,
+    ) -> Vec<PathBuf> {
+        self.clock += 1;
+        let mut start = Vec::new();
+        for path in paths {
+            if let Some(entry) = self.entries.get_mut(path) {
+                entry.used = self.clock;
+            } else if self.pending.len() < workers && self.pending.insert(path.clone()) {
+                start.push(path.clone());
+            }
+        }
+        start
+    }
+
+    /// Forgets every thumbnail, so cells on screen are generated again.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+
+    /// Drops the thumbnail of a file that changed, so it is made again.
+    pub fn forget(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.bytes -= entry.thumbnail.as_ref().map_or(0, Thumbnail::size_bytes);
+        }
+    }
+
+    pub fn insert(&mut self, path: PathBuf, thumbnail: Option<Thumbnail>) {
+        self.pending.remove(&path);
+        self.bytes += thumbnail.as_ref().map_or(0, Thumbnail::size_bytes);
+        let entry = Entry {
+            thumbnail,
+            used: self.clock,
+        };
+        if let Some(old) = self.entries.insert(path.clone(), entry) {
+            self.bytes -= old.thumbnail.as_ref().map_or(0, Thumbnail::size_bytes);
+        }
+        self.evict(&path);
+    }
+
+    /// Drops the least recently shown pictures until the budget holds,
+    /// sparing `keep`, the one just arrived.
+    fn evict(&mut self, keep: &Path) {
+        while self.bytes > self.budget {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(p, e)| p.as_path() != keep && e.thumbnail.is_some())
+                .min_by_key(|(_, e)| e.used)
+                .map(|(p, _)| p.clone());
+            let Some(path) = oldest else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&path) {
+                self.bytes -= entry.thumbnail.as_ref().map_or(0, Thumbnail::size_bytes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thumbnail(side: u32) -> Option<Thumbnail> {
+        let pixels = vec![0; (side * side * 4) as usize];
+        Some(Thumbnail {
+            handle: Handle::from_rgba(side, side, pixels),
+            width: side,
+            height: side,
+        })
+    }
+
+    #[test]
+    fn fits_long_side_without_upscaling() {
+        assert_eq!(fit_within(4000, 3000, 256), (256, 192));
+        assert_eq!(fit_within(100, 5000, 256), (5, 256));
+        assert_eq!(fit_within(120, 80, 256), (120, 80));
+        assert_eq!(fit_within(10000, 1, 256), (256, 1));
+    }
+
+    #[test]
+    fn evicts_least_recently_shown_over_budget() {
+        let one = 16 * 16 * 4;
+        let mut cache = ThumbnailCache::with_budget(2 * one);
+        let paths: Vec<PathBuf> = ["a", "b", "c"].iter().map(PathBuf::from).collect();
+
+        assert_eq!(cache.want(&paths[..2], 8).len(), 2);
+        cache.insert(paths[0].clone(), thumbnail(16));
+        cache.insert(paths[1].clone(), thumbnail(16));
+        // Showing "a" again makes "b" the stalest.
+        cache.want(&paths[..1], 8);
+        cache.want(&paths[2..], 8);
+        cache.insert(paths[2].clone(), thumbnail(16));
+
+        assert!(cache.get(&paths[0]).is_some());
+        assert!(cache.get(&paths[1]).is_none());
+        assert!(cache.get(&paths[2]).is_some());
+        assert!(cache.bytes <= 2 * one);
+    }
+
+    #[test]
+    fn limits_generations_in_flight() {
+        let mut cache = ThumbnailCache::default();
+        let paths: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(i.to_string())).collect();
+        assert_eq!(cache.want(&paths, 3).len(), 3);
+        assert!(cache.want(&paths, 3).is_empty());
+        cache.insert(paths[0].clone(), None);
+        assert_eq!(cache.want(&paths, 3), vec![paths[3].clone()]);
+        assert!(matches!(cache.get(&paths[0]), Some(None)));
+    }
+}
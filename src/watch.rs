@@ -0,0 +1,306 @@
+//! Watching the gallery's folder for files other programs add, remove or
+//! rewrite, so a folder a renderer is writing into browses live.
+//!
+//! On Linux the folder gets an inotify watch, and in a gallery that spans
+//! subfolders so does every folder the listing reads, all on the one
+//! descriptor. Only finished changes are reported: a file closed after
+//! writing or renamed in, and one deleted or renamed out. A file still being
+//! written is never read half done, and a program that saves under a
+//! temporary name and renames it over the old file looks the same as one
+//! that rewrites it in place.
+//!
+//! Changes come in bursts, a copy of many files or a render writing frame
+//! after frame, so they are gathered until the folder has been quiet for
+//! SETTLE and sent as one batch, or for at most MAX_LATENCY when it never
+//! goes quiet. When a subfolder comes or goes, or inotify's queue overflows
+//! and drops changes, what changed is not known file by file and the whole
+//! folder is listed again instead. The watch runs on its own thread and
+//! ends when the subscription drops its receiver or the folder itself goes
+//! away. Other platforms do not watch.
+
+use std::path::PathBuf;
+
+use futures::channel::mpsc::{UnboundedReceiver, unbounded};
+
+use crate::app::Message;
+
+/// The changes to a folder, and with the flag set the folders under it, in
+/// batches, for as long as the receiver is held.
+pub fn changes((folder, recursive): &(PathBuf, bool)) -> UnboundedReceiver<Message> {
+    let (tx, rx) = unbounded();
+    #[cfg(target_os = "linux")]
+    {
+        let (folder, recursive) = (folder.clone(), *recursive);
+        std::thread::spawn(move || linux::watch(folder, recursive, tx));
+    }
+    #[cfg(not(target_os = "linux"))]
+    drop((folder, recursive, tx));
+    rx
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr, OsString};
+    use std::fs::File;
+    use std::io::{self, ErrorKind, Read};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    use futures::channel::mpsc::UnboundedSender;
+
+    use crate::app::Message;
+    use crate::gallery;
+
+    /// How long the folder stays quiet before a batch goes out.
+    const SETTLE: Duration = Duration::from_millis(200);
+    /// How long a change waits at most, so a folder written to without pause
+    /// still updates.
+    const MAX_LATENCY: Duration = Duration::from_secs(2);
+
+    pub fn watch(folder: PathBuf, recursive: bool, output: UnboundedSender<Message>) {
+        let Ok(mut inotify) = Inotify::new(&folder) else {
+            return;
+        };
+        if recursive {
+            inotify.add_tree(&folder);
+        }
+        let mut changed: Vec<PathBuf> = Vec::new();
+        let mut relist = false;
+        // When the oldest change not yet sent came in.
+        let mut since: Option<Instant> = None;
+        while !output.is_closed() {
+            let quiet = match inotify.wait(SETTLE) {
+                Ok(Some(events)) => {
+                    let quiet = events.is_empty();
+                    for event in events {
+                        match event {
+                            Event::Changed(path) => {
+                                if !changed.contains(&path) {
+                                    changed.push(path);
+                                }
+                            }
+                            // A flat gallery lists no folders, so it has
+                            // nothing to do when one comes or goes.
+                            Event::Folder(_) if !recursive => continue,
+                            Event::Folder(dir) => {
+                                if dir.is_dir() {
+                                    inotify.add_tree(&dir);
+                                }
+                                relist = true;
+                            }
+                            Event::Overflow => relist = true,
+                        }
+                        since.get_or_insert_with(Instant::now);
+                    }
+                    quiet
+                }
+                Ok(None) | Err(_) => return,
+            };
+            if !since.is_some_and(|t| quiet || t.elapsed() >= MAX_LATENCY) {
+                continue;
+            }
+            since = None;
+            let message = if std::mem::take(&mut relist) {
+                changed.clear();
+                Message::RelistFolder
+            } else {
+                Message::FolderChanged(std::mem::take(&mut changed))
+            };
+            if output.unbounded_send(message).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// What inotify reported about the folders watched.
+    #[derive(Debug, PartialEq)]
+    pub enum Event {
+        /// This file was written, added or removed.
+        Changed(PathBuf),
+        /// This folder was added or removed.
+        Folder(PathBuf),
+        /// The queue filled and changes were dropped.
+        Overflow,
+    }
+
+    pub struct Inotify {
+        file: File,
+        /// The folder watched under each watch descriptor.
+        dirs: HashMap<i32, PathBuf>,
+        /// The descriptor of the folder the watch is for, whose going ends it.
+        root: i32,
+    }
+
+    impl Inotify {
+        pub fn new(folder: &Path) -> io::Result<Self> {
+            // SAFETY: inotify_init1 takes no pointers, and the descriptor it
+            // returns is owned by `file` from here on.
+            let file = unsafe {
+                let fd = libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                File::from(OwnedFd::from_raw_fd(fd))
+            };
+            let mut inotify = Self {
+                file,
+                dirs: HashMap::new(),
+                root: -1,
+            };
+            inotify.root = inotify.add(folder)?;
+            Ok(inotify)
+        }
+
+        /// Watches `dir` too, on the same descriptor.
+        pub fn add(&mut self, dir: &Path) -> io::Result<i32> {
+            let path = CString::new(dir.as_os_str().as_bytes())?;
+            let mask = libc::IN_CLOSE_WRITE
+                | libc::IN_MOVED_TO
+                | libc::IN_MOVED_FROM
+                | libc::IN_DELETE
+                | libc::IN_CREATE
+                | libc::IN_DELETE_SELF
+                | libc::IN_MOVE_SELF
+                | libc::IN_ONLYDIR;
+            // SAFETY: `path` is a NUL terminated string that outlives the call.
+            let wd = unsafe { libc::inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), mask) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.dirs.insert(wd, dir.to_path_buf());
+            Ok(wd)
+        }
+
+        /// Watches `dir` and the folders under it that a recursive gallery
+        /// reads. Those past the system's limit on watches go unwatched.
+        pub fn add_tree(&mut self, dir: &Path) {
+            for sub in gallery::subfolders(dir) {
+                if self.add(&sub).is_err() {
+                    return;
+                }
+            }
+        }
+
+        /// Waits up to `timeout` for changes and returns them, or None once
+        /// the folder itself is gone.
+        pub fn wait(&mut self, timeout: Duration) -> io::Result<Option<Vec<Event>>> {
+            let mut poll = libc::pollfd {
+                fd: self.file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: one pollfd, living on the stack for the whole call.
+            let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+            let mut events = Vec::new();
+            if ready <= 0 {
+                return Ok(Some(events));
+            }
+            let mut buf = [0u8; 4096];
+            loop {
+                let len = match (&self.file).read(&mut buf) {
+                    Ok(0) => return Ok(Some(events)),
+                    Ok(len) => len,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(Some(events)),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                for (wd, mask, name) in parse(&buf[..len]) {
+                    if mask & libc::IN_Q_OVERFLOW != 0 {
+                        events.push(Event::Overflow);
+                        continue;
+                    }
+                    let gone = libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_IGNORED;
+                    if mask & gone != 0 {
+                        if wd == self.root {
+                            return Ok(None);
+                        }
+                        // The folder above reports a subfolder going.
+                        if mask & libc::IN_IGNORED != 0 {
+                            self.dirs.remove(&wd);
+                        }
+                        continue;
+                    }
+                    let Some(dir) = self.dirs.get(&wd).filter(|_| !name.is_empty()) else {
+                        continue;
+                    };
+                    let path = dir.join(&name);
+                    if mask & libc::IN_ISDIR != 0 {
+                        events.push(Event::Folder(path));
+                    } else if mask & libc::IN_CREATE == 0 {
+                        // A file is reported once written, not when created.
+                        events.push(Event::Changed(path));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Splits what a read from inotify returned into each event's watch
+    /// descriptor, mask and file name.
+    fn parse(mut buf: &[u8]) -> Vec<(i32, u32, OsString)> {
+        const HEADER: usize = size_of::<libc::inotify_event>();
+        let mut events = Vec::new();
+        while buf.len() >= HEADER {
+            let field =
+                |at: usize| u32::from_ne_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+            let (wd, mask, len) = (field(0) as i32, field(4), field(12) as usize);
+            let Some(name) = buf.get(HEADER..HEADER + len) else {
+                break;
+            };
+            // The name is padded out with NULs.
+            let end = name.iter().position(|&b| b == 0).unwrap_or(len);
+            events.push((wd, mask, OsStr::from_bytes(&name[..end]).to_os_string()));
+            buf = &buf[HEADER + len..];
+        }
+        events
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn reports_finished_writes_and_the_folder_going() {
+            let dir = std::env::temp_dir().join(format!("bloom-watch-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut inotify = Inotify::new(&dir).unwrap();
+
+            std::fs::write(dir.join("frame_0001.png"), b"x").unwrap();
+            let names = inotify.wait(Duration::from_secs(1)).unwrap();
+            assert_eq!(
+                names,
+                Some(vec![Event::Changed(dir.join("frame_0001.png"))])
+            );
+
+            std::fs::remove_dir_all(&dir).unwrap();
+            let gone = (0..3).any(|_| inotify.wait(Duration::from_secs(1)).unwrap().is_none());
+            assert!(gone);
+        }
+
+        #[test]
+        fn watches_the_folders_under_it_on_one_descriptor() {
+            let dir = std::env::temp_dir().join(format!("bloom-watch-tree-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(dir.join("renders/shot_01")).unwrap();
+            let mut inotify = Inotify::new(&dir).unwrap();
+            inotify.add_tree(&dir);
+
+            let frame = dir.join("renders/shot_01/frame_0001.exr");
+            std::fs::write(&frame, b"x").unwrap();
+            std::fs::create_dir(dir.join("renders/shot_02")).unwrap();
+            let events = inotify.wait(Duration::from_secs(1)).unwrap().unwrap();
+            assert_eq!(
+                events,
+                vec![
+                    Event::Changed(frame),
+                    Event::Folder(dir.join("renders/shot_02")),
+                ]
+            );
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+}
@@ -31,6 +31,26 @@ pub fn fullscreen_pipeline(
     format: TextureFormat,
     blend: BlendState,
     bind_group_layout: &BindGroupLayout,
+) -> RenderPipeline {
+    fullscreen_pipeline_layouts(
+        device,
+        shader_src,
+        label,
+        topology,
+        format,
+        blend,
+        &[bind_group_layout],
+    )
+}
+
+pub fn fullscreen_pipeline_layouts(
+    device: &Device,
+    shader_src: &str,
+    label: Option<&str>,
+    topology: PrimitiveTopology,
+    format: TextureFormat,
+    blend: BlendState,
+    bind_group_layouts: &[&BindGroupLayout],
 ) -> RenderPipeline {
     let shader = device.create_shader_module(ShaderModuleDescriptor {
         label,
@@ -39,7 +59,7 @@ pub fn fullscreen_pipeline(
 
     let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
         label,
-        bind_group_layouts: &[bind_group_layout],
+        bind_group_layouts,
         push_constant_ranges: &[],
     });
 
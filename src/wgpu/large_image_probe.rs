@@ -23,6 +23,11 @@
 //! *next* image (`app::mod`), so it never lowers the peak for the image being
 //! viewed. `TiledSource` uploads every tile up front and never evicts, so VRAM
 //! residency tracks total image size rather than what is visible.
+//!
+//! This is the whole-image load path. An image past `STREAM_MIN_PIXELS` in a
+//! format that can stream skips it: it is decoded in bands straight into the
+//! tiles and has no full-size host copy at all (see `media/stream.rs`). VRAM
+//! still holds all of it.
 
 #[cfg(test)]
 mod tests {
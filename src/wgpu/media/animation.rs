@@ -5,6 +5,10 @@ use std::time::{Duration, Instant};
 use image::ImageError;
 
 use super::image_data::ImageData;
+use super::sequence::{Sequence, Slot};
+
+/// How soon to look again when the next frame of a sequence is still decoding.
+const STALL_POLL: Duration = Duration::from_millis(5);
 
 #[derive(Debug)]
 pub struct Frame {
@@ -12,9 +16,17 @@ pub struct Frame {
     pub delay: Duration,
 }
 
+#[derive(Debug, Clone)]
+enum Frames {
+    Decoded(Arc<Vec<Frame>>),
+    /// Decoded on demand. See sequence.rs.
+    Sequence(Arc<Sequence>),
+}
+
 #[derive(Debug, Clone)]
 pub struct Animation {
-    frames: Arc<Vec<Frame>>,
+    frames: Frames,
+    shown: Arc<ImageData>,
     total_duration: Duration,
     current: usize,
     current_timestamp: Duration,
@@ -25,20 +37,37 @@ pub struct Animation {
 
 impl Animation {
     pub fn new(frames: Vec<Frame>) -> Result<Self, ImageError> {
-        let first_delay = frames
+        let first = frames
             .first()
-            .ok_or_else(|| ImageError::IoError(Error::other("animation has no frames")))?
-            .delay;
+            .ok_or_else(|| ImageError::IoError(Error::other("animation has no frames")))?;
+        let shown = Arc::clone(&first.data);
         let total_duration = frames.iter().map(|f| f.delay).sum();
-        Ok(Self {
-            frames: Arc::new(frames),
+        Ok(Self::start(
+            Frames::Decoded(Arc::new(frames)),
+            shown,
+            total_duration,
+        ))
+    }
+
+    /// An animation over `sequence`, whose first frame is `first`.
+    pub fn from_sequence(sequence: Arc<Sequence>, first: Arc<ImageData>) -> Self {
+        let total_duration = sequence.delay() * sequence.frame_count() as u32;
+        Self::start(Frames::Sequence(sequence), first, total_duration)
+    }
+
+    fn start(frames: Frames, shown: Arc<ImageData>, total_duration: Duration) -> Self {
+        let mut anim = Self {
+            frames,
+            shown,
             total_duration,
             current: 0,
             current_timestamp: Duration::ZERO,
-            deadline: Instant::now() + first_delay,
+            deadline: Instant::now(),
             looping: true,
             ended: false,
-        })
+        };
+        anim.deadline += anim.delay(0);
+        anim
     }
 
     pub fn set_looping(&mut self, looping: bool) {
@@ -53,15 +82,40 @@ impl Animation {
     }
 
     pub fn current_image(&self) -> &Arc<ImageData> {
-        &self.frames[self.current].data
+        &self.shown
     }
 
     pub fn frame_count(&self) -> usize {
-        self.frames.len()
+        match &self.frames {
+            Frames::Decoded(frames) => frames.len(),
+            Frames::Sequence(sequence) => sequence.frame_count(),
+        }
+    }
+
+    /// Every frame, when they are all decoded; None for a sequence.
+    pub fn frames(&self) -> Option<&[Frame]> {
+        match &self.frames {
+            Frames::Decoded(frames) => Some(frames.as_slice()),
+            Frames::Sequence(_) => None,
+        }
+    }
+
+    pub fn sequence(&self) -> Option<&Arc<Sequence>> {
+        match &self.frames {
+            Frames::Decoded(_) => None,
+            Frames::Sequence(sequence) => Some(sequence),
+        }
     }
 
-    pub fn frames(&self) -> &[Frame] {
-        &self.frames
+    pub fn delay(&self, index: usize) -> Duration {
+        match &self.frames {
+            Frames::Decoded(frames) => frames[index].delay,
+            Frames::Sequence(sequence) => sequence.delay(),
+        }
+    }
+
+    pub fn delays(&self) -> impl Iterator<Item = Duration> + '_ {
+        (0..self.frame_count()).map(|i| self.delay(i))
     }
 
     pub fn current_index(&self) -> usize {
@@ -80,19 +134,28 @@ impl Animation {
         self.current_timestamp
     }
 
+    /// Jumps to frame `index`. A sequence frame that cannot be shown leaves
+    /// the previous image up.
     pub fn seek(&mut self, index: usize) -> Arc<ImageData> {
-        let index = index.min(self.frames.len() - 1);
+        let index = index.min(self.frame_count() - 1);
         self.current = index;
-        self.current_timestamp = self.frames[..index].iter().map(|f| f.delay).sum();
-        self.deadline = Instant::now() + self.frames[index].delay;
+        self.current_timestamp = self.delays().take(index).sum();
+        self.deadline = Instant::now() + self.delay(index);
         self.ended = false;
-        Arc::clone(&self.frames[index].data)
+        let data = match &self.frames {
+            Frames::Decoded(frames) => Some(Arc::clone(&frames[index].data)),
+            Frames::Sequence(sequence) => sequence.fetch_now(index),
+        };
+        if let Some(data) = data {
+            self.shown = data;
+        }
+        Arc::clone(&self.shown)
     }
 
     pub fn resume(&mut self) {
         let now = Instant::now();
         let remaining = self.deadline.saturating_duration_since(now);
-        self.deadline = now + remaining.max(self.frames[self.current].delay);
+        self.deadline = now + remaining.max(self.delay(self.current));
     }
 
     pub fn tick(&mut self, now: Instant) -> Option<Arc<ImageData>> {
@@ -100,23 +163,39 @@ impl Animation {
             return None;
         }
 
+        let mut changed = false;
         loop {
-            let next = (self.current + 1) % self.frames.len();
+            let next = (self.current + 1) % self.frame_count();
             if next == 0 && !self.looping {
                 self.ended = true;
-                return None;
+                break;
             }
-            self.current_timestamp += self.frames[self.current].delay;
+            let data = match &self.frames {
+                Frames::Decoded(frames) => Some(Arc::clone(&frames[next].data)),
+                Frames::Sequence(sequence) => match sequence.fetch(next) {
+                    Slot::Ready(data) => Some(data),
+                    Slot::Unusable => None,
+                    Slot::Pending => {
+                        self.deadline = now + STALL_POLL;
+                        break;
+                    }
+                },
+            };
+            self.current_timestamp += self.delay(self.current);
             self.current = next;
             if self.current == 0 {
                 self.current_timestamp = Duration::ZERO;
             }
-            self.deadline += self.frames[self.current].delay;
+            if let Some(data) = data {
+                self.shown = data;
+                changed = true;
+            }
+            self.deadline += self.delay(self.current);
             if self.deadline > now {
                 break;
             }
         }
 
-        Some(Arc::clone(&self.frames[self.current].data))
+        changed.then(|| Arc::clone(&self.shown))
     }
 }
@@ -0,0 +1,214 @@
+//! ZIP and CBZ archives, browsed as if they were folders.
+//!
+//! An entry is addressed by a virtual path: the archive's own path with the
+//! entry's name joined on, as in `scans/batch.zip/page01.jpg`. Everything
+//! that passes paths around (the gallery, loads, the title) keeps working on
+//! those, and split recovers the two halves wherever the distinction
+//! matters. Nothing is extracted: an entry is inflated into memory and
+//! decoded from there.
+//!
+//! Only formats the image crate decodes from a buffer are listed, which
+//! covers what scans and comics are made of. Animated GIF and WebP entries
+//! show their first frame. macOS resource forks (`__MACOSX/`, `._name`) and
+//! names that would climb out of the archive are skipped.
+
+use std::fs::File;
+use std::io::{BufReader, Error, Read};
+use std::path::{Component, Path};
+
+use image::{ImageError, ImageFormat};
+use zip::ZipArchive;
+
+use super::exif_data::ExifData;
+use super::image_data::{ImageData, MediaData};
+use super::sniff::{HEADER_LEN, sniff_bytes};
+
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "cbz"];
+
+/// Entry formats that decode from memory.
+pub const ENTRY_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff", "tga", "qoi", "pbm", "pgm", "ppm",
+    "ico", "ff", "hdr", "exr",
+];
+
+/// The most an entry may inflate to. Its header's size is only a claim, so
+/// reads stop here whatever the header says.
+const ENTRY_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+fn archive_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| extensions.iter().any(|s| s.eq_ignore_ascii_case(ext)))
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    has_extension(path, ARCHIVE_EXTENSIONS) && path.is_file()
+}
+
+/// The archive a virtual path points into, and the entry's name within it.
+pub fn split(path: &Path) -> Option<(&Path, String)> {
+    let archive = path.ancestors().skip(1).find(|p| is_archive(p))?;
+    let entry = path.strip_prefix(archive).ok()?;
+    Some((archive, entry_name(entry)))
+}
+
+/// The name within the archive of an entry's path relative to it.
+pub fn entry_name(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn open(archive: &Path) -> Result<ZipArchive<BufReader<File>>, ImageError> {
+    let file = File::open(archive).map_err(ImageError::IoError)?;
+    ZipArchive::new(BufReader::new(file)).map_err(archive_error)
+}
+
+/// The names of the entries worth showing, in the archive's own order.
+pub fn entries(archive: &Path) -> Vec<String> {
+    let Ok(zip) = open(archive) else {
+        return Vec::new();
+    };
+    zip.file_names()
+        .filter(|name| listable(name))
+        .map(str::to_string)
+        .collect()
+}
+
+fn listable(name: &str) -> bool {
+    let path = Path::new(name);
+    !name.ends_with('/')
+        && has_extension(path, ENTRY_EXTENSIONS)
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+        && !name.starts_with("__MACOSX/")
+        && !path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with("._"))
+}
+
+/// The uncompressed size of an entry.
+pub fn entry_size(archive: &Path, name: &str) -> Option<u64> {
+    open(archive).ok()?.by_name(name).ok().map(|f| f.size())
+}
+
+/// The entry's format as read from its first bytes.
+pub fn sniff(archive: &Path, name: &str) -> Option<&'static str> {
+    let mut zip = open(archive).ok()?;
+    let entry = zip.by_name(name).ok()?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    entry
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    sniff_bytes(&header)
+}
+
+pub fn read_entry(archive: &Path, name: &str) -> Result<Vec<u8>, ImageError> {
+    let mut zip = open(archive)?;
+    let entry = zip.by_name(name).map_err(archive_error)?;
+    let size = entry.size();
+    read_capped(entry, size, ENTRY_MAX_BYTES)
+}
+
+/// Reads all of `reader`, which claims to hold `size` bytes, failing once it
+/// turns out to hold more than `max`.
+pub(crate) fn read_capped(reader: impl Read, size: u64, max: u64) -> Result<Vec<u8>, ImageError> {
+    let too_large = || archive_error(format!("entry inflates to more than {max} bytes"));
+    if size > max {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::with_capacity(size as usize);
+    reader
+        .take(max + 1)
+        .read_to_end(&mut bytes)
+        .map_err(ImageError::IoError)?;
+    if bytes.len() as u64 > max {
+        return Err(too_large());
+    }
+    Ok(bytes)
+}
+
+pub fn load(archive: &Path, name: &str) -> Result<MediaData, ImageError> {
+    let bytes = read_entry(archive, name)?;
+    let hint = ImageFormat::from_path(name).ok();
+    let mut data = ImageData::load_from_memory(&bytes, hint)?;
+    data.exif = ExifData::from_bytes(&bytes);
+    Ok(MediaData::Image(Box::new(data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_image_entries_inside_the_archive_are_listed() {
+        assert!(listable("page01.jpg"));
+        assert!(listable("ch1/page02.PNG"));
+        assert!(!listable("ch1/"));
+        assert!(!listable("notes.txt"));
+        assert!(!listable("__MACOSX/ch1/._page01.jpg"));
+        assert!(!listable("ch1/._page01.jpg"));
+        assert!(!listable("../escape.jpg"));
+        assert!(!listable("/abs.jpg"));
+    }
+
+    #[test]
+    fn a_virtual_path_splits_at_the_archive() {
+        let dir = std::env::temp_dir().join(format!("bloom-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("book.cbz");
+        std::fs::write(&archive, b"PK\x05\x06").unwrap();
+
+        let (found, name) = split(&archive.join("ch1").join("p1.jpg")).unwrap();
+        assert_eq!(found, archive);
+        assert_eq!(name, "ch1/p1.jpg");
+        assert!(split(&dir.join("loose.jpg")).is_none());
+        assert!(split(&archive).is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn an_entry_decodes_from_memory() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let mut png = Vec::new();
+        image::RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 0, 0, 255, 255])
+            .unwrap()
+            .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("bloom-entry-{}.zip", std::process::id()));
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("scans/a.png", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&png).unwrap();
+        zip.finish().unwrap();
+
+        assert_eq!(entries(&path), vec!["scans/a.png"]);
+        assert_eq!(sniff(&path, "scans/a.png"), Some("png"));
+        let MediaData::Image(data) = load(&path, "scans/a.png").unwrap() else {
+            panic!("a PNG entry is a still");
+        };
+        assert_eq!((data.width, data.height), (2, 1));
+        assert_eq!(&data.pixels_snapshot()[..4], [255, 0, 0, 255]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn an_entry_larger_than_the_cap_is_refused() {
+        let data = [7u8; 10];
+        assert_eq!(read_capped(&data[..], 10, 10).unwrap(), data);
+        assert!(read_capped(&data[..], 20, 10).is_err());
+        assert!(
+            read_capped(&data[..], 4, 8).is_err(),
+            "a header that undercounts does not get past the cap"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! DICOM: medical images, whose pixels are measurements rather than colors.
+//!
+//! A CT slice stores Hounsfield units; an MR or PET slice stores whatever scale
+//! its scanner uses. Monochrome frames go through the modality LUT (the rescale
+//! slope and intercept) and are kept as those floats. The picture comes from a
+//! window applied at display time, the way an HDR image is tonemapped, so
+//! dragging level and width is a uniform write rather than a re-decode. A view
+//! opens on the file's own VOI window, or on the full range of the first
+//! frame when the file does not name one.
+//!
+//! Multi-frame files (cine loops, enhanced CT and MR) load as an Animation
+//! with one frame per slice, so the timeline scrolls through the stack. The
+//! slices decode one at a time as playback nears them, the way a numbered
+//! sequence's files do, so a stack of hundreds of slices holds only the few
+//! around the playhead. The window is view state, so every slice of the stack
+//! shares it.
+//!
+//! Color images (RGB, YBR) have no window and go through dicom-pixeldata's own
+//! conversion to RGBA8.
+//!
+//! The top-level data elements are kept as text for the info panel's tag
+//! viewer, with patient identifiers flagged so the panel can blank them.
+
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dicom_core::dictionary::{DataDictionary, DataDictionaryEntry};
+use dicom_core::{DicomValue, Tag};
+use dicom_dictionary_std::{StandardDataDictionary, tags};
+use dicom_object::{DefaultDicomObject, InMemDicomObject, open_file};
+use dicom_pixeldata::{DecodedPixelData, PixelDecoder};
+use image::ImageError;
+
+use super::image_data::{ImageData, MediaData, Source};
+use super::sequence;
+use super::tonemap::Window;
+
+/// Longest value the tag viewer shows before cutting it off. Binary elements
+/// would otherwise print thousands of numbers.
+const VALUE_MAX_CHARS: usize = 64;
+
+/// Used when the file gives neither a frame time nor a frame rate.
+const DEFAULT_FRAME_TIME: Duration = Duration::from_millis(100);
+
+/// Identifying elements outside the patient group (0010).
+const IDENTIFYING: &[Tag] = &[
+    tags::ACCESSION_NUMBER,
+    tags::INSTITUTION_NAME,
+    tags::INSTITUTION_ADDRESS,
+    tags::REFERRING_PHYSICIAN_NAME,
+    tags::PHYSICIANS_OF_RECORD,
+    tags::PERFORMING_PHYSICIAN_NAME,
+    tags::OPERATORS_NAME,
+    tags::REQUESTING_PHYSICIAN,
+    tags::STUDY_ID,
+];
+
+#[derive(Debug, Clone)]
+pub struct DicomTag {
+    pub tag: (u16, u16),
+    pub name: String,
+    pub value: String,
+    /// Names, identifies or dates the patient.
+    pub identifying: bool,
+}
+
+#[derive(Debug)]
+pub struct DicomInfo {
+    pub tags: Vec<DicomTag>,
+    /// The window the view opens on, None for a color image.
+    pub window: Option<Window>,
+    /// Every window the file suggests, plus the full data range.
+    pub presets: Vec<(String, Window)>,
+}
+
+fn dicom_error(e: impl std::error::Error + Send + Sync + 'static) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+pub fn load(path: &Path) -> Result<MediaData, ImageError> {
+    let obj = open_file(path).map_err(dicom_error)?;
+    let count = obj
+        .element(tags::NUMBER_OF_FRAMES)
+        .ok()
+        .and_then(|e| e.to_int::<u32>().ok())
+        .unwrap_or(1)
+        .max(1);
+    let photometric = text(&obj, tags::PHOTOMETRIC_INTERPRETATION).unwrap_or_default();
+    let bits = obj
+        .element(tags::BITS_STORED)
+        .ok()
+        .and_then(|e| e.to_int::<u8>().ok());
+
+    let (info, first) = {
+        let pixels = obj.decode_pixel_data_frame(0).map_err(dicom_error)?;
+        let mut values = None;
+        let presets = if pixels.samples_per_pixel() == 1 {
+            let first: Vec<f32> = pixels.to_vec_frame(0).map_err(dicom_error)?;
+            let range = first
+                .iter()
+                .filter(|v| v.is_finite())
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| {
+                    (lo.min(v), hi.max(v))
+                });
+            values = Some(first);
+            presets(&obj, range, photometric == "MONOCHROME1")
+        } else {
+            Vec::new()
+        };
+        let info = Arc::new(DicomInfo {
+            tags: read_tags(&obj),
+            window: presets.first().map(|(_, w)| *w),
+            presets,
+        });
+        let first = slice_image(&pixels, values, info.window)?;
+        (info, first)
+    };
+
+    let delay = frame_time(&obj);
+    let slices = Slices {
+        obj,
+        count,
+        info,
+        photometric,
+        bits,
+    };
+    let first = slices.label(first);
+    if count == 1 {
+        return Ok(MediaData::Image(Box::new(first)));
+    }
+    Ok(sequence::from_slices(slices, delay, first))
+}
+
+/// The slices of a multi-frame file, each decoded from the pixel data when
+/// playback nears it. See sequence.rs.
+#[derive(Debug)]
+pub struct Slices {
+    obj: DefaultDicomObject,
+    count: u32,
+    info: Arc<DicomInfo>,
+    photometric: String,
+    bits: Option<u8>,
+}
+
+impl Slices {
+    pub fn frame_count(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn decode(&self, index: usize) -> Result<ImageData, ImageError> {
+        let pixels = self
+            .obj
+            .decode_pixel_data_frame(index as u32)
+            .map_err(dicom_error)?;
+        let image = slice_image(&pixels, None, self.info.window)?;
+        Ok(self.label(image))
+    }
+
+    fn label(&self, mut data: ImageData) -> ImageData {
+        data.color_space = Some(self.photometric.clone()).filter(|p| !p.is_empty());
+        if let Some(bits) = self.bits {
+            data.bit_depth = bits;
+        }
+        data.source = Source::Dicom(Arc::clone(&self.info));
+        data
+    }
+}
+
+/// One decoded frame as an image: measurements seen through `window`, or
+/// RGBA8 for color. `values` are the frame's samples when already read.
+fn slice_image(
+    pixels: &DecodedPixelData,
+    values: Option<Vec<f32>>,
+    window: Option<Window>,
+) -> Result<ImageData, ImageError> {
+    let (width, height) = (pixels.columns(), pixels.rows());
+    Ok(match window {
+        Some(window) => {
+            let values = match values {
+                Some(values) => values,
+                None => pixels.to_vec_frame(0).map_err(dicom_error)?,
+            };
+            let samples = values.iter().flat_map(|&v| [v, v, v, 1.0]).collect();
+            ImageData::from_data_values(samples, width, height, window)
+        }
+        None => {
+            let img = pixels
+                .to_dynamic_image(0)
+                .map_err(dicom_error)?
+                .into_rgba8();
+            ImageData::new(img.into_raw(), width, height)
+        }
+    })
+}
+
+fn text(obj: &InMemDicomObject, tag: Tag) -> Option<String> {
+    let value = obj.element(tag).ok()?.to_str().ok()?;
+    Some(value.trim().to_string())
+}
+
+fn floats(obj: &InMemDicomObject, tag: Tag) -> Vec<f32> {
+    obj.element(tag)
+        .ok()
+        .and_then(|e| e.to_multi_float64().ok())
+        .map(|v| v.into_iter().map(|f| f as f32).collect())
+        .unwrap_or_default()
+}
+
+/// The file's VOI windows in order, then one over the full range of `range`.
+fn presets(obj: &InMemDicomObject, range: (f32, f32), invert: bool) -> Vec<(String, Window)> {
+    let centers = floats(obj, tags::WINDOW_CENTER);
+    let widths = floats(obj, tags::WINDOW_WIDTH);
+    let explanation = text(obj, tags::WINDOW_CENTER_WIDTH_EXPLANATION).unwrap_or_default();
+    let names: Vec<&str> = explanation.split('\\').map(str::trim).collect();
+
+    let mut presets: Vec<(String, Window)> = centers
+        .iter()
+        .zip(&widths)
+        .enumerate()
+        .filter(|(_, (_, w))| **w > 0.0)
+        .map(|(i, (&center, &width))| {
+            let name = names.get(i).filter(|n| !n.is_empty());
+            (
+                name.map_or_else(|| format!("Window {}", i + 1), |n| n.to_string()),
+                Window {
+                    center,
+                    width,
+                    invert,
+                },
+            )
+        })
+        .collect();
+
+    let (lo, hi) = if range.0 <= range.1 {
+        range
+    } else {
+        (0.0, 1.0)
+    };
+    presets.push((
+        "Full range".to_string(),
+        Window {
+            center: (lo + hi) / 2.0,
+            width: (hi - lo).max(1.0),
+            invert,
+        },
+    ));
+    presets
+}
+
+fn frame_time(obj: &InMemDicomObject) -> Duration {
+    let ms = floats(obj, tags::FRAME_TIME).first().copied().or_else(|| {
+        [tags::CINE_RATE, tags::RECOMMENDED_DISPLAY_FRAME_RATE]
+            .into_iter()
+            .find_map(|tag| floats(obj, tag).first().copied())
+            .filter(|fps| *fps > 0.0)
+            .map(|fps| 1000.0 / fps)
+    });
+    ms.filter(|ms| ms.is_finite() && *ms > 0.0)
+        .map_or(DEFAULT_FRAME_TIME, |ms| {
+            Duration::from_secs_f32(ms / 1000.0)
+        })
+}
+
+fn read_tags(obj: &InMemDicomObject) -> Vec<DicomTag> {
+    obj.iter()
+        .filter(|e| e.header().tag != tags::PIXEL_DATA)
+        .map(|e| {
+            let tag = e.header().tag;
+            let name = StandardDataDictionary
+                .by_tag(tag)
+                .map(|entry| entry.alias().to_string())
+                .unwrap_or_else(|| {
+                    if tag.group() % 2 == 1 {
+                        "Private".to_string()
+                    } else {
+                        "Unknown".to_string()
+                    }
+                });
+            let value = match e.value() {
+                DicomValue::Primitive(v) => v.to_str().trim().to_string(),
+                DicomValue::Sequence(s) => format!("({} items)", s.items().len()),
+                DicomValue::PixelSequence(_) => "(encapsulated)".to_string(),
+            };
+            DicomTag {
+                tag: (tag.group(), tag.element()),
+                name,
+                value: truncate(value),
+                identifying: tag.group() == 0x0010 || IDENTIFYING.contains(&tag),
+            }
+        })
+        .collect()
+}
+
+fn truncate(value: String) -> String {
+    match value.char_indices().nth(VALUE_MAX_CHARS) {
+        Some((i, _)) => format!("{}…", &value[..i]),
+        None => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::{DataElement, VR, dicom_value};
+
+    #[test]
+    fn presets_follow_the_file_then_the_full_range() {
+        let obj = InMemDicomObject::from_element_iter([
+            DataElement::new(
+                tags::WINDOW_CENTER,
+                VR::DS,
+                dicom_value!(F64, [40.0, 400.0]),
+            ),
+            DataElement::new(
+                tags::WINDOW_WIDTH,
+                VR::DS,
+                dicom_value!(F64, [80.0, 2000.0]),
+            ),
+            DataElement::new(
+                tags::WINDOW_CENTER_WIDTH_EXPLANATION,
+                VR::LO,
+                dicom_value!(Strs, ["BRAIN", ""]),
+            ),
+        ]);
+        let presets = presets(&obj, (-1024.0, 3071.0), true);
+        let names: Vec<_> = presets.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["BRAIN", "Window 2", "Full range"]);
+        assert_eq!(
+            presets[0].1,
+            Window {
+                center: 40.0,
+                width: 80.0,
+                invert: true,
+            }
+        );
+        assert_eq!(presets[2].1.center, 1023.5);
+        assert_eq!(presets[2].1.width, 4095.0);
+    }
+
+    #[test]
+    fn patient_tags_are_flagged_and_pixel_data_is_skipped() {
+        let obj = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::PATIENT_NAME, VR::PN, dicom_value!(Str, "Doe^Jane")),
+            DataElement::new(tags::MODALITY, VR::CS, dicom_value!(Str, "CT")),
+            DataElement::new(tags::PIXEL_DATA, VR::OW, dicom_value!(U16, [0, 1])),
+        ]);
+        let read = read_tags(&obj);
+        let seen: Vec<_> = read
+            .iter()
+            .map(|t| (t.name.as_str(), t.value.as_str(), t.identifying))
+            .collect();
+        assert_eq!(
+            seen,
+            [("PatientName", "Doe^Jane", true), ("Modality", "CT", false)]
+        );
+    }
+
+    #[test]
+    fn long_values_are_cut_on_a_character_boundary() {
+        let long = "é".repeat(VALUE_MAX_CHARS + 10);
+        let cut = truncate(long);
+        assert_eq!(cut.chars().count(), VALUE_MAX_CHARS + 1);
+        assert!(cut.ends_with('…'));
+        assert_eq!(truncate("HEAD".to_string()), "HEAD");
+    }
+}
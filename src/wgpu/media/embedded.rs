@@ -0,0 +1,105 @@
+//! Embedded previews, shown while the real decode runs.
+//!
+//! A camera writes a full-size or near full-size JPEG into every RAW beside
+//! the sensor data, so that its own screen has something to show. Decoding
+//! that JPEG takes a fraction of the time a RawDevelop does, which makes it
+//! the first thing to paint when flicking through a folder of RAWs. When the
+//! file's decoder finds no such preview, its thumbnail does, and failing that
+//! the EXIF thumbnail of a TIFF-based RAW.
+//!
+//! A large JPEG gets its EXIF thumbnail. That one is small, typically 160 by
+//! 120, but it is there almost at once and holds the picture's layout until
+//! the full decode lands.
+//!
+//! A preview carries the file's EXIF, since the camera stores it in sensor
+//! orientation just like the RAW data, and is marked embedded_preview so the
+//! info panel can say it is not the real thing.
+
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use exif::{In, Reader, Tag};
+use image::{ImageFormat, ImageReader};
+
+use super::exif_data::ExifData;
+use super::image_data::{ImageData, Source};
+use super::stream::too_large_to_hold;
+
+/// JPEGs smaller than this decode fast enough without a thumbnail first.
+const JPEG_PREVIEW_MIN_BYTES: u64 = 8 * 1024 * 1024;
+
+/// The preview JPEG a camera embeds in a RAW.
+pub fn raw_preview(path: &Path) -> Option<ImageData> {
+    let image = camera_preview(path)
+        .map(ImageData::from_dynamic)
+        .or_else(|| exif_thumbnail(path))?;
+    Some(mark(image, path))
+}
+
+/// The EXIF thumbnail of a JPEG big enough to be worth one.
+pub fn jpeg_preview(path: &Path) -> Option<ImageData> {
+    let size = std::fs::metadata(path).ok()?.len();
+    if size < JPEG_PREVIEW_MIN_BYTES {
+        return None;
+    }
+    Some(mark(exif_thumbnail(path)?, path))
+}
+
+fn mark(mut image: ImageData, path: &Path) -> ImageData {
+    image.exif = ExifData::read(path);
+    image.source = Source::EmbeddedPreview;
+    image
+}
+
+fn camera_preview(path: &Path) -> Option<image::DynamicImage> {
+    let source = rawler::rawsource::RawSource::new(path).ok()?;
+    let decoder = rawler::get_decoder(&source).ok()?;
+    let params = rawler::decoders::RawDecodeParams::default();
+    decoder
+        .preview_image(&source, &params)
+        .ok()
+        .flatten()
+        .or_else(|| decoder.thumbnail_image(&source, &params).ok().flatten())
+}
+
+fn exif_thumbnail(path: &Path) -> Option<ImageData> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = Reader::new().read_from_container(&mut reader).ok()?;
+    let field = |tag| exif.get_field(tag, In::THUMBNAIL)?.value.get_uint(0);
+    let bytes = thumbnail_bytes(
+        exif.buf(),
+        field(Tag::JPEGInterchangeFormat)?,
+        field(Tag::JPEGInterchangeFormatLength)?,
+    )?;
+    // A few kilobytes can claim any size, so check before decoding.
+    let (width, height) = ImageReader::with_format(Cursor::new(bytes), ImageFormat::Jpeg)
+        .into_dimensions()
+        .ok()?;
+    if too_large_to_hold(width, height) {
+        return None;
+    }
+    ImageData::load_from_memory(bytes, Some(ImageFormat::Jpeg)).ok()
+}
+
+/// The thumbnail's bytes within the EXIF block, when its offset and length
+/// fit inside it.
+fn thumbnail_bytes(buf: &[u8], offset: u32, len: u32) -> Option<&[u8]> {
+    let start = offset as usize;
+    let bytes = buf.get(start..start.checked_add(len as usize)?)?;
+    bytes.starts_with(b"\xff\xd8").then_some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_thumbnail_must_lie_inside_the_exif_block() {
+        let buf = b"II*\0\xff\xd8\xff\xdbdata\xff\xd9";
+        assert_eq!(thumbnail_bytes(buf, 4, 10), Some(&buf[4..]));
+        assert_eq!(thumbnail_bytes(buf, 4, 11), None);
+        assert_eq!(thumbnail_bytes(buf, 0, 4), None, "not a JPEG");
+        assert_eq!(thumbnail_bytes(buf, u32::MAX, u32::MAX), None);
+    }
+}
@@ -1,5 +1,5 @@
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader, Cursor, Seek};
 use std::path::Path;
 
 use exif::{Exif, Field, In, Reader, Tag, Value};
@@ -9,6 +9,8 @@ pub struct ExifData {
     pub make: Option<String>,
     pub model: Option<String>,
     pub datetime: Option<String>,
+    /// When the picture was taken, where `datetime` is when it was last saved.
+    pub captured: Option<String>,
     pub exposure_time: Option<String>,
     pub f_number: Option<String>,
     pub iso: Option<String>,
@@ -16,6 +18,7 @@ pub struct ExifData {
     pub gps: Option<String>,
     pub dpi: Option<String>,
     pub color_space: Option<String>,
+    pub orientation: Option<u16>,
 }
 
 impl ExifData {
@@ -23,20 +26,68 @@ impl ExifData {
         let Ok(file) = File::open(path) else {
             return Self::default();
         };
-        let Ok(exif) = Reader::new().read_from_container(&mut BufReader::new(file)) else {
-            return Self::default();
+        Self::from_container(&mut BufReader::new(file))
+    }
+
+    /// Reads the metadata of a file already in memory.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self::from_container(&mut Cursor::new(bytes))
+    }
+
+    fn from_container<R: BufRead + Seek>(reader: &mut R) -> Self {
+        match Reader::new().read_from_container(reader) {
+            Ok(exif) => Self::from_exif(&exif),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Reads the metadata of one directory of a TIFF, the page at `offset`,
+    /// where reading the file as a whole only ever sees the first. The
+    /// header is pointed at that directory instead, since everything in a
+    /// TIFF is located by its offset from the start.
+    pub fn from_tiff_page(mut tiff: Vec<u8>, offset: u32) -> Self {
+        let offset = match tiff.get(..4) {
+            Some(b"II*\0") => offset.to_le_bytes(),
+            Some(b"MM\0*") => offset.to_be_bytes(),
+            _ => return Self::default(),
         };
+        tiff[4..8].copy_from_slice(&offset);
+        match Reader::new().read_raw(tiff) {
+            Ok(exif) => Self::from_exif(&exif),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn from_exif(exif: &Exif) -> Self {
         Self {
-            make: str_field(&exif, Tag::Make),
-            model: str_field(&exif, Tag::Model),
-            datetime: str_field(&exif, Tag::DateTime),
-            exposure_time: exposure_time_str(&exif),
-            f_number: f_number_str(&exif),
-            iso: str_field(&exif, Tag::PhotographicSensitivity),
-            focal_length: focal_length_str(&exif),
-            gps: gps_str(&exif),
-            dpi: dpi_str(&exif),
-            color_space: color_space_str(&exif),
+            make: str_field(exif, Tag::Make),
+            model: str_field(exif, Tag::Model),
+            datetime: str_field(exif, Tag::DateTime),
+            captured: str_field(exif, Tag::DateTimeOriginal),
+            exposure_time: exposure_time_str(exif),
+            f_number: f_number_str(exif),
+            iso: str_field(exif, Tag::PhotographicSensitivity),
+            focal_length: focal_length_str(exif),
+            gps: gps_str(exif),
+            dpi: dpi_str(exif),
+            color_space: color_space_str(exif),
+            orientation: orientation_tag(exif),
+        }
+    }
+
+    /// Quarter turns clockwise and whether to mirror horizontally first, to
+    /// bring the stored pixels upright. None when the tag is absent or invalid.
+    pub fn display_transform(&self) -> Option<(u8, bool)> {
+        match self.orientation? {
+            1 => Some((0, false)),
+            2 => Some((0, true)),
+            3 => Some((2, false)),
+            4 => Some((2, true)),
+            5 => Some((3, true)),
+            6 => Some((1, false)),
+            7 => Some((1, true)),
+            8 => Some((3, false)),
+            _ => None,
         }
     }
 }
@@ -133,6 +184,14 @@ fn dpi_str(exif: &Exif) -> Option<String> {
     })
 }
 
+fn orientation_tag(exif: &Exif) -> Option<u16> {
+    if let Value::Short(ref v) = exif.get_field(Tag::Orientation, In::PRIMARY)?.value {
+        v.first().copied()
+    } else {
+        None
+    }
+}
+
 fn color_space_str(exif: &Exif) -> Option<String> {
     if let Value::Short(ref v) = exif.get_field(Tag::ColorSpace, In::PRIMARY)?.value {
         match v.first().copied()? {
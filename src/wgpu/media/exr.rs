@@ -0,0 +1,512 @@
+//! OpenEXR documents: every part, layer and channel of a file, not just the
+//! beauty pass.
+//!
+//! Render EXRs carry their AOVs as dotted channel names inside one part
+//! (`diffuse.R`, `N.X`, `Z`), as separately named parts, or both. All of it is
+//! flattened into one list of layers here by grouping channels on the name
+//! before their last dot, so a `beauty` part's `diffuse.R` lands in
+//! `beauty.diffuse` and a bare `Z` lands in the unnamed default layer.
+//!
+//! A layer with red, green and blue (or only luminance) is shown as color and
+//! stays scene-linear, so the view's tonemap applies to it like to any other
+//! HDR image. Any single channel can be shown instead, mapped through a range
+//! to grayscale or false color. Depth, normals and masks are data rather than
+//! light, so those renderings are not tonemapped. Cryptomatte channels hold
+//! float-encoded hashes, which only make sense shown as one color per ID.
+//!
+//! The decoded document stays alive alongside whichever rendering of it is on
+//! screen, so switching layers re-renders from memory instead of rereading
+//! the file.
+
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+use exr::prelude::{FlatSamples, ReadChannels, ReadLayers, read};
+use half::f16;
+use image::ImageError;
+use rayon::prelude::*;
+
+use super::image_data::{ImageData, Source};
+use super::samples::DeepPixels;
+
+/// Samples taken for a channel's automatic range. Enough for stable
+/// percentiles without sorting a whole 8K plane.
+const RANGE_SAMPLES: usize = 1 << 16;
+
+#[derive(Debug)]
+enum Plane {
+    F16(Vec<f16>),
+    F32(Vec<f32>),
+}
+
+impl Plane {
+    fn len(&self) -> usize {
+        match self {
+            Plane::F16(v) => v.len(),
+            Plane::F32(v) => v.len(),
+        }
+    }
+
+    fn get(&self, i: usize) -> f32 {
+        match self {
+            Plane::F16(v) => v[i].to_f32(),
+            Plane::F32(v) => v[i],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExrChannel {
+    /// The name within its layer: `R` for `diffuse.R`.
+    pub name: String,
+    plane: Plane,
+}
+
+impl ExrChannel {
+    pub fn is_half(&self) -> bool {
+        matches!(self.plane, Plane::F16(_))
+    }
+
+    /// A range covering all but the extreme half percent at either end, so a
+    /// depth pass with an "infinitely far" background still shows its detail.
+    fn auto_range(&self) -> (f32, f32) {
+        let step = (self.plane.len() / RANGE_SAMPLES).max(1);
+        let mut values: Vec<f32> = (0..self.plane.len())
+            .step_by(step)
+            .map(|i| self.plane.get(i))
+            .filter(|v| v.is_finite())
+            .collect();
+        if values.is_empty() {
+            return (0.0, 1.0);
+        }
+        values.sort_unstable_by(f32::total_cmp);
+        let at = |q: f32| values[((values.len() - 1) as f32 * q).round() as usize];
+        let (lo, hi) = (at(0.005), at(0.995));
+        if hi > lo { (lo, hi) } else { (lo, lo + 1.0) }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExrLayer {
+    /// Empty for channels with no dotted prefix in an unnamed part.
+    pub name: String,
+    pub channels: Vec<ExrChannel>,
+    width: usize,
+    height: usize,
+    /// Where the layer's data window sits on the document canvas.
+    offset: (usize, usize),
+    color: Option<[usize; 3]>,
+    alpha: Option<usize>,
+}
+
+impl ExrLayer {
+    pub fn label(&self) -> &str {
+        if self.name.is_empty() {
+            "Default"
+        } else {
+            &self.name
+        }
+    }
+
+    pub fn has_color(&self) -> bool {
+        self.color.is_some()
+    }
+
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|c| names.iter().any(|n| c.name.eq_ignore_ascii_case(n)))
+    }
+
+    fn is_cryptomatte(&self) -> bool {
+        self.name.to_ascii_lowercase().contains("crypto")
+    }
+
+    /// Index into the layer's planes for a document pixel, if the layer
+    /// covers it.
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        let (x, y) = (x.checked_sub(self.offset.0)?, y.checked_sub(self.offset.1)?);
+        (x < self.width && y < self.height).then(|| y * self.width + x)
+    }
+}
+
+#[derive(Debug)]
+pub struct ExrDocument {
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<ExrLayer>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    #[default]
+    Grayscale,
+    FalseColor,
+    Ids,
+}
+
+impl ChannelMode {
+    pub const ALL: &[(ChannelMode, &str)] = &[
+        (ChannelMode::Grayscale, "Grayscale"),
+        (ChannelMode::FalseColor, "False color"),
+        (ChannelMode::Ids, "IDs"),
+    ];
+
+    fn map(self, v: f32, (lo, hi): (f32, f32)) -> [f32; 3] {
+        if self == ChannelMode::Ids {
+            return id_color(v);
+        }
+        if !v.is_finite() {
+            return [0.0; 3];
+        }
+        let span = hi - lo;
+        let t = if span == 0.0 { 0.0 } else { (v - lo) / span };
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            ChannelMode::FalseColor => turbo(t),
+            _ => [t; 3],
+        }
+    }
+}
+
+/// Which rendering of a document is on screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExrView {
+    pub layer: usize,
+    /// None shows the layer's color channels together.
+    pub channel: Option<usize>,
+    pub mode: ChannelMode,
+    /// Channel values mapped to black and white. Unused for color.
+    pub range: (f32, f32),
+}
+
+#[derive(Debug, Clone)]
+pub struct ExrSelection {
+    pub document: Arc<ExrDocument>,
+    pub view: ExrView,
+}
+
+struct Part {
+    name: Option<String>,
+    position: (i64, i64),
+    size: (usize, usize),
+    channels: Vec<(String, Plane)>,
+}
+
+impl ExrDocument {
+    pub fn read(path: &Path) -> Result<Self, ImageError> {
+        let image = read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+            .from_file(path)
+            .map_err(|e| ImageError::IoError(Error::other(e)))?;
+
+        let parts = image
+            .layer_data
+            .into_iter()
+            .map(|layer| Part {
+                name: layer.attributes.layer_name.as_ref().map(|n| n.to_string()),
+                position: (
+                    layer.attributes.layer_position.0 as i64,
+                    layer.attributes.layer_position.1 as i64,
+                ),
+                size: (layer.size.0, layer.size.1),
+                channels: layer
+                    .channel_data
+                    .list
+                    .into_iter()
+                    .map(|c| {
+                        let plane = match c.sample_data {
+                            FlatSamples::F16(v) => Plane::F16(v),
+                            FlatSamples::F32(v) => Plane::F32(v),
+                            FlatSamples::U32(v) => {
+                                Plane::F32(v.into_iter().map(|s| s as f32).collect())
+                            }
+                        };
+                        (c.name.to_string(), plane)
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self::assemble(parts)
+    }
+
+    fn assemble(parts: Vec<Part>) -> Result<Self, ImageError> {
+        let x0 = parts.iter().map(|p| p.position.0).min();
+        let y0 = parts.iter().map(|p| p.position.1).min();
+        let x1 = parts.iter().map(|p| p.position.0 + p.size.0 as i64).max();
+        let y1 = parts.iter().map(|p| p.position.1 + p.size.1 as i64).max();
+        let (Some(x0), Some(y0), Some(x1), Some(y1)) = (x0, y0, x1, y1) else {
+            return Err(ImageError::IoError(Error::other("EXR has no layers")));
+        };
+
+        let mut layers: Vec<ExrLayer> = Vec::new();
+        for part in parts {
+            let (width, height) = part.size;
+            let offset = (
+                (part.position.0 - x0) as usize,
+                (part.position.1 - y0) as usize,
+            );
+            for (channel, plane) in part.channels {
+                // Subsampled channels (luminance/chroma files) do not line up
+                // with the pixel grid.
+                if plane.len() != width * height {
+                    continue;
+                }
+                let full = match &part.name {
+                    Some(prefix) => format!("{prefix}.{channel}"),
+                    None => channel,
+                };
+                let (layer_name, name) = match full.rsplit_once('.') {
+                    Some((layer, name)) => (layer.to_string(), name.to_string()),
+                    None => (String::new(), full),
+                };
+                let existing = layers.iter().position(|l| {
+                    l.name == layer_name && l.offset == offset && (l.width, l.height) == part.size
+                });
+                let index = existing.unwrap_or_else(|| {
+                    layers.push(ExrLayer {
+                        name: layer_name,
+                        channels: Vec::new(),
+                        width,
+                        height,
+                        offset,
+                        color: None,
+                        alpha: None,
+                    });
+                    layers.len() - 1
+                });
+                layers[index].channels.push(ExrChannel { name, plane });
+            }
+        }
+        if layers.is_empty() {
+            return Err(ImageError::IoError(Error::other(
+                "EXR has no full-resolution channels",
+            )));
+        }
+
+        layers.sort_by(|a, b| (!a.name.is_empty(), &a.name).cmp(&(!b.name.is_empty(), &b.name)));
+        for layer in &mut layers {
+            layer
+                .channels
+                .sort_by(|a, b| channel_rank(&a.name).cmp(&channel_rank(&b.name)));
+            layer.color = match (
+                layer.find(&["R", "red"]),
+                layer.find(&["G", "green"]),
+                layer.find(&["B", "blue"]),
+            ) {
+                (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+                _ => layer.find(&["Y"]).map(|y| [y; 3]),
+            };
+            layer.alpha = layer.find(&["A", "alpha"]);
+        }
+
+        Ok(Self {
+            width: (x1 - x0) as u32,
+            height: (y1 - y0) as u32,
+            layers,
+        })
+    }
+
+    /// The first layer that has color, which is the beauty pass in any
+    /// renderer's output.
+    pub fn default_view(&self) -> ExrView {
+        let layer = self.layers.iter().position(|l| l.has_color()).unwrap_or(0);
+        self.view_of(layer, None)
+    }
+
+    /// A fresh view of a layer or one of its channels, with the range fitted
+    /// to the channel's values. A layer with no color falls back to its first
+    /// channel.
+    pub fn view_of(&self, layer: usize, channel: Option<usize>) -> ExrView {
+        let layer = layer.min(self.layers.len() - 1);
+        let l = &self.layers[layer];
+        let channel = channel
+            .filter(|&c| c < l.channels.len())
+            .or_else(|| (!l.has_color()).then_some(0));
+        match channel {
+            Some(c) => ExrView {
+                layer,
+                channel: Some(c),
+                mode: if l.is_cryptomatte() {
+                    ChannelMode::Ids
+                } else {
+                    ChannelMode::Grayscale
+                },
+                range: l.channels[c].auto_range(),
+            },
+            None => ExrView {
+                layer,
+                channel: None,
+                mode: ChannelMode::default(),
+                range: (0.0, 1.0),
+            },
+        }
+    }
+
+    pub fn render(self: &Arc<Self>, view: ExrView) -> ImageData {
+        let layer = &self.layers[view.layer.min(self.layers.len() - 1)];
+        let width = self.width as usize;
+        let mut samples = vec![0.0f32; width * self.height as usize * 4];
+        samples.par_chunks_mut(4).enumerate().for_each(|(i, px)| {
+            let Some(j) = layer.index(i % width, i / width) else {
+                return;
+            };
+            match view.channel {
+                Some(c) => {
+                    px[..3].copy_from_slice(
+                        &view.mode.map(layer.channels[c].plane.get(j), view.range),
+                    );
+                    px[3] = 1.0;
+                }
+                None => {
+                    for (dst, &c) in px.iter_mut().zip(&layer.color.unwrap_or_default()) {
+                        *dst = layer.channels[c].plane.get(j);
+                    }
+                    px[3] = layer
+                        .alpha
+                        .map_or(1.0, |a| layer.channels[a].plane.get(j).clamp(0.0, 1.0));
+                }
+            }
+        });
+
+        let mut data = match view.channel {
+            Some(_) => ImageData::with_deep(DeepPixels::F32(samples), self.width, self.height),
+            None => ImageData::from_scene_linear(samples, self.width, self.height),
+        };
+        data.source = Source::Exr(ExrSelection {
+            document: Arc::clone(self),
+            view,
+        });
+        data
+    }
+}
+
+/// Color channels first in their usual order, everything else alphabetical.
+fn channel_rank(name: &str) -> (u8, String) {
+    let rank = match name.to_ascii_uppercase().as_str() {
+        "R" | "RED" => 0,
+        "G" | "GREEN" => 1,
+        "B" | "BLUE" => 2,
+        "A" | "ALPHA" => 3,
+        _ => 4,
+    };
+    (rank, name.to_string())
+}
+
+/// Scatters the bits of a float-encoded ID so neighbouring IDs get unrelated
+/// colors. Zero is empty coverage and stays black.
+fn id_color(v: f32) -> [f32; 3] {
+    if v == 0.0 {
+        return [0.0; 3];
+    }
+    let mut h = v.to_bits();
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    [h, h >> 8, h >> 16].map(|c| (c & 0xff) as f32 / 255.0)
+}
+
+/// Polynomial fit of Google's Turbo colormap.
+fn turbo(t: f32) -> [f32; 3] {
+    let poly = |c: [f32; 6]| {
+        (c[0] + t * (c[1] + t * (c[2] + t * (c[3] + t * (c[4] + t * c[5]))))).clamp(0.0, 1.0)
+    };
+    [
+        poly([0.135721, 4.61539, -42.6603, 132.131, -152.942, 59.2864]),
+        poly([0.0914026, 2.19419, 4.84297, -14.185, 4.2773, 2.82957]),
+        poly([0.106673, 12.6419, -60.582, 110.363, -89.9031, 27.3482]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(name: Option<&str>, channels: &[(&str, Vec<f32>)]) -> Part {
+        Part {
+            name: name.map(str::to_string),
+            position: (0, 0),
+            size: (2, 1),
+            channels: channels
+                .iter()
+                .map(|(n, v)| (n.to_string(), Plane::F32(v.clone())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn channels_group_by_the_name_before_their_last_dot() {
+        let doc = ExrDocument::assemble(vec![
+            part(
+                None,
+                &[
+                    ("B", vec![0.0; 2]),
+                    ("G", vec![0.0; 2]),
+                    ("R", vec![0.0; 2]),
+                    ("Z", vec![0.0; 2]),
+                    ("diffuse.R", vec![0.0; 2]),
+                ],
+            ),
+            part(Some("spec"), &[("indirect.G", vec![0.0; 2])]),
+        ])
+        .unwrap();
+        let names: Vec<_> = doc.layers.iter().map(|l| l.label()).collect();
+        assert_eq!(names, ["Default", "diffuse", "spec.indirect"]);
+        let default: Vec<_> = doc.layers[0].channels.iter().map(|c| &c.name).collect();
+        assert_eq!(default, ["R", "G", "B", "Z"]);
+        assert!(doc.layers[0].has_color());
+        assert!(!doc.layers[1].has_color());
+        assert_eq!(doc.default_view().layer, 0);
+    }
+
+    #[test]
+    fn depth_range_ignores_an_infinitely_far_background() {
+        let mut depth: Vec<f32> = (0..1000).map(|i| 1.0 + i as f32 / 100.0).collect();
+        depth.extend(std::iter::repeat_n(f32::INFINITY, 100));
+        depth.extend(std::iter::repeat_n(1e10, 4));
+        let channel = ExrChannel {
+            name: "Z".into(),
+            plane: Plane::F32(depth),
+        };
+        let (lo, hi) = channel.auto_range();
+        assert!(lo < 1.1 && (10.0..11.0).contains(&hi), "{lo}..{hi}");
+    }
+
+    #[test]
+    fn color_stays_scene_linear_and_channels_do_not() {
+        let doc = Arc::new(
+            ExrDocument::assemble(vec![part(
+                None,
+                &[
+                    ("R", vec![4.0, 0.5]),
+                    ("G", vec![4.0, 0.5]),
+                    ("B", vec![4.0, 0.5]),
+                    ("Z", vec![2.0, 6.0]),
+                ],
+            )])
+            .unwrap(),
+        );
+        let color = doc.render(doc.default_view());
+        assert!(color.scene_linear);
+        let Some(DeepPixels::F32(s)) = color.deep_snapshot().as_deref().cloned() else {
+            panic!("color keeps float samples");
+        };
+        assert_eq!(s[0], 4.0, "highlights are not clipped before the tonemap");
+
+        let mut view = doc.view_of(0, Some(3));
+        view.range = (2.0, 6.0);
+        let depth = doc.render(view);
+        assert!(!depth.scene_linear);
+        assert_eq!(
+            &depth.pixels_snapshot()[..],
+            &[0, 0, 0, 255, 255, 255, 255, 255]
+        );
+    }
+}
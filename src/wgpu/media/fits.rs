@@ -0,0 +1,794 @@
+//! FITS: astronomical images, whose pixels are photon counts or fluxes
+//! spanning an enormous range.
+//!
+//! A file is a chain of HDUs (header and data units), and any of them can hold
+//! an image: the primary one, or an IMAGE extension further in, which is where
+//! instruments put their separate detectors or a science, error and mask
+//! triple. Every HDU is listed and any image among them can be shown. Axes past
+//! the second (NAXIS3 and up) make a cube, shown one plane at a time.
+//!
+//! The format is read directly rather than through a FITS crate. Headers are
+//! 80-character text cards in 2880-byte blocks and image data is big-endian
+//! samples, and the info panel wants the cards exactly as written anyway.
+//! BSCALE and BZERO are applied on read, and an integer image's BLANK value
+//! becomes NaN, which is drawn transparent. Only the plane on screen is read,
+//! so a cube larger than memory still opens.
+//!
+//! Sky images are mostly empty background with a few bright sources, so a
+//! min/max ramp shows the stars on black and nothing else. The plane is
+//! stretched instead: values between the black and white points go through a
+//! linear, log, square-root or asinh curve. ZScale is IRAF's way of picking
+//! those points, fitting a line to the sorted pixel values with outliers
+//! rejected so the points bracket the background noise and the faint structure
+//! just above it. Every HDU opens on its ZScale points.
+//!
+//! FITS counts rows from the bottom, so the first row is drawn at the bottom of
+//! the view, as in every astronomy viewer.
+//!
+//! A header with a celestial WCS (CTYPE RA---TAN and the like) lets the cursor
+//! readout give right ascension and declination. The zenithal projections TAN,
+//! SIN and ARC are handled; other projections and SIP distortion are not.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::ImageError;
+use rayon::prelude::*;
+
+use super::image_data::{ImageData, Source};
+use super::samples::DeepPixels;
+
+const BLOCK: usize = 2880;
+const CARD: usize = 80;
+
+/// Values ZScale fits its line to. IRAF's default.
+const ZSCALE_SAMPLES: usize = 1000;
+/// How far the points sit from the median, as a fraction of the fitted slope.
+const ZSCALE_CONTRAST: f64 = 0.25;
+/// Residuals beyond this many sigma are rejected from the fit.
+const ZSCALE_REJECT: f64 = 2.5;
+const ZSCALE_ITERATIONS: usize = 5;
+
+/// DS9's log exponent.
+const LOG_EXPONENT: f32 = 1000.0;
+/// Where asinh turns from linear to logarithmic, as in DS9.
+const ASINH_SOFTENING: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Stretch {
+    Linear,
+    Log,
+    Sqrt,
+    Asinh,
+    #[default]
+    ZScale,
+}
+
+impl Stretch {
+    pub const ALL: &[(Stretch, &str)] = &[
+        (Stretch::Linear, "Linear"),
+        (Stretch::Log, "Log"),
+        (Stretch::Sqrt, "Sqrt"),
+        (Stretch::Asinh, "Asinh"),
+        (Stretch::ZScale, "ZScale"),
+    ];
+
+    /// Maps 0..1 between the black and white points to a gray level. ZScale
+    /// is linear; what sets it apart is where it puts the points.
+    fn curve(self, t: f32) -> f32 {
+        match self {
+            Stretch::Linear | Stretch::ZScale => t,
+            Stretch::Log => (LOG_EXPONENT * t).ln_1p() / LOG_EXPONENT.ln_1p(),
+            Stretch::Sqrt => t.sqrt(),
+            Stretch::Asinh => (ASINH_SOFTENING * t).asinh() / ASINH_SOFTENING.asinh(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Projection {
+    Tan,
+    Sin,
+    Arc,
+}
+
+/// A celestial world coordinate system for one of the zenithal projections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wcs {
+    crpix: [f64; 2],
+    /// Longitude and latitude of the reference point, in degrees.
+    crval: [f64; 2],
+    cd: [[f64; 2]; 2],
+    projection: Projection,
+    lonpole: f64,
+    /// The first pixel axis is declination.
+    lat_first: bool,
+}
+
+impl Wcs {
+    fn from_header(h: &Header) -> Option<Self> {
+        let (c1, c2) = (h.text("CTYPE1")?, h.text("CTYPE2")?);
+        let lat_first = match (c1.get(..4)?, c2.get(..4)?) {
+            ("RA--", "DEC-") => false,
+            ("DEC-", "RA--") => true,
+            _ => return None,
+        };
+        let code = c1.get(5..8)?;
+        if c2.get(5..8)? != code {
+            return None;
+        }
+        let projection = match code {
+            "TAN" => Projection::Tan,
+            "SIN" => Projection::Sin,
+            "ARC" => Projection::Arc,
+            _ => return None,
+        };
+
+        let crpix = [
+            h.num("CRPIX1").unwrap_or(0.0),
+            h.num("CRPIX2").unwrap_or(0.0),
+        ];
+        let mut crval = [h.num("CRVAL1")?, h.num("CRVAL2")?];
+        if lat_first {
+            crval.swap(0, 1);
+        }
+        let cd_keys = [["CD1_1", "CD1_2"], ["CD2_1", "CD2_2"]];
+        let pc_keys = [["PC1_1", "PC1_2"], ["PC2_1", "PC2_2"]];
+        let cdelt = [
+            h.num("CDELT1").unwrap_or(1.0),
+            h.num("CDELT2").unwrap_or(1.0),
+        ];
+        let cd = if cd_keys.iter().flatten().any(|k| h.num(k).is_some()) {
+            cd_keys.map(|row| row.map(|k| h.num(k).unwrap_or(0.0)))
+        } else if pc_keys.iter().flatten().any(|k| h.num(k).is_some()) {
+            let pc = |i: usize, j: usize| {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                h.num(pc_keys[i][j]).unwrap_or(identity)
+            };
+            [
+                [cdelt[0] * pc(0, 0), cdelt[0] * pc(0, 1)],
+                [cdelt[1] * pc(1, 0), cdelt[1] * pc(1, 1)],
+            ]
+        } else {
+            let rho = h.num("CROTA2").unwrap_or(0.0).to_radians();
+            [
+                [cdelt[0] * rho.cos(), -cdelt[1] * rho.sin()],
+                [cdelt[0] * rho.sin(), cdelt[1] * rho.cos()],
+            ]
+        };
+        let lonpole = h
+            .num("LONPOLE")
+            .unwrap_or(if crval[1] >= 90.0 { 0.0 } else { 180.0 });
+
+        Some(Self {
+            crpix,
+            crval,
+            cd,
+            projection,
+            lonpole,
+            lat_first,
+        })
+    }
+
+    /// Right ascension and declination in degrees of a 1-based FITS pixel
+    /// position, following Calabretta and Greisen's zenithal formulae.
+    pub fn pixel_to_world(&self, p1: f64, p2: f64) -> Option<(f64, f64)> {
+        let d = [p1 - self.crpix[0], p2 - self.crpix[1]];
+        let i1 = self.cd[0][0] * d[0] + self.cd[0][1] * d[1];
+        let i2 = self.cd[1][0] * d[0] + self.cd[1][1] * d[1];
+        let (x, y) = if self.lat_first { (i2, i1) } else { (i1, i2) };
+
+        let r = x.hypot(y);
+        let phi = if r == 0.0 { 0.0 } else { x.atan2(-y) };
+        let theta = match self.projection {
+            Projection::Tan => (180.0 / PI).atan2(r),
+            Projection::Sin => {
+                let s = r.to_radians();
+                if s > 1.0 {
+                    return None;
+                }
+                s.acos()
+            }
+            Projection::Arc => (90.0 - r).to_radians(),
+        };
+
+        let (ra0, dec0) = (self.crval[0].to_radians(), self.crval[1].to_radians());
+        let dphi = phi - self.lonpole.to_radians();
+        let dec = (theta.sin() * dec0.sin() + theta.cos() * dec0.cos() * dphi.cos())
+            .clamp(-1.0, 1.0)
+            .asin();
+        let ra = ra0
+            + (-theta.cos() * dphi.sin())
+                .atan2(theta.sin() * dec0.cos() - theta.cos() * dec0.sin() * dphi.cos());
+        Some((ra.to_degrees().rem_euclid(360.0), dec.to_degrees()))
+    }
+}
+
+/// Right ascension as hours, minutes and seconds.
+pub fn format_ra(deg: f64) -> String {
+    let cs = (deg.rem_euclid(360.0) / 15.0 * 360_000.0).round() as u64;
+    let s = cs % 6000;
+    format!(
+        "{:02}:{:02}:{:02}.{:02}",
+        cs / 360_000 % 24,
+        cs / 6000 % 60,
+        s / 100,
+        s % 100
+    )
+}
+
+/// Declination as signed degrees, arcminutes and arcseconds.
+pub fn format_dec(deg: f64) -> String {
+    let sign = if deg < 0.0 { '-' } else { '+' };
+    let ds = (deg.abs() * 36_000.0).round() as u64;
+    let s = ds % 600;
+    format!(
+        "{sign}{:02}:{:02}:{:02}.{}",
+        ds / 36_000,
+        ds / 600 % 60,
+        s / 10,
+        s % 10
+    )
+}
+
+/// The keyword values of one header, with strings unquoted and comments cut.
+struct Header(HashMap<String, String>);
+
+impl Header {
+    fn parse(cards: &[String]) -> Self {
+        let mut map = HashMap::new();
+        for card in cards {
+            if let Some((key, value)) = card_value(card) {
+                map.entry(key.to_string()).or_insert(value);
+            }
+        }
+        Self(map)
+    }
+
+    fn text(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    fn num(&self, key: &str) -> Option<f64> {
+        self.text(key)?.replace(['D', 'd'], "E").parse().ok()
+    }
+
+    fn int(&self, key: &str) -> Option<i64> {
+        self.text(key)?.parse().ok()
+    }
+}
+
+fn card_value(card: &str) -> Option<(&str, String)> {
+    let key = card.get(..8)?.trim_end();
+    if card.get(8..10) != Some("= ") {
+        return None;
+    }
+    let rest = card[10..].trim_start();
+    let value = match rest.strip_prefix('\'') {
+        Some(quoted) => {
+            let mut out = String::new();
+            let mut chars = quoted.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c != '\'' {
+                    out.push(c);
+                } else if chars.peek() == Some(&'\'') {
+                    out.push('\'');
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            out.trim_end().to_string()
+        }
+        None => rest.split('/').next().unwrap_or("").trim().to_string(),
+    };
+    Some((key, value))
+}
+
+#[derive(Debug)]
+pub struct FitsHdu {
+    /// EXTNAME, or what the HDU is when it has none.
+    pub name: String,
+    /// The header as written, one card per line, without END and padding.
+    pub cards: Vec<String>,
+    /// NAXIS1, NAXIS2 and so on.
+    pub axes: Vec<usize>,
+    pub bitpix: i32,
+    image: bool,
+    data_offset: u64,
+    data_len: u64,
+    bscale: f64,
+    bzero: f64,
+    blank: Option<i64>,
+    pub wcs: Option<Wcs>,
+}
+
+impl FitsHdu {
+    fn parse(index: usize, cards: Vec<String>, data_offset: u64) -> Result<Self, ImageError> {
+        let h = Header::parse(&cards);
+        let bitpix = h.int("BITPIX").unwrap_or(0) as i32;
+        if ![8, 16, 32, 64, -32, -64].contains(&bitpix) {
+            return Err(fits_error(format!("HDU {index}: invalid BITPIX {bitpix}")));
+        }
+        let naxis = h.int("NAXIS").unwrap_or(0).max(0);
+        let axes: Vec<usize> = (1..=naxis)
+            .map(|n| h.int(&format!("NAXIS{n}")).unwrap_or(0).max(0) as usize)
+            .collect();
+        let xtension = h.text("XTENSION");
+        let image = (index == 0 || xtension == Some("IMAGE"))
+            && axes.len() >= 2
+            && axes.iter().all(|&n| n > 0);
+        let name = match h.text("EXTNAME").filter(|n| !n.is_empty()) {
+            Some(name) => name.to_string(),
+            None if index == 0 => "Primary".to_string(),
+            None => xtension.unwrap_or("Extension").to_string(),
+        };
+        let wcs = if image { Wcs::from_header(&h) } else { None };
+        let data_len = if axes.is_empty() {
+            0
+        } else {
+            let pcount = h.int("PCOUNT").unwrap_or(0).max(0) as u64;
+            let gcount = h.int("GCOUNT").unwrap_or(1).max(0) as u64;
+            let samples: u64 = axes.iter().map(|&n| n as u64).product();
+            bitpix.unsigned_abs() as u64 / 8 * gcount * (pcount + samples)
+        };
+        Ok(Self {
+            name,
+            image,
+            data_offset,
+            data_len,
+            bscale: h.num("BSCALE").unwrap_or(1.0),
+            bzero: h.num("BZERO").unwrap_or(0.0),
+            blank: h.int("BLANK"),
+            wcs,
+            axes,
+            bitpix,
+            cards,
+        })
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.image
+    }
+
+    pub fn width(&self) -> usize {
+        self.axes.first().copied().unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.axes.get(1).copied().unwrap_or(0)
+    }
+
+    /// Every axis past the second is flattened into one run of planes.
+    pub fn planes(&self) -> usize {
+        self.axes.iter().skip(2).product::<usize>().max(1)
+    }
+
+    fn sample_size(&self) -> usize {
+        self.bitpix.unsigned_abs() as usize / 8
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        let scaled = |raw: f64| (raw * self.bscale + self.bzero) as f32;
+        let int = |raw: i64| {
+            if Some(raw) == self.blank {
+                f32::NAN
+            } else {
+                scaled(raw as f64)
+            }
+        };
+        let size = self.sample_size();
+        let chunks = bytes.par_chunks_exact(size);
+        match self.bitpix {
+            8 => chunks.map(|c| int(c[0] as i64)).collect(),
+            16 => chunks
+                .map(|c| int(i16::from_be_bytes([c[0], c[1]]) as i64))
+                .collect(),
+            32 => chunks
+                .map(|c| int(i32::from_be_bytes(c.try_into().unwrap()) as i64))
+                .collect(),
+            64 => chunks
+                .map(|c| int(i64::from_be_bytes(c.try_into().unwrap())))
+                .collect(),
+            -32 => chunks
+                .map(|c| scaled(f32::from_be_bytes(c.try_into().unwrap()) as f64))
+                .collect(),
+            _ => chunks
+                .map(|c| scaled(f64::from_be_bytes(c.try_into().unwrap())))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FitsDocument {
+    path: PathBuf,
+    pub hdus: Vec<FitsHdu>,
+}
+
+/// Which HDU and plane is on screen, and how it is stretched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FitsView {
+    pub hdu: usize,
+    pub plane: usize,
+    pub stretch: Stretch,
+    /// The data values drawn black and white.
+    pub points: (f32, f32),
+}
+
+#[derive(Debug, Clone)]
+pub struct FitsSelection {
+    pub document: Arc<FitsDocument>,
+    pub view: FitsView,
+    /// The plane `view` shows, kept so a new stretch does not reread it.
+    values: Arc<Vec<f32>>,
+}
+
+fn fits_error(msg: impl Into<String>) -> ImageError {
+    ImageError::IoError(Error::other(msg.into()))
+}
+
+/// Reads one header's cards, or None at the end of the file or at anything
+/// after the last HDU that is not another extension.
+fn read_header(reader: &mut impl Read, first: bool) -> Result<Option<(Vec<String>, u64)>, Error> {
+    let mut cards = Vec::new();
+    let mut block = [0u8; BLOCK];
+    let mut read = 0u64;
+    loop {
+        match reader.read_exact(&mut block) {
+            Ok(()) => read += BLOCK as u64,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && cards.is_empty() => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        }
+        if read == BLOCK as u64 {
+            let lead: &[u8] = if first { b"SIMPLE  " } else { b"XTENSION" };
+            if !block.starts_with(lead) {
+                return if first {
+                    Err(Error::other("not a FITS file"))
+                } else {
+                    Ok(None)
+                };
+            }
+        }
+        for card in block.chunks_exact(CARD) {
+            let card = String::from_utf8_lossy(card);
+            if card.get(..8).map(str::trim_end) == Some("END") {
+                return Ok(Some((cards, read)));
+            }
+            let card = card.trim_end();
+            if !card.is_empty() {
+                cards.push(card.to_string());
+            }
+        }
+    }
+}
+
+impl FitsDocument {
+    pub fn read(path: &Path) -> Result<Self, ImageError> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        let len = file.metadata().map_err(ImageError::IoError)?.len();
+        let mut reader = BufReader::new(file);
+        let mut hdus = Vec::new();
+        let mut offset = 0u64;
+        while offset < len {
+            reader
+                .seek(SeekFrom::Start(offset))
+                .map_err(ImageError::IoError)?;
+            let Some((cards, header_len)) =
+                read_header(&mut reader, hdus.is_empty()).map_err(ImageError::IoError)?
+            else {
+                break;
+            };
+            let hdu = FitsHdu::parse(hdus.len(), cards, offset + header_len)?;
+            offset = hdu.data_offset + hdu.data_len.div_ceil(BLOCK as u64) * BLOCK as u64;
+            hdus.push(hdu);
+        }
+        if !hdus.iter().any(FitsHdu::is_image) {
+            return Err(fits_error("FITS file has no image HDU"));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            hdus,
+        })
+    }
+
+    /// Shows the first image HDU on its ZScale points.
+    pub fn open(self: &Arc<Self>) -> Result<ImageData, ImageError> {
+        let hdu = self.hdus.iter().position(FitsHdu::is_image).unwrap_or(0);
+        let values = Arc::new(self.read_plane(hdu, 0)?);
+        let view = FitsView {
+            hdu,
+            plane: 0,
+            stretch: Stretch::default(),
+            points: zscale(&values),
+        };
+        Ok(self.render(view, values))
+    }
+
+    fn read_plane(&self, index: usize, plane: usize) -> Result<Vec<f32>, ImageError> {
+        let hdu = self
+            .hdus
+            .get(index)
+            .filter(|h| h.is_image())
+            .ok_or_else(|| fits_error(format!("HDU {index} is not an image")))?;
+        let plane = plane.min(hdu.planes() - 1);
+        let len = hdu.width() * hdu.height() * hdu.sample_size();
+        let mut bytes = vec![0u8; len];
+        let mut file = File::open(&self.path).map_err(ImageError::IoError)?;
+        file.seek(SeekFrom::Start(hdu.data_offset + (plane * len) as u64))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .map_err(ImageError::IoError)?;
+        Ok(hdu.decode(&bytes))
+    }
+
+    fn render(self: &Arc<Self>, view: FitsView, values: Arc<Vec<f32>>) -> ImageData {
+        let hdu = &self.hdus[view.hdu];
+        let (width, height) = (hdu.width(), hdu.height());
+        let (black, white) = view.points;
+        let span = white - black;
+        let mut samples = vec![0.0f32; width * height * 4];
+        samples
+            .par_chunks_mut(width * 4)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let src = &values[(height - 1 - y) * width..][..width];
+                for (px, &v) in row.chunks_exact_mut(4).zip(src) {
+                    if !v.is_finite() {
+                        continue;
+                    }
+                    let t = if span == 0.0 { 0.0 } else { (v - black) / span };
+                    let g = view.stretch.curve(t.clamp(0.0, 1.0));
+                    px.copy_from_slice(&[g, g, g, 1.0]);
+                }
+            });
+
+        let mut data = ImageData::with_deep(DeepPixels::F32(samples), width as u32, height as u32);
+        data.bit_depth = hdu.sample_size() as u8 * 8;
+        data.source = Source::Fits(FitsSelection {
+            document: Arc::clone(self),
+            view,
+            values,
+        });
+        data
+    }
+}
+
+impl FitsSelection {
+    pub fn hdu(&self) -> &FitsHdu {
+        &self.document.hdus[self.view.hdu]
+    }
+
+    /// The current view with another stretch. Picking ZScale moves the points
+    /// to its limits; the others keep the points as they are.
+    pub fn with_stretch(&self, stretch: Stretch) -> FitsView {
+        let points = match stretch {
+            Stretch::ZScale => zscale(&self.values),
+            _ => self.view.points,
+        };
+        FitsView {
+            stretch,
+            points,
+            ..self.view
+        }
+    }
+
+    /// Renders another view, reading the file only when the plane changes. A
+    /// different HDU opens on its own ZScale points.
+    pub fn render(&self, mut view: FitsView) -> Result<ImageData, ImageError> {
+        let values = if (view.hdu, view.plane) == (self.view.hdu, self.view.plane) {
+            Arc::clone(&self.values)
+        } else {
+            Arc::new(self.document.read_plane(view.hdu, view.plane)?)
+        };
+        if view.hdu != self.view.hdu {
+            view.points = zscale(&values);
+        }
+        Ok(self.document.render(view, values))
+    }
+
+    /// The data value under a display pixel, which counts rows from the top.
+    pub fn value_at(&self, x: u32, y: u32) -> Option<f32> {
+        let (w, h) = (self.hdu().width(), self.hdu().height());
+        let (x, y) = (x as usize, y as usize);
+        (x < w && y < h).then(|| self.values[(h - 1 - y) * w + x])
+    }
+
+    /// Right ascension and declination of a display pixel's center.
+    pub fn world_at(&self, x: u32, y: u32) -> Option<(f64, f64)> {
+        let hdu = self.hdu();
+        let wcs = hdu.wcs.as_ref()?;
+        wcs.pixel_to_world(x as f64 + 1.0, (hdu.height() as u32).checked_sub(y)? as f64)
+    }
+}
+
+/// IRAF's zscale: black and white points around the median, as far out as a
+/// line fitted to the sorted values climbs, eased by the contrast.
+pub fn zscale(values: &[f32]) -> (f32, f32) {
+    let step = (values.len() / ZSCALE_SAMPLES).max(1);
+    let mut samples: Vec<f64> = values
+        .iter()
+        .step_by(step)
+        .filter(|v| v.is_finite())
+        .map(|&v| v as f64)
+        .collect();
+    if samples.is_empty() {
+        return (0.0, 1.0);
+    }
+    samples.sort_unstable_by(f64::total_cmp);
+    let n = samples.len();
+    let (lo, hi) = (samples[0], samples[n - 1]);
+    let min_kept = (n / 2).max(5);
+
+    let mut keep = vec![true; n];
+    let mut slope = 0.0;
+    for _ in 0..ZSCALE_ITERATIONS {
+        let kept: Vec<(f64, f64)> = (0..n)
+            .filter(|&i| keep[i])
+            .map(|i| (i as f64, samples[i]))
+            .collect();
+        if kept.len() < min_kept {
+            break;
+        }
+        let k = kept.len() as f64;
+        let (sx, sy) = kept
+            .iter()
+            .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+        let (mx, my) = (sx / k, sy / k);
+        let (sxx, sxy) = kept.iter().fold((0.0, 0.0), |(sxx, sxy), (x, y)| {
+            (sxx + (x - mx) * (x - mx), sxy + (x - mx) * (y - my))
+        });
+        slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        let intercept = my - slope * mx;
+        let residual = |i: usize| samples[i] - (intercept + slope * i as f64);
+        let sigma = (kept
+            .iter()
+            .map(|&(x, _)| residual(x as usize).powi(2))
+            .sum::<f64>()
+            / k)
+            .sqrt();
+        let mut rejected = false;
+        for (i, k) in keep.iter_mut().enumerate() {
+            if *k && residual(i).abs() > ZSCALE_REJECT * sigma {
+                *k = false;
+                rejected = true;
+            }
+        }
+        if !rejected {
+            break;
+        }
+    }
+
+    let (z1, z2) = if keep.iter().filter(|&&k| k).count() < min_kept {
+        (lo, hi)
+    } else {
+        let median = samples[n / 2];
+        let center = (n / 2) as f64;
+        let slope = slope / ZSCALE_CONTRAST;
+        (
+            (median - center * slope).max(lo),
+            (median + (n as f64 - center) * slope).min(hi),
+        )
+    };
+    if z2 > z1 {
+        (z1 as f32, z2 as f32)
+    } else {
+        (lo as f32, lo as f32 + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn header(cards: &[&str]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = cards
+            .iter()
+            .chain(&["END"])
+            .flat_map(|c| format!("{c:<80}").into_bytes())
+            .collect();
+        bytes.resize(bytes.len().div_ceil(BLOCK) * BLOCK, b' ');
+        bytes
+    }
+
+    fn pad(mut data: Vec<u8>) -> Vec<u8> {
+        data.resize(data.len().div_ceil(BLOCK) * BLOCK, 0);
+        data
+    }
+
+    #[test]
+    fn every_hdu_is_listed_and_cube_planes_read_bottom_up() {
+        let mut file = header(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                   16",
+            "NAXIS   =                    2",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "BZERO   =                32768",
+            "BLANK   =                   -1",
+        ]);
+        let primary: Vec<i16> = vec![-32768, -1];
+        file.extend(pad(primary.iter().flat_map(|v| v.to_be_bytes()).collect()));
+        file.extend(header(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                  -32",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    1",
+            "NAXIS2  =                    2",
+            "NAXIS3  =                    2",
+            "EXTNAME = 'SCI''S'             / science",
+        ]));
+        let cube: Vec<f32> = vec![1.0, 2.0, 3.0, 4.0];
+        file.extend(pad(cube.iter().flat_map(|v| v.to_be_bytes()).collect()));
+
+        let path =
+            std::env::temp_dir().join(format!("bloom-test-cube-{}.fits", std::process::id()));
+        File::create(&path).unwrap().write_all(&file).unwrap();
+        let document = Arc::new(FitsDocument::read(&path).unwrap());
+
+        let names: Vec<_> = document.hdus.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["Primary", "SCI'S"]);
+        assert_eq!(document.hdus[1].planes(), 2);
+        let primary = document.read_plane(0, 0).unwrap();
+        assert_eq!(primary[0], 0.0);
+        assert!(primary[1].is_nan(), "BLANK is applied before BZERO");
+
+        let data = document.open().unwrap();
+        let selection = data.fits().cloned().unwrap();
+        let view = FitsView {
+            hdu: 1,
+            plane: 1,
+            ..selection.view
+        };
+        let plane = selection.render(view).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let plane = plane.fits().cloned().unwrap();
+        assert_eq!(plane.value_at(0, 0), Some(4.0), "the last row is on top");
+        assert_eq!(plane.value_at(0, 1), Some(3.0));
+    }
+
+    #[test]
+    fn zscale_brackets_the_background_not_the_stars() {
+        let mut values: Vec<f32> = (0..10_000)
+            .map(|i| 100.0 + ((i * 7919) % 21) as f32 - 10.0)
+            .collect();
+        for v in values.iter_mut().step_by(1000) {
+            *v = 60_000.0;
+        }
+        let (black, white) = zscale(&values);
+        assert!(black >= 89.0 && black < 100.0, "black point {black}");
+        assert!(white > 100.0 && white < 200.0, "white point {white}");
+    }
+
+    #[test]
+    fn tan_wcs_maps_pixels_to_the_sky() {
+        let cards: Vec<String> = [
+            "CTYPE1  = 'RA---TAN'",
+            "CTYPE2  = 'DEC--TAN'",
+            "CRPIX1  =                 50.0",
+            "CRPIX2  =                 50.0",
+            "CRVAL1  =                150.0",
+            "CRVAL2  =                  0.0",
+            "CDELT1  =                -0.01",
+            "CDELT2  =                 0.01",
+        ]
+        .map(String::from)
+        .to_vec();
+        let wcs = Wcs::from_header(&Header::parse(&cards)).unwrap();
+
+        let (ra, dec) = wcs.pixel_to_world(50.0, 50.0).unwrap();
+        assert!((ra - 150.0).abs() < 1e-9 && dec.abs() < 1e-9);
+        let (ra, dec) = wcs.pixel_to_world(49.0, 51.0).unwrap();
+        assert!((ra - 150.01).abs() < 1e-6, "east is left, got {ra}");
+        assert!((dec - 0.01).abs() < 1e-6, "north is up, got {dec}");
+
+        assert_eq!(format_ra(150.0), "10:00:00.00");
+        assert_eq!(format_dec(-0.5), "-00:30:00.0");
+    }
+}
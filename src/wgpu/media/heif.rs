@@ -0,0 +1,288 @@
+//! HEIF and AVIF: stills, bursts and image sequences, through libheif.
+//!
+//! A HEIF file is a collection of items. The primary item is the picture a
+//! plain viewer shows. Further top-level items of the same size make the file
+//! a burst or collection, and load as an Animation stepped through at
+//! BURST_DELAY, since items carry no timing of their own. Depth maps, alpha
+//! planes, gain maps and mattes hang off an item rather than standing alone,
+//! so they are listed in the info panel instead of shown.
+//!
+//! An image sequence ('avis' or 'msf1' brand) is a different thing: a track of
+//! coded frames with timestamps, like a video's. The libheif this build
+//! targets (1.17) predates its sequence API, so the av build decodes the track
+//! with FFmpeg instead. Without it, or when that fails, the file opens on its
+//! still primary item, which most sequence files also carry. FFmpeg reads only
+//! the colour track, so an AVIF sequence with alpha plays opaque.
+
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::ImageError;
+use libheif_rs::{AuxiliaryImagesFilter, ColorSpace, HeifContext, ImageHandle, LibHeif, RgbChroma};
+
+use super::animation::{Animation, Frame};
+use super::image_data::{ImageData, MediaData, Source};
+
+const BURST_DELAY: Duration = Duration::from_millis(250);
+
+/// Brands that mark a file as holding a timed track rather than only items.
+#[cfg(any(feature = "av", test))]
+const SEQUENCE_BRANDS: &[&[u8; 4]] = &[b"avis", b"msf1", b"hevc", b"hevx"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeifItem {
+    pub kind: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeifInfo {
+    /// "AVIF" or "HEIF", after the major brand.
+    pub container: &'static str,
+    /// Top-level items, or frames for a sequence.
+    pub images: usize,
+    /// Whether the images are a sequence's frames rather than items.
+    pub sequence: bool,
+    /// The primary item's depth and auxiliary images.
+    pub auxiliary: Vec<HeifItem>,
+}
+
+fn heif_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+pub fn load(path: &Path) -> Result<MediaData, ImageError> {
+    let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
+    let brands = ftyp_brands(&bytes).unwrap_or_default();
+    let container = if brands.iter().any(|b| b.starts_with(b"avi")) {
+        "AVIF"
+    } else {
+        "HEIF"
+    };
+
+    let ctx = HeifContext::read_from_bytes(&bytes).ok();
+    let primary = ctx.as_ref().and_then(|c| c.primary_image_handle().ok());
+    let auxiliary = primary.as_ref().map(auxiliary_images).unwrap_or_default();
+
+    #[cfg(feature = "av")]
+    if brands.iter().any(|b| SEQUENCE_BRANDS.contains(b))
+        && let Ok(frames) = super::video::decode_sequence(path)
+        && !frames.is_empty()
+    {
+        let info = Arc::new(HeifInfo {
+            container,
+            images: frames.len(),
+            sequence: true,
+            auxiliary,
+        });
+        let frames = frames
+            .into_iter()
+            .map(|(mut data, delay)| {
+                data.source = Source::Heif(Arc::clone(&info));
+                Frame {
+                    data: Arc::new(data),
+                    delay,
+                }
+            })
+            .collect();
+        return Ok(MediaData::Animation(Animation::new(frames)?));
+    }
+
+    let (Some(ctx), Some(primary)) = (ctx, primary) else {
+        let img = image::load_from_memory(&bytes)?.into_rgba8();
+        let (width, height) = img.dimensions();
+        return Ok(MediaData::Image(Box::new(ImageData::new(
+            img.into_raw(),
+            width,
+            height,
+        ))));
+    };
+
+    let size = (primary.width(), primary.height());
+    let burst: Vec<ImageHandle> = ctx
+        .top_level_image_handles()
+        .into_iter()
+        .filter(|h| (h.width(), h.height()) == size)
+        .collect();
+    let info = Arc::new(HeifInfo {
+        container,
+        images: ctx.number_of_top_level_images(),
+        sequence: false,
+        auxiliary,
+    });
+
+    let lib_heif = LibHeif::new();
+    if burst.len() < 2 {
+        let mut data = decode(&lib_heif, &primary)?;
+        data.source = Source::Heif(info);
+        return Ok(MediaData::Image(Box::new(data)));
+    }
+    let frames = burst
+        .iter()
+        .map(|handle| {
+            let mut data = decode(&lib_heif, handle)?;
+            data.source = Source::Heif(Arc::clone(&info));
+            Ok(Frame {
+                data: Arc::new(data),
+                delay: BURST_DELAY,
+            })
+        })
+        .collect::<Result<Vec<_>, ImageError>>()?;
+    Ok(MediaData::Animation(Animation::new(frames)?))
+}
+
+fn decode(lib_heif: &LibHeif, handle: &ImageHandle) -> Result<ImageData, ImageError> {
+    let icc = handle.color_profile_raw().map(|p| p.data);
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = if has_alpha {
+        RgbChroma::Rgba
+    } else {
+        RgbChroma::Rgb
+    };
+
+    let image = lib_heif
+        .decode(handle, ColorSpace::Rgb(chroma), None)
+        .map_err(heif_error)?;
+
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or_else(|| heif_error("no interleaved plane in HEIF"))?;
+
+    let width = plane.width as usize;
+    let height = plane.height as usize;
+    let stride = plane.stride;
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    if has_alpha {
+        for y in 0..height {
+            pixels.extend_from_slice(&plane.data[y * stride..y * stride + width * 4]);
+        }
+    } else {
+        for y in 0..height {
+            for rgb in plane.data[y * stride..y * stride + width * 3].chunks_exact(3) {
+                pixels.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+        }
+    }
+
+    let mut data = ImageData::new(pixels, plane.width, plane.height);
+    if let Some(icc) = icc {
+        data.apply_icc_profile(icc);
+    }
+    Ok(data)
+}
+
+fn auxiliary_images(handle: &ImageHandle) -> Vec<HeifItem> {
+    let item = |kind: &str, h: &ImageHandle| HeifItem {
+        kind: kind.to_string(),
+        width: h.width(),
+        height: h.height(),
+    };
+    let mut items = Vec::new();
+    let mut ids = vec![0; handle.number_of_depth_images() as usize];
+    let count = handle.depth_image_ids(&mut ids);
+    for &id in &ids[..count] {
+        if let Ok(depth) = handle.depth_image_handle(id) {
+            items.push(item("Depth map", &depth));
+        }
+    }
+    for aux in handle.auxiliary_images(AuxiliaryImagesFilter::OMIT_DEPTH) {
+        let kind = aux
+            .auxiliary_type()
+            .map(|t| auxiliary_name(&t.to_string_lossy()))
+            .unwrap_or_else(|_| "Auxiliary".to_string());
+        items.push(item(&kind, &aux));
+    }
+    items
+}
+
+/// A readable name for an auxiliary image's type URN.
+fn auxiliary_name(urn: &str) -> String {
+    let last = urn.rsplit(':').next().unwrap_or(urn);
+    match last {
+        "alpha" => "Alpha",
+        "depth" => "Depth map",
+        "hdrgainmap" => "HDR gain map",
+        "portraiteffectsmatte" => "Portrait matte",
+        "semanticskinmatte" => "Skin matte",
+        "semantichairmatte" => "Hair matte",
+        "semanticteethmatte" => "Teeth matte",
+        "semanticglassesmatte" => "Glasses matte",
+        "semanticskymatte" => "Sky matte",
+        _ if urn.starts_with("urn:mpeg:hevc:2015:auxid:") => match last {
+            "1" => "Alpha",
+            "2" => "Depth map",
+            _ => "Auxiliary",
+        },
+        _ if last.is_empty() => "Auxiliary",
+        other => return other.to_string(),
+    }
+    .to_string()
+}
+
+/// The major and compatible brands of a leading ftyp box.
+fn ftyp_brands(bytes: &[u8]) -> Option<Vec<&[u8; 4]>> {
+    if bytes.get(4..8)? != b"ftyp" {
+        return None;
+    }
+    let size = u32::from_be_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+    let end = size.min(bytes.len());
+    let mut brands = vec![bytes.get(8..12)?.try_into().ok()?];
+    brands.extend(
+        bytes
+            .get(16..end)?
+            .chunks_exact(4)
+            .filter_map(|b| b.try_into().ok()),
+    );
+    Some(brands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let size = 16 + 4 * compatible.len() as u32;
+        let mut b = size.to_be_bytes().to_vec();
+        b.extend_from_slice(b"ftyp");
+        b.extend_from_slice(major);
+        b.extend_from_slice(&0u32.to_be_bytes());
+        for brand in compatible {
+            b.extend_from_slice(*brand);
+        }
+        b.extend_from_slice(b"\0\0\0\x08meta");
+        b
+    }
+
+    #[test]
+    fn brands_come_from_the_ftyp_box_only() {
+        let bytes = ftyp(b"avif", &[b"avif", b"mif1", b"avis"]);
+        let brands = ftyp_brands(&bytes).unwrap();
+        assert_eq!(brands, [b"avif", b"avif", b"mif1", b"avis"]);
+        assert!(brands.iter().any(|b| SEQUENCE_BRANDS.contains(b)));
+
+        let still = ftyp(b"heic", &[b"mif1", b"heic"]);
+        let brands = ftyp_brands(&still).unwrap();
+        assert!(!brands.iter().any(|b| SEQUENCE_BRANDS.contains(b)));
+        assert_eq!(ftyp_brands(b"\0\0\0\x08moov"), None);
+    }
+
+    #[test]
+    fn auxiliary_types_get_readable_names() {
+        assert_eq!(
+            auxiliary_name("urn:mpeg:mpegB:cicp:systems:auxiliary:alpha"),
+            "Alpha"
+        );
+        assert_eq!(auxiliary_name("urn:mpeg:hevc:2015:auxid:2"), "Depth map");
+        assert_eq!(
+            auxiliary_name("urn:com:apple:photo:2020:aux:hdrgainmap"),
+            "HDR gain map"
+        );
+        assert_eq!(auxiliary_name("urn:example:thermal"), "thermal");
+        assert_eq!(auxiliary_name(""), "Auxiliary");
+    }
+}
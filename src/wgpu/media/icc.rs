@@ -0,0 +1,348 @@
+//! Color management: bringing pixels decoded in a file's own color space into
+//! sRGB, the space the swapchain presents in.
+//!
+//! Conversion happens once, at load, on the most precise buffer the image has:
+//! its high-precision samples when it carries them (through an f32 view), the
+//! RGBA8 buffer otherwise. Nothing downstream (tiles, modifiers, export) needs
+//! to know the source was ever anything else, at the cost of clipping colors
+//! outside the sRGB gamut. The display's own
+//! profile is not consulted; a wide-gamut monitor shows the sRGB result the way
+//! it shows every other sRGB window.
+//!
+//! Sources describe their space in one of three ways: an embedded ICC profile
+//! (JPEG, PNG iCCP, TIFF, WebP, JXL, HEIC), a PNG cICP chunk naming coded
+//! primaries and transfer, or the older PNG gAMA/cHRM pair. ICC profiles go
+//! through moxcms. cICP maps onto moxcms's built-in profiles for the handful of
+//! combinations seen in practice. gAMA/cHRM is small enough to apply directly:
+//! linearize with the file gamma, move primaries through XYZ, re-encode sRGB.
+//!
+//! Export can put the profile back: from_srgb builds the reverse transform, so
+//! a Display P3 photo can leave tagged Display P3 rather than untagged sRGB.
+
+use glam::{Mat3, Vec3};
+use moxcms::{ColorProfile, Layout, Transform8BitExecutor, TransformOptions};
+
+/// Color chunks read from a PNG that carries no iCCP profile.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PngColor {
+    pub srgb: bool,
+    pub cicp: Option<(u8, u8)>,
+    pub gamma: Option<f32>,
+    /// White, red, green, blue chromaticities, as (x, y).
+    pub chromaticities: Option<[(f32, f32); 4]>,
+}
+
+/// The sample types a conversion runs on: RGBA8, and RGBA f32 for an image's
+/// high-precision samples.
+pub trait Channel: Copy {
+    fn transform(pixels: &mut [Self], src: &ColorProfile, dst: &ColorProfile) -> bool;
+    fn apply_gamma(pixels: &mut [Self], gamma: f32, matrix: Option<Mat3>);
+}
+
+impl Channel for u8 {
+    fn transform(pixels: &mut [u8], src: &ColorProfile, dst: &ColorProfile) -> bool {
+        let Ok(transform) =
+            src.create_transform_8bit(Layout::Rgba, dst, Layout::Rgba, TransformOptions::default())
+        else {
+            return false;
+        };
+        let input = pixels.to_vec();
+        transform.transform(&input, pixels).is_ok()
+    }
+
+    fn apply_gamma(pixels: &mut [u8], gamma: f32, matrix: Option<Mat3>) {
+        let linear: Vec<f32> = (0..256)
+            .map(|v| (v as f32 / 255.0).powf(1.0 / gamma))
+            .collect();
+        let encode = |v: f32| (srgb_encode(v) * 255.0).round() as u8;
+        match matrix {
+            None => {
+                let lut: Vec<u8> = linear.iter().map(|&l| encode(l)).collect();
+                for p in pixels.chunks_exact_mut(4) {
+                    for c in &mut p[..3] {
+                        *c = lut[*c as usize];
+                    }
+                }
+            }
+            Some(m) => {
+                for p in pixels.chunks_exact_mut(4) {
+                    let rgb = m * Vec3::new(
+                        linear[p[0] as usize],
+                        linear[p[1] as usize],
+                        linear[p[2] as usize],
+                    );
+                    p[0] = encode(rgb.x);
+                    p[1] = encode(rgb.y);
+                    p[2] = encode(rgb.z);
+                }
+            }
+        }
+    }
+}
+
+impl Channel for f32 {
+    fn transform(pixels: &mut [f32], src: &ColorProfile, dst: &ColorProfile) -> bool {
+        let Ok(transform) =
+            src.create_transform_f32(Layout::Rgba, dst, Layout::Rgba, TransformOptions::default())
+        else {
+            return false;
+        };
+        let input = pixels.to_vec();
+        transform.transform(&input, pixels).is_ok()
+    }
+
+    fn apply_gamma(pixels: &mut [f32], gamma: f32, matrix: Option<Mat3>) {
+        for p in pixels.chunks_exact_mut(4) {
+            let linear = Vec3::new(p[0], p[1], p[2])
+                .max(Vec3::ZERO)
+                .powf(1.0 / gamma);
+            let rgb = matrix.map_or(linear, |m| m * linear);
+            p[0] = srgb_encode(rgb.x);
+            p[1] = srgb_encode(rgb.y);
+            p[2] = srgb_encode(rgb.z);
+        }
+    }
+}
+
+/// Converts `pixels` from the embedded profile to sRGB. Returns the profile's
+/// name, or None when the profile cannot describe RGB data (a CMYK profile left
+/// on a JPEG the decoder already turned into RGB, for example) and the pixels
+/// were left untouched.
+pub fn to_srgb<T: Channel>(pixels: &mut [T], icc: &[u8]) -> Option<String> {
+    let name = profile_name(icc).unwrap_or_else(|| "Embedded ICC".to_string());
+    if is_srgb_name(&name) {
+        return Some(name);
+    }
+    let profile = ColorProfile::new_from_slice(icc).ok()?;
+    T::transform(pixels, &profile, &ColorProfile::new_srgb()).then_some(name)
+}
+
+/// Converts `pixels` described by PNG color chunks to sRGB and names the space.
+pub fn png_to_srgb<T: Channel>(pixels: &mut [T], color: PngColor) -> Option<String> {
+    if let Some((primaries, transfer)) = color.cicp {
+        return cicp_to_srgb(pixels, primaries, transfer);
+    }
+    if color.srgb {
+        return Some("sRGB".to_string());
+    }
+    let gamma = color.gamma?;
+    if gamma <= 0.0 {
+        return None;
+    }
+    let matrix = color.chromaticities.and_then(primaries_to_srgb);
+    let near_srgb = (gamma - 1.0 / 2.2).abs() < 0.01 && matrix.is_none_or(is_near_identity);
+    if !near_srgb {
+        T::apply_gamma(pixels, gamma, matrix);
+    }
+    Some(if color.chromaticities.is_some() {
+        format!("Gamma {:.2}, custom primaries", 1.0 / gamma)
+    } else {
+        format!("Gamma {:.2}", 1.0 / gamma)
+    })
+}
+
+/// A transform from sRGB back into a source profile, for tagged export.
+pub struct FromSrgb {
+    transform: Box<Transform8BitExecutor>,
+}
+
+impl FromSrgb {
+    pub fn convert(&self, rgba: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; rgba.len()];
+        match self.transform.transform(rgba, &mut out) {
+            Ok(()) => out,
+            Err(_) => rgba.to_vec(),
+        }
+    }
+}
+
+pub fn from_srgb(icc: &[u8]) -> Option<FromSrgb> {
+    let profile = ColorProfile::new_from_slice(icc).ok()?;
+    let transform = ColorProfile::new_srgb()
+        .create_transform_8bit(
+            Layout::Rgba,
+            &profile,
+            Layout::Rgba,
+            TransformOptions::default(),
+        )
+        .ok()?;
+    Some(FromSrgb { transform })
+}
+
+fn cicp_to_srgb<T: Channel>(pixels: &mut [T], primaries: u8, transfer: u8) -> Option<String> {
+    let (profile, name) = match (primaries, transfer) {
+        (1, 1 | 6 | 13 | 14 | 15) => return Some("sRGB (cICP)".to_string()),
+        (12, 1 | 6 | 13 | 14 | 15) => (ColorProfile::new_display_p3(), "Display P3 (cICP)"),
+        (9, 1 | 6 | 13 | 14 | 15) => (ColorProfile::new_bt2020(), "BT.2020 (cICP)"),
+        (9, 16) => (ColorProfile::new_bt2020_pq(), "BT.2100 PQ (cICP)"),
+        (9, 18) => (ColorProfile::new_bt2020_hlg(), "BT.2100 HLG (cICP)"),
+        _ => return Some(format!("cICP {primaries}/{transfer}")),
+    };
+    T::transform(pixels, &profile, &ColorProfile::new_srgb()).then(|| name.to_string())
+}
+
+fn is_srgb_name(name: &str) -> bool {
+    name.starts_with("sRGB")
+}
+
+fn is_near_identity(m: Mat3) -> bool {
+    m.abs_diff_eq(Mat3::IDENTITY, 0.01)
+}
+
+fn xyz_of(x: f32, y: f32) -> Vec3 {
+    Vec3::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+/// RGB-to-XYZ for the given white and primaries, the usual construction from
+/// chromaticities: scale each primary's XYZ so that RGB white lands on the
+/// white point.
+fn rgb_to_xyz([w, r, g, b]: [(f32, f32); 4]) -> Option<Mat3> {
+    if [w, r, g, b].iter().any(|&(_, y)| y <= 0.0) {
+        return None;
+    }
+    let m = Mat3::from_cols(xyz_of(r.0, r.1), xyz_of(g.0, g.1), xyz_of(b.0, b.1));
+    if m.determinant().abs() < 1e-6 {
+        return None;
+    }
+    let s = m.inverse() * xyz_of(w.0, w.1);
+    Some(Mat3::from_cols(
+        m.x_axis * s.x,
+        m.y_axis * s.y,
+        m.z_axis * s.z,
+    ))
+}
+
+fn primaries_to_srgb(chromaticities: [(f32, f32); 4]) -> Option<Mat3> {
+    let srgb = rgb_to_xyz([(0.3127, 0.3290), (0.64, 0.33), (0.30, 0.60), (0.15, 0.06)])?;
+    Some(srgb.inverse() * rgb_to_xyz(chromaticities)?)
+}
+
+pub(crate) fn srgb_encode(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn be_u32(b: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The profile's description tag: `desc` text in v2 profiles, the first `mluc`
+/// record in v4 ones.
+pub fn profile_name(icc: &[u8]) -> Option<String> {
+    let count = be_u32(icc, 128)? as usize;
+    let entry = (0..count.min(256))
+        .map(|i| 132 + i * 12)
+        .find(|&e| icc.get(e..e + 4) == Some(&b"desc"[..]))?;
+    let offset = be_u32(icc, entry + 4)? as usize;
+    let tag = icc.get(offset..)?;
+
+    let name = match tag.get(0..4)? {
+        b"desc" => {
+            let len = be_u32(tag, 8)? as usize;
+            let ascii = tag.get(12..12 + len)?;
+            String::from_utf8_lossy(ascii)
+                .trim_end_matches('\0')
+                .to_string()
+        }
+        b"mluc" => {
+            if be_u32(tag, 8)? == 0 {
+                return None;
+            }
+            let len = be_u32(tag, 20)? as usize;
+            let start = be_u32(tag, 24)? as usize;
+            let units: Vec<u16> = tag
+                .get(start..start + len)?
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+                .trim_end_matches('\0')
+                .to_string()
+        }
+        _ => return None,
+    };
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile_with_tag(tag: &[u8]) -> Vec<u8> {
+        let mut b = vec![0u8; 128];
+        b.extend_from_slice(&1u32.to_be_bytes());
+        b.extend_from_slice(b"desc");
+        b.extend_from_slice(&144u32.to_be_bytes());
+        b.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        b.extend_from_slice(tag);
+        b
+    }
+
+    #[test]
+    fn reads_v2_and_v4_descriptions() {
+        let mut v2 = b"desc\0\0\0\0".to_vec();
+        v2.extend_from_slice(&11u32.to_be_bytes());
+        v2.extend_from_slice(b"Adobe RGB\0\0");
+        assert_eq!(
+            profile_name(&profile_with_tag(&v2)).as_deref(),
+            Some("Adobe RGB")
+        );
+
+        let text: Vec<u8> = "Display P3"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let mut v4 = b"mluc\0\0\0\0".to_vec();
+        v4.extend_from_slice(&1u32.to_be_bytes());
+        v4.extend_from_slice(&12u32.to_be_bytes());
+        v4.extend_from_slice(b"enUS");
+        v4.extend_from_slice(&(text.len() as u32).to_be_bytes());
+        v4.extend_from_slice(&28u32.to_be_bytes());
+        v4.extend_from_slice(&text);
+        assert_eq!(
+            profile_name(&profile_with_tag(&v4)).as_deref(),
+            Some("Display P3")
+        );
+
+        assert_eq!(profile_name(&[0u8; 40]), None);
+    }
+
+    #[test]
+    fn a_linear_png_is_brightened_and_a_2_2_png_is_left_alone() {
+        let gray = || vec![128u8, 128, 128, 200];
+
+        let mut linear = gray();
+        let name = png_to_srgb(
+            &mut linear,
+            PngColor {
+                gamma: Some(1.0),
+                ..Default::default()
+            },
+        );
+        assert_eq!(name.as_deref(), Some("Gamma 1.00"));
+        assert!(linear[0] > 180, "linear mid gray should encode brighter");
+        assert_eq!(linear[3], 200, "alpha is not a color channel");
+
+        let mut srgbish = gray();
+        png_to_srgb(
+            &mut srgbish,
+            PngColor {
+                gamma: Some(0.45455),
+                ..Default::default()
+            },
+        );
+        assert_eq!(srgbish, gray());
+    }
+
+    #[test]
+    fn srgb_primaries_map_to_the_identity() {
+        let m = primaries_to_srgb([(0.3127, 0.3290), (0.64, 0.33), (0.30, 0.60), (0.15, 0.06)])
+            .unwrap();
+        assert!(is_near_identity(m));
+    }
+}
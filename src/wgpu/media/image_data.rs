@@ -1,15 +1,23 @@
 //! Decoding still images into a common RGBA8 buffer, across roughly fifteen
-//! container and codec families.
+//! container and codec families, along with whatever of the source the
+//! format's controls need: the EXR an image is one layer of, the page of a
+//! multi-page file, and so on (see Source).
 //!
-//! Every loader here decodes the whole image into memory. That is the
-//! remaining memory ceiling on the input side: export streams in bands, but
-//! loading does not, so peak use scales with the source's full pixel count.
+//! Loaders decode the whole image into memory, so peak use scales with the
+//! source's full pixel count. The exception is an image big enough that this
+//! would not fit, in PNG, TIFF, JPEG or JPEG 2000: that one streams to the GPU
+//! in bands and is never whole on the host (see stream.rs).
 //!
 //! Pixels sit behind a Mutex<Arc<Vec<u8>>> so a snapshot can be handed out
 //! cheaply while the buffer stays replaceable. release_pixels drops the
 //! backing store for an image that is no longer displayed, though it only runs
 //! when selecting a different image, so it never lowers the peak for the image
 //! being viewed.
+//!
+//! A 16-bit or float source also keeps its samples at full precision, in the
+//! same kind of slot (see samples.rs). The RGBA8 buffer is then quantized from
+//! them, which doubles the resident size of such images in exchange for
+//! modifiers that do not band.
 
 use std::fs::File;
 use std::io::{BufReader, Error};
@@ -19,28 +27,14 @@ use std::sync::Mutex;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
-use basis_universal::transcoding::{
-    DecodeFlags, TranscodeParameters, Transcoder, TranscoderTextureFormat,
-};
 use bytemuck::cast_slice;
-use dds::{ColorFormat, Decoder as DdsDecoder, ImageViewMut};
-use dicom_object::open_file as dicom_open_file;
-use dicom_pixeldata::PixelDecoder;
-use fitsrs::hdu::data::image::Pixels;
-use fitsrs::{Fits, HDU};
 use icns::{IconFamily, PixelFormat as IcnsPixelFormat};
 use image::{
-    AnimationDecoder, ColorType, ImageDecoder, ImageError, ImageReader, codecs::hdr::HdrDecoder,
-    codecs::openexr::OpenExrDecoder, codecs::png::PngDecoder,
+    AnimationDecoder, ColorType, DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader,
+    codecs::hdr::HdrDecoder, codecs::png::PngDecoder,
 };
 use jpeg2k::Image as Jp2Image;
-use jxl_oxide::JxlImage;
-use ktx2::{Reader as Ktx2Reader, SupercompressionScheme};
-use resvg;
 use rgb::ComponentBytes;
-use tiny_skia::Pixmap;
-use usvg::Options as SvgOptions;
-use xcf_rs::data::xcf::Xcf;
 use zip::ZipArchive;
 use zune_core::bytestream::ZCursor;
 use zune_core::colorspace::ColorSpace;
@@ -48,7 +42,19 @@ use zune_core::result::DecodingResult;
 use zune_psd::PSDDecoder;
 
 use super::animation::{Animation, Frame};
+use super::dicom::DicomInfo;
 use super::exif_data::ExifData;
+use super::exr::{ExrDocument, ExrSelection};
+use super::fits::{FitsDocument, FitsSelection};
+use super::icc;
+use super::layers::{LayerDocument, LayerSelection};
+use super::pages::{PageSource, Pages};
+use super::raw::{RawDocument, RawSelection};
+use super::samples::{DeepPixels, SampleFormat};
+use super::stream::StreamSource;
+use super::svg::SvgDocument;
+use super::texture::{TextureDocument, TextureSelection};
+use super::tonemap::{Tone, Tonemap, Window};
 
 #[derive(Debug, Clone)]
 pub enum MediaData {
@@ -60,30 +66,81 @@ pub enum MediaData {
 
 static NEXT_ID: AtomicU64 = AtomicU64::new(1);
 
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "ari", "arw", "cr2", "cr3", "crm", "crw", "dcr", "dcs", "dng", "erf", "fff", "iiq", "kdc",
+    "mef", "mos", "mrw", "nef", "nrw", "orf", "ori", "pef", "qtk", "raf", "raw", "rw2", "rwl",
+    "srw", "x3f", "3fr",
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ImageId(u64);
 
+/// What an image is a view of, for the formats that have more to show than
+/// one picture. A file is only ever one of these.
+#[derive(Debug, Clone, Default)]
+pub enum Source {
+    #[default]
+    Plain,
+    /// The whole EXR this image is one layer or channel of. See exr.rs.
+    Exr(ExrSelection),
+    /// The whole FITS file this image is one HDU or plane of. See fits.rs.
+    Fits(FitsSelection),
+    /// The layered document this image is a composite of. See layers.rs.
+    Layers(LayerSelection),
+    /// The multi-page file this image is one page of. See pages.rs.
+    Pages(Pages),
+    /// The DICOM tags and VOI windows of the file. See dicom.rs.
+    Dicom(Arc<DicomInfo>),
+    /// The texture this image is one surface of. See texture.rs.
+    Texture(TextureSelection),
+    /// The camera RAW this image is a development of. See raw.rs.
+    Raw(RawSelection),
+    /// The container's item and auxiliary image summary. See heif.rs.
+    #[cfg(feature = "heif")]
+    Heif(Arc<super::heif::HeifInfo>),
+    /// The vector document this image is the native-size raster of. See svg.rs.
+    Svg(Arc<SvgDocument>),
+    /// A preview the file carries, standing in for the full decode. See
+    /// embedded.rs.
+    EmbeddedPreview,
+    /// Where the pixels come from when the image is too big to hold. The
+    /// pixel buffer is then empty. See stream.rs.
+    Stream(Arc<StreamSource>),
+}
+
 #[derive(Debug)]
 pub struct ImageData {
     pixels: Mutex<Arc<Vec<u8>>>,
+    deep: Mutex<Option<Arc<DeepPixels>>>,
     pub width: u32,
     pub height: u32,
     pub id: ImageId,
     pub exif: ExifData,
     pub bit_depth: u8,
-    pub color_space: Option<&'static str>,
+    pub color_space: Option<String>,
+    /// The source's embedded ICC profile, kept so export can tag its output
+    /// with it. The pixels themselves are already converted to sRGB.
+    pub icc_profile: Option<Arc<Vec<u8>>>,
+    /// The samples are scene-linear HDR values, displayed through a tonemap
+    /// rather than as they are. See tonemap.rs.
+    pub scene_linear: bool,
+    pub source: Source,
 }
 
 impl Clone for ImageData {
     fn clone(&self) -> Self {
         Self {
             pixels: Mutex::new(self.pixels_snapshot()),
+            deep: Mutex::new(self.deep_snapshot()),
             width: self.width,
             height: self.height,
             id: self.id,
             exif: self.exif.clone(),
             bit_depth: self.bit_depth,
-            color_space: self.color_space,
+            color_space: self.color_space.clone(),
+            icc_profile: self.icc_profile.clone(),
+            scene_linear: self.scene_linear,
+            source: self.source.clone(),
         }
     }
 }
@@ -97,12 +154,139 @@ impl ImageData {
         let id = ImageId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
         Self {
             pixels: Mutex::new(Arc::new(pixels)),
+            deep: Mutex::new(None),
             width,
             height,
             id,
             exif: ExifData::default(),
             bit_depth: 8,
             color_space: None,
+            icc_profile: None,
+            scene_linear: false,
+            source: Source::Plain,
+        }
+    }
+
+    pub fn exr(&self) -> Option<&ExrSelection> {
+        match &self.source {
+            Source::Exr(selection) => Some(selection),
+            _ => None,
+        }
+    }
+
+    pub fn fits(&self) -> Option<&FitsSelection> {
+        match &self.source {
+            Source::Fits(selection) => Some(selection),
+            _ => None,
+        }
+    }
+
+    pub fn layers(&self) -> Option<&LayerSelection> {
+        match &self.source {
+            Source::Layers(selection) => Some(selection),
+            _ => None,
+        }
+    }
+
+    pub fn pages(&self) -> Option<&Pages> {
+        match &self.source {
+            Source::Pages(pages) => Some(pages),
+            _ => None,
+        }
+    }
+
+    pub fn dicom(&self) -> Option<&DicomInfo> {
+        match &self.source {
+            Source::Dicom(info) => Some(info),
+            _ => None,
+        }
+    }
+
+    pub fn texture(&self) -> Option<&TextureSelection> {
+        match &self.source {
+            Source::Texture(selection) => Some(selection),
+            _ => None,
+        }
+    }
+
+    pub fn raw(&self) -> Option<&RawSelection> {
+        match &self.source {
+            Source::Raw(selection) => Some(selection),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "heif")]
+    pub fn heif(&self) -> Option<&super::heif::HeifInfo> {
+        match &self.source {
+            Source::Heif(info) => Some(info),
+            _ => None,
+        }
+    }
+
+    pub fn svg(&self) -> Option<&Arc<SvgDocument>> {
+        match &self.source {
+            Source::Svg(document) => Some(document),
+            _ => None,
+        }
+    }
+
+    pub fn embedded_preview(&self) -> bool {
+        matches!(self.source, Source::EmbeddedPreview)
+    }
+
+    pub fn stream(&self) -> Option<&Arc<StreamSource>> {
+        match &self.source {
+            Source::Stream(source) => Some(source),
+            _ => None,
+        }
+    }
+
+    pub fn with_deep(deep: DeepPixels, width: u32, height: u32) -> Self {
+        let mut data = Self::new(deep.to_rgba8(), width, height);
+        data.bit_depth = deep.bit_depth();
+        *data.deep.get_mut().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(deep));
+        data
+    }
+
+    pub(crate) fn from_scene_linear(samples: Vec<f32>, width: u32, height: u32) -> Self {
+        let mut data = Self::new(Tonemap::default().to_rgba8(&samples), width, height);
+        data.bit_depth = 32;
+        data.color_space = Some("Linear".to_string());
+        data.scene_linear = true;
+        *data.deep.get_mut().unwrap_or_else(|e| e.into_inner()) =
+            Some(Arc::new(DeepPixels::F32(samples)));
+        data
+    }
+
+    /// Measurements such as Hounsfield units, seen through `window`. The RGBA8
+    /// buffer is baked with that window; the view applies its own.
+    pub(crate) fn from_data_values(
+        samples: Vec<f32>,
+        width: u32,
+        height: u32,
+        window: Window,
+    ) -> Self {
+        let mut data = Self::new(Tone::Window(window).to_rgba8(&samples), width, height);
+        data.bit_depth = 32;
+        *data.deep.get_mut().unwrap_or_else(|e| e.into_inner()) =
+            Some(Arc::new(DeepPixels::F32(samples)));
+        data
+    }
+
+    /// Keeps 16-bit and float samples that into_rgba8 would have rounded away.
+    pub(crate) fn from_dynamic(img: DynamicImage) -> Self {
+        let (width, height) = (img.width(), img.height());
+        match img.color() {
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+                Self::with_deep(DeepPixels::U16(img.into_rgba16().into_raw()), width, height)
+            }
+            ColorType::Rgb32F | ColorType::Rgba32F => Self::with_deep(
+                DeepPixels::F32(img.into_rgba32f().into_raw()),
+                width,
+                height,
+            ),
+            _ => Self::new(img.into_rgba8().into_raw(), width, height),
         }
     }
 
@@ -110,30 +294,129 @@ impl ImageData {
         Arc::clone(&self.pixels.lock().unwrap_or_else(|e| e.into_inner()))
     }
 
+    pub fn deep_snapshot(&self) -> Option<Arc<DeepPixels>> {
+        self.deep.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn sample_format(&self) -> Option<SampleFormat> {
+        self.deep
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|d| d.format())
+    }
+
     pub fn pixels_available(&self) -> bool {
         self.pixels_snapshot().len() >= self.size_bytes()
     }
 
     pub fn release_pixels(&self) {
         *self.pixels.lock().unwrap_or_else(|e| e.into_inner()) = Arc::new(Vec::new());
+        *self.deep.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// Runs a color conversion over the most precise buffer the image has,
+    /// re-deriving the RGBA8 buffer when that was the high-precision one.
+    fn convert_color(
+        &mut self,
+        rgba8: impl FnOnce(&mut [u8]) -> Option<String>,
+        deep: impl FnOnce(&mut [f32]) -> Option<String>,
+    ) -> Option<String> {
+        let pixels = self.pixels.get_mut().unwrap_or_else(|e| e.into_inner());
+        match self.deep.get_mut().unwrap_or_else(|e| e.into_inner()) {
+            Some(samples) => {
+                let samples = Arc::make_mut(samples);
+                let name = samples.map_f32(deep);
+                *pixels = Arc::new(samples.to_rgba8());
+                name
+            }
+            None => rgba8(Arc::make_mut(pixels)),
+        }
+    }
+
+    /// Converts the pixels from an embedded ICC profile to sRGB, recording the
+    /// profile's name. A profile that cannot describe RGB is dropped.
+    pub fn apply_icc_profile(&mut self, icc: Vec<u8>) {
+        if let Some(name) = self.convert_color(|p| icc::to_srgb(p, &icc), |s| icc::to_srgb(s, &icc))
+        {
+            self.color_space = Some(name);
+            self.icc_profile = Some(Arc::new(icc));
+        }
+    }
+
+    fn apply_png_color(&mut self, path: &Path) {
+        let Some(color) = Self::png_color(path) else {
+            return;
+        };
+        if let Some(name) = self.convert_color(
+            |p| icc::png_to_srgb(p, color),
+            |s| icc::png_to_srgb(s, color),
+        ) {
+            self.color_space = Some(name);
+        }
+    }
+
+    fn png_color(path: &Path) -> Option<icc::PngColor> {
+        let file = File::open(path).ok()?;
+        let limits = png::Limits { bytes: usize::MAX };
+        let reader = png::Decoder::new_with_limits(BufReader::new(file), limits)
+            .read_info()
+            .ok()?;
+        let info = reader.info();
+        Some(icc::PngColor {
+            srgb: info.srgb.is_some(),
+            cicp: info
+                .coding_independent_code_points
+                .map(|c| (c.color_primaries, c.transfer_function)),
+            gamma: info.gama_chunk.map(|g| g.into_value()),
+            chromaticities: info.chrm_chunk.map(|c| {
+                [c.white, c.red, c.green, c.blue].map(|(x, y)| (x.into_value(), y.into_value()))
+            }),
+        })
+    }
+
+    /// Decodes a file already read into memory. The content decides the
+    /// format when it can, and `hint` (from the name) when it cannot, as for
+    /// TGA.
+    pub fn load_from_memory(bytes: &[u8], hint: Option<ImageFormat>) -> Result<Self, ImageError> {
+        let mut reader = ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format()?;
+        if reader.format().is_none()
+            && let Some(format) = hint
+        {
+            reader.set_format(format);
+        }
+        reader.no_limits();
+        let mut decoder = reader.into_decoder()?;
+        let icc = decoder.icc_profile().ok().flatten();
+        let mut data = Self::from_dynamic(DynamicImage::from_decoder(decoder)?);
+        if let Some(icc) = icc {
+            data.apply_icc_profile(icc);
+        }
+        Ok(data)
     }
 
     pub fn load(path: &Path) -> Result<Self, ImageError> {
         let mut reader = ImageReader::open(path)?.with_guessed_format()?;
         reader.no_limits();
-        let dyn_img = reader.decode()?;
-        let bit_depth = match dyn_img.color() {
-            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => 16,
-            ColorType::Rgb32F | ColorType::Rgba32F => 32,
-            _ => 8,
-        };
-        let img = dyn_img.into_rgba8();
-        let (width, height) = img.dimensions();
-        let mut data = Self::new(img.into_raw(), width, height);
-        data.bit_depth = bit_depth;
+        let format = reader.format();
+        let mut decoder = reader.into_decoder()?;
+        let icc = decoder.icc_profile().ok().flatten();
+        let mut data = Self::from_dynamic(DynamicImage::from_decoder(decoder)?);
+        match icc {
+            Some(icc) => data.apply_icc_profile(icc),
+            None if format == Some(ImageFormat::Png) => data.apply_png_color(path),
+            None => {}
+        }
         Ok(data)
     }
 
+    pub fn load_tiff(path: &Path) -> Result<Self, ImageError> {
+        match PageSource::tiff(path) {
+            Some(source) => Arc::new(source).load(0),
+            None => Self::load(path),
+        }
+    }
+
     pub fn load_gif(path: &Path) -> Result<Animation, ImageError> {
         let file = File::open(path).map_err(ImageError::IoError)?;
         let mut gif_opts = gif::DecodeOptions::new();
@@ -165,15 +448,6 @@ impl ImageData {
         Animation::new(frames)
     }
 
-    fn tonemap_scale(r: f32, g: f32, b: f32) -> f32 {
-        let peak = r.max(g).max(b);
-        if peak > 1e-6 { 1.0 / (1.0 + peak) } else { 1.0 }
-    }
-
-    fn tonemap_channel(v: f32, scale: f32) -> u8 {
-        ((v * scale).clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0) as u8
-    }
-
     pub fn load_hdr(path: &Path) -> Result<Self, ImageError> {
         let file = File::open(path).map_err(ImageError::IoError)?;
         let decoder = HdrDecoder::new(BufReader::new(file))?;
@@ -185,95 +459,38 @@ impl ImageData {
         decoder.read_image(&mut buf)?;
 
         let floats: &[f32] = cast_slice(&buf);
-        let mut pixels = Vec::with_capacity(pixel_count * 4);
+        let mut samples = Vec::with_capacity(pixel_count * 4);
         for chunk in floats.chunks_exact(3) {
-            let scale = Self::tonemap_scale(chunk[0], chunk[1], chunk[2]);
-            pixels.push(Self::tonemap_channel(chunk[0], scale));
-            pixels.push(Self::tonemap_channel(chunk[1], scale));
-            pixels.push(Self::tonemap_channel(chunk[2], scale));
-            pixels.push(255);
+            samples.extend_from_slice(chunk);
+            samples.push(1.0);
         }
 
-        let mut data = Self::new(pixels, width, height);
-        data.bit_depth = 32;
-        data.color_space = Some("Linear");
-        Ok(data)
+        Ok(Self::from_scene_linear(samples, width, height))
     }
 
     pub fn load_exr(path: &Path) -> Result<Self, ImageError> {
-        let file = File::open(path).map_err(ImageError::IoError)?;
-        let decoder = OpenExrDecoder::new(BufReader::new(file))?;
-        let (width, height) = decoder.dimensions();
-        let color_type = decoder.color_type();
-        let pixel_count = width as usize * height as usize;
-        let mut pixels = Vec::with_capacity(pixel_count * 4);
-
-        match color_type {
-            ColorType::Rgb32F => {
-                let mut buf = vec![0u8; pixel_count * 12];
-                decoder.read_image(&mut buf)?;
-                let floats: &[f32] = cast_slice(&buf);
-                for chunk in floats.chunks_exact(3) {
-                    let scale = Self::tonemap_scale(chunk[0], chunk[1], chunk[2]);
-                    pixels.push(Self::tonemap_channel(chunk[0], scale));
-                    pixels.push(Self::tonemap_channel(chunk[1], scale));
-                    pixels.push(Self::tonemap_channel(chunk[2], scale));
-                    pixels.push(255);
-                }
-            }
-            ColorType::Rgba32F => {
-                let mut buf = vec![0u8; pixel_count * 16];
-                decoder.read_image(&mut buf)?;
-                let floats: &[f32] = cast_slice(&buf);
-                for chunk in floats.chunks_exact(4) {
-                    let scale = Self::tonemap_scale(chunk[0], chunk[1], chunk[2]);
-                    pixels.push(Self::tonemap_channel(chunk[0], scale));
-                    pixels.push(Self::tonemap_channel(chunk[1], scale));
-                    pixels.push(Self::tonemap_channel(chunk[2], scale));
-                    pixels.push((chunk[3].clamp(0.0, 1.0) * 255.0) as u8);
-                }
-            }
-            _ => return Self::load(path),
-        }
-
-        let mut data = Self::new(pixels, width, height);
-        data.bit_depth = 32;
-        data.color_space = Some("Linear");
-        Ok(data)
+        let document = Arc::new(ExrDocument::read(path)?);
+        let view = document.default_view();
+        Ok(document.render(view))
     }
 
-    pub fn load_jxl(path: &Path) -> Result<Self, ImageError> {
-        let image =
-            JxlImage::open_with_defaults(path).map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let width = image.width();
-        let height = image.height();
-        let render = image
-            .render_frame(0)
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-
-        let fb = render.image_all_channels();
-        let channels = fb.channels();
-        let buf = fb.buf();
-
-        let pixel_count = width as usize * height as usize;
-        let mut pixels = Vec::with_capacity(pixel_count * 4);
-        for chunk in buf.chunks_exact(channels) {
-            pixels.push((chunk[0].clamp(0.0, 1.0) * 255.0) as u8);
-            pixels.push((chunk[1].clamp(0.0, 1.0) * 255.0) as u8);
-            pixels.push((chunk[2].clamp(0.0, 1.0) * 255.0) as u8);
-            pixels.push(if channels >= 4 {
-                (chunk[3].clamp(0.0, 1.0) * 255.0) as u8
-            } else {
-                255
-            });
+    /// Opens a PSD on its layers when they can be read, and on the composite
+    /// alone when they cannot.
+    pub fn load_psd(path: &Path) -> Result<Self, ImageError> {
+        let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
+        let composite = Self::psd_composite(&bytes);
+        let merged = composite
+            .as_ref()
+            .ok()
+            .map(|c| Arc::unwrap_or_clone(c.pixels_snapshot()));
+        match LayerDocument::psd(&bytes, merged) {
+            Ok(document) => Ok(Arc::new(document).open()),
+            Err(_) => composite,
         }
-
-        Ok(Self::new(pixels, width, height))
     }
 
-    pub fn load_psd(path: &Path) -> Result<Self, ImageError> {
-        let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
-        let mut decoder = PSDDecoder::new(ZCursor::new(&bytes));
+    fn psd_composite(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut decoder = PSDDecoder::new(ZCursor::new(bytes));
         let pixels = decoder
             .decode()
             .map_err(|e| ImageError::IoError(Error::other(format!("{e:?}"))))?;
@@ -328,6 +545,13 @@ impl ImageData {
     }
 
     pub fn load_kra(path: &Path) -> Result<Self, ImageError> {
+        match LayerDocument::kra(path) {
+            Ok(document) => Ok(Arc::new(document).open()),
+            Err(_) => Self::load_kra_merged(path),
+        }
+    }
+
+    fn load_kra_merged(path: &Path) -> Result<Self, ImageError> {
         let file = File::open(path).map_err(ImageError::IoError)?;
         let mut archive = ZipArchive::new(BufReader::new(file))
             .map_err(|e| ImageError::IoError(Error::other(e)))?;
@@ -342,7 +566,21 @@ impl ImageData {
         Ok(Self::new(img.into_raw(), width, height))
     }
 
+    pub fn load_ico(path: &Path) -> Result<Self, ImageError> {
+        match PageSource::ico(path) {
+            Some(source) => {
+                let source = Arc::new(source);
+                source.load(source.largest())
+            }
+            None => Self::load(path),
+        }
+    }
+
     pub fn load_icns(path: &Path) -> Result<Self, ImageError> {
+        if let Some(source) = PageSource::icns(path) {
+            let source = Arc::new(source);
+            return source.load(source.largest());
+        }
         let file = File::open(path).map_err(ImageError::IoError)?;
         let family = IconFamily::read(BufReader::new(file)).map_err(ImageError::IoError)?;
         let icon_type = family
@@ -364,21 +602,11 @@ impl ImageData {
     }
 
     pub fn load_svg(path: &Path) -> Result<Self, ImageError> {
-        let svg_data = std::fs::read(path).map_err(ImageError::IoError)?;
-        let mut opt = SvgOptions {
-            resources_dir: path.parent().map(|p| p.to_path_buf()),
-            ..SvgOptions::default()
-        };
-        opt.fontdb_mut().load_system_fonts();
-        let tree = usvg::Tree::from_data(&svg_data, &opt)
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let size = tree.size().to_int_size();
-        let width = size.width().max(1);
-        let height = size.height().max(1);
-        let mut pixmap = Pixmap::new(width, height)
-            .ok_or_else(|| ImageError::IoError(Error::other("SVG dimensions too large")))?;
-        resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
-        Ok(Self::new(pixmap.take(), width, height))
+        let document = SvgDocument::load(path)?;
+        let (width, height) = (document.width, document.height);
+        let mut data = Self::new(document.render(width, height)?, width, height);
+        data.source = Source::Svg(Arc::new(document));
+        Ok(data)
     }
 
     pub fn load_apng(path: &Path) -> Result<Animation, ImageError> {
@@ -439,6 +667,10 @@ impl ImageData {
     pub fn load_jp2(path: &Path) -> Result<Self, ImageError> {
         let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
         let img = Jp2Image::from_bytes(&bytes).map_err(|e| ImageError::IoError(Error::other(e)))?;
+        Self::from_jp2(&img)
+    }
+
+    pub(crate) fn from_jp2(img: &Jp2Image) -> Result<Self, ImageError> {
         let img_data = img
             .get_pixels(Some(255))
             .map_err(|e| ImageError::IoError(Error::other(e)))?;
@@ -490,276 +722,24 @@ impl ImageData {
         Ok(data)
     }
 
-    pub fn load_dicom(path: &Path) -> Result<Self, ImageError> {
-        let obj = dicom_open_file(path).map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let pixel_data = obj
-            .decode_pixel_data()
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let img = pixel_data
-            .to_dynamic_image(0)
-            .map_err(|e| ImageError::IoError(Error::other(e)))?
-            .into_rgba8();
-        let (width, height) = img.dimensions();
-        Ok(Self::new(img.into_raw(), width, height))
-    }
-
     pub fn load_dds(path: &Path) -> Result<Self, ImageError> {
-        let file = File::open(path).map_err(ImageError::IoError)?;
-        let mut decoder = DdsDecoder::new(BufReader::new(file))
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let size = decoder.main_size();
-        let width = size.width;
-        let height = size.height;
-        let buf_len = ColorFormat::RGBA_U8
-            .buffer_size(size)
-            .ok_or_else(|| ImageError::IoError(Error::other("DDS dimensions overflow")))?;
-        let mut pixels = vec![0u8; buf_len];
-        let view = ImageViewMut::new(&mut pixels, size, ColorFormat::RGBA_U8)
-            .ok_or_else(|| ImageError::IoError(Error::other("DDS buffer size mismatch")))?;
-        decoder
-            .read_surface(view)
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-        Ok(Self::new(pixels, width, height))
+        Arc::new(TextureDocument::dds(path)?).open()
     }
 
     pub fn load_ktx2(path: &Path) -> Result<Self, ImageError> {
-        use ktx2::Format;
-
-        let data = std::fs::read(path).map_err(ImageError::IoError)?;
-        let reader = Ktx2Reader::new(&data)
-            .map_err(|e| ImageError::IoError(Error::other(format!("{e:?}"))))?;
-        let header = reader.header();
-        let width = header.pixel_width;
-        let height = header.pixel_height;
-        let level = reader
-            .levels()
-            .next()
-            .ok_or_else(|| ImageError::IoError(Error::other("KTX2 has no mip levels")))?;
-
-        let is_basis = header.supercompression_scheme == Some(SupercompressionScheme::BasisLZ)
-            || header.format.is_none();
-        if is_basis {
-            let basis_data = level.data.to_vec();
-            let mut transcoder = Transcoder::new();
-            transcoder.prepare_transcoding(&basis_data).map_err(|_| {
-                ImageError::IoError(Error::other("KTX2 prepare_transcoding failed"))
-            })?;
-            let desc = transcoder
-                .image_level_description(&basis_data, 0, 0)
-                .ok_or_else(|| {
-                    ImageError::IoError(Error::other("KTX2 no image level description"))
-                })?;
-            let params = TranscodeParameters {
-                image_index: 0,
-                level_index: 0,
-                decode_flags: Some(DecodeFlags::HIGH_QUALITY),
-                output_row_pitch_in_blocks_or_pixels: None,
-                output_rows_in_pixels: None,
-            };
-            let pixels = transcoder
-                .transcode_image_level(&basis_data, TranscoderTextureFormat::RGBA32, params)
-                .map_err(|e| ImageError::IoError(Error::other(format!("{e:?}"))))?;
-            return Ok(Self::new(pixels, desc.original_width, desc.original_height));
-        }
-
-        let raw: Vec<u8> = match header.supercompression_scheme {
-            Some(s) if s == SupercompressionScheme::Zstandard => {
-                zstd::decode_all(level.data).map_err(|e| ImageError::IoError(Error::other(e)))?
-            }
-            _ => level.data.to_vec(),
-        };
-
-        let fmt = header
-            .format
-            .ok_or_else(|| ImageError::IoError(Error::other("KTX2: missing format field")))?;
-        let pixels: Vec<u8> = match fmt {
-            f if f == Format::R8G8B8A8_UNORM || f == Format::R8G8B8A8_SRGB => raw,
-            f if f == Format::B8G8R8A8_UNORM || f == Format::B8G8R8A8_SRGB => raw
-                .chunks_exact(4)
-                .flat_map(|p| [p[2], p[1], p[0], p[3]])
-                .collect(),
-            f if f == Format::R8G8B8_UNORM || f == Format::R8G8B8_SRGB => raw
-                .chunks_exact(3)
-                .flat_map(|p| [p[0], p[1], p[2], 255])
-                .collect(),
-            f if f == Format::B8G8R8_UNORM || f == Format::B8G8R8_SRGB => raw
-                .chunks_exact(3)
-                .flat_map(|p| [p[2], p[1], p[0], 255])
-                .collect(),
-            _ => {
-                return Err(ImageError::IoError(Error::other(format!(
-                    "KTX2: unsupported Vulkan format {:?}",
-                    fmt.value()
-                ))));
-            }
-        };
-
-        Ok(Self::new(pixels, width, height))
+        Arc::new(TextureDocument::ktx2(path)?).open()
     }
 
     pub fn load_raw(path: &Path) -> Result<Self, ImageError> {
-        use rawler::imgop::develop::RawDevelop;
-
-        let raw = rawler::decode_file(path).map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let intermediate = RawDevelop::default()
-            .develop_intermediate(&raw)
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let img = intermediate
-            .to_dynamic_image()
-            .ok_or_else(|| ImageError::IoError(Error::other("failed to convert RAW to image")))?
-            .into_rgba8();
-        let (width, height) = img.dimensions();
-        let mut data = Self::new(img.into_raw(), width, height);
-        data.bit_depth = 16;
-        Ok(data)
-    }
-
-    #[cfg(feature = "heif")]
-    pub fn load_heic(path: &Path) -> Result<Self, ImageError> {
-        use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
-
-        let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
-        let lib_heif = LibHeif::new();
-        let ctx = match HeifContext::read_from_bytes(&bytes) {
-            Ok(ctx) => ctx,
-            Err(_) => {
-                let img = image::load_from_memory(&bytes)?.into_rgba8();
-                let (width, height) = img.dimensions();
-                return Ok(Self::new(img.into_raw(), width, height));
-            }
-        };
-        let handle = ctx
-            .primary_image_handle()
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-
-        let has_alpha = handle.has_alpha_channel();
-        let chroma = if has_alpha {
-            RgbChroma::Rgba
-        } else {
-            RgbChroma::Rgb
-        };
-
-        let image = lib_heif
-            .decode(&handle, ColorSpace::Rgb(chroma), None)
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-
-        let plane = image
-            .planes()
-            .interleaved
-            .ok_or_else(|| ImageError::IoError(Error::other("no interleaved plane in HEIC")))?;
-
-        let width = plane.width as usize;
-        let height = plane.height as usize;
-        let stride = plane.stride;
-
-        let mut pixels = Vec::with_capacity(width * height * 4);
-        if has_alpha {
-            for y in 0..height {
-                pixels.extend_from_slice(&plane.data[y * stride..y * stride + width * 4]);
-            }
-        } else {
-            for y in 0..height {
-                for rgb in plane.data[y * stride..y * stride + width * 3].chunks_exact(3) {
-                    pixels.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
-                }
-            }
-        }
-
-        Ok(Self::new(pixels, plane.width, plane.height))
+        Arc::new(RawDocument::decode(path)?).open()
     }
 
     pub fn load_xcf(path: &Path) -> Result<Self, ImageError> {
-        let path_buf = path.to_path_buf();
-        let xcf = std::panic::catch_unwind(|| Xcf::open(&path_buf))
-            .map_err(|_| {
-                ImageError::IoError(Error::other("xcf-rs panicked parsing XCF tile data"))
-            })?
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-        let (width, height) = xcf.dimensions();
-        let mut canvas = vec![0u8; width as usize * height as usize * 4];
-
-        for layer in xcf.layers.iter().rev() {
-            let (lw, lh): (u32, u32) = layer.dimensions();
-            let buf = layer.raw_rgba_buffer();
-            for py in 0..lh.min(height) {
-                for px in 0..lw.min(width) {
-                    let src = buf[py as usize * lw as usize + px as usize].0;
-                    let dst = (py as usize * width as usize + px as usize) * 4;
-                    let sa = src[3] as u32;
-                    let da = canvas[dst + 3] as u32;
-                    let out_a = sa + da * (255 - sa) / 255;
-                    for c in 0..3 {
-                        let num =
-                            src[c] as u32 * sa + canvas[dst + c] as u32 * da * (255 - sa) / 255;
-                        if let Some(v) = num.checked_div(out_a) {
-                            canvas[dst + c] = v as u8;
-                        }
-                    }
-                    if out_a > 0 {
-                        canvas[dst + 3] = out_a as u8;
-                    }
-                }
-            }
-        }
-
-        Ok(Self::new(canvas, width, height))
+        Ok(Arc::new(LayerDocument::xcf(path)?).open())
     }
 
     pub fn load_fits(path: &Path) -> Result<Self, ImageError> {
-        let file = File::open(path).map_err(ImageError::IoError)?;
-        let mut hdu_list = Fits::from_reader(BufReader::new(file));
-
-        let hdu = hdu_list
-            .next()
-            .ok_or_else(|| ImageError::IoError(Error::other("FITS file has no HDUs")))?
-            .map_err(|e| ImageError::IoError(Error::other(e)))?;
-
-        let HDU::Primary(primary) = hdu else {
-            return Err(ImageError::IoError(Error::other(
-                "FITS primary HDU is not an image",
-            )));
-        };
-
-        let naxis = primary.get_header().get_xtension().get_naxis();
-        let (width, height) = match naxis {
-            [w, h] | [w, h, _] => (*w as u32, *h as u32),
-            _ => {
-                return Err(ImageError::IoError(Error::other(
-                    "FITS image must be 2D or 3D",
-                )));
-            }
-        };
-
-        let image_data = hdu_list.get_data(&primary);
-        let floats: Vec<f32> = match image_data.pixels() {
-            Pixels::I16(it) => it.map(|v| v as f32).collect(),
-            Pixels::I32(it) => it.map(|v| v as f32).collect(),
-            Pixels::I64(it) => it.map(|v| v as f32).collect(),
-            Pixels::F32(it) => it.collect(),
-            Pixels::F64(it) => it.map(|v| v as f32).collect(),
-            Pixels::U8(it) => it.map(|v| v as f32).collect(),
-        };
-
-        let plane_len = (width * height) as usize;
-        let floats = &floats[..plane_len.min(floats.len())];
-
-        let (mut min, mut max) = (f32::MAX, f32::MIN);
-        for &v in floats {
-            if v.is_finite() {
-                min = min.min(v);
-                max = max.max(v);
-            }
-        }
-        let range = (max - min).max(1e-10);
-
-        let mut pixels = Vec::with_capacity(plane_len * 4);
-        for &v in floats {
-            let byte = ((v - min) / range * 255.0) as u8;
-            pixels.extend_from_slice(&[byte, byte, byte, 255]);
-        }
-        pixels.resize(plane_len * 4, 0);
-
-        Ok(Self::new(pixels, width, height))
+        Arc::new(FitsDocument::read(path)?).open()
     }
 
     pub fn load_eps(path: &Path) -> Result<Self, ImageError> {
@@ -812,10 +792,13 @@ impl ImageData {
     }
 
     pub fn load_media(path: &Path) -> Result<MediaData, ImageError> {
-        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            Self::load_media_inner(path)
-        }))
-        .unwrap_or_else(|payload| {
+        Self::guarded(|| Self::load_media_inner(path))
+    }
+
+    /// Runs a decode, turning a panic inside it into an error so that a
+    /// broken file fails to open rather than taking the thread down.
+    fn guarded<T>(decode: impl FnOnce() -> Result<T, ImageError>) -> Result<T, ImageError> {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(decode)).unwrap_or_else(|payload| {
             let msg = payload
                 .downcast_ref::<&str>()
                 .map(|s| s.to_string())
@@ -827,16 +810,51 @@ impl ImageData {
         })
     }
 
+    /// A quick, coarse stand-in to show while load_media runs, for the few
+    /// formats that can give one. Guarded as load_media is, and none is
+    /// decoded that claims to be too large to hold.
+    pub fn load_preview(path: &Path) -> Option<Self> {
+        let preview = || match super::sniff::dispatch_extension(path).as_str() {
+            "jxl" => super::jxl::preview(path),
+            "jpg" | "jpeg" => super::embedded::jpeg_preview(path),
+            ext if RAW_EXTENSIONS.contains(&ext) => super::embedded::raw_preview(path),
+            _ => None,
+        };
+        Self::guarded(|| Ok(preview())).ok().flatten()
+    }
+
+    /// A RAW's embedded preview, to open in its place when developing the
+    /// sensor data is not wanted. None for anything else.
+    pub fn load_embedded_preview(path: &Path) -> Option<Self> {
+        let ext = super::sniff::dispatch_extension(path);
+        if !RAW_EXTENSIONS.contains(&ext.as_str()) {
+            return None;
+        }
+        Self::guarded(|| Ok(super::embedded::raw_preview(path)))
+            .ok()
+            .flatten()
+    }
+
     fn load_media_inner(path: &Path) -> Result<MediaData, ImageError> {
-        let ext = path
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("")
-            .to_ascii_lowercase();
+        if let Some((archive, name)) = super::archive::split(path) {
+            return super::archive::load(archive, &name);
+        }
+        let ext = super::sniff::dispatch_extension(path);
+        if let Some(data) = super::stream::open(path, &ext) {
+            return Ok(Self::attach_exif(
+                path,
+                &ext,
+                MediaData::Image(Box::new(data)),
+            ));
+        }
 
         let media = match ext.as_str() {
             "gif" => MediaData::Animation(Self::load_gif(path)?),
             "apng" => MediaData::Animation(Self::load_apng(path)?),
+            "dcm" | "dicom" => super::dicom::load(path)?,
+            "jxl" => super::jxl::load(path)?,
+            #[cfg(feature = "heif")]
+            "heic" | "heif" | "avif" => super::heif::load(path)?,
             #[cfg(feature = "av")]
             e if super::video::VIDEO_EXTENSIONS.contains(&e) => {
                 MediaData::Video(Box::new(super::video::probe_video(path)?))
@@ -856,28 +874,19 @@ impl ImageData {
                 static TABLE: &[(&[&str], fn(&Path) -> Result<ImageData, ImageError>)] = &[
                     (&["hdr"], ImageData::load_hdr),
                     (&["exr"], ImageData::load_exr),
-                    (&["jxl"], ImageData::load_jxl),
+                    (&["tif", "tiff"], ImageData::load_tiff),
                     (&["psd", "psb"], ImageData::load_psd),
+                    (&["ico"], ImageData::load_ico),
                     (&["icns"], ImageData::load_icns),
                     (&["kra"], ImageData::load_kra),
                     (&["xcf"], ImageData::load_xcf),
                     (&["svg", "svgz"], ImageData::load_svg),
                     (&["jp2", "j2k", "j2c", "jpx"], ImageData::load_jp2),
-                    (&["dcm", "dicom"], ImageData::load_dicom),
                     (&["dds"], ImageData::load_dds),
                     (&["ktx2"], ImageData::load_ktx2),
                     (&["fits", "fit", "fts"], ImageData::load_fits),
                     (&["eps", "ps", "epsf"], ImageData::load_eps),
-                    (
-                        &[
-                            "ari", "arw", "cr2", "cr3", "crm", "crw", "dcr", "dcs", "dng", "erf",
-                            "fff", "iiq", "kdc", "mef", "mos", "mrw", "nef", "nrw", "orf", "ori",
-                            "pef", "qtk", "raf", "raw", "rw2", "rwl", "srw", "x3f", "3fr",
-                        ],
-                        ImageData::load_raw,
-                    ),
-                    #[cfg(feature = "heif")]
-                    (&["heic", "heif"], ImageData::load_heic),
+                    (RAW_EXTENSIONS, ImageData::load_raw),
                 ];
 
                 let loader = TABLE
@@ -890,12 +899,20 @@ impl ImageData {
             }
         };
 
-        Ok(Self::attach_exif(path, media))
+        Ok(Self::attach_exif(path, &ext, media))
     }
 
-    fn attach_exif(path: &Path, media: MediaData) -> MediaData {
+    fn attach_exif(path: &Path, ext: &str, media: MediaData) -> MediaData {
         if let MediaData::Image(mut img) = media {
+            // A loader that knows the orientation better than EXIF can read
+            // it, as a RAW decoder does for CR3 or RAF, keeps its own.
+            let orientation = img.exif.orientation;
             img.exif = ExifData::read(path);
+            img.exif.orientation = img.exif.orientation.or(orientation);
+            if matches!(ext, "heic" | "heif" | "avif") {
+                // libheif already applies the irot/imir boxes while decoding.
+                img.exif.orientation = None;
+            }
             MediaData::Image(img)
         } else {
             media
@@ -961,4 +978,14 @@ mod tests {
             assert_eq!(&px[o..o + 4], &[g, g, g, 255], "pixel {i}");
         }
     }
+
+    #[test]
+    fn a_decoder_that_panics_fails_instead() {
+        let result: Result<(), _> = ImageData::guarded(|| panic!("bad huffman table"));
+        let err = result.expect_err("the panic is caught");
+        assert!(
+            err.to_string()
+                .contains("decoder panicked: bad huffman table")
+        );
+    }
 }
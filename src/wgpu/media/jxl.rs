@@ -0,0 +1,195 @@
+//! JPEG XL: stills, animations and a first look at progressive files.
+//!
+//! jxl-oxide hands back keyframes, the frames a viewer actually shows, with
+//! blending and patches already composited. An animated file loads as an
+//! Animation with one frame per keyframe, each held for its own duration in
+//! the animation's ticks. The file's loop count is not used: like GIF and
+//! WebP, playback follows the loop preference.
+//!
+//! Every frame keeps its precision. Samples above 8 bits stay deep, as 16-bit
+//! integers or half or single floats, the same as a still would.
+//!
+//! A progressive file stores a coarse pass first and refines it after. While
+//! the full decode runs, preview feeds jxl-oxide only the front of the file
+//! and renders what has loaded, which for such a file is the whole picture
+//! at low detail. Files without passes, animations and small files get no
+//! preview, since a partial render of those would show holes or would not
+//! arrive any sooner than the real thing.
+
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use half::f16;
+use image::ImageError;
+use jxl_oxide::{InitializeResult, JxlImage, Render, image::BitDepth};
+
+use super::animation::{Animation, Frame};
+use super::image_data::{ImageData, MediaData};
+use super::samples::DeepPixels;
+use super::stream::too_large_to_hold;
+
+/// Files smaller than this decode fast enough that a preview only flickers.
+const PREVIEW_MIN_BYTES: usize = 1024 * 1024;
+
+/// How much of a progressive file is fed for its preview.
+const PREVIEW_FRACTION: usize = 4;
+
+/// Shown for a keyframe whose duration is zero.
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+fn jxl_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+pub fn load(path: &Path) -> Result<MediaData, ImageError> {
+    let image = JxlImage::open_with_defaults(path).map_err(jxl_error)?;
+    let animation = image
+        .image_header()
+        .metadata
+        .animation
+        .as_ref()
+        .filter(|_| image.num_loaded_keyframes() > 1);
+    let Some(animation) = animation else {
+        let render = image.render_frame(0).map_err(jxl_error)?;
+        return Ok(MediaData::Image(Box::new(to_image_data(&image, &render))));
+    };
+
+    let frames = (0..image.num_loaded_keyframes())
+        .map(|index| {
+            let render = image.render_frame(index).map_err(jxl_error)?;
+            Ok(Frame {
+                data: Arc::new(to_image_data(&image, &render)),
+                delay: frame_delay(
+                    animation.tps_numerator,
+                    animation.tps_denominator,
+                    render.duration(),
+                ),
+            })
+        })
+        .collect::<Result<Vec<_>, ImageError>>()?;
+    Ok(MediaData::Animation(Animation::new(frames)?))
+}
+
+/// How long a keyframe of `ticks` shows, at `numerator / denominator` ticks
+/// per second.
+fn frame_delay(numerator: u32, denominator: u32, ticks: u32) -> Duration {
+    if numerator == 0 || ticks == 0 {
+        return DEFAULT_DELAY;
+    }
+    Duration::from_secs_f64(ticks as f64 * denominator as f64 / numerator as f64)
+}
+
+/// The coarse first pass of a progressive still, or None when the file has
+/// nothing to show early.
+pub fn preview(path: &Path) -> Option<ImageData> {
+    let bytes = std::fs::read(path).ok()?;
+    if bytes.len() < PREVIEW_MIN_BYTES {
+        return None;
+    }
+    let mut uninit = JxlImage::builder().build_uninit();
+    uninit
+        .feed_bytes(&bytes[..bytes.len() / PREVIEW_FRACTION])
+        .ok()?;
+    let InitializeResult::Initialized(image) = uninit.try_init().ok()? else {
+        return None;
+    };
+    if image.image_header().metadata.animation.is_some() || image.num_loaded_keyframes() > 0 {
+        return None;
+    }
+    // A preview renders at full size.
+    if too_large_to_hold(image.width(), image.height()) {
+        return None;
+    }
+    let frame = image.current_loading_frame()?;
+    if frame.header().passes.num_passes < 2 {
+        return None;
+    }
+    let render = image.render_loading_frame().ok()?;
+    Some(to_image_data(&image, &render))
+}
+
+fn to_image_data(image: &JxlImage, render: &Render) -> ImageData {
+    let width = image.width();
+    let height = image.height();
+    let fb = render.image_all_channels();
+    let channels = fb.channels();
+    let buf = fb.buf();
+    let format = image.pixel_format();
+    let gray = format.is_grayscale();
+    // Alpha is the first channel after the color ones.
+    let alpha = format.has_alpha().then_some(if gray { 1 } else { 3 });
+
+    let pixel_count = width as usize * height as usize;
+    let mut data = match image.image_header().metadata.bit_depth {
+        BitDepth::IntegerSample { bits_per_sample } if bits_per_sample <= 8 => {
+            let mut pixels = Vec::with_capacity(pixel_count * 4);
+            for chunk in buf.chunks_exact(channels) {
+                for v in rgba(chunk, gray, alpha) {
+                    pixels.push((v.clamp(0.0, 1.0) * 255.0) as u8);
+                }
+            }
+            ImageData::new(pixels, width, height)
+        }
+        depth => {
+            let mut samples = Vec::with_capacity(pixel_count * 4);
+            for chunk in buf.chunks_exact(channels) {
+                samples.extend_from_slice(&rgba(chunk, gray, alpha));
+            }
+            let deep = match depth {
+                BitDepth::IntegerSample { .. } => DeepPixels::U16(
+                    samples
+                        .iter()
+                        .map(|&v| (v.clamp(0.0, 1.0) * 65535.0).round() as u16)
+                        .collect(),
+                ),
+                BitDepth::FloatSample {
+                    bits_per_sample, ..
+                } if bits_per_sample <= 16 => {
+                    DeepPixels::F16(samples.iter().map(|&v| f16::from_f32(v)).collect())
+                }
+                BitDepth::FloatSample { .. } => DeepPixels::F32(samples),
+            };
+            ImageData::with_deep(deep, width, height)
+        }
+    };
+    data.apply_icc_profile(image.rendered_icc());
+    data
+}
+
+/// One pixel's channels as RGBA, gray spread across red, green and blue and
+/// alpha taken from channel `alpha` when there is one.
+fn rgba(chunk: &[f32], gray: bool, alpha: Option<usize>) -> [f32; 4] {
+    let a = alpha.and_then(|i| chunk.get(i)).copied().unwrap_or(1.0);
+    match chunk {
+        [r, g, b, ..] if !gray => [*r, *g, *b, a],
+        [v, ..] => [*v, *v, *v, a],
+        [] => [0.0, 0.0, 0.0, a],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyframe_delays_follow_the_tick_rate() {
+        assert_eq!(frame_delay(100, 1, 5), Duration::from_millis(50));
+        assert_eq!(frame_delay(1000, 1, 42), Duration::from_millis(42));
+        assert_eq!(frame_delay(30000, 1001, 1).as_micros(), 33366);
+        assert_eq!(frame_delay(100, 1, 0), DEFAULT_DELAY);
+        assert_eq!(frame_delay(0, 1, 5), DEFAULT_DELAY);
+    }
+
+    #[test]
+    fn gray_spreads_across_the_color_channels() {
+        assert_eq!(rgba(&[0.5], true, None), [0.5, 0.5, 0.5, 1.0]);
+        assert_eq!(rgba(&[0.5, 0.25], true, Some(1)), [0.5, 0.5, 0.5, 0.25]);
+        assert_eq!(rgba(&[0.1, 0.2, 0.3], false, None), [0.1, 0.2, 0.3, 1.0]);
+        assert_eq!(
+            rgba(&[0.1, 0.2, 0.3, 0.4], false, Some(3)),
+            [0.1, 0.2, 0.3, 0.4]
+        );
+    }
+}
@@ -0,0 +1,1474 @@
+//! Layered documents: Photoshop PSD and PSB, GIMP XCF and Krita KRA.
+//!
+//! Each format is read into the same flat list of layers, top of the stack
+//! first, with every group directly above its contents and a depth saying how
+//! far in a layer sits. The info panel draws that list as a tree, and toggling a
+//! layer, changing its opacity or soloing it renders a new composite in the
+//! background the way another EXR channel or FITS plane is rendered.
+//!
+//! Layers are kept as straight-alpha RGBA8 at their own bounds, cut down to
+//! the canvas, and a document whose layers would take more than
+//! LAYERS_MAX_BYTES between them does not open. 16-bit and
+//! float documents are brought down to 8 bits on read, and a linear XCF is
+//! sRGB encoded first, so every layer composites the same way.
+//!
+//! The compositor only does normal blending. A hidden group hides everything
+//! inside it and a group's opacity scales its contents, which is what pass
+//! through does for normal layers. Blend modes, clipping, masks, adjustment
+//! layers and layer effects are not reproduced, so a file that leans on them
+//! will look different once recomposited. For that reason a PSD or KRA opens
+//! on the composite saved in the file, and only switches to our own when the
+//! user changes a layer. XCF files carry no composite, so those are always
+//! composited here.
+//!
+//! PSD layers are read from the layer and mask section, including the Lr16
+//! block 16-bit documents keep them in, with raw, RLE and zip channels. Only
+//! RGB and grayscale documents at 8 or 16 bits get layers; anything else opens
+//! as its composite. XCF tiles may be raw, RLE or zlib at any precision, and
+//! indexed layers go through the image's colormap. KRA paint layers are
+//! Krita's LZF-compressed tiles, in 8 or 16-bit RGBA or gray. Vector, file and
+//! filter layers are listed but have no pixels.
+
+use std::fs::File;
+use std::io::{BufReader, Error, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use flate2::read::ZlibDecoder;
+use half::f16;
+use image::ImageError;
+use rayon::prelude::*;
+use zip::ZipArchive;
+
+use super::archive;
+use super::icc::srgb_encode;
+use super::image_data::{ImageData, Source};
+
+/// PSB block keys whose length is 8 bytes rather than 4.
+const PSB_LONG_KEYS: &[&[u8; 4]] = &[
+    b"LMsk", b"Lr16", b"Lr32", b"Layr", b"Mt16", b"Mt32", b"Mtrn", b"Alph", b"FMsk", b"lnk2",
+    b"FEid", b"FXid", b"PxSD",
+];
+
+const XCF_TILE: usize = 64;
+/// How many bytes of pixels a document's layers may hold between them, so a
+/// file claiming layers it cannot have fails to open instead of exhausting
+/// memory.
+const LAYERS_MAX_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+const PROP_END: u32 = 0;
+const PROP_COLORMAP: u32 = 1;
+const PROP_OPACITY: u32 = 6;
+const PROP_VISIBLE: u32 = 8;
+const PROP_OFFSETS: u32 = 15;
+const PROP_COMPRESSION: u32 = 17;
+const PROP_GROUP_ITEM: u32 = 29;
+const PROP_ITEM_PATH: u32 = 30;
+const PROP_FLOAT_OPACITY: u32 = 33;
+
+#[derive(Debug)]
+pub struct LayerPixels {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Straight-alpha RGBA8.
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Layer {
+    pub name: String,
+    /// How many groups the layer is inside.
+    pub depth: usize,
+    pub group: bool,
+    pub visible: bool,
+    pub opacity: f32,
+    /// None for a group, an empty layer, or content that is not read.
+    pub pixels: Option<LayerPixels>,
+}
+
+#[derive(Debug)]
+pub struct LayerDocument {
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// Top of the stack first, each group directly above its contents.
+    pub layers: Vec<Layer>,
+    pub bit_depth: u8,
+    /// The composite saved in the file, shown while the view is the file's own.
+    merged: Option<Vec<u8>>,
+}
+
+/// Which layers are drawn and how strongly, by index into the document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerView {
+    pub visible: Vec<bool>,
+    pub opacity: Vec<f32>,
+    /// Draw only this layer (with its contents, for a group), even if hidden.
+    pub solo: Option<usize>,
+}
+
+impl LayerView {
+    pub fn with_visible(&self, layer: usize, visible: bool) -> Self {
+        let mut view = self.clone();
+        if let Some(v) = view.visible.get_mut(layer) {
+            *v = visible;
+        }
+        view
+    }
+
+    pub fn with_opacity(&self, layer: usize, opacity: f32) -> Self {
+        let mut view = self.clone();
+        if let Some(o) = view.opacity.get_mut(layer) {
+            *o = opacity.clamp(0.0, 1.0);
+        }
+        view
+    }
+
+    pub fn with_solo(&self, solo: Option<usize>) -> Self {
+        Self {
+            solo,
+            ..self.clone()
+        }
+    }
+}
+
+/// The document an image is a composite of, and the view it shows.
+#[derive(Debug, Clone)]
+pub struct LayerSelection {
+    pub document: Arc<LayerDocument>,
+    pub view: LayerView,
+}
+
+fn layer_error(msg: impl Into<String>) -> ImageError {
+    ImageError::IoError(Error::other(msg.into()))
+}
+
+/// The distance between two edges of a layer as the file gives them, failing
+/// when they are the wrong way round or too far apart to measure.
+fn extent(lo: i32, hi: i32) -> Result<usize, ImageError> {
+    hi.checked_sub(lo)
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| layer_error(format!("layer edges {lo} and {hi} are out of range")))
+}
+
+/// The canvas a document's layers are read onto: its size, which each layer
+/// is cut down to, and what the layers kept so far leave of
+/// LAYERS_MAX_BYTES.
+struct Canvas {
+    width: u32,
+    height: u32,
+    left: u64,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            left: LAYERS_MAX_BYTES,
+        }
+    }
+
+    /// The bytes a `width` by `height` layer decodes to, failing when that
+    /// is more than is left.
+    fn fits(&self, width: usize, height: usize) -> Result<usize, ImageError> {
+        width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(4))
+            .filter(|&n| n as u64 <= self.left)
+            .ok_or_else(|| {
+                layer_error(format!(
+                    "a {width}x{height} layer takes the document past {LAYERS_MAX_BYTES} bytes"
+                ))
+            })
+    }
+
+    /// The part of `pixels` on the canvas, counted against what is left, or
+    /// None when none of it is.
+    fn keep(&mut self, pixels: LayerPixels) -> Option<LayerPixels> {
+        let (x, y) = (pixels.x as i64, pixels.y as i64);
+        let (x0, y0) = (x.max(0), y.max(0));
+        let x1 = (x + pixels.width as i64).min(self.width as i64);
+        let y1 = (y + pixels.height as i64).min(self.height as i64);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        let (width, height) = ((x1 - x0) as usize, (y1 - y0) as usize);
+        let rgba = if (width, height) == (pixels.width as usize, pixels.height as usize) {
+            pixels.rgba
+        } else {
+            let (dx, dy) = ((x0 - x) as usize, (y0 - y) as usize);
+            pixels
+                .rgba
+                .chunks_exact(pixels.width as usize * 4)
+                .skip(dy)
+                .take(height)
+                .flat_map(|row| &row[dx * 4..(dx + width) * 4])
+                .copied()
+                .collect()
+        };
+        self.left = self.left.saturating_sub(rgba.len() as u64);
+        Some(LayerPixels {
+            x: x0 as i32,
+            y: y0 as i32,
+            width: width as u32,
+            height: height as u32,
+            rgba,
+        })
+    }
+}
+
+impl LayerDocument {
+    /// The visibility and opacity the file was saved with.
+    pub fn file_view(&self) -> LayerView {
+        LayerView {
+            visible: self.layers.iter().map(|l| l.visible).collect(),
+            opacity: self.layers.iter().map(|l| l.opacity).collect(),
+            solo: None,
+        }
+    }
+
+    pub fn open(self: &Arc<Self>) -> ImageData {
+        self.render(self.file_view())
+    }
+
+    pub fn render(self: &Arc<Self>, view: LayerView) -> ImageData {
+        let rgba = match &self.merged {
+            Some(merged) if view == self.file_view() => merged.clone(),
+            _ => self.composite(&view),
+        };
+        let mut data = ImageData::new(rgba, self.width, self.height);
+        data.bit_depth = self.bit_depth;
+        data.source = Source::Layers(LayerSelection {
+            document: Arc::clone(self),
+            view,
+        });
+        data
+    }
+
+    /// The layer and everything inside it, as a range of indices.
+    fn subtree(&self, layer: usize) -> Range<usize> {
+        let depth = self.layers[layer].depth;
+        let end = self.layers[layer + 1..]
+            .iter()
+            .position(|l| l.depth <= depth)
+            .map_or(self.layers.len(), |p| layer + 1 + p);
+        layer..end
+    }
+
+    /// Each layer's opacity after its groups', or None where it is not drawn.
+    fn alphas(&self, view: &LayerView) -> Vec<Option<f32>> {
+        let mut alphas = vec![None; self.layers.len()];
+        let range = match view.solo {
+            Some(solo) if solo < self.layers.len() => self.subtree(solo),
+            _ => 0..self.layers.len(),
+        };
+        let base = self.layers.get(range.start).map_or(0, |l| l.depth);
+        let mut groups: Vec<Option<f32>> = Vec::new();
+        for i in range {
+            let layer = &self.layers[i];
+            groups.truncate(layer.depth.saturating_sub(base));
+            let parent = groups.last().copied().unwrap_or(Some(1.0));
+            let shown = view.visible[i] || view.solo == Some(i);
+            let alpha = parent.filter(|_| shown).map(|p| p * view.opacity[i]);
+            if layer.group {
+                groups.push(alpha);
+            }
+            alphas[i] = alpha;
+        }
+        alphas
+    }
+
+    fn composite(&self, view: &LayerView) -> Vec<u8> {
+        let alphas = self.alphas(view);
+        let drawn: Vec<(&LayerPixels, f32)> = self
+            .layers
+            .iter()
+            .zip(&alphas)
+            .rev()
+            .filter_map(|(layer, alpha)| Some((layer.pixels.as_ref()?, (*alpha)?)))
+            .filter(|(_, alpha)| *alpha > 0.0)
+            .collect();
+
+        let width = self.width as usize;
+        let mut out = vec![0u8; width * self.height as usize * 4];
+        out.par_chunks_mut(width * 4)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let mut acc = vec![[0.0f32; 4]; width];
+                for (pixels, alpha) in &drawn {
+                    let ly = y as i64 - pixels.y as i64;
+                    if ly < 0 || ly >= pixels.height as i64 {
+                        continue;
+                    }
+                    let x0 = pixels.x.max(0) as usize;
+                    let x1 =
+                        (pixels.x as i64 + pixels.width as i64).clamp(0, width as i64) as usize;
+                    if x0 >= x1 {
+                        continue;
+                    }
+                    let start = ly as usize * pixels.width as usize
+                        + (x0 as i64 - pixels.x as i64) as usize;
+                    let src = &pixels.rgba[start * 4..(start + x1 - x0) * 4];
+                    for (d, s) in acc[x0..x1].iter_mut().zip(src.chunks_exact(4)) {
+                        let a = s[3] as f32 / 255.0 * alpha;
+                        for (dc, &sc) in d[..3].iter_mut().zip(&s[..3]) {
+                            *dc = sc as f32 / 255.0 * a + *dc * (1.0 - a);
+                        }
+                        d[3] = a + d[3] * (1.0 - a);
+                    }
+                }
+                for (px, d) in row.chunks_exact_mut(4).zip(&acc) {
+                    if d[3] <= 0.0 {
+                        continue;
+                    }
+                    for (pc, dc) in px[..3].iter_mut().zip(&d[..3]) {
+                        *pc = (dc / d[3] * 255.0).round().min(255.0) as u8;
+                    }
+                    px[3] = (d[3] * 255.0).round() as u8;
+                }
+            });
+        out
+    }
+}
+
+/// A PSD tagged block's key and data.
+type Block<'a> = ([u8; 4], &'a [u8]);
+
+/// Big-endian reads over a byte slice, failing at the end instead of
+/// panicking.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn at(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ImageError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| layer_error("layer data ends early"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), ImageError> {
+        self.take(n).map(|_| ())
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ImageError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ImageError> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, ImageError> {
+        self.array().map(i16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ImageError> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, ImageError> {
+        self.array().map(i32::from_be_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, ImageError> {
+        self.array().map(u64::from_be_bytes)
+    }
+
+    /// A 4-byte length, or an 8-byte one when `wide`.
+    fn length(&mut self, wide: bool) -> Result<usize, ImageError> {
+        if wide {
+            Ok(self.u64()? as usize)
+        } else {
+            Ok(self.u32()? as usize)
+        }
+    }
+
+    /// The next PSD tagged block as its key and data, padded to `align`, or
+    /// None once the blocks run out.
+    fn block(&mut self, psb: bool, align: usize) -> Result<Option<Block<'a>>, ImageError> {
+        if self.remaining() < 12 {
+            return Ok(None);
+        }
+        let signature: [u8; 4] = self.array()?;
+        if &signature != b"8BIM" && &signature != b"8B64" {
+            return Ok(None);
+        }
+        let key: [u8; 4] = self.array()?;
+        let len = self.length(psb && PSB_LONG_KEYS.contains(&&key))?;
+        let data = self.take(len)?;
+        let pad = len.next_multiple_of(align) - len;
+        self.pos = (self.pos + pad).min(self.data.len());
+        Ok(Some((key, data)))
+    }
+
+    fn line(&mut self) -> Result<&'a str, ImageError> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| layer_error("layer data ends early"))?;
+        self.pos += len + 1;
+        std::str::from_utf8(&rest[..len]).map_err(|_| layer_error("layer header is not text"))
+    }
+}
+
+impl LayerDocument {
+    /// Reads the layers of a PSD or PSB. `merged` is the file's composite, if
+    /// it could be decoded.
+    pub fn psd(bytes: &[u8], merged: Option<Vec<u8>>) -> Result<Self, ImageError> {
+        let mut r = Cursor::new(bytes);
+        if r.take(4)? != b"8BPS" {
+            return Err(layer_error("not a PSD file"));
+        }
+        let psb = r.u16()? == 2;
+        r.skip(6)?;
+        let _channels = r.u16()?;
+        let height = r.u32()?;
+        let width = r.u32()?;
+        let depth = r.u16()?;
+        let gray = match r.u16()? {
+            1 => true,
+            3 => false,
+            mode => return Err(layer_error(format!("PSD color mode {mode} has no layers"))),
+        };
+        if depth != 8 && depth != 16 {
+            return Err(layer_error(format!(
+                "{depth}-bit PSD layers are not supported"
+            )));
+        }
+        let color_data = r.u32()? as usize;
+        r.skip(color_data)?;
+        let resources = r.u32()? as usize;
+        r.skip(resources)?;
+
+        let section_len = r.length(psb)?;
+        let mut section = Cursor::new(r.take(section_len)?);
+        let info_len = section.length(psb)?;
+        let info = if info_len > 0 {
+            section.take(info_len)?
+        } else {
+            // Newer 16-bit documents leave this empty and put the layers in
+            // an Lr16 block after the global mask instead.
+            let mask = section.u32()? as usize;
+            section.skip(mask)?;
+            let mut info: &[u8] = &[];
+            while let Some((key, data)) = section.block(psb, 4)? {
+                if matches!(&key, b"Lr16" | b"Layr") {
+                    info = data;
+                    break;
+                }
+            }
+            info
+        };
+        if info.is_empty() {
+            return Err(layer_error("PSD has no layers"));
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        let layers = psd_layers(info, psb, gray, depth as usize / 8, &mut canvas)?;
+        let len = width as usize * height as usize * 4;
+        Ok(Self {
+            format: if psb { "PSB" } else { "PSD" },
+            width,
+            height,
+            layers,
+            bit_depth: depth as u8,
+            merged: merged.filter(|m| m.len() == len),
+        })
+    }
+}
+
+struct PsdRecord {
+    name: String,
+    bounds: (i32, i32, i32, i32),
+    channels: Vec<(i16, usize)>,
+    opacity: u8,
+    hidden: bool,
+    /// The lsct section divider type: 1 or 2 opens a group, 3 closes one.
+    divider: u32,
+    pixels: Option<LayerPixels>,
+}
+
+fn psd_layers(
+    info: &[u8],
+    psb: bool,
+    gray: bool,
+    bpc: usize,
+    canvas: &mut Canvas,
+) -> Result<Vec<Layer>, ImageError> {
+    let mut r = Cursor::new(info);
+    let count = r.i16()?.unsigned_abs() as usize;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        let top = r.i32()?;
+        let left = r.i32()?;
+        let bottom = r.i32()?;
+        let right = r.i32()?;
+        let channel_count = r.u16()?;
+        let mut channels = Vec::with_capacity(channel_count as usize);
+        for _ in 0..channel_count {
+            channels.push((r.i16()?, r.length(psb)?));
+        }
+        // Blend mode signature and key.
+        r.skip(8)?;
+        let opacity = r.u8()?;
+        let _clipping = r.u8()?;
+        let flags = r.u8()?;
+        r.skip(1)?;
+        let extra_len = r.u32()? as usize;
+        let mut extra = Cursor::new(r.take(extra_len)?);
+        let mask = extra.u32()? as usize;
+        extra.skip(mask)?;
+        let ranges = extra.u32()? as usize;
+        extra.skip(ranges)?;
+        let name_len = extra.u8()? as usize;
+        let mut name = String::from_utf8_lossy(extra.take(name_len)?).into_owned();
+        // The Pascal name is padded to a multiple of four, length byte included.
+        extra.pos = (extra.pos + (4 - (name_len + 1) % 4) % 4).min(extra.data.len());
+
+        let mut divider = 0;
+        while let Some((key, data)) = extra.block(psb, 2)? {
+            let mut block = Cursor::new(data);
+            match &key {
+                b"luni" => {
+                    let units = block.u32()? as usize;
+                    let utf16: Vec<u16> = block
+                        .take(units * 2)?
+                        .chunks_exact(2)
+                        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                        .collect();
+                    name = String::from_utf16_lossy(&utf16)
+                        .trim_end_matches('\0')
+                        .to_string();
+                }
+                b"lsct" => divider = block.u32()?,
+                _ => {}
+            }
+        }
+        records.push(PsdRecord {
+            name,
+            bounds: (top, left, bottom, right),
+            channels,
+            opacity,
+            hidden: flags & 0x02 != 0,
+            divider,
+            pixels: None,
+        });
+    }
+
+    for record in &mut records {
+        let (top, left, bottom, right) = record.bounds;
+        let (width, height) = (extent(left, right)?, extent(top, bottom)?);
+        let mut rgba = vec![255u8; canvas.fits(width, height)?];
+        for &(id, len) in &record.channels {
+            let data = r.take(len)?;
+            let slots: &[usize] = match (id, gray) {
+                (0, true) => &[0, 1, 2],
+                (0..=2, false) => &[id as usize],
+                (-1, _) => &[3],
+                _ => continue,
+            };
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let samples = psd_channel(data, width, height, bpc, psb)?;
+            for (px, &v) in rgba.chunks_exact_mut(4).zip(&samples) {
+                for &slot in slots {
+                    px[slot] = v;
+                }
+            }
+        }
+        if record.divider == 0 {
+            record.pixels = canvas.keep(LayerPixels {
+                x: left,
+                y: top,
+                width: width as u32,
+                height: height as u32,
+                rgba,
+            });
+        }
+    }
+
+    // Records run bottom to top, with a hidden divider below each group's
+    // contents and the group's own record above them.
+    let mut layers = Vec::with_capacity(records.len());
+    let mut depth = 0;
+    for record in records.into_iter().rev() {
+        let layer = |depth, group| Layer {
+            name: record.name,
+            depth,
+            group,
+            visible: !record.hidden,
+            opacity: record.opacity as f32 / 255.0,
+            pixels: record.pixels,
+        };
+        match record.divider {
+            1 | 2 => {
+                layers.push(layer(depth, true));
+                depth += 1;
+            }
+            3 => depth = depth.saturating_sub(1),
+            _ => layers.push(layer(depth, false)),
+        }
+    }
+    Ok(layers)
+}
+
+/// One channel of a layer as 8-bit samples.
+fn psd_channel(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bpc: usize,
+    psb: bool,
+) -> Result<Vec<u8>, ImageError> {
+    let mut r = Cursor::new(data);
+    let compression = r.u16()?;
+    let row = width * bpc;
+    let mut raw = match compression {
+        0 => r.take(row * height)?.to_vec(),
+        1 => {
+            let mut counts = Vec::with_capacity(height);
+            for _ in 0..height {
+                counts.push(if psb {
+                    r.u32()? as usize
+                } else {
+                    r.u16()? as usize
+                });
+            }
+            let mut raw = Vec::with_capacity(row * height);
+            for count in counts {
+                packbits(r.take(count)?, row, &mut raw);
+            }
+            raw
+        }
+        2 | 3 => {
+            let mut raw = vec![0u8; row * height];
+            ZlibDecoder::new(r.take(r.remaining())?)
+                .read_exact(&mut raw)
+                .map_err(ImageError::IoError)?;
+            if compression == 3 {
+                unpredict(&mut raw, row, bpc);
+            }
+            raw
+        }
+        other => return Err(layer_error(format!("PSD channel compression {other}"))),
+    };
+    raw.truncate(row * height);
+    Ok(match bpc {
+        1 => raw,
+        _ => raw.chunks_exact(bpc).map(|s| s[0]).collect(),
+    })
+}
+
+/// Decodes one PackBits row into `out`, padded or cut to `len` bytes.
+fn packbits(src: &[u8], len: usize, out: &mut Vec<u8>) {
+    let start = out.len();
+    let mut i = 0;
+    while i < src.len() && out.len() - start < len {
+        let n = src[i] as i8;
+        i += 1;
+        if n >= 0 {
+            let end = (i + n as usize + 1).min(src.len());
+            out.extend_from_slice(&src[i..end]);
+            i = end;
+        } else if n != -128 {
+            if let Some(&b) = src.get(i) {
+                out.extend(std::iter::repeat_n(b, 1 + n.unsigned_abs() as usize));
+            }
+            i += 1;
+        }
+    }
+    out.resize(start + len, 0);
+}
+
+/// Undoes the per-row delta encoding of zip-with-prediction channels.
+fn unpredict(raw: &mut [u8], row: usize, bpc: usize) {
+    for line in raw.chunks_exact_mut(row) {
+        if bpc == 2 {
+            let mut last = 0u16;
+            for s in line.chunks_exact_mut(2) {
+                last = last.wrapping_add(u16::from_be_bytes([s[0], s[1]]));
+                s.copy_from_slice(&last.to_be_bytes());
+            }
+        } else {
+            for i in 1..line.len() {
+                line[i] = line[i].wrapping_add(line[i - 1]);
+            }
+        }
+    }
+}
+
+/// How an XCF stores its samples.
+#[derive(Debug, Clone, Copy)]
+struct XcfFormat {
+    channels: usize,
+    bpc: usize,
+    float: bool,
+    linear: bool,
+    indexed: bool,
+}
+
+impl XcfFormat {
+    /// Reads the precision field, whose numbering changed over versions 4 to
+    /// 7, as whether samples are floats and whether they are linear.
+    fn precision(version: u32, precision: u32) -> (bool, bool) {
+        match version {
+            0..=3 => (false, false),
+            4 => (precision >= 3, precision >= 2),
+            5 | 6 => (precision >= 400, precision.is_multiple_of(100)),
+            _ => (precision >= 500, precision.is_multiple_of(100)),
+        }
+    }
+
+    fn sample(&self, b: &[u8]) -> f32 {
+        match (b.len(), self.float) {
+            (1, _) => b[0] as f32 / 255.0,
+            (2, false) => u16::from_be_bytes([b[0], b[1]]) as f32 / 65535.0,
+            (2, true) => f16::from_be_bytes([b[0], b[1]]).to_f32(),
+            (4, false) => {
+                (u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64 / u32::MAX as f64) as f32
+            }
+            (4, true) => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            _ => f64::from_be_bytes(b.try_into().unwrap_or_default()) as f32,
+        }
+    }
+
+    fn rgba(&self, px: &[u8], colormap: &[[u8; 3]]) -> [u8; 4] {
+        let quantize = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let color = |i: usize| {
+            let s = &px[i * self.bpc..][..self.bpc];
+            match (self.bpc, self.linear) {
+                (1, false) => s[0],
+                (_, true) => quantize(srgb_encode(self.sample(s).clamp(0.0, 1.0))),
+                _ => quantize(self.sample(s)),
+            }
+        };
+        let alpha = |i: usize| {
+            let s = &px[i * self.bpc..][..self.bpc];
+            if self.bpc == 1 {
+                s[0]
+            } else {
+                quantize(self.sample(s))
+            }
+        };
+        if self.indexed {
+            let [r, g, b] = colormap.get(px[0] as usize).copied().unwrap_or_default();
+            let a = if self.channels == 2 { px[1] } else { 255 };
+            return [r, g, b, a];
+        }
+        match self.channels {
+            1 => {
+                let v = color(0);
+                [v, v, v, 255]
+            }
+            2 => {
+                let v = color(0);
+                [v, v, v, alpha(1)]
+            }
+            3 => [color(0), color(1), color(2), 255],
+            _ => [color(0), color(1), color(2), alpha(3)],
+        }
+    }
+}
+
+fn xcf_properties<'a>(r: &mut Cursor<'a>) -> Result<Vec<(u32, &'a [u8])>, ImageError> {
+    let mut props = Vec::new();
+    loop {
+        let kind = r.u32()?;
+        let len = r.u32()? as usize;
+        if kind == PROP_END {
+            return Ok(props);
+        }
+        props.push((kind, r.take(len)?));
+    }
+}
+
+impl LayerDocument {
+    pub fn xcf(path: &Path) -> Result<Self, ImageError> {
+        let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
+        let mut r = Cursor::new(&bytes);
+        let magic = r.take(14)?;
+        if !magic.starts_with(b"gimp xcf ") {
+            return Err(layer_error("not an XCF file"));
+        }
+        let version = match &magic[9..13] {
+            b"file" => 0,
+            [b'v', digits @ ..] => std::str::from_utf8(digits)
+                .ok()
+                .and_then(|d| d.parse().ok())
+                .ok_or_else(|| layer_error("unreadable XCF version"))?,
+            _ => return Err(layer_error("unreadable XCF version")),
+        };
+        let width = r.u32()?;
+        let height = r.u32()?;
+        let _base_type = r.u32()?;
+        let precision = if version >= 4 { r.u32()? } else { 0 };
+        let (float, linear) = XcfFormat::precision(version, precision);
+        let wide = version >= 11;
+
+        let mut colormap = Vec::new();
+        let mut compression = 0;
+        for (kind, data) in xcf_properties(&mut r)? {
+            let mut p = Cursor::new(data);
+            match kind {
+                PROP_COLORMAP => {
+                    let n = p.u32()? as usize;
+                    colormap = p
+                        .take(n * 3)?
+                        .chunks_exact(3)
+                        .map(|c| [c[0], c[1], c[2]])
+                        .collect();
+                }
+                PROP_COMPRESSION => compression = p.u8()?,
+                _ => {}
+            }
+        }
+
+        let mut pointers = Vec::new();
+        loop {
+            match r.length(wide)? {
+                0 => break,
+                p => pointers.push(p),
+            }
+        }
+        let mut bit_depth = 8;
+        let mut canvas = Canvas::new(width, height);
+        let layers = pointers
+            .into_iter()
+            .map(|offset| {
+                let mut r = Cursor::at(&bytes, offset);
+                let layer_width = r.u32()? as usize;
+                let layer_height = r.u32()? as usize;
+                let kind = r.u32()? as usize;
+                let name_len = r.u32()? as usize;
+                let name = String::from_utf8_lossy(r.take(name_len)?)
+                    .trim_end_matches('\0')
+                    .to_string();
+
+                let mut layer = Layer {
+                    name,
+                    depth: 0,
+                    group: false,
+                    visible: true,
+                    opacity: 1.0,
+                    pixels: None,
+                };
+                let (mut x, mut y) = (0, 0);
+                for (kind, data) in xcf_properties(&mut r)? {
+                    let mut p = Cursor::new(data);
+                    match kind {
+                        PROP_OPACITY => layer.opacity = p.u32()?.min(255) as f32 / 255.0,
+                        PROP_FLOAT_OPACITY => {
+                            layer.opacity = f32::from_bits(p.u32()?).clamp(0.0, 1.0)
+                        }
+                        PROP_VISIBLE => layer.visible = p.u32()? != 0,
+                        PROP_OFFSETS => (x, y) = (p.i32()?, p.i32()?),
+                        PROP_GROUP_ITEM => layer.group = true,
+                        PROP_ITEM_PATH => layer.depth = (data.len() / 4).saturating_sub(1),
+                        _ => {}
+                    }
+                }
+                if layer.group || layer_width == 0 || layer_height == 0 {
+                    return Ok(layer);
+                }
+
+                let hierarchy = r.length(wide)?;
+                let mut h = Cursor::at(&bytes, hierarchy);
+                let _ = (h.u32()?, h.u32()?);
+                let bpp = h.u32()? as usize;
+                let channels = [3, 4, 1, 2, 1, 2].get(kind).copied().unwrap_or(0);
+                if channels == 0 || !bpp.is_multiple_of(channels) {
+                    return Err(layer_error(format!(
+                        "XCF layer type {kind} with {bpp} bytes"
+                    )));
+                }
+                let format = XcfFormat {
+                    channels,
+                    bpc: bpp / channels,
+                    float,
+                    linear,
+                    indexed: kind >= 4,
+                };
+                bit_depth = bit_depth.max(format.bpc as u8 * 8);
+                canvas.fits(layer_width, layer_height)?;
+                let level = h.length(wide)?;
+                let rgba = xcf_level(
+                    &bytes,
+                    level,
+                    wide,
+                    (layer_width, layer_height),
+                    bpp,
+                    compression,
+                    |px| format.rgba(px, &colormap),
+                )?;
+                layer.pixels = canvas.keep(LayerPixels {
+                    x,
+                    y,
+                    width: layer_width as u32,
+                    height: layer_height as u32,
+                    rgba,
+                });
+                Ok(layer)
+            })
+            .collect::<Result<Vec<_>, ImageError>>()?;
+
+        Ok(Self {
+            format: "XCF",
+            width,
+            height,
+            layers,
+            bit_depth,
+            merged: None,
+        })
+    }
+}
+
+/// Reads the full-size level of a layer's tile hierarchy into RGBA8.
+fn xcf_level(
+    bytes: &[u8],
+    offset: usize,
+    wide: bool,
+    (width, height): (usize, usize),
+    bpp: usize,
+    compression: u8,
+    convert: impl Fn(&[u8]) -> [u8; 4],
+) -> Result<Vec<u8>, ImageError> {
+    let mut r = Cursor::at(bytes, offset);
+    let _ = (r.u32()?, r.u32()?);
+    let columns = width.div_ceil(XCF_TILE);
+    let tiles = columns * height.div_ceil(XCF_TILE);
+    let mut rgba = vec![0u8; width * height * 4];
+    for t in 0..tiles {
+        let pointer = r.length(wide)?;
+        let (tx, ty) = (t % columns * XCF_TILE, t / columns * XCF_TILE);
+        let (tw, th) = ((width - tx).min(XCF_TILE), (height - ty).min(XCF_TILE));
+        let src = bytes
+            .get(pointer..)
+            .ok_or_else(|| layer_error("XCF tile outside the file"))?;
+        let tile = match compression {
+            0 => Cursor::new(src).take(tw * th * bpp)?.to_vec(),
+            1 => xcf_rle(src, tw * th, bpp)?,
+            2 => {
+                let mut tile = vec![0u8; tw * th * bpp];
+                ZlibDecoder::new(src)
+                    .read_exact(&mut tile)
+                    .map_err(ImageError::IoError)?;
+                tile
+            }
+            other => return Err(layer_error(format!("XCF compression {other}"))),
+        };
+        for (row, line) in tile.chunks_exact(tw * bpp).enumerate() {
+            let start = ((ty + row) * width + tx) * 4;
+            for (dst, px) in rgba[start..start + tw * 4]
+                .chunks_exact_mut(4)
+                .zip(line.chunks_exact(bpp))
+            {
+                dst.copy_from_slice(&convert(px));
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+/// Decodes an RLE tile, which stores each byte of the pixel as its own run
+/// of `pixels` bytes, back into interleaved pixels.
+fn xcf_rle(src: &[u8], pixels: usize, bpp: usize) -> Result<Vec<u8>, ImageError> {
+    let mut out = vec![0u8; pixels * bpp];
+    let mut r = Cursor::new(src);
+    for plane in 0..bpp {
+        let mut i = 0;
+        while i < pixels {
+            let op = r.u8()?;
+            let (count, repeat) = match op {
+                0..=126 => (op as usize + 1, true),
+                127 => (r.u16()? as usize, true),
+                128 => (r.u16()? as usize, false),
+                _ => (256 - op as usize, false),
+            };
+            if count > pixels - i {
+                return Err(layer_error("XCF tile run overflows the tile"));
+            }
+            let targets = out[plane..].iter_mut().step_by(bpp).skip(i).take(count);
+            if repeat {
+                let v = r.u8()?;
+                targets.for_each(|t| *t = v);
+            } else {
+                targets.zip(r.take(count)?).for_each(|(t, &v)| *t = v);
+            }
+            i += count;
+        }
+    }
+    Ok(out)
+}
+
+impl LayerDocument {
+    pub fn kra(path: &Path) -> Result<Self, ImageError> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        let mut archive =
+            ZipArchive::new(BufReader::new(file)).map_err(|e| layer_error(e.to_string()))?;
+        let xml = zip_entry(&mut archive, "maindoc.xml")?;
+        let xml = String::from_utf8_lossy(&xml);
+        let doc = roxmltree::Document::parse(&xml).map_err(|e| layer_error(e.to_string()))?;
+        let image = doc
+            .descendants()
+            .find(|n| n.has_tag_name("IMAGE"))
+            .ok_or_else(|| layer_error("KRA has no IMAGE element"))?;
+        let size = |name| {
+            image
+                .attribute(name)
+                .and_then(|v| v.parse::<u32>().ok())
+                .ok_or_else(|| layer_error("KRA image has no size"))
+        };
+        let (width, height) = (size("width")?, size("height")?);
+
+        let mut layers = Vec::new();
+        if let Some(list) = image.children().find(|n| n.has_tag_name("layers")) {
+            let prefix = format!("{}/layers/", image.attribute("name").unwrap_or_default());
+            let mut canvas = Canvas::new(width, height);
+            kra_layers(&mut archive, &prefix, list, 0, &mut layers, &mut canvas)?;
+        }
+        if layers.is_empty() {
+            return Err(layer_error("KRA has no layers"));
+        }
+        let deep = image
+            .attribute("colorspacename")
+            .is_some_and(|c| c.ends_with("16"));
+        let merged = zip_entry(&mut archive, "mergedimage.png")
+            .ok()
+            .and_then(|png| image::load_from_memory_with_format(&png, image::ImageFormat::Png).ok())
+            .map(|img| img.into_rgba8())
+            .filter(|img| img.dimensions() == (width, height))
+            .map(|img| img.into_raw());
+
+        Ok(Self {
+            format: "KRA",
+            width,
+            height,
+            layers,
+            bit_depth: if deep { 16 } else { 8 },
+            merged,
+        })
+    }
+}
+
+fn zip_entry(archive: &mut ZipArchive<BufReader<File>>, name: &str) -> Result<Vec<u8>, ImageError> {
+    let entry = archive
+        .by_name(name)
+        .map_err(|e| layer_error(e.to_string()))?;
+    let size = entry.size();
+    archive::read_capped(entry, size, LAYERS_MAX_BYTES)
+}
+
+fn kra_layers(
+    archive: &mut ZipArchive<BufReader<File>>,
+    prefix: &str,
+    list: roxmltree::Node,
+    depth: usize,
+    layers: &mut Vec<Layer>,
+    canvas: &mut Canvas,
+) -> Result<(), ImageError> {
+    for node in list.children().filter(|n| n.has_tag_name("layer")) {
+        let kind = node.attribute("nodetype").unwrap_or_default();
+        let offset = |name| {
+            node.attribute(name)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0)
+        };
+        let pixels = match (kind, node.attribute("filename")) {
+            ("paintlayer", Some(file)) => {
+                let tiles = zip_entry(archive, &format!("{prefix}{file}"))?;
+                let colorspace = node.attribute("colorspacename").unwrap_or_default();
+                kra_pixels(&tiles, colorspace, offset("x"), offset("y"), canvas)?
+            }
+            _ => None,
+        };
+        let group = kind == "grouplayer";
+        layers.push(Layer {
+            name: node.attribute("name").unwrap_or_default().to_string(),
+            depth,
+            group,
+            visible: node.attribute("visible") != Some("0"),
+            opacity: node
+                .attribute("opacity")
+                .and_then(|v| v.parse::<f32>().ok())
+                .map_or(1.0, |o| (o / 255.0).clamp(0.0, 1.0)),
+            pixels,
+        });
+        if group && let Some(children) = node.children().find(|n| n.has_tag_name("layers")) {
+            kra_layers(archive, prefix, children, depth + 1, layers, canvas)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a Krita paint device: a text header, then tiles that each store
+/// their pixels one byte position at a time, usually LZF-compressed. None for
+/// a color space other than 8 or 16-bit RGBA or gray.
+fn kra_pixels(
+    data: &[u8],
+    colorspace: &str,
+    x: i32,
+    y: i32,
+    canvas: &mut Canvas,
+) -> Result<Option<LayerPixels>, ImageError> {
+    // Krita keeps its integer RGBA spaces in BGRA order.
+    let (channels, bpc, order): (usize, usize, &[usize]) = match colorspace {
+        "RGBA" => (4, 1, &[2, 1, 0, 3]),
+        "RGBA16" => (4, 2, &[2, 1, 0, 3]),
+        "GRAYA" => (2, 1, &[0, 0, 0, 1]),
+        "GRAYA16" => (2, 2, &[0, 0, 0, 1]),
+        _ => return Ok(None),
+    };
+    let mut r = Cursor::new(data);
+    let (mut tile_w, mut tile_h, mut pixel_size) = (64, 64, channels * bpc);
+    let count = loop {
+        let line = r.line()?;
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim().parse::<usize>().unwrap_or(0);
+        match key {
+            "TILEWIDTH" => tile_w = value,
+            "TILEHEIGHT" => tile_h = value,
+            "PIXELSIZE" => pixel_size = value,
+            "DATA" => break value,
+            _ => {}
+        }
+    };
+    if pixel_size != channels * bpc || tile_w == 0 || tile_h == 0 {
+        return Err(layer_error(format!(
+            "KRA {colorspace} tiles of {pixel_size} bytes"
+        )));
+    }
+
+    let mut tiles = Vec::with_capacity(count);
+    for _ in 0..count {
+        let line = r.line()?;
+        let mut fields = line.split(',');
+        let mut field = || fields.next().map(str::trim).unwrap_or_default();
+        let tx: i32 = field()
+            .parse()
+            .map_err(|_| layer_error("bad KRA tile header"))?;
+        let ty: i32 = field()
+            .parse()
+            .map_err(|_| layer_error("bad KRA tile header"))?;
+        let _codec = field();
+        let len: usize = field()
+            .parse()
+            .map_err(|_| layer_error("bad KRA tile header"))?;
+        tiles.push((tx, ty, r.take(len)?));
+    }
+    let Some(left) = tiles.iter().map(|t| t.0).min() else {
+        return Ok(None);
+    };
+    let top = tiles.iter().map(|t| t.1).min().unwrap_or(0);
+    let out_of_range = || layer_error("KRA tiles lie out of range");
+    let beyond = |edge: i32, span: usize| {
+        i32::try_from(span)
+            .ok()
+            .and_then(|span| edge.checked_add(span))
+            .ok_or_else(out_of_range)
+    };
+    let right = beyond(tiles.iter().map(|t| t.0).max().unwrap_or(0), tile_w)?;
+    let bottom = beyond(tiles.iter().map(|t| t.1).max().unwrap_or(0), tile_h)?;
+    let (width, height) = (extent(left, right)?, extent(top, bottom)?);
+
+    // No bigger than the layer, which fits.
+    let mut rgba = vec![0u8; canvas.fits(width, height)?];
+    let area = tile_w * tile_h;
+    for (tx, ty, data) in tiles {
+        let raw = match data.split_first() {
+            Some((1, packed)) => lzf(packed, area * pixel_size)?,
+            Some((_, plain)) => plain.to_vec(),
+            None => continue,
+        };
+        if raw.len() < area * pixel_size {
+            return Err(layer_error("KRA tile is short"));
+        }
+        // Little-endian 16-bit samples keep their high byte second.
+        let plane = |channel: usize| &raw[(channel * bpc + bpc - 1) * area..][..area];
+        let (ox, oy) = ((tx - left) as usize, (ty - top) as usize);
+        for row in 0..tile_h {
+            let start = ((oy + row) * width + ox) * 4;
+            for (col, dst) in rgba[start..start + tile_w * 4]
+                .chunks_exact_mut(4)
+                .enumerate()
+            {
+                let i = row * tile_w + col;
+                for (d, &channel) in dst.iter_mut().zip(order) {
+                    *d = plane(channel)[i];
+                }
+            }
+        }
+    }
+    Ok(canvas.keep(LayerPixels {
+        x: x.checked_add(left).ok_or_else(out_of_range)?,
+        y: y.checked_add(top).ok_or_else(out_of_range)?,
+        width: width as u32,
+        height: height as u32,
+        rgba,
+    }))
+}
+
+/// liblzf decompression: literal runs and back references into the output.
+fn lzf(src: &[u8], len: usize) -> Result<Vec<u8>, ImageError> {
+    let broken = || layer_error("broken LZF data");
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < src.len() {
+        let ctrl = src[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            let run = src.get(i..i + ctrl + 1).ok_or_else(broken)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            let mut n = ctrl >> 5;
+            if n == 7 {
+                n += *src.get(i).ok_or_else(broken)? as usize;
+                i += 1;
+            }
+            let back = ((ctrl & 0x1f) << 8) + *src.get(i).ok_or_else(broken)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(back).ok_or_else(broken)?;
+            for k in start..start + n + 2 {
+                out.push(out[k]);
+            }
+        }
+    }
+    if out.len() != len {
+        return Err(broken());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(x: i32, y: i32, w: u32, h: u32, rgba: [u8; 4]) -> Option<LayerPixels> {
+        Some(LayerPixels {
+            x,
+            y,
+            width: w,
+            height: h,
+            rgba: rgba.repeat((w * h) as usize),
+        })
+    }
+
+    fn layer(name: &str, depth: usize, group: bool, pixels: Option<LayerPixels>) -> Layer {
+        Layer {
+            name: name.to_string(),
+            depth,
+            group,
+            visible: true,
+            opacity: 1.0,
+            pixels,
+        }
+    }
+
+    fn document(layers: Vec<Layer>) -> LayerDocument {
+        LayerDocument {
+            format: "test",
+            width: 2,
+            height: 1,
+            layers,
+            bit_depth: 8,
+            merged: None,
+        }
+    }
+
+    #[test]
+    fn groups_hide_and_fade_their_contents_and_solo_isolates() {
+        let doc = document(vec![
+            layer("group", 0, true, None),
+            layer("red", 1, false, solid(0, 0, 1, 1, [255, 0, 0, 255])),
+            layer("blue", 0, false, solid(0, 0, 2, 1, [0, 0, 255, 255])),
+        ]);
+        let view = doc.file_view();
+        assert_eq!(doc.composite(&view), [255, 0, 0, 255, 0, 0, 255, 255]);
+
+        let hidden = view.with_visible(0, false);
+        assert_eq!(doc.composite(&hidden), [0, 0, 255, 255, 0, 0, 255, 255]);
+
+        let faded = view.with_opacity(0, 0.5);
+        assert_eq!(&doc.composite(&faded)[..4], [128, 0, 128, 255]);
+
+        let solo = hidden.with_solo(Some(0));
+        assert_eq!(doc.composite(&solo), [255, 0, 0, 255, 0, 0, 0, 0]);
+    }
+
+    fn psd_record(
+        name: &str,
+        rect: [i32; 4],
+        flags: u8,
+        divider: Option<u32>,
+        channels: &[(i16, Vec<u8>)],
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut record = Vec::new();
+        for v in rect {
+            record.extend_from_slice(&v.to_be_bytes());
+        }
+        record.extend_from_slice(&(channels.len() as u16).to_be_bytes());
+        let mut data = Vec::new();
+        for (id, samples) in channels {
+            record.extend_from_slice(&id.to_be_bytes());
+            record.extend_from_slice(&(samples.len() as u32 + 2).to_be_bytes());
+            data.extend_from_slice(&0u16.to_be_bytes());
+            data.extend_from_slice(samples);
+        }
+        record.extend_from_slice(b"8BIMnorm");
+        record.extend_from_slice(&[255, 0, flags, 0]);
+        let mut extra = vec![0u8; 8];
+        let mut pascal = vec![name.len() as u8];
+        pascal.extend_from_slice(name.as_bytes());
+        pascal.resize(pascal.len().next_multiple_of(4), 0);
+        extra.extend_from_slice(&pascal);
+        if let Some(divider) = divider {
+            extra.extend_from_slice(b"8BIMlsct");
+            extra.extend_from_slice(&4u32.to_be_bytes());
+            extra.extend_from_slice(&divider.to_be_bytes());
+        }
+        record.extend_from_slice(&(extra.len() as u32).to_be_bytes());
+        record.extend_from_slice(&extra);
+        (record, data)
+    }
+
+    /// A 2x1 RGB PSD holding `records`, bottom to top.
+    fn psd_file(records: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut info = (records.len() as i16).to_be_bytes().to_vec();
+        for (record, _) in records {
+            info.extend_from_slice(record);
+        }
+        for (_, data) in records {
+            info.extend_from_slice(data);
+        }
+        let mut psd = b"8BPS".to_vec();
+        psd.extend_from_slice(&1u16.to_be_bytes());
+        psd.extend_from_slice(&[0; 6]);
+        psd.extend_from_slice(&3u16.to_be_bytes());
+        psd.extend_from_slice(&1u32.to_be_bytes());
+        psd.extend_from_slice(&2u32.to_be_bytes());
+        psd.extend_from_slice(&8u16.to_be_bytes());
+        psd.extend_from_slice(&3u16.to_be_bytes());
+        psd.extend_from_slice(&[0; 8]);
+        psd.extend_from_slice(&(info.len() as u32 + 4).to_be_bytes());
+        psd.extend_from_slice(&(info.len() as u32).to_be_bytes());
+        psd.extend_from_slice(&info);
+        psd
+    }
+
+    #[test]
+    fn psd_layers_come_out_top_first_with_their_groups() {
+        // Bottom to top: background, the group's closing divider, a hidden
+        // layer inside the group, then the group itself.
+        let records = [
+            psd_record(
+                "Background",
+                [0, 0, 1, 2],
+                0,
+                None,
+                &[(0, vec![10, 20]), (1, vec![0, 0]), (2, vec![0, 0])],
+            ),
+            psd_record("</Layer group>", [0, 0, 0, 0], 0, Some(3), &[]),
+            psd_record(
+                "Ink",
+                [0, 1, 1, 2],
+                2,
+                None,
+                &[(-1, vec![128]), (0, vec![0]), (1, vec![255]), (2, vec![0])],
+            ),
+            psd_record("Folder", [0, 0, 0, 0], 0, Some(1), &[]),
+        ];
+        let doc = LayerDocument::psd(&psd_file(&records), None).expect("layers parse");
+        let tree: Vec<_> = doc
+            .layers
+            .iter()
+            .map(|l| (l.name.as_str(), l.depth, l.group, l.visible))
+            .collect();
+        assert_eq!(
+            tree,
+            [
+                ("Folder", 0, true, true),
+                ("Ink", 1, false, false),
+                ("Background", 0, false, true),
+            ]
+        );
+        let ink = doc.layers[1].pixels.as_ref().expect("ink has pixels");
+        assert_eq!((ink.x, ink.width), (1, 1));
+        assert_eq!(ink.rgba, [0, 255, 0, 128]);
+
+        let view = doc.file_view().with_visible(1, true);
+        assert_eq!(doc.composite(&view), [10, 0, 0, 255, 10, 128, 0, 255]);
+    }
+
+    #[test]
+    fn layers_are_cut_to_the_canvas_and_impossible_bounds_fail() {
+        // Three pixels wide from x -1, hanging off the canvas to the left.
+        let wide = psd_record(
+            "Wide",
+            [0, -1, 1, 2],
+            0,
+            None,
+            &[(0, vec![1, 2, 3]), (1, vec![0; 3]), (2, vec![0; 3])],
+        );
+        let doc = LayerDocument::psd(&psd_file(&[wide]), None).expect("layers parse");
+        let wide = doc.layers[0].pixels.as_ref().expect("wide has pixels");
+        assert_eq!((wide.x, wide.width, wide.height), (0, 2, 1));
+        assert_eq!(wide.rgba, [2, 0, 0, 255, 3, 0, 0, 255]);
+
+        let offscreen = psd_record("Off", [5, 5, 6, 6], 0, None, &[(0, vec![1])]);
+        let doc = LayerDocument::psd(&psd_file(&[offscreen]), None).expect("layers parse");
+        assert!(doc.layers[0].pixels.is_none());
+
+        for rect in [
+            [0, i32::MIN, 1, i32::MAX],
+            [0, 2, 1, 0],
+            [0, 0, 1 << 20, 1 << 20],
+        ] {
+            let bad = psd_record("Bad", rect, 0, None, &[]);
+            assert!(LayerDocument::psd(&psd_file(&[bad]), None).is_err());
+        }
+    }
+
+    #[test]
+    fn compressed_runs_decode() {
+        let mut row = Vec::new();
+        packbits(&[0xFE, 7, 1, 1, 2], 5, &mut row);
+        assert_eq!(row, [7, 7, 7, 1, 2]);
+
+        // Two literal bytes, then a back reference repeating them three times.
+        let unpacked = lzf(&[1, b'a', b'b', 0b010_00000, 1], 6).unwrap();
+        assert_eq!(unpacked, b"ababab");
+
+        // One RLE plane per byte of a two-byte pixel.
+        let tile = xcf_rle(&[2, 9, 128, 0, 3, 1, 2, 3], 3, 2).unwrap();
+        assert_eq!(tile, [9, 1, 9, 2, 9, 3]);
+    }
+}
@@ -1,7 +1,26 @@
 pub mod animation;
+pub mod archive;
 #[cfg(feature = "av")]
 pub mod audio;
+pub mod dicom;
+pub mod embedded;
 pub mod exif_data;
+pub mod exr;
+pub mod fits;
+#[cfg(feature = "heif")]
+pub mod heif;
+pub mod icc;
 pub mod image_data;
+pub mod jxl;
+pub mod layers;
+pub mod pages;
+pub mod raw;
+pub mod samples;
+pub mod sequence;
+pub mod sniff;
+pub mod stream;
+pub mod svg;
+pub mod texture;
+pub mod tonemap;
 #[cfg(feature = "av")]
 pub mod video;
@@ -0,0 +1,566 @@
+//! Files that hold several separate images rather than one, such as the pages
+//! of a multi-page TIFF or the sizes of an icon.
+//!
+//! Only the page on screen is decoded. Opening the file walks it once to list
+//! every page's size and encoding for the info panel, and switching pages
+//! decodes the new one in the background and swaps it in the way an EXR view
+//! is, so zoom, rotation and the modifier stack carry over.
+//!
+//! The first page goes through the same loader as a single-page file. Later
+//! pages are read straight from the TIFF's directory chain with the tiff
+//! crate, since image only ever decodes the first one, and keep their own ICC
+//! profile and EXIF. Those reads cover gray, gray-alpha, RGB and RGBA at 8 and
+//! 16 bits and 32-bit float, and bilevel and 2- or 4-bit gray as scanners and
+//! fax machines write it. A palette, CMYK or YCbCr page further in is reported
+//! as unsupported rather than guessed at.
+//!
+//! ICO and ICNS files are icon families: one picture drawn at several sizes
+//! and bit depths. Each entry is a page, and the view opens on the largest,
+//! which is what a single-image loader would have picked. An ICO entry is
+//! decoded by wrapping it in a one-entry ICO of its own, so image's decoder
+//! handles both the PNG and the BMP kind. The sheet lays every entry out side
+//! by side at its own size, for comparing how each one was drawn.
+
+use std::fs::File;
+use std::io::{BufReader, Error};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use icns::{IconFamily, IconType, PixelFormat as IcnsPixelFormat};
+use image::{ImageError, ImageFormat, RgbaImage};
+use tiff::ColorType as TiffColor;
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+
+use super::exif_data::ExifData;
+use super::image_data::{ImageData, Source};
+use super::samples::DeepPixels;
+
+#[derive(Debug, Clone)]
+pub struct PageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub compression: String,
+    /// Marked as a reduced-resolution copy of another page (a thumbnail).
+    pub reduced: bool,
+    /// Bits per pixel of an icon entry. TIFF pages leave it out.
+    pub bit_depth: Option<u8>,
+}
+
+/// Transparent space between the entries of an icon sheet.
+const SHEET_GAP: u32 = 16;
+
+#[derive(Debug)]
+pub enum PageSource {
+    Tiff {
+        path: PathBuf,
+        pages: Vec<PageInfo>,
+    },
+    Ico {
+        path: PathBuf,
+        pages: Vec<PageInfo>,
+    },
+    Icns {
+        path: PathBuf,
+        pages: Vec<PageInfo>,
+        types: Vec<IconType>,
+    },
+}
+
+impl PageSource {
+    /// Lists the pages of a TIFF, or None when it only has one.
+    pub fn tiff(path: &Path) -> Option<Self> {
+        let pages = tiff_pages(path).ok()?;
+        (pages.len() > 1).then(|| PageSource::Tiff {
+            path: path.to_path_buf(),
+            pages,
+        })
+    }
+
+    /// Lists the entries of a Windows icon, or None when it only has one.
+    pub fn ico(path: &Path) -> Option<Self> {
+        let bytes = std::fs::read(path).ok()?;
+        let pages: Vec<_> = ico_entries(&bytes)?
+            .into_iter()
+            .map(|(dir, data)| ico_info(dir, data))
+            .collect();
+        (pages.len() > 1).then(|| PageSource::Ico {
+            path: path.to_path_buf(),
+            pages,
+        })
+    }
+
+    /// Lists the entries of an Apple icon, or None when it only has one.
+    pub fn icns(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let family = IconFamily::read(BufReader::new(file)).ok()?;
+        let types = family.available_icons();
+        let pages = types
+            .iter()
+            .map(|t| PageInfo {
+                width: t.pixel_width(),
+                height: t.pixel_height(),
+                compression: match t.pixel_density() {
+                    1 => t.ostype().to_string(),
+                    density => format!("{} @{density}x", t.ostype()),
+                },
+                reduced: false,
+                // The older types keep alpha in a separate mask entry.
+                bit_depth: Some(if t.mask_type().is_some() { 24 } else { 32 }),
+            })
+            .collect();
+        (types.len() > 1).then(|| PageSource::Icns {
+            path: path.to_path_buf(),
+            pages,
+            types,
+        })
+    }
+
+    pub fn pages(&self) -> &[PageInfo] {
+        match self {
+            PageSource::Tiff { pages, .. }
+            | PageSource::Ico { pages, .. }
+            | PageSource::Icns { pages, .. } => pages,
+        }
+    }
+
+    /// Entries are sizes of one picture rather than pages of a document.
+    pub fn is_icon(&self) -> bool {
+        !matches!(self, PageSource::Tiff { .. })
+    }
+
+    /// The page with the most pixels, the deepest one among equals.
+    pub fn largest(&self) -> usize {
+        let pages = self.pages();
+        (0..pages.len())
+            .max_by_key(|&i| {
+                let p = &pages[i];
+                (p.width as u64 * p.height as u64, p.bit_depth)
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn count(&self) -> usize {
+        self.pages().len()
+    }
+
+    pub fn load(self: &Arc<Self>, index: usize) -> Result<ImageData, ImageError> {
+        let mut data = match self.as_ref() {
+            PageSource::Tiff { path, .. } if index == 0 => {
+                let mut data = ImageData::load(path)?;
+                data.exif = ExifData::read(path);
+                data
+            }
+            PageSource::Tiff { path, .. } => tiff_page(path, index)?,
+            PageSource::Ico { path, .. } => ico_entry(path, index)?,
+            PageSource::Icns { path, types, .. } => icns_entry(path, types[index])?,
+        };
+        data.source = Source::Pages(Pages {
+            source: Arc::clone(self),
+            index,
+            sheet: false,
+        });
+        Ok(data)
+    }
+
+    /// Every page side by side at its own size, top-aligned on a transparent
+    /// background. `index` is the page to return to from the sheet.
+    pub fn load_sheet(self: &Arc<Self>, index: usize) -> Result<ImageData, ImageError> {
+        let entries = (0..self.count())
+            .map(|i| {
+                let data = self.load(i)?;
+                RgbaImage::from_raw(data.width, data.height, data.pixels_snapshot().to_vec())
+                    .ok_or_else(|| icon_error("entry has the wrong number of pixels"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let width = entries.iter().map(RgbaImage::width).sum::<u32>()
+            + SHEET_GAP * (entries.len() as u32 + 1);
+        let height = entries.iter().map(RgbaImage::height).max().unwrap_or(0) + SHEET_GAP * 2;
+        let mut sheet = RgbaImage::new(width, height);
+        let mut x = SHEET_GAP;
+        for entry in &entries {
+            image::imageops::replace(&mut sheet, entry, x as i64, SHEET_GAP as i64);
+            x += entry.width() + SHEET_GAP;
+        }
+        let mut data = ImageData::new(sheet.into_raw(), width, height);
+        data.source = Source::Pages(Pages {
+            source: Arc::clone(self),
+            index,
+            sheet: true,
+        });
+        Ok(data)
+    }
+}
+
+/// The document an image is one page of, and which page.
+#[derive(Debug, Clone)]
+pub struct Pages {
+    pub source: Arc<PageSource>,
+    pub index: usize,
+    /// Showing every page at once rather than page `index`.
+    pub sheet: bool,
+}
+
+impl Pages {
+    pub fn count(&self) -> usize {
+        self.source.count()
+    }
+}
+
+fn tiff_error(e: tiff::TiffError) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+fn open_tiff(path: &Path) -> Result<Decoder<BufReader<File>>, ImageError> {
+    let file = File::open(path).map_err(ImageError::IoError)?;
+    Decoder::new(BufReader::new(file))
+        .map(|d| d.with_limits(Limits::unlimited()))
+        .map_err(tiff_error)
+}
+
+fn tiff_pages(path: &Path) -> Result<Vec<PageInfo>, ImageError> {
+    let mut decoder = open_tiff(path)?;
+    let mut pages = Vec::new();
+    loop {
+        let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+        let compression = decoder.get_tag_u32(Tag::Compression).unwrap_or(1);
+        let subfile = decoder.get_tag_u32(Tag::NewSubfileType).unwrap_or(0);
+        pages.push(PageInfo {
+            width,
+            height,
+            compression: compression_name(compression),
+            reduced: subfile & 1 != 0,
+            bit_depth: None,
+        });
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image().map_err(tiff_error)?;
+    }
+    Ok(pages)
+}
+
+fn compression_name(tag: u32) -> String {
+    let name = match tag {
+        1 => "None",
+        2 => "CCITT RLE",
+        3 => "CCITT G3",
+        4 => "CCITT G4",
+        5 => "LZW",
+        6 | 7 => "JPEG",
+        8 | 32946 => "Deflate",
+        32773 => "PackBits",
+        34712 => "JPEG 2000",
+        34887 => "LERC",
+        34925 => "LZMA",
+        50000 => "Zstd",
+        50001 => "WebP",
+        _ => return format!("Type {tag}"),
+    };
+    name.to_string()
+}
+
+fn tiff_page(path: &Path, index: usize) -> Result<ImageData, ImageError> {
+    let mut decoder = open_tiff(path)?;
+    decoder.seek_to_image(index).map_err(tiff_error)?;
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let color = decoder.colortype().map_err(tiff_error)?;
+    let (channels, bits) = match color {
+        TiffColor::Gray(bits) => (1, bits),
+        TiffColor::GrayA(bits) => (2, bits),
+        TiffColor::RGB(bits) => (3, bits),
+        TiffColor::RGBA(bits) => (4, bits),
+        other => {
+            return Err(ImageError::IoError(Error::other(format!(
+                "TIFF page {}: unsupported color type {other:?}",
+                index + 1
+            ))));
+        }
+    };
+    let icc = decoder.get_tag_u8_vec(Tag::IccProfile).ok();
+    let ifd = decoder.ifd_pointer();
+    let mut data = match decoder.read_image().map_err(tiff_error)? {
+        DecodingResult::U8(s) if bits < 8 => {
+            let row = width as usize * channels;
+            let s = unpack(&s, bits, row, height as usize);
+            ImageData::new(expand(&s, channels, u8::MAX), width, height)
+        }
+        DecodingResult::U8(s) => ImageData::new(expand(&s, channels, u8::MAX), width, height),
+        DecodingResult::U16(s) => ImageData::with_deep(
+            DeepPixels::U16(expand(&s, channels, u16::MAX)),
+            width,
+            height,
+        ),
+        DecodingResult::F32(s) => {
+            ImageData::with_deep(DeepPixels::F32(expand(&s, channels, 1.0)), width, height)
+        }
+        _ => {
+            return Err(ImageError::IoError(Error::other(format!(
+                "TIFF page {}: unsupported sample format",
+                index + 1
+            ))));
+        }
+    };
+    if let Some(icc) = icc {
+        data.apply_icc_profile(icc);
+    }
+    if let Some(offset) = ifd.and_then(|ifd| u32::try_from(ifd.0).ok()) {
+        let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
+        data.exif = ExifData::from_tiff_page(bytes, offset);
+    }
+    Ok(data)
+}
+
+/// Spreads samples of fewer than 8 bits, packed several to a byte with each
+/// row starting on a fresh byte, out to one byte each at full scale. The tiff
+/// crate has already turned WhiteIsZero around, fax pages included, so a set
+/// bit is white whichever way the file stores it.
+fn unpack(packed: &[u8], bits: u8, row: usize, rows: usize) -> Vec<u8> {
+    let bits = bits as usize;
+    let max = (1u16 << bits) - 1;
+    let stride = (row * bits).div_ceil(8);
+    let mut out = Vec::with_capacity(row * rows);
+    for line in packed.chunks(stride).take(rows) {
+        for i in 0..row {
+            let bit = i * bits;
+            let byte = line.get(bit / 8).copied().unwrap_or(0);
+            let value = (byte >> (8 - bits - bit % 8)) as u16 & max;
+            out.push((value * 255 / max) as u8);
+        }
+    }
+    out
+}
+
+fn icon_error(message: &str) -> ImageError {
+    ImageError::IoError(Error::other(message))
+}
+
+/// The directory entry and image data of every entry in an ICO file.
+fn ico_entries(bytes: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let header = bytes.get(..6)?;
+    if header[..4] != [0, 0, 1, 0] {
+        return None;
+    }
+    let count = u16::from_le_bytes([header[4], header[5]]) as usize;
+    (0..count)
+        .map(|i| {
+            let dir = bytes.get(6 + i * 16..6 + (i + 1) * 16)?;
+            let len = u32::from_le_bytes(dir[8..12].try_into().ok()?) as usize;
+            let offset = u32::from_le_bytes(dir[12..16].try_into().ok()?) as usize;
+            Some((dir, bytes.get(offset..offset.checked_add(len)?)?))
+        })
+        .collect()
+}
+
+/// Size and depth from the entry's own header, since the directory stores
+/// 256 as 0 and often leaves the bit count out for PNG entries.
+fn ico_info(dir: &[u8], data: &[u8]) -> PageInfo {
+    let be = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") && data.len() >= 26 {
+        let channels = match data[25] {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            _ => 4,
+        };
+        return PageInfo {
+            width: be(16),
+            height: be(20),
+            compression: "PNG".to_string(),
+            reduced: false,
+            bit_depth: Some(data[24].saturating_mul(channels)),
+        };
+    }
+    let side = |b: u8| if b == 0 { 256 } else { b as u32 };
+    let bits = data
+        .get(14..16)
+        .map_or(u16::from_le_bytes([dir[6], dir[7]]), |b| {
+            u16::from_le_bytes([b[0], b[1]])
+        });
+    PageInfo {
+        width: side(dir[0]),
+        height: side(dir[1]),
+        compression: "BMP".to_string(),
+        reduced: false,
+        bit_depth: Some(bits.min(u8::MAX as u16) as u8),
+    }
+}
+
+fn ico_entry(path: &Path, index: usize) -> Result<ImageData, ImageError> {
+    let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
+    let entries = ico_entries(&bytes).ok_or_else(|| icon_error("unreadable ICO directory"))?;
+    let (dir, data) = entries
+        .get(index)
+        .ok_or_else(|| icon_error("no such ICO entry"))?;
+    let mut single = vec![0, 0, 1, 0, 1, 0];
+    single.extend_from_slice(&dir[..12]);
+    single.extend_from_slice(&22u32.to_le_bytes());
+    single.extend_from_slice(data);
+    let img = image::load_from_memory_with_format(&single, ImageFormat::Ico)?.into_rgba8();
+    let (width, height) = img.dimensions();
+    Ok(ImageData::new(img.into_raw(), width, height))
+}
+
+fn icns_entry(path: &Path, icon_type: IconType) -> Result<ImageData, ImageError> {
+    let file = File::open(path).map_err(ImageError::IoError)?;
+    let family = IconFamily::read(BufReader::new(file)).map_err(ImageError::IoError)?;
+    let image = family
+        .get_icon_with_type(icon_type)
+        .map_err(ImageError::IoError)?;
+    Ok(ImageData::new(
+        image
+            .convert_to(IcnsPixelFormat::RGBA)
+            .into_data()
+            .into_vec(),
+        image.width(),
+        image.height(),
+    ))
+}
+
+/// Spreads gray, gray-alpha or RGB samples out to RGBA.
+pub(super) fn expand<T: Copy>(samples: &[T], channels: usize, opaque: T) -> Vec<T> {
+    if channels == 4 {
+        return samples.to_vec();
+    }
+    let mut rgba = Vec::with_capacity(samples.len() / channels * 4);
+    for p in samples.chunks_exact(channels) {
+        let (rgb, alpha) = match p {
+            [v] => ([*v; 3], opaque),
+            [v, a] => ([*v; 3], *a),
+            [r, g, b] => ([*r, *g, *b], opaque),
+            _ => unreachable!(),
+        };
+        rgba.extend_from_slice(&rgb);
+        rgba.push(alpha);
+    }
+    rgba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiff::encoder::{TiffEncoder, colortype};
+
+    #[test]
+    fn every_page_of_a_tiff_is_listed_and_loads() {
+        let path =
+            std::env::temp_dir().join(format!("bloom-test-pages-{}.tiff", std::process::id()));
+        {
+            let file = File::create(&path).unwrap();
+            let mut tiff = TiffEncoder::new(file).unwrap();
+            tiff.write_image::<colortype::RGB8>(2, 1, &[255, 0, 0, 0, 255, 0])
+                .unwrap();
+            tiff.write_image::<colortype::Gray16>(3, 2, &[0, 65535, 0, 65535, 0, 65535])
+                .unwrap();
+        }
+
+        let source = Arc::new(PageSource::tiff(&path).expect("two pages"));
+        let sizes: Vec<_> = source.pages().iter().map(|p| (p.width, p.height)).collect();
+        assert_eq!(sizes, [(2, 1), (3, 2)]);
+        assert_eq!(source.pages()[1].compression, "None");
+
+        let page = source.load(1).expect("second page loads");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!((page.width, page.height), (3, 2));
+        assert_eq!(page.bit_depth, 16);
+        assert_eq!(&page.pixels_snapshot()[4..8], &[255, 255, 255, 255]);
+        assert_eq!(page.pages().map(|p| p.index), Some(1));
+    }
+
+    /// A little-endian directory, its entries given as tag, type and value.
+    fn ifd(entries: &[(u16, u16, u32)], next: u32) -> Vec<u8> {
+        let mut b = (entries.len() as u16).to_le_bytes().to_vec();
+        for &(tag, kind, value) in entries {
+            let count: u32 = if tag == 271 { 5 } else { 1 };
+            b.extend_from_slice(&tag.to_le_bytes());
+            b.extend_from_slice(&kind.to_le_bytes());
+            b.extend_from_slice(&count.to_le_bytes());
+            b.extend_from_slice(&value.to_le_bytes());
+        }
+        b.extend_from_slice(&next.to_le_bytes());
+        b
+    }
+
+    #[test]
+    fn a_bilevel_fax_page_unpacks_to_one_pixel_per_sample() {
+        // A one-pixel first page, then a 10x2 WhiteIsZero page packed eight
+        // pixels to the byte, each row padded out to two bytes.
+        let bilevel = [0b1010_0000, 0b1100_0000, 0b0000_0000, 0b0100_0000];
+        let page = |width, bits, photometric, offset, bytes| {
+            vec![
+                (256, 4, width),
+                (257, 4, 2),
+                (258, 3, bits),
+                (259, 3, 1),
+                (262, 3, photometric),
+                (271, 2, 16),
+                (273, 4, offset),
+                (277, 3, 1),
+                (278, 4, 2),
+                (279, 4, bytes),
+            ]
+        };
+        let first = ifd(&page(1, 8, 1, 8, 2), 150);
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&24u32.to_le_bytes());
+        tiff.extend_from_slice(&[0, 255, 0, 0]);
+        tiff.extend_from_slice(&bilevel);
+        tiff.extend_from_slice(b"Scan\0\0\0\0");
+        tiff.extend_from_slice(&first);
+        tiff.extend_from_slice(&ifd(&page(10, 1, 0, 12, 4), 0));
+        let path =
+            std::env::temp_dir().join(format!("bloom-test-pages-{}-fax.tiff", std::process::id()));
+        std::fs::write(&path, &tiff).unwrap();
+
+        let source = Arc::new(PageSource::tiff(&path).expect("two pages"));
+        let page = source.load(1).expect("bilevel page loads");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!((page.width, page.height), (10, 2));
+        let pixels = page.pixels_snapshot();
+        assert_eq!(pixels.len(), 10 * 2 * 4);
+        let gray: Vec<u8> = pixels.chunks(4).map(|p| p[0]).collect();
+        let (b, w) = (0, 255);
+        assert_eq!(
+            gray,
+            [b, w, b, w, w, w, w, w, b, b, w, w, w, w, w, w, w, w, w, b]
+        );
+        assert_eq!(page.exif.make.as_deref(), Some("Scan"));
+    }
+
+    #[test]
+    fn icon_entries_are_listed_and_open_on_the_largest() {
+        use image::codecs::ico::{IcoEncoder, IcoFrame};
+
+        let small = [255, 0, 0, 255].repeat(16 * 16);
+        let large = [0, 0, 255, 128].repeat(32 * 32);
+        let frames = [
+            IcoFrame::as_png(&small, 16, 16, image::ExtendedColorType::Rgba8).unwrap(),
+            IcoFrame::as_png(&large, 32, 32, image::ExtendedColorType::Rgba8).unwrap(),
+        ];
+        let path =
+            std::env::temp_dir().join(format!("bloom-test-pages-{}.ico", std::process::id()));
+        IcoEncoder::new(File::create(&path).unwrap())
+            .encode_images(&frames)
+            .unwrap();
+
+        let source = Arc::new(PageSource::ico(&path).expect("two entries"));
+        let seen: Vec<_> = source
+            .pages()
+            .iter()
+            .map(|p| (p.width, p.bit_depth, p.compression.as_str()))
+            .collect();
+        assert_eq!(seen, [(16, Some(32), "PNG"), (32, Some(32), "PNG")]);
+        assert_eq!(source.largest(), 1);
+
+        let entry = source.load(1).expect("entry loads");
+        let sheet = source.load_sheet(1).expect("sheet loads");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!((entry.width, entry.height), (32, 32));
+        assert_eq!(&entry.pixels_snapshot()[..4], &[0, 0, 255, 128]);
+        assert_eq!(
+            (sheet.width, sheet.height),
+            (16 + 32 + SHEET_GAP * 3, 32 + SHEET_GAP * 2)
+        );
+        assert!(sheet.pages().is_some_and(|p| p.sheet));
+    }
+}
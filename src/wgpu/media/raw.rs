@@ -0,0 +1,529 @@
+//! Camera RAW development.
+//!
+//! rawler decodes the sensor data once, into a RawDocument that stays around
+//! for as long as the file is open. Every change in the develop panel runs
+//! the pipeline again from that data, in the background, and the result
+//! replaces the current image the way another EXR layer or FITS HDU would.
+//!
+//! rawler's own steps take the mosaic as far as linear sRGB: rescale to the
+//! black and white levels, demosaic, white balance, the camera's color
+//! matrix and, when asked, its default crop. Everything after that is done
+//! here on floats, before anything is quantized:
+//!
+//! - Auto white balance is gray-world, measured on the as-shot result and
+//!   leaving out near-clipped pixels, which say more about the sensor than
+//!   the light.
+//! - Custom white balance sets the channel multipliers rawler applies, from
+//!   the color a black body at that temperature makes on this sensor. The
+//!   camera's D65 matrix gives that color.
+//! - Exposure is a plain multiply in linear light.
+//! - Highlight recovery rolls values past a knee off towards white instead of
+//!   clipping each channel on its own, and takes the color out as it goes.
+//!   A channel that clipped on the sensor has no detail to bring back, but
+//!   the magenta and cyan casts clipping leaves in skies and lamps go.
+//!
+//! Demosaicing is the slow step, and there are two to choose from. The full
+//! one is rawler's, which interpolates the two colors each photosite did not
+//! see. The superpixel one interpolates nothing: each 2×2 cell of a Bayer
+//! mosaic becomes one pixel from its own red, blue and the mean of its
+//! greens, so it runs in a fraction of the time and leaves a half-size image
+//! free of interpolation artifacts. It then does the white balance and the
+//! camera matrix itself, the same way rawler's steps would. Mosaics other
+//! than 2×2, such as X-Trans, always get the full demosaic.
+//!
+//! The camera's orientation comes from the decoder, for the RAW containers
+//! whose EXIF kamadak-exif cannot read.
+
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+use image::ImageError;
+use rawler::RawImage;
+use rayon::prelude::*;
+
+use super::image_data::{ImageData, Source};
+use super::samples::DeepPixels;
+
+/// Fraction of white above which a pixel is left out of auto white balance.
+const AUTO_WB_CLIP: f32 = 0.95;
+
+fn raw_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhiteBalance {
+    #[default]
+    AsShot,
+    Auto,
+    Custom,
+}
+
+impl WhiteBalance {
+    pub const ALL: &[(WhiteBalance, &str)] = &[
+        (WhiteBalance::AsShot, "As shot"),
+        (WhiteBalance::Auto, "Auto"),
+        (WhiteBalance::Custom, "Custom"),
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Demosaic {
+    /// rawler's interpolating demosaic, at full size.
+    #[default]
+    Full,
+    /// One pixel per 2×2 cell, at half size.
+    Superpixel,
+}
+
+impl Demosaic {
+    pub const ALL: &[(Demosaic, &str)] = &[
+        (Demosaic::Full, "Full demosaic"),
+        (Demosaic::Superpixel, "Fast superpixel, half size"),
+    ];
+}
+
+/// How a RAW is developed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawSettings {
+    pub white_balance: WhiteBalance,
+    /// Color temperature for custom white balance.
+    pub kelvin: f32,
+    /// Exposure compensation in stops.
+    pub exposure: f32,
+    /// Highlight recovery strength, 0 to clip and 1 for the widest roll-off.
+    pub highlights: f32,
+    pub demosaic: Demosaic,
+    /// Apply the crop the camera records for its output.
+    pub camera_crop: bool,
+}
+
+impl RawSettings {
+    pub const KELVIN_RANGE: std::ops::RangeInclusive<f32> = 2000.0..=12000.0;
+    pub const EXPOSURE_RANGE: std::ops::RangeInclusive<f32> = -3.0..=3.0;
+}
+
+impl Default for RawSettings {
+    fn default() -> Self {
+        Self {
+            white_balance: WhiteBalance::AsShot,
+            kelvin: 5500.0,
+            exposure: 0.0,
+            highlights: 0.0,
+            demosaic: Demosaic::Full,
+            camera_crop: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RawDocument {
+    raw: RawImage,
+    /// EXIF orientation, 1 to 8.
+    orientation: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RawSelection {
+    pub document: Arc<RawDocument>,
+    pub settings: RawSettings,
+}
+
+impl RawDocument {
+    pub fn decode(path: &Path) -> Result<Self, ImageError> {
+        let raw = rawler::decode_file(path).map_err(raw_error)?;
+        let orientation = Some(raw.orientation.to_u16()).filter(|o| (1..=8).contains(o));
+        Ok(Self { raw, orientation })
+    }
+
+    pub fn open(self: &Arc<Self>) -> Result<ImageData, ImageError> {
+        self.render(RawSettings::default())
+    }
+
+    pub fn render(self: &Arc<Self>, settings: RawSettings) -> Result<ImageData, ImageError> {
+        let superpixel = match settings.demosaic {
+            Demosaic::Superpixel => self.superpixel(settings)?,
+            Demosaic::Full => None,
+        };
+        let (mut rgb, width, height) = match superpixel {
+            Some(developed) => developed,
+            None => self.develop(settings)?,
+        };
+        if settings.white_balance == WhiteBalance::Auto {
+            gray_world(&mut rgb);
+        }
+
+        let gain = settings.exposure.exp2();
+        let highlights = settings.highlights.clamp(0.0, 1.0);
+        let samples: Vec<u16> = rgb
+            .par_chunks_exact(3)
+            .flat_map_iter(|px| {
+                let [r, g, b] = roll_off([px[0] * gain, px[1] * gain, px[2] * gain], highlights);
+                let quantize = |v: f32| (srgb_encode(v) * 65535.0).round() as u16;
+                [quantize(r), quantize(g), quantize(b), u16::MAX]
+            })
+            .collect();
+
+        let mut data = ImageData::with_deep(DeepPixels::U16(samples), width, height);
+        data.exif.orientation = self.orientation;
+        data.source = Source::Raw(RawSelection {
+            document: Arc::clone(self),
+            settings,
+        });
+        Ok(data)
+    }
+
+    /// Runs rawler's pipeline up to linear sRGB, returning RGB floats.
+    fn develop(&self, settings: RawSettings) -> Result<(Vec<f32>, u32, u32), ImageError> {
+        use rawler::imgop::develop::{ProcessingStep, RawDevelop};
+
+        let mut develop = RawDevelop::default();
+        develop.steps.retain(|step| match step {
+            ProcessingStep::SRgb => false,
+            ProcessingStep::CropDefault => settings.camera_crop,
+            _ => true,
+        });
+        let intermediate = if settings.white_balance == WhiteBalance::Custom {
+            let mut raw = self.raw.clone();
+            if let Some(coeffs) = self.kelvin_coeffs(settings.kelvin) {
+                raw.wb_coeffs = coeffs;
+            }
+            develop.develop_intermediate(&raw)
+        } else {
+            develop.develop_intermediate(&self.raw)
+        }
+        .map_err(raw_error)?;
+        let image = intermediate
+            .to_dynamic_image()
+            .ok_or_else(|| raw_error("failed to convert RAW to image"))?
+            .into_rgb32f();
+        let (width, height) = image.dimensions();
+        Ok((image.into_raw(), width, height))
+    }
+
+    /// Develops to linear sRGB without interpolating, one pixel per 2×2 cell
+    /// of the mosaic. None when the mosaic is not 2×2 with red, green and
+    /// blue, or the camera has no matrix to go to sRGB with.
+    fn superpixel(
+        &self,
+        settings: RawSettings,
+    ) -> Result<Option<(Vec<f32>, u32, u32)>, ImageError> {
+        use rawler::imgop::xyz::Illuminant;
+        use rawler::rawimage::RawImageData;
+
+        let cfa = &self.raw.camera.cfa;
+        let Some(matrix) = self.raw.color_matrix.get(&Illuminant::D65) else {
+            return Ok(None);
+        };
+        if cfa.width != 2 || cfa.height != 2 || matrix.len() != 9 {
+            return Ok(None);
+        }
+        let mut colors = [0; 4];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = cfa.color_at(i / 2, i % 2);
+        }
+        if !(0..3).all(|c| colors.contains(&c)) || colors.iter().any(|&c| c > 2) {
+            return Ok(None);
+        }
+        let Some(cam_to_srgb) = cam_to_srgb(matrix) else {
+            return Ok(None);
+        };
+        let coeffs = match settings.white_balance {
+            WhiteBalance::Custom => self.kelvin_coeffs(settings.kelvin),
+            _ => None,
+        }
+        .unwrap_or(self.raw.wb_coeffs);
+
+        let mut raw = self.raw.clone();
+        raw.apply_scaling().map_err(raw_error)?;
+        let RawImageData::Float(mosaic) = &raw.data else {
+            return Ok(None);
+        };
+        let area = if settings.camera_crop {
+            raw.crop_area.or(raw.active_area)
+        } else {
+            raw.active_area
+        };
+        let rect = area.map_or((0, 0, raw.width, raw.height), |r| {
+            (r.p.x, r.p.y, r.d.w, r.d.h)
+        });
+        let (rgb, width, height) = superpixel(mosaic, raw.width, rect, colors);
+        let balance = white_balance(coeffs);
+        let developed = rgb
+            .par_chunks_exact(3)
+            .flat_map_iter(|px| {
+                let cam = [px[0] * balance[0], px[1] * balance[1], px[2] * balance[2]];
+                cam_to_srgb.map(|row| row[0] * cam[0] + row[1] * cam[1] + row[2] * cam[2])
+            })
+            .collect();
+        Ok(Some((developed, width, height)))
+    }
+
+    /// White balance multipliers that make a black body at `kelvin` neutral,
+    /// when the camera has a D65 color matrix to work them out from.
+    fn kelvin_coeffs(&self, kelvin: f32) -> Option<[f32; 4]> {
+        use rawler::imgop::xyz::Illuminant;
+
+        let matrix = self.raw.color_matrix.get(&Illuminant::D65)?;
+        let mut coeffs = wb_coeffs(matrix, planckian_xyz(kelvin))?;
+        if matrix.len() == 9 {
+            // A three-color matrix on a four-site mosaic: the second green.
+            coeffs[3] = coeffs[1];
+        }
+        Some(coeffs)
+    }
+}
+
+/// Multipliers that bring the camera's response to `xyz` to equal channels,
+/// normalized to green. `matrix` maps XYZ to camera space, a row per channel.
+fn wb_coeffs(matrix: &[f32], xyz: [f32; 3]) -> Option<[f32; 4]> {
+    let mut coeffs = [0.0; 4];
+    for (coeff, row) in coeffs.iter_mut().zip(matrix.chunks_exact(3)) {
+        let response: f32 = row.iter().zip(xyz).map(|(m, v)| m * v).sum();
+        if response <= 0.0 {
+            return None;
+        }
+        *coeff = 1.0 / response;
+    }
+    let green = coeffs[1];
+    (green > 0.0).then(|| coeffs.map(|c| c / green))
+}
+
+/// The XYZ color, at Y = 1, of a black body at `kelvin`, from Kim et al.'s
+/// fit of the Planckian locus.
+fn planckian_xyz(kelvin: f32) -> [f32; 3] {
+    let t = f64::from(kelvin.clamp(1667.0, 25000.0));
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+    [(x / y) as f32, 1.0, ((1.0 - x - y) / y) as f32]
+}
+
+/// Scales each channel so the average of the unclipped pixels is gray.
+fn gray_world(rgb: &mut [f32]) {
+    let (sum, count) = rgb
+        .par_chunks_exact(3)
+        .filter(|px| px.iter().all(|&v| v < AUTO_WB_CLIP))
+        .map(|px| ([px[0] as f64, px[1] as f64, px[2] as f64], 1usize))
+        .reduce(
+            || ([0.0; 3], 0),
+            |(a, n), (b, m)| ([a[0] + b[0], a[1] + b[1], a[2] + b[2]], n + m),
+        );
+    if count == 0 || sum.iter().any(|&s| s <= 0.0) {
+        return;
+    }
+    let gains = sum.map(|s| (sum[1] / s) as f32);
+    rgb.par_chunks_exact_mut(3).for_each(|px| {
+        for (v, gain) in px.iter_mut().zip(gains) {
+            *v *= gain;
+        }
+    });
+}
+
+/// One RGB pixel per 2×2 cell of `mosaic`, a row `stride` samples wide,
+/// within `rect` as (x, y, width, height). `colors` gives the color, 0 red,
+/// 1 green and 2 blue, of the cell's sites in reading order, for a cell at an
+/// even row and column; the rect is moved to one that starts there.
+fn superpixel(
+    mosaic: &[f32],
+    stride: usize,
+    (x, y, width, height): (usize, usize, usize, usize),
+    colors: [usize; 4],
+) -> (Vec<f32>, u32, u32) {
+    let (x0, y0) = (x.next_multiple_of(2), y.next_multiple_of(2));
+    let w = (x + width).saturating_sub(x0) / 2;
+    let h = (y + height).saturating_sub(y0) / 2;
+    let rgb = (0..h)
+        .into_par_iter()
+        .flat_map_iter(|j| {
+            (0..w).map(move |i| {
+                let (sx, sy) = (x0 + 2 * i, y0 + 2 * j);
+                let mut sum = [0.0f32; 3];
+                let mut count = [0.0f32; 3];
+                for (k, &c) in colors.iter().enumerate() {
+                    let at = (sy + k / 2) * stride + sx + k % 2;
+                    sum[c] += mosaic.get(at).copied().unwrap_or(0.0);
+                    count[c] += 1.0;
+                }
+                [0, 1, 2].map(|c| sum[c] / count[c])
+            })
+        })
+        .flatten()
+        .collect();
+    (rgb, w as u32, h as u32)
+}
+
+/// rawler's multipliers normalized to green, with any the camera left out
+/// taken as 1.
+fn white_balance(coeffs: [f32; 4]) -> [f32; 3] {
+    let green = coeffs[1];
+    let ok = |c: f32| c.is_finite() && c > 0.0;
+    if !ok(green) {
+        return [1.0; 3];
+    }
+    [0, 1, 2].map(|c| {
+        if ok(coeffs[c]) {
+            coeffs[c] / green
+        } else {
+            1.0
+        }
+    })
+}
+
+/// The matrix from white balanced camera RGB to linear sRGB, as dcraw works
+/// it out: the camera's response to sRGB's primaries, rows scaled so white
+/// stays white, then inverted. `xyz_to_cam` is the camera's D65 matrix, a
+/// row per channel.
+fn cam_to_srgb(xyz_to_cam: &[f32]) -> Option<[[f32; 3]; 3]> {
+    const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+        [0.4124564, 0.3575761, 0.1804375],
+        [0.2126729, 0.7151522, 0.0721750],
+        [0.0193339, 0.119192, 0.9503041],
+    ];
+    let mut m = [[0.0f32; 3]; 3];
+    for (r, row) in m.iter_mut().enumerate() {
+        for (c, v) in row.iter_mut().enumerate() {
+            *v = (0..3)
+                .map(|k| xyz_to_cam[r * 3 + k] * SRGB_TO_XYZ[k][c])
+                .sum();
+        }
+        let sum: f32 = row.iter().sum();
+        if sum.abs() < 1e-6 {
+            return None;
+        }
+        row.iter_mut().for_each(|v| *v /= sum);
+    }
+    invert(m)
+}
+
+fn invert(m: [[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+    let cofactor = |r: usize, c: usize| {
+        let (r1, r2) = ((r + 1) % 3, (r + 2) % 3);
+        let (c1, c2) = ((c + 1) % 3, (c + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+    let det: f32 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    Some([0, 1, 2].map(|r| [0, 1, 2].map(|c| cofactor(c, r) / det)))
+}
+
+/// Compresses values past a knee towards white, fading the color towards the
+/// brightest channel on the way, or clips when `strength` is 0.
+fn roll_off(rgb: [f32; 3], strength: f32) -> [f32; 3] {
+    let peak = rgb[0].max(rgb[1]).max(rgb[2]);
+    let knee = 1.0 - 0.6 * strength;
+    if strength <= 0.0 || peak <= knee {
+        return rgb.map(|v| v.clamp(0.0, 1.0));
+    }
+    let room = 1.0 - knee;
+    let rolled = 1.0 - (-(peak - knee) / room).exp();
+    let top = knee + room * rolled;
+    let fade = rolled * strength;
+    rgb.map(|v| {
+        let v = v.max(0.0) * top / peak;
+        (v + (top - v) * fade).clamp(0.0, 1.0)
+    })
+}
+
+fn srgb_encode(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_planckian_locus_passes_near_d65_at_6500k() {
+        let [x, y, z] = planckian_xyz(6500.0);
+        let sum = x + y + z;
+        assert!((x / sum - 0.3135).abs() < 0.002);
+        assert!((y / sum - 0.3237).abs() < 0.002);
+
+        let warm = planckian_xyz(3000.0);
+        assert!(
+            warm[0] > x && warm[2] < z,
+            "tungsten is redder than daylight"
+        );
+    }
+
+    #[test]
+    fn custom_white_balance_makes_the_light_neutral() {
+        // A camera that sees XYZ as it is.
+        let identity = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+        let xyz = planckian_xyz(3200.0);
+        let coeffs = wb_coeffs(&identity, xyz).unwrap();
+        assert_eq!(coeffs[1], 1.0);
+        for (c, v) in coeffs.iter().zip(xyz) {
+            assert!((c * v - 1.0).abs() < 1e-5);
+        }
+        assert!(wb_coeffs(&[0.0; 9], xyz).is_none());
+    }
+
+    #[test]
+    fn a_superpixel_takes_each_color_from_its_own_cell() {
+        // RGGB, five sites across and two down.
+        let mosaic = [
+            0.0, 0.9, 0.2, 0.5, 0.4, //
+            0.0, 0.4, 0.1, 0.2, 0.3,
+        ];
+        let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6);
+
+        let (rgb, w, h) = superpixel(&mosaic, 5, (0, 0, 5, 2), [0, 1, 1, 2]);
+        assert_eq!((w, h), (2, 1), "the odd column is left out");
+        assert!(close(&rgb, &[0.0, 0.45, 0.4, 0.2, 0.3, 0.2]), "{rgb:?}");
+
+        // A rect off the grid moves to the next cell.
+        let (rgb, w, h) = superpixel(&mosaic, 5, (1, 0, 4, 2), [0, 1, 1, 2]);
+        assert_eq!((w, h), (1, 1));
+        assert!(close(&rgb, &[0.2, 0.3, 0.2]), "{rgb:?}");
+    }
+
+    #[test]
+    fn a_camera_that_sees_srgb_needs_no_conversion() {
+        // XYZ to camera as the inverse of sRGB's own matrix.
+        let xyz_to_srgb = [
+            3.2404542, -1.5371385, -0.4985314, //
+            -0.969266, 1.8760108, 0.041556, //
+            0.0556434, -0.2040259, 1.0572252,
+        ];
+        let m = cam_to_srgb(&xyz_to_srgb).unwrap();
+        for (r, row) in m.iter().enumerate() {
+            for (c, v) in row.iter().enumerate() {
+                let expected = if r == c { 1.0 } else { 0.0 };
+                assert!((v - expected).abs() < 1e-3, "{m:?}");
+            }
+        }
+        assert!(cam_to_srgb(&[0.0; 9]).is_none());
+        assert_eq!(white_balance([2.0, 1.0, 1.5, 1.0]), [2.0, 1.0, 1.5]);
+        assert_eq!(white_balance([f32::NAN, 2.0, 4.0, 0.0]), [1.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn highlights_roll_off_to_white_instead_of_clipping_per_channel() {
+        assert_eq!(roll_off([1.4, 0.5, 1.2], 0.0), [1.0, 0.5, 1.0]);
+        assert_eq!(roll_off([0.3, 0.2, 0.1], 1.0), [0.3, 0.2, 0.1]);
+
+        let [r, g, b] = roll_off([3.0, 1.0, 2.5], 1.0);
+        assert!(r <= 1.0 && r > 0.9);
+        assert!(r - g < 3.0 - 1.0, "the cast is taken out");
+        assert!(b < r);
+    }
+}
@@ -0,0 +1,134 @@
+//! High-precision pixels: what a 16-bit or floating-point source holds beyond
+//! the RGBA8 buffer every loader produces.
+//!
+//! The RGBA8 buffer stays the common currency. Cursor readouts, the histogram
+//! and the clipboard all read it and gain nothing from more bits. What does
+//! gain is anything that moves values before they are quantized: the modifier
+//! chain, on the CPU for export and on the GPU for the preview. Those take
+//! DeepPixels when an image has them, so levels or exposure pushed hard on a
+//! 16-bit gradient stay smooth instead of opening up into the steps 8 bits
+//! would have left behind.
+//!
+//! Samples are in the same encoding as the RGBA8 buffer (sRGB, after any color
+//! conversion), only without the rounding. Integers are unsigned normalized,
+//! floats are nominally 0..1. The RGBA8 buffer is always derived from these
+//! samples, never converted separately, so the two cannot drift apart.
+
+use half::f16;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    U16,
+    F16,
+    F32,
+}
+
+/// RGBA samples at the source's own precision.
+#[derive(Debug, Clone)]
+pub enum DeepPixels {
+    U16(Vec<u16>),
+    F16(Vec<f16>),
+    F32(Vec<f32>),
+}
+
+impl DeepPixels {
+    pub fn format(&self) -> SampleFormat {
+        match self {
+            DeepPixels::U16(_) => SampleFormat::U16,
+            DeepPixels::F16(_) => SampleFormat::F16,
+            DeepPixels::F32(_) => SampleFormat::F32,
+        }
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            DeepPixels::U16(_) | DeepPixels::F16(_) => 16,
+            DeepPixels::F32(_) => 32,
+        }
+    }
+
+    pub fn to_f32(&self) -> Vec<f32> {
+        match self {
+            DeepPixels::U16(s) => s.par_iter().map(|&v| v as f32 / 65535.0).collect(),
+            DeepPixels::F16(s) => s.par_iter().map(|v| v.to_f32()).collect(),
+            DeepPixels::F32(s) => s.clone(),
+        }
+    }
+
+    pub fn to_f16(&self) -> Vec<f16> {
+        match self {
+            DeepPixels::U16(s) => s
+                .par_iter()
+                .map(|&v| f16::from_f32(v as f32 / 65535.0))
+                .collect(),
+            DeepPixels::F16(s) => s.clone(),
+            DeepPixels::F32(s) => s.par_iter().map(|&v| f16::from_f32(v)).collect(),
+        }
+    }
+
+    /// Runs `f` over an f32 view of the samples and stores the result back at
+    /// this precision. Color conversion goes through here.
+    pub fn map_f32<R>(&mut self, f: impl FnOnce(&mut [f32]) -> R) -> R {
+        let mut samples = self.to_f32();
+        let out = f(&mut samples);
+        *self = match self {
+            DeepPixels::U16(_) => DeepPixels::U16(
+                samples
+                    .par_iter()
+                    .map(|&v| (v.clamp(0.0, 1.0) * 65535.0).round() as u16)
+                    .collect(),
+            ),
+            DeepPixels::F16(_) => {
+                DeepPixels::F16(samples.par_iter().map(|&v| f16::from_f32(v)).collect())
+            }
+            DeepPixels::F32(_) => DeepPixels::F32(samples),
+        };
+        out
+    }
+
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        match self {
+            DeepPixels::U16(s) => s
+                .par_iter()
+                .map(|&v| ((v as u32 * 255 + 32767) / 65535) as u8)
+                .collect(),
+            DeepPixels::F16(s) => s.par_iter().map(|v| unit_to_u8(v.to_f32())).collect(),
+            DeepPixels::F32(s) => s.par_iter().map(|&v| unit_to_u8(v)).collect(),
+        }
+    }
+}
+
+fn unit_to_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sixteen_bit_steps_survive_until_quantized() {
+        let deep = DeepPixels::U16(vec![0, 128, 257, 65535]);
+        assert_eq!(deep.to_rgba8(), vec![0, 0, 1, 255]);
+        let f = deep.to_f32();
+        assert!(f[1] > 0.0 && f[1] < f[2], "sub-8-bit steps are kept");
+    }
+
+    #[test]
+    fn map_f32_keeps_the_storage_precision() {
+        let mut deep = DeepPixels::U16(vec![1000, 2000, 3000, 65535]);
+        deep.map_f32(|s| s.iter_mut().for_each(|v| *v *= 2.0));
+        let DeepPixels::U16(s) = &deep else {
+            panic!("format changed");
+        };
+        assert_eq!(s, &[2000, 4000, 6000, 65535]);
+    }
+
+    #[test]
+    fn floats_are_clamped_only_when_quantized() {
+        let deep = DeepPixels::F32(vec![-0.5, 0.5, 4.0, 1.0]);
+        assert_eq!(deep.to_rgba8(), vec![0, 128, 255, 255]);
+        assert_eq!(deep.to_f32()[2], 4.0);
+    }
+}
@@ -0,0 +1,349 @@
+//! Numbered image sequences, played as an animation.
+//!
+//! Renders and timelapses come out as folders of `frame_0001.png` through
+//! `frame_0480.png`. A run is the files whose names differ only in their last
+//! number: the same text before it, the same text and extension after it.
+//! Played as a sequence, a run becomes an Animation with one frame per file,
+//! each held for the same time at the chosen frame rate.
+//!
+//! Only the first frame decodes up front. The rest decode on the rayon pool as
+//! playback nears them, into a window that runs a little behind the playhead
+//! and as far ahead as WINDOW_BUDGET_BYTES allows. Frames that fall out of the
+//! window are dropped, so a run of any length holds a bounded amount of memory.
+//! Playback waits on a frame that has not arrived rather than skip it. A frame
+//! that fails to decode, or is not the first frame's size, is stepped over
+//! with the previous one left on screen.
+//!
+//! Export reads the frames it needs one at a time, through read, without
+//! touching the window.
+//!
+//! The slices of a multi-frame DICOM file play the same way, decoded from the
+//! file's pixel data rather than from files of their own. See dicom.rs.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use image::ImageError;
+
+use super::animation::Animation;
+use super::dicom::Slices;
+use super::image_data::{ImageData, MediaData};
+
+/// What the decoded frames ahead of the playhead may take up.
+const WINDOW_BUDGET_BYTES: usize = 512 * 1024 * 1024;
+
+/// Frames decoded ahead at most, however small they are.
+const MAX_AHEAD: usize = 48;
+
+/// Frames kept behind the playhead, for stepping back.
+const BEHIND: usize = 2;
+
+fn sequence_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+/// A file name split around its last number: the text before, the number,
+/// and the text after.
+pub fn numbered(name: &str) -> Option<(&str, u64, &str)> {
+    let end = name.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = name[..end]
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len();
+    let number = name[start..end].parse().ok()?;
+    Some((&name[..start], number, &name[end..]))
+}
+
+/// The run among `paths` that `path` is part of, in frame order, or None when
+/// no other file shares its name.
+pub fn run_of(path: &Path, paths: &[PathBuf]) -> Option<Vec<PathBuf>> {
+    let key = |p: &Path| {
+        let (before, number, after) = numbered(p.file_name()?.to_str()?)?;
+        Some((p.parent(), before.to_string(), number, after.to_string()))
+    };
+    let (parent, before, _, after) = key(path)?;
+    let mut run: Vec<(u64, &PathBuf)> = paths
+        .iter()
+        .filter_map(|p| {
+            let (pp, b, number, a) = key(p)?;
+            (pp == parent && b == before && a == after).then_some((number, p))
+        })
+        .collect();
+    if run.len() < 2 {
+        return None;
+    }
+    run.sort_by_key(|&(number, _)| number);
+    Some(run.into_iter().map(|(_, p)| p.clone()).collect())
+}
+
+/// Opens `paths` as one animation at `fps` frames a second.
+pub fn load(paths: Vec<PathBuf>, fps: u32) -> Result<MediaData, ImageError> {
+    let first_path = paths
+        .first()
+        .ok_or_else(|| sequence_error("sequence has no frames"))?;
+    let first = decode(first_path)?;
+    let delay = Duration::from_secs(1) / fps.max(1);
+    Ok(start(FrameSource::Files(paths), delay, first))
+}
+
+/// Plays the slices of a DICOM stack, each held for `delay`. `first` is the
+/// first slice, already decoded.
+pub fn from_slices(slices: Slices, delay: Duration, first: ImageData) -> MediaData {
+    start(FrameSource::Dicom(slices), delay, Arc::new(first))
+}
+
+fn start(frames: FrameSource, delay: Duration, first: Arc<ImageData>) -> MediaData {
+    let ahead = (WINDOW_BUDGET_BYTES / first.size_bytes().max(1))
+        .clamp(2, MAX_AHEAD)
+        .min(frames.len() - 1);
+    let mut window = Window::default();
+    window.frames.insert(0, Some(Arc::clone(&first)));
+    let sequence = Arc::new(Sequence {
+        delay,
+        width: first.width,
+        height: first.height,
+        frames,
+        ahead,
+        window: Mutex::new(window),
+    });
+    sequence.fetch(0);
+    MediaData::Animation(Animation::from_sequence(sequence, first))
+}
+
+fn decode(path: &Path) -> Result<Arc<ImageData>, ImageError> {
+    match ImageData::load_media(path)? {
+        MediaData::Image(data) => Ok(Arc::from(data)),
+        MediaData::Animation(anim) => Ok(Arc::clone(anim.current_image())),
+        #[cfg(feature = "av")]
+        MediaData::Video(_) => Err(sequence_error("a video cannot be a sequence frame")),
+    }
+}
+
+/// Where the frames of a sequence decode from.
+#[derive(Debug)]
+enum FrameSource {
+    /// One file a frame.
+    Files(Vec<PathBuf>),
+    /// The slices of one multi-frame DICOM file.
+    Dicom(Slices),
+}
+
+impl FrameSource {
+    fn len(&self) -> usize {
+        match self {
+            FrameSource::Files(paths) => paths.len(),
+            FrameSource::Dicom(slices) => slices.frame_count(),
+        }
+    }
+
+    fn decode(&self, index: usize) -> Result<Arc<ImageData>, ImageError> {
+        match self {
+            FrameSource::Files(paths) => decode(&paths[index]),
+            FrameSource::Dicom(slices) => slices.decode(index).map(Arc::new),
+        }
+    }
+
+    /// Frame `index` as an error message names it.
+    fn name(&self, index: usize) -> String {
+        match self {
+            FrameSource::Files(paths) => paths[index].display().to_string(),
+            FrameSource::Dicom(_) => format!("Slice {}", index + 1),
+        }
+    }
+}
+
+/// Where a frame of a sequence stands.
+pub enum Slot {
+    Ready(Arc<ImageData>),
+    /// Failed to decode or was the wrong size.
+    Unusable,
+    Pending,
+}
+
+#[derive(Debug)]
+pub struct Sequence {
+    frames: FrameSource,
+    delay: Duration,
+    width: u32,
+    height: u32,
+    ahead: usize,
+    window: Mutex<Window>,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    /// The frame the playhead last asked for.
+    anchor: usize,
+    /// Decoded frames, with None for one that cannot be shown.
+    frames: HashMap<usize, Option<Arc<ImageData>>>,
+    decoding: HashSet<usize>,
+}
+
+impl Sequence {
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    fn window(&self) -> std::sync::MutexGuard<'_, Window> {
+        self.window.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn in_window(&self, index: usize, anchor: usize) -> bool {
+        let len = self.frame_count();
+        (index + len - anchor) % len <= self.ahead || (anchor + len - index) % len <= BEHIND
+    }
+
+    /// Frame `index`, decoded on this thread unless the window has it.
+    pub fn read(&self, index: usize) -> Result<Arc<ImageData>, ImageError> {
+        if let Some(Some(data)) = self.window().frames.get(&index) {
+            return Ok(Arc::clone(data));
+        }
+        let data = self.frames.decode(index)?;
+        if (data.width, data.height) != (self.width, self.height) {
+            return Err(sequence_error(format!(
+                "{} is {}×{}, not the {}×{} of the first frame",
+                self.frames.name(index),
+                data.width,
+                data.height,
+                self.width,
+                self.height
+            )));
+        }
+        Ok(data)
+    }
+
+    /// Moves the window to `index` and reports where that frame stands,
+    /// queueing decodes for whatever in the window is missing.
+    pub fn fetch(self: &Arc<Self>, index: usize) -> Slot {
+        let mut window = self.window();
+        window.anchor = index;
+        window.frames.retain(|&i, _| self.in_window(i, index));
+        let len = self.frame_count();
+        for i in (0..=self.ahead).map(|step| (index + step) % len) {
+            if window.frames.contains_key(&i) || !window.decoding.insert(i) {
+                continue;
+            }
+            let sequence = Arc::downgrade(self);
+            rayon::spawn(move || decode_into(sequence, i));
+        }
+        match window.frames.get(&index) {
+            Some(Some(data)) => Slot::Ready(Arc::clone(data)),
+            Some(None) => Slot::Unusable,
+            None => Slot::Pending,
+        }
+    }
+
+    /// Frame `index` now, decoding it here when the window does not have it
+    /// yet. For seeking, where waiting on the pool would leave the old frame up.
+    pub fn fetch_now(self: &Arc<Self>, index: usize) -> Option<Arc<ImageData>> {
+        if let Slot::Ready(data) = self.fetch(index) {
+            return Some(data);
+        }
+        let data = self.read(index).ok();
+        let mut window = self.window();
+        if self.in_window(index, window.anchor) {
+            window.frames.insert(index, data.clone());
+        }
+        data
+    }
+}
+
+fn decode_into(sequence: Weak<Sequence>, index: usize) {
+    let Some(sequence) = sequence.upgrade() else {
+        return;
+    };
+    let wanted = {
+        let window = sequence.window();
+        sequence.in_window(index, window.anchor)
+    };
+    let data = wanted.then(|| sequence.read(index).ok());
+    let mut window = sequence.window();
+    window.decoding.remove(&index);
+    if let Some(data) = data
+        && sequence.in_window(index, window.anchor)
+    {
+        window.frames.insert(index, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_split_around_their_last_number() {
+        assert_eq!(numbered("frame_0001.png"), Some(("frame_", 1, ".png")));
+        assert_eq!(
+            numbered("shot2_v3_0480.exr"),
+            Some(("shot2_v3_", 480, ".exr"))
+        );
+        assert_eq!(numbered("0042"), Some(("", 42, "")));
+        assert_eq!(numbered("cover.jpg"), None);
+    }
+
+    #[test]
+    fn a_run_is_the_files_sharing_a_name_in_frame_order() {
+        let dir = Path::new("/renders");
+        let paths: Vec<PathBuf> = [
+            "frame_10.png",
+            "frame_9.png",
+            "frame_0001.png",
+            "frame_0002.jpg",
+            "matte_0001.png",
+            "notes.txt",
+        ]
+        .iter()
+        .map(|n| dir.join(n))
+        .collect();
+
+        let run = run_of(&dir.join("frame_9.png"), &paths).unwrap();
+        assert_eq!(
+            run,
+            [
+                dir.join("frame_0001.png"),
+                dir.join("frame_9.png"),
+                dir.join("frame_10.png")
+            ]
+        );
+        assert!(run_of(&dir.join("matte_0001.png"), &paths).is_none());
+        assert!(run_of(&dir.join("notes.txt"), &paths).is_none());
+    }
+
+    #[test]
+    fn frames_decode_into_a_window_around_the_playhead() {
+        let dir = std::env::temp_dir().join(format!("bloom-sequence-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = (0..6u8)
+            .map(|i| {
+                let path = dir.join(format!("f_{i:04}.png"));
+                image::RgbaImage::from_pixel(2, 2, image::Rgba([i, 0, 0, 255]))
+                    .save(&path)
+                    .unwrap();
+                path
+            })
+            .collect();
+        image::RgbaImage::new(3, 3)
+            .save(dir.join("f_0003.png"))
+            .unwrap();
+
+        let MediaData::Animation(anim) = load(paths, 25).unwrap() else {
+            panic!("a sequence plays as an animation");
+        };
+        assert_eq!(anim.frame_count(), 6);
+        assert_eq!(anim.total_duration(), Duration::from_millis(240));
+        let sequence = anim.sequence().unwrap();
+        assert_eq!(sequence.read(4).unwrap().pixels_snapshot()[0], 4);
+        assert!(
+            sequence.read(3).is_err(),
+            "a frame of another size is refused"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
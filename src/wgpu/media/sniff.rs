@@ -0,0 +1,410 @@
+//! Identifying a file's format from its leading bytes rather than its name.
+//!
+//! sniff answers with the extension the loader table knows the format by, so
+//! dispatch stays a lookup on one string whichever way the format was found.
+//! The extension is only a fallback, for formats with no signature (TGA) and
+//! for files too short or unreadable to sniff.
+//!
+//! One case needs the name as well as the content: most camera RAW formats are
+//! TIFF containers, so their bytes say TIFF and only the extension says which
+//! decoder can develop them. dispatch_extension keeps a RAW extension when the
+//! content is TIFF.
+//!
+//! APNG shares PNG's signature and differs only by an acTL chunk, which the
+//! spec requires to come before the first IDAT. Finding it means walking chunk
+//! headers, since an iCCP or text chunk can push acTL well past any fixed
+//! prefix.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::image_data::RAW_EXTENSIONS;
+
+pub(crate) const HEADER_LEN: usize = 4096;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// The most images an ICO's directory is taken to list.
+const ICO_MAX_IMAGES: u16 = 256;
+
+/// Extensions that name the same format, so content sniffed as one and a file
+/// named with another are not reported as a mismatch.
+const FAMILIES: &[&[&str]] = &[
+    &["jpg", "jpeg"],
+    &["png", "apng"],
+    &["tif", "tiff"],
+    &["psd", "psb"],
+    &["svg", "svgz"],
+    &["jp2", "j2k", "j2c", "jpx"],
+    &["dcm", "dicom"],
+    &["fits", "fit", "fts"],
+    &["eps", "ps", "epsf"],
+    &["pbm", "pgm", "ppm"],
+    &["heic", "heif", "avif"],
+    &["mp4", "m4v", "mov"],
+    &["mkv", "webm"],
+    &["mpg", "mpeg"],
+    &["ts", "m2ts"],
+];
+
+pub fn named_extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+pub fn sniff(path: &Path) -> Option<&'static str> {
+    let mut file = File::open(path).ok()?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    (&mut file)
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    let found = sniff_bytes(&header)?;
+    if found == "png" && png_is_animated(&mut file) {
+        return Some("apng");
+    }
+    Some(found)
+}
+
+/// The extension to dispatch a load on: the sniffed format when there is one,
+/// otherwise whatever the file is named.
+pub fn dispatch_extension(path: &Path) -> String {
+    let named = named_extension(path);
+    match sniff(path) {
+        Some("tiff" | "raw") if RAW_EXTENSIONS.contains(&named.as_str()) => named,
+        Some(found) => found.to_string(),
+        None => named,
+    }
+}
+
+/// Whether content sniffed as `found` is fairly described by extension `named`.
+pub fn agrees(found: &str, named: &str) -> bool {
+    if found == named {
+        return true;
+    }
+    if matches!(found, "tiff" | "raw") && RAW_EXTENSIONS.contains(&named) {
+        return true;
+    }
+    FAMILIES
+        .iter()
+        .any(|f| f.contains(&found) && f.contains(&named))
+}
+
+fn at(header: &[u8], offset: usize, magic: &[u8]) -> bool {
+    header
+        .get(offset..offset + magic.len())
+        .is_some_and(|b| b == magic)
+}
+
+pub(crate) fn sniff_bytes(h: &[u8]) -> Option<&'static str> {
+    if at(h, 0, b"\xff\xd8\xff") {
+        return Some("jpg");
+    }
+    if at(h, 0, PNG_SIGNATURE) {
+        return Some("png");
+    }
+    if at(h, 0, b"GIF87a") || at(h, 0, b"GIF89a") {
+        return Some("gif");
+    }
+    if at(h, 0, b"RIFF") && at(h, 8, b"WEBP") {
+        return Some("webp");
+    }
+    if let Some(raw) = sniff_raw(h) {
+        return Some(raw);
+    }
+    if at(h, 0, b"II*\0") || at(h, 0, b"MM\0*") || at(h, 0, b"II+\0") || at(h, 0, b"MM\0+") {
+        return Some("tiff");
+    }
+    if at(h, 0, b"qoif") {
+        return Some("qoi");
+    }
+    if at(h, 0, b"farbfeld") {
+        return Some("ff");
+    }
+    if at(h, 0, b"DDS ") {
+        return Some("dds");
+    }
+    if at(h, 0, b"#?RADIANCE") || at(h, 0, b"#?RGBE") {
+        return Some("hdr");
+    }
+    if at(h, 0, b"\x76\x2f\x31\x01") {
+        return Some("exr");
+    }
+    if at(h, 0, b"\xff\x0a") || at(h, 0, b"\0\0\0\x0cJXL \r\n\x87\n") {
+        return Some("jxl");
+    }
+    if at(h, 0, b"8BPS") {
+        return Some(if at(h, 4, b"\0\x02") { "psb" } else { "psd" });
+    }
+    if at(h, 0, b"icns") {
+        return Some("icns");
+    }
+    if at(h, 0, b"gimp xcf ") {
+        return Some("xcf");
+    }
+    if at(h, 0, b"\0\0\0\x0cjP  \r\n\x87\n") {
+        return Some("jp2");
+    }
+    if at(h, 0, b"\xff\x4f\xff\x51") {
+        return Some("j2k");
+    }
+    if at(h, 128, b"DICM") {
+        return Some("dcm");
+    }
+    if at(h, 0, b"\xabKTX 20\xbb\r\n\x1a\n") {
+        return Some("ktx2");
+    }
+    if at(h, 0, b"SIMPLE  =") {
+        return Some("fits");
+    }
+    if at(h, 0, b"%!PS") || at(h, 0, b"\xc5\xd0\xd3\xc6") {
+        return Some("eps");
+    }
+    if at(h, 0, b"PK\x03\x04") && contains(&h[..h.len().min(128)], b"application/x-krita") {
+        return Some("kra");
+    }
+    if is_ico(h) {
+        return Some("ico");
+    }
+    if let Some(ftyp) = sniff_ftyp(h) {
+        return Some(ftyp);
+    }
+    #[cfg(feature = "av")]
+    if let Some(video) = sniff_video(h) {
+        return Some(video);
+    }
+    if let Some(pnm) = sniff_pnm(h) {
+        return Some(pnm);
+    }
+    if at(h, 0, b"BM") && h.len() >= 26 {
+        return Some("bmp");
+    }
+    if looks_like_svg(h) {
+        return Some("svg");
+    }
+    None
+}
+
+/// Whether `h` starts an ICO. Its four-byte header could begin almost any
+/// binary file, so the directory after it has to make sense too: a
+/// plausible number of images, and a first entry with the planes and bit
+/// count an icon has and its data somewhere past the directory.
+fn is_ico(h: &[u8]) -> bool {
+    let u16_at = |i: usize| Some(u16::from_le_bytes(h.get(i..i + 2)?.try_into().ok()?));
+    let u32_at = |i: usize| Some(u32::from_le_bytes(h.get(i..i + 4)?.try_into().ok()?));
+    let sane = || {
+        let count = u16_at(4).filter(|n| (1..=ICO_MAX_IMAGES).contains(n))?;
+        let reserved = *h.get(9)?;
+        let (planes, bits) = (u16_at(10)?, u16_at(12)?);
+        let (size, offset) = (u32_at(14)?, u32_at(18)?);
+        Some(
+            matches!(reserved, 0 | 255)
+                && planes <= 1
+                && matches!(bits, 0 | 1 | 4 | 8 | 16 | 24 | 32)
+                && size > 0
+                && offset as usize >= 6 + 16 * count as usize,
+        )
+    };
+    at(h, 0, b"\0\0\x01\0") && sane() == Some(true)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn sniff_raw(h: &[u8]) -> Option<&'static str> {
+    let raw = at(h, 0, b"IIRO")
+        || at(h, 0, b"IIRS")
+        || at(h, 0, b"IIU\0")
+        || at(h, 0, b"FUJIFILMCCD-RAW")
+        || at(h, 0, b"\0MRM")
+        || at(h, 0, b"FOVb")
+        || at(h, 0, b"II\x1a\0\0\0HEAPCCDR")
+        || (at(h, 0, b"II*\0") && at(h, 8, b"CR"));
+    raw.then_some("raw")
+}
+
+fn sniff_ftyp(h: &[u8]) -> Option<&'static str> {
+    if !at(h, 4, b"ftyp") {
+        return None;
+    }
+    let brand = h.get(8..12)?;
+    match brand {
+        b"crx " => Some("raw"),
+        #[cfg(feature = "heif")]
+        b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1" => {
+            Some("heic")
+        }
+        #[cfg(feature = "heif")]
+        b"avif" | b"avis" => Some("avif"),
+        #[cfg(feature = "av")]
+        b"qt  " => Some("mov"),
+        #[cfg(feature = "av")]
+        b"isom" | b"iso2" | b"iso4" | b"iso5" | b"iso6" | b"mp41" | b"mp42" | b"avc1" | b"dash"
+        | b"M4V " | b"M4VH" | b"MSNV" => Some("mp4"),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "av")]
+fn sniff_video(h: &[u8]) -> Option<&'static str> {
+    if at(h, 0, b"\x1a\x45\xdf\xa3") {
+        return Some(if contains(&h[..h.len().min(64)], b"webm") {
+            "webm"
+        } else {
+            "mkv"
+        });
+    }
+    if at(h, 0, b"RIFF") && at(h, 8, b"AVI ") {
+        return Some("avi");
+    }
+    if at(h, 0, b"\0\0\x01\xba") || at(h, 0, b"\0\0\x01\xb3") {
+        return Some("mpg");
+    }
+    if at(h, 0, b"FLV\x01") {
+        return Some("flv");
+    }
+    if at(h, 0, b"\x30\x26\xb2\x75\x8e\x66\xcf\x11") {
+        return Some("wmv");
+    }
+    if h.first() == Some(&0x47) && h.get(188) == Some(&0x47) {
+        return Some("ts");
+    }
+    None
+}
+
+fn sniff_pnm(h: &[u8]) -> Option<&'static str> {
+    if h.first() != Some(&b'P') || !h.get(2).is_some_and(u8::is_ascii_whitespace) {
+        return None;
+    }
+    match h.get(1)? {
+        b'1' | b'4' => Some("pbm"),
+        b'2' | b'5' => Some("pgm"),
+        b'3' | b'6' => Some("ppm"),
+        _ => None,
+    }
+}
+
+fn looks_like_svg(h: &[u8]) -> bool {
+    let text = h.strip_prefix(b"\xef\xbb\xbf").unwrap_or(h);
+    let start = text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    let text = &text[start..];
+    (text.starts_with(b"<?xml") || text.starts_with(b"<svg") || text.starts_with(b"<!"))
+        && contains(text, b"<svg")
+}
+
+fn png_is_animated(file: &mut File) -> bool {
+    if file
+        .seek(SeekFrom::Start(PNG_SIGNATURE.len() as u64))
+        .is_err()
+    {
+        return false;
+    }
+    let mut chunk = [0u8; 8];
+    while file.read_exact(&mut chunk).is_ok() {
+        let len = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        match &chunk[4..8] {
+            b"acTL" => return true,
+            b"IDAT" | b"IEND" => return false,
+            _ => {}
+        }
+        if file.seek(SeekFrom::Current(len as i64 + 4)).is_err() {
+            return false;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_with_chunks(chunks: &[&[u8; 4]]) -> Vec<u8> {
+        let mut b = PNG_SIGNATURE.to_vec();
+        for name in chunks {
+            let body = vec![0u8; 13];
+            b.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            b.extend_from_slice(*name);
+            b.extend_from_slice(&body);
+            b.extend_from_slice(&[0u8; 4]);
+        }
+        b
+    }
+
+    fn sniff_file(name: &str, bytes: &[u8]) -> Option<&'static str> {
+        let path = std::env::temp_dir().join(format!("bloom-sniff-{}-{name}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let found = sniff(&path);
+        let _ = std::fs::remove_file(&path);
+        found
+    }
+
+    #[test]
+    fn signatures_resolve_to_loader_extensions() {
+        assert_eq!(sniff_bytes(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("jpg"));
+        assert_eq!(sniff_bytes(b"GIF89a\x01\0\x01\0"), Some("gif"));
+        assert_eq!(sniff_bytes(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(sniff_bytes(b"8BPS\0\x01\0\0"), Some("psd"));
+        assert_eq!(sniff_bytes(b"8BPS\0\x02\0\0"), Some("psb"));
+        assert_eq!(sniff_bytes(b"P6\n2 2\n255\n"), Some("ppm"));
+        assert_eq!(sniff_bytes(b"  <svg xmlns='x'/>"), Some("svg"));
+        assert_eq!(sniff_bytes(b"plain text, not an image"), None);
+    }
+
+    #[test]
+    fn an_ico_needs_a_sane_directory_behind_its_header() {
+        let mut ico = b"\0\0\x01\0\x01\0".to_vec();
+        // One 16x16, 32-bit image of 1128 bytes, right after the directory.
+        ico.extend_from_slice(&[16, 16, 0, 0, 1, 0, 32, 0]);
+        ico.extend_from_slice(&1128u32.to_le_bytes());
+        ico.extend_from_slice(&22u32.to_le_bytes());
+        assert_eq!(sniff_bytes(&ico), Some("ico"));
+
+        let mut no_images = ico.clone();
+        no_images[4] = 0;
+        let mut odd_bits = ico.clone();
+        odd_bits[12] = 7;
+        let mut inside_directory = ico.clone();
+        inside_directory[18] = 6;
+        for bytes in [&no_images, &odd_bits, &inside_directory, &ico[..8].to_vec()] {
+            assert_eq!(sniff_bytes(bytes), None);
+        }
+    }
+
+    #[test]
+    fn actl_before_idat_marks_a_png_as_animated() {
+        let still = png_with_chunks(&[b"IHDR", b"IDAT", b"IEND"]);
+        let animated = png_with_chunks(&[b"IHDR", b"iCCP", b"acTL", b"IDAT", b"IEND"]);
+        assert_eq!(sniff_file("still", &still), Some("png"));
+        assert_eq!(sniff_file("animated", &animated), Some("apng"));
+    }
+
+    #[test]
+    fn a_tiff_based_raw_keeps_its_extension() {
+        let dir = std::env::temp_dir();
+        let nef = dir.join(format!("bloom-sniff-{}-shot.NEF", std::process::id()));
+        let png = dir.join(format!("bloom-sniff-{}-scan.png", std::process::id()));
+        std::fs::write(&nef, b"MM\0*\0\0\0\x08").unwrap();
+        std::fs::write(&png, b"MM\0*\0\0\0\x08").unwrap();
+        assert_eq!(dispatch_extension(&nef), "nef");
+        assert_eq!(dispatch_extension(&png), "tiff");
+        let _ = std::fs::remove_file(&nef);
+        let _ = std::fs::remove_file(&png);
+    }
+
+    #[test]
+    fn family_members_agree_and_strangers_do_not() {
+        assert!(agrees("jpg", "jpeg"));
+        assert!(agrees("apng", "png"));
+        assert!(agrees("tiff", "dng"));
+        assert!(agrees("heic", "avif"));
+        assert!(!agrees("jpg", "png"));
+        assert!(!agrees("png", ""));
+    }
+}
@@ -0,0 +1,473 @@
+//! Streamed decoding, for images too big to hold in memory.
+//!
+//! An image of STREAM_MIN_PIXELS or more, in a format that can be read a part
+//! at a time, never has a full-size buffer. Loading only reads its header and
+//! returns an ImageData with no pixels and a StreamSource in their place. When
+//! the view uploads it, the source decodes on a thread of its own, top to
+//! bottom, in bands of about BAND_BYTES: PNG row by row, TIFF a row of strips
+//! or tiles at a time, JPEG by scanline through libjpeg, and JPEG 2000 as a
+//! decode area per band. Bands pass through a channel BANDS_IN_FLIGHT deep,
+//! the renderer writes each into the tiles it covers and drops it, so the host
+//! holds a few bands however large the image is. The picture fills in as they
+//! land.
+//!
+//! Nothing is kept once uploaded, so a second upload, say after the mipmap
+//! setting changes, decodes the file again. Export does the same, reading
+//! bands as its PNG encoder walks down the image. What needs all the pixels
+//! on the host at once goes without: the histogram, the cursor readout, copy
+//! and export to other formats. Streamed pixels are 8-bit and taken to be
+//! sRGB; embedded profiles are not applied.
+//!
+//! A file that turns out not to stream (an interlaced PNG, a TIFF in a color
+//! model other than gray or RGB) is decoded whole instead, as before.
+
+use std::fs::File;
+use std::io::{BufReader, Error, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+
+use image::{ImageError, ImageReader};
+use jpeg2k::{DecodeArea, DecodeParameters, Image as Jp2Image};
+use tiff::ColorType as TiffColor;
+use tiff::decoder::{ChunkType, Decoder, DecodingResult, Limits};
+
+use super::image_data::{ImageData, Source};
+use super::pages::expand;
+
+/// Images with at least this many pixels stream: 1 GiB at RGBA8.
+pub const STREAM_MIN_PIXELS: u64 = 1 << 28;
+
+/// Whether a `width` by `height` image is too big to decode whole, so it
+/// streams if it can and is not decoded at all otherwise.
+pub fn too_large_to_hold(width: u32, height: u32) -> bool {
+    u64::from(width) * u64::from(height) >= STREAM_MIN_PIXELS
+}
+
+/// About what one band holds.
+const BAND_BYTES: usize = 64 * 1024 * 1024;
+
+/// Decoded bands waiting for the renderer before the decoder blocks.
+const BANDS_IN_FLIGHT: usize = 2;
+
+/// How far into a JPEG 2000 file to look for its size.
+const JP2_HEADER_BYTES: u64 = 64 * 1024;
+
+fn stream_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Png,
+    Tiff,
+    Jpeg,
+    Jp2,
+}
+
+/// Rows `y..y + rows` of a streamed image, as RGBA8.
+pub struct Band {
+    pub y: u32,
+    pub rows: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct StreamSource {
+    path: PathBuf,
+    codec: Codec,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Opens `path` as a streamed image when it is big enough to need it and its
+/// format allows it, and None to decode it whole.
+pub fn open(path: &Path, ext: &str) -> Option<ImageData> {
+    let codec = match ext {
+        "png" => Codec::Png,
+        "tif" | "tiff" => Codec::Tiff,
+        "jpg" | "jpeg" => Codec::Jpeg,
+        "jp2" | "j2k" | "j2c" | "jpx" => Codec::Jp2,
+        _ => return None,
+    };
+    let (width, height) = match codec {
+        Codec::Jp2 => jp2_dimensions(&read_head(path)?)?,
+        _ => ImageReader::open(path)
+            .ok()?
+            .with_guessed_format()
+            .ok()?
+            .into_dimensions()
+            .ok()?,
+    };
+    if !too_large_to_hold(width, height) {
+        return None;
+    }
+    let source = StreamSource {
+        path: path.to_path_buf(),
+        codec,
+        width,
+        height,
+    };
+    // Find out now whether it streams, while decoding it whole is still an
+    // option.
+    source.reader().ok()?;
+    let mut data = ImageData::new(Vec::new(), width, height);
+    data.source = Source::Stream(Arc::new(source));
+    Some(data)
+}
+
+fn read_head(path: &Path) -> Option<Vec<u8>> {
+    let mut head = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(JP2_HEADER_BYTES)
+        .read_to_end(&mut head)
+        .ok()?;
+    Some(head)
+}
+
+/// Width and height from the `ihdr` box of a JP2, or the SIZ marker of a bare
+/// codestream.
+fn jp2_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    let be32 = |at: usize| Some(u32::from_be_bytes(head.get(at..at + 4)?.try_into().ok()?));
+    if let Some(at) = head.windows(4).position(|w| w == b"ihdr") {
+        return Some((be32(at + 8)?, be32(at + 4)?));
+    }
+    let siz = head
+        .windows(4)
+        .position(|w| w == [0xff, 0x4f, 0xff, 0x51])?
+        + 8;
+    let (x, y) = (be32(siz)?, be32(siz + 4)?);
+    let (x0, y0) = (be32(siz + 8)?, be32(siz + 12)?);
+    Some((x.checked_sub(x0)?, y.checked_sub(y0)?))
+}
+
+fn band_rows(width: u32) -> u32 {
+    (BAND_BYTES / (width as usize * 4)).max(1) as u32
+}
+
+impl StreamSource {
+    /// Starts decoding from the top, on a thread that stops once the
+    /// receiver is dropped. A decoder that panics, as mozjpeg does on a
+    /// truncated file, sends that as the last band's error.
+    pub fn bands(self: &Arc<Self>) -> Receiver<Result<Band, String>> {
+        let (tx, rx) = mpsc::sync_channel(BANDS_IN_FLIGHT);
+        let source = Arc::clone(self);
+        std::thread::spawn(move || {
+            let decode = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let mut reader = match source.reader() {
+                    Ok(reader) => reader,
+                    Err(e) => {
+                        let _ = tx.send(Err(e.to_string()));
+                        return;
+                    }
+                };
+                loop {
+                    let band = match reader.next_band() {
+                        Ok(Some(band)) => Ok(band),
+                        Ok(None) => return,
+                        Err(e) => Err(e.to_string()),
+                    };
+                    let failed = band.is_err();
+                    if tx.send(band).is_err() || failed {
+                        return;
+                    }
+                }
+            }));
+            if let Err(payload) = decode {
+                let msg = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                let _ = tx.send(Err(format!("decoder panicked: {msg}")));
+            }
+        });
+        rx
+    }
+
+    fn reader(&self) -> Result<Box<dyn BandReader>, ImageError> {
+        let (path, width, height) = (self.path.as_path(), self.width, self.height);
+        Ok(match self.codec {
+            Codec::Png => Box::new(PngBands::open(path)?),
+            Codec::Tiff => Box::new(TiffBands::open(path)?),
+            Codec::Jpeg => Box::new(JpegBands::open(path, width, height)?),
+            Codec::Jp2 => Box::new(Jp2Bands {
+                path: path.to_path_buf(),
+                width,
+                height,
+                y: 0,
+            }),
+        })
+    }
+}
+
+trait BandReader {
+    /// The next rows down, or None past the bottom.
+    fn next_band(&mut self) -> Result<Option<Band>, ImageError>;
+}
+
+struct PngBands {
+    reader: png::Reader<BufReader<File>>,
+    channels: usize,
+    y: u32,
+}
+
+impl PngBands {
+    fn open(path: &Path) -> Result<Self, ImageError> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        let limits = png::Limits { bytes: usize::MAX };
+        let mut decoder = png::Decoder::new_with_limits(BufReader::new(file), limits);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let reader = decoder.read_info().map_err(stream_error)?;
+        if reader.info().interlaced {
+            return Err(stream_error("an interlaced PNG does not stream"));
+        }
+        let channels = match reader.output_color_type().0 {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => return Err(stream_error("PNG palette was not expanded")),
+        };
+        Ok(Self {
+            reader,
+            channels,
+            y: 0,
+        })
+    }
+}
+
+impl BandReader for PngBands {
+    fn next_band(&mut self) -> Result<Option<Band>, ImageError> {
+        let (width, height) = self.reader.info().size();
+        let rows = band_rows(width).min(height - self.y);
+        if rows == 0 {
+            return Ok(None);
+        }
+        let mut pixels = Vec::with_capacity(rows as usize * width as usize * 4);
+        for _ in 0..rows {
+            let row = self
+                .reader
+                .next_row()
+                .map_err(stream_error)?
+                .ok_or_else(|| stream_error("PNG ended early"))?;
+            pixels.extend(expand(row.data(), self.channels, u8::MAX));
+        }
+        let band = Band {
+            y: self.y,
+            rows,
+            pixels,
+        };
+        self.y += rows;
+        Ok(Some(band))
+    }
+}
+
+/// A TIFF read a row of chunks at a time. Strips are chunks one image wide.
+struct TiffBands {
+    decoder: Decoder<BufReader<File>>,
+    width: u32,
+    height: u32,
+    chunk_width: u32,
+    across: u32,
+    channels: usize,
+    chunk_row: u32,
+    y: u32,
+}
+
+impl TiffBands {
+    fn open(path: &Path) -> Result<Self, ImageError> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        let mut decoder = Decoder::new(BufReader::new(file))
+            .map(|d| d.with_limits(Limits::unlimited()))
+            .map_err(stream_error)?;
+        let (width, height) = decoder.dimensions().map_err(stream_error)?;
+        let channels = match decoder.colortype().map_err(stream_error)? {
+            TiffColor::Gray(8 | 16) => 1,
+            TiffColor::GrayA(8 | 16) => 2,
+            TiffColor::RGB(8 | 16) => 3,
+            TiffColor::RGBA(8 | 16) => 4,
+            other => return Err(stream_error(format!("{other:?} TIFF does not stream"))),
+        };
+        let chunk_width = match decoder.get_chunk_type() {
+            ChunkType::Strip => width,
+            ChunkType::Tile => decoder.chunk_dimensions().0,
+        };
+        Ok(Self {
+            decoder,
+            width,
+            height,
+            chunk_width,
+            across: width.div_ceil(chunk_width),
+            channels,
+            chunk_row: 0,
+            y: 0,
+        })
+    }
+
+    /// Decodes one row of chunks onto the end of `pixels`, returning its
+    /// height.
+    fn read_chunk_row(&mut self, pixels: &mut Vec<u8>) -> Result<u32, ImageError> {
+        let first = self.chunk_row * self.across;
+        let rows = self.decoder.chunk_data_dimensions(first).1;
+        let stride = self.width as usize * 4;
+        let start = pixels.len();
+        pixels.resize(start + rows as usize * stride, 0);
+        for col in 0..self.across {
+            let index = first + col;
+            let (chunk_width, chunk_rows) = self.decoder.chunk_data_dimensions(index);
+            let samples = match self.decoder.read_chunk(index).map_err(stream_error)? {
+                DecodingResult::U8(s) => s,
+                DecodingResult::U16(s) => s.into_iter().map(|v| (v >> 8) as u8).collect(),
+                _ => return Err(stream_error("unexpected TIFF sample type")),
+            };
+            let rgba = expand(&samples, self.channels, u8::MAX);
+            let row_bytes = chunk_width as usize * 4;
+            if rgba.len() < row_bytes * chunk_rows.min(rows) as usize {
+                return Err(stream_error(format!("TIFF chunk {index} is short")));
+            }
+            let x = (col * self.chunk_width) as usize * 4;
+            for (r, src) in rgba.chunks_exact(row_bytes).take(rows as usize).enumerate() {
+                let at = start + r * stride + x;
+                pixels[at..at + row_bytes].copy_from_slice(src);
+            }
+        }
+        self.chunk_row += 1;
+        Ok(rows)
+    }
+}
+
+impl BandReader for TiffBands {
+    fn next_band(&mut self) -> Result<Option<Band>, ImageError> {
+        let y = self.y;
+        let target = band_rows(self.width);
+        let mut pixels = Vec::new();
+        while self.y < self.height && self.y - y < target {
+            self.y += self.read_chunk_row(&mut pixels)?;
+        }
+        let rows = self.y - y;
+        Ok((rows > 0).then_some(Band { y, rows, pixels }))
+    }
+}
+
+struct JpegBands {
+    read: Box<dyn FnMut(&mut [[u8; 4]]) -> std::io::Result<()>>,
+    width: u32,
+    height: u32,
+    y: u32,
+}
+
+impl JpegBands {
+    fn open(path: &Path, width: u32, height: u32) -> Result<Self, ImageError> {
+        let mut image = mozjpeg::Decompress::with_markers(&[])
+            .from_path(path)
+            .map_err(ImageError::IoError)?
+            .rgba()
+            .map_err(ImageError::IoError)?;
+        Ok(Self {
+            read: Box::new(move |dest| image.read_scanlines_into(dest).map(|_| ())),
+            width,
+            height,
+            y: 0,
+        })
+    }
+}
+
+impl BandReader for JpegBands {
+    fn next_band(&mut self) -> Result<Option<Band>, ImageError> {
+        let rows = band_rows(self.width).min(self.height - self.y);
+        if rows == 0 {
+            return Ok(None);
+        }
+        let mut lines = vec![[0u8; 4]; rows as usize * self.width as usize];
+        (self.read)(&mut lines).map_err(ImageError::IoError)?;
+        let band = Band {
+            y: self.y,
+            rows,
+            pixels: lines.as_flattened().to_vec(),
+        };
+        self.y += rows;
+        Ok(Some(band))
+    }
+}
+
+/// JPEG 2000, one decode area per band. OpenJPEG only decodes the tiles and
+/// code blocks an area touches.
+struct Jp2Bands {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    y: u32,
+}
+
+impl BandReader for Jp2Bands {
+    fn next_band(&mut self) -> Result<Option<Band>, ImageError> {
+        let rows = band_rows(self.width).min(self.height - self.y);
+        if rows == 0 {
+            return Ok(None);
+        }
+        let area = DecodeArea::new(0, self.y, self.width, self.y + rows);
+        let params = DecodeParameters::new().decode_area(Some(area));
+        let image = Jp2Image::from_file_with(&self.path, params).map_err(stream_error)?;
+        let data = ImageData::from_jp2(&image)?;
+        if (data.width, data.height) != (self.width, rows) {
+            return Err(stream_error(
+                "JPEG 2000 decode area came back the wrong size",
+            ));
+        }
+        let band = Band {
+            y: self.y,
+            rows,
+            pixels: Arc::unwrap_or_clone(data.pixels_snapshot()),
+        };
+        self.y += rows;
+        Ok(Some(band))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jp2_size_comes_from_ihdr_or_siz() {
+        let mut jp2 = b"\0\0\0\x2djp2h\0\0\0\x16ihdr".to_vec();
+        jp2.extend_from_slice(&40000u32.to_be_bytes());
+        jp2.extend_from_slice(&60000u32.to_be_bytes());
+        assert_eq!(jp2_dimensions(&jp2), Some((60000, 40000)));
+
+        let mut j2k = vec![0xff, 0x4f, 0xff, 0x51, 0, 47, 0, 0];
+        for v in [1200u32, 900, 200, 100] {
+            j2k.extend_from_slice(&v.to_be_bytes());
+        }
+        assert_eq!(jp2_dimensions(&j2k), Some((1000, 800)));
+        assert_eq!(jp2_dimensions(b"not a codestream"), None);
+    }
+
+    #[test]
+    fn a_striped_tiff_streams_as_rgba_rows() {
+        use tiff::encoder::{TiffEncoder, colortype};
+
+        let path = std::env::temp_dir().join(format!("bloom-stream-{}.tiff", std::process::id()));
+        let (width, height) = (3u32, 5u32);
+        let gray: Vec<u8> = (0..width * height).map(|i| i as u8).collect();
+        let mut tiff = TiffEncoder::new(File::create(&path).unwrap()).unwrap();
+        let mut image = tiff.new_image::<colortype::Gray8>(width, height).unwrap();
+        image.rows_per_strip(2).unwrap();
+        image.write_data(&gray).unwrap();
+
+        let mut reader = TiffBands::open(&path).unwrap();
+        let mut pixels = Vec::new();
+        let mut next_y = 0;
+        while let Some(band) = reader.next_band().unwrap() {
+            assert_eq!(band.y, next_y);
+            assert_eq!(band.pixels.len(), (band.rows * width * 4) as usize);
+            next_y += band.rows;
+            pixels.extend(band.pixels);
+        }
+        assert_eq!(next_y, height);
+        assert_eq!(&pixels[4 * 7..4 * 8], &[7, 7, 7, 255]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,285 @@
+//! SVG: a vector document, rasterized again for whatever the view shows.
+//!
+//! The file is parsed once into a usvg tree and kept. Opening it renders the
+//! whole document at its native size, and that bitmap is the image the rest
+//! of the viewer works from: the edit chain, the histogram, copy and the
+//! pixel readout. Zoomed in past 1:1 it would only be magnified, so the view
+//! asks for a detail raster of the visible region instead and draws it over
+//! the base.
+//!
+//! Detail scales come in power-of-two buckets. Zooming within a bucket reuses
+//! its raster, sampled down a little, rather than re-rendering on every wheel
+//! notch, and the raster is never coarser than the screen. It covers the
+//! visible region plus a margin so a short pan stays sharp. MAX_DETAIL_SIDE
+//! caps each side of a raster: past it the region gives up its margin, then
+//! the bucket steps down, trading sharpness for memory.
+//!
+//! Export is the other consumer. A vector has no native size worth keeping,
+//! so render draws the tree at whatever resolution was asked for.
+
+use std::fmt;
+use std::io::Error;
+use std::path::Path;
+
+use image::ImageError;
+use tiny_skia::{Pixmap, Transform};
+use usvg::Options as SvgOptions;
+
+use super::image_data::ImageId;
+
+/// Finest detail bucket. Past 64x an SVG's own precision runs out anyway.
+pub const MAX_BUCKET: u32 = 64;
+
+const MAX_DETAIL_SIDE: u32 = 4096;
+
+/// How many buckets' rasters are kept for zooming back to.
+pub const DETAIL_BUCKETS: usize = 3;
+
+/// Fraction of the visible region added on each side of a detail raster.
+const MARGIN: f32 = 0.25;
+
+pub struct SvgDocument {
+    tree: usvg::Tree,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Debug for SvgDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SvgDocument")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A region of the document to rasterize at a bucket's scale. rect is in
+/// native pixels, as left, top, right, bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetailRequest {
+    pub image_id: ImageId,
+    pub bucket: u32,
+    pub rect: [u32; 4],
+}
+
+/// The raster for a DetailRequest, `bucket` times the size of its rect.
+#[derive(Debug)]
+pub struct SvgDetail {
+    pub request: DetailRequest,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl SvgDetail {
+    /// Whether this raster can stand in for `visible` at `bucket`.
+    pub fn covers(&self, bucket: u32, visible: [f32; 4]) -> bool {
+        let [l, t, r, b] = self.request.rect.map(|v| v as f32);
+        self.request.bucket == bucket
+            && visible[0] >= l
+            && visible[1] >= t
+            && visible[2] <= r
+            && visible[3] <= b
+    }
+}
+
+fn svg_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::IoError(Error::other(e))
+}
+
+/// The bucket a view at `zoom` screen pixels per native pixel draws from.
+pub fn bucket_for(zoom: f32) -> u32 {
+    if zoom.is_nan() || zoom <= 1.0 {
+        return 1;
+    }
+    (zoom.log2().ceil().exp2() as u32).clamp(1, MAX_BUCKET)
+}
+
+impl SvgDocument {
+    pub fn load(path: &Path) -> Result<Self, ImageError> {
+        let data = std::fs::read(path).map_err(ImageError::IoError)?;
+        let mut opt = SvgOptions {
+            resources_dir: path.parent().map(|p| p.to_path_buf()),
+            ..SvgOptions::default()
+        };
+        opt.fontdb_mut().load_system_fonts();
+        Self::from_tree(usvg::Tree::from_data(&data, &opt).map_err(svg_error)?)
+    }
+
+    fn from_tree(tree: usvg::Tree) -> Result<Self, ImageError> {
+        let size = tree.size().to_int_size();
+        Ok(Self {
+            tree,
+            width: size.width().max(1),
+            height: size.height().max(1),
+        })
+    }
+
+    /// The whole document scaled to `width` by `height`.
+    pub fn render(&self, width: u32, height: u32) -> Result<Vec<u8>, ImageError> {
+        let mut pixmap =
+            Pixmap::new(width, height).ok_or_else(|| svg_error("SVG dimensions too large"))?;
+        let transform = Transform::from_scale(
+            width as f32 / self.width as f32,
+            height as f32 / self.height as f32,
+        );
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+        Ok(pixmap.take())
+    }
+
+    /// What to rasterize for a view at `zoom` showing `visible`, in native
+    /// pixels. None when the base image is already fine enough.
+    pub fn detail_request(
+        &self,
+        image_id: ImageId,
+        zoom: f32,
+        visible: [f32; 4],
+    ) -> Option<DetailRequest> {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let clamp = |r: [f32; 4]| [r[0].max(0.0), r[1].max(0.0), r[2].min(w), r[3].min(h)];
+        let [l, t, r, b] = clamp(visible);
+        if r <= l || b <= t {
+            return None;
+        }
+        let (mx, my) = ((r - l) * MARGIN, (b - t) * MARGIN);
+        let candidates = [clamp([l - mx, t - my, r + mx, b + my]), [l, t, r, b]];
+
+        let mut bucket = bucket_for(zoom);
+        while bucket > 1 {
+            for c in candidates {
+                let rect = [
+                    c[0].floor() as u32,
+                    c[1].floor() as u32,
+                    c[2].ceil() as u32,
+                    c[3].ceil() as u32,
+                ];
+                if (rect[2] - rect[0]) * bucket <= MAX_DETAIL_SIDE
+                    && (rect[3] - rect[1]) * bucket <= MAX_DETAIL_SIDE
+                {
+                    return Some(DetailRequest {
+                        image_id,
+                        bucket,
+                        rect,
+                    });
+                }
+            }
+            bucket /= 2;
+        }
+        None
+    }
+
+    pub fn render_detail(&self, request: DetailRequest) -> Result<SvgDetail, ImageError> {
+        let [l, t, r, b] = request.rect;
+        let scale = request.bucket as f32;
+        let (width, height) = ((r - l) * request.bucket, (b - t) * request.bucket);
+        let mut pixmap =
+            Pixmap::new(width, height).ok_or_else(|| svg_error("SVG detail region is empty"))?;
+        let transform = Transform::from_row(
+            scale,
+            0.0,
+            0.0,
+            scale,
+            -(l as f32) * scale,
+            -(t as f32) * scale,
+        );
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+        Ok(SvgDetail {
+            request,
+            width,
+            height,
+            pixels: pixmap.take(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wgpu::media::image_data::ImageData;
+
+    /// Red on the left half, blue on the right.
+    fn halves() -> SvgDocument {
+        let svg = br##"<svg xmlns="http://www.w3.org/2000/svg" width="8" height="4">
+            <rect x="0" y="0" width="4" height="4" fill="#ff0000"/>
+            <rect x="4" y="0" width="4" height="4" fill="#0000ff"/>
+        </svg>"##;
+        let tree = usvg::Tree::from_data(svg, &SvgOptions::default()).unwrap();
+        SvgDocument::from_tree(tree).unwrap()
+    }
+
+    #[test]
+    fn buckets_are_powers_of_two_at_or_above_the_zoom() {
+        assert_eq!(bucket_for(0.5), 1);
+        assert_eq!(bucket_for(1.0), 1);
+        assert_eq!(bucket_for(1.01), 2);
+        assert_eq!(bucket_for(3.0), 4);
+        assert_eq!(bucket_for(4.0), 4);
+        assert_eq!(bucket_for(1000.0), MAX_BUCKET);
+        assert_eq!(bucket_for(f32::NAN), 1);
+    }
+
+    #[test]
+    fn a_detail_region_keeps_its_margin_inside_the_document() {
+        let doc = halves();
+        let id = ImageData::new(vec![0; 4], 1, 1).id;
+        assert_eq!(doc.detail_request(id, 1.0, [0.0, 0.0, 8.0, 4.0]), None);
+
+        let request = doc.detail_request(id, 3.0, [2.0, 1.0, 6.0, 3.0]).unwrap();
+        assert_eq!(request.bucket, 4);
+        assert_eq!(request.rect, [1, 0, 7, 4]);
+
+        let request = doc.detail_request(id, 3.0, [-5.0, -5.0, 2.0, 2.0]).unwrap();
+        assert_eq!(request.rect, [0, 0, 3, 3]);
+    }
+
+    #[test]
+    fn a_region_too_large_for_its_bucket_steps_down() {
+        let tree = usvg::Tree::from_data(
+            br#"<svg xmlns="http://www.w3.org/2000/svg" width="2000" height="100"/>"#,
+            &SvgOptions::default(),
+        )
+        .unwrap();
+        let doc = SvgDocument::from_tree(tree).unwrap();
+        let id = ImageData::new(vec![0; 4], 1, 1).id;
+        let request = doc
+            .detail_request(id, 4.0, [0.0, 0.0, 2000.0, 100.0])
+            .unwrap();
+        assert_eq!(request.bucket, 2);
+        assert_eq!(request.rect, [0, 0, 2000, 100]);
+        assert_eq!(
+            doc.detail_request(id, 4.0, [0.0, 0.0, 1.0, 1.0])
+                .unwrap()
+                .bucket,
+            4
+        );
+    }
+
+    #[test]
+    fn a_detail_raster_is_the_region_at_the_bucket_scale() {
+        let doc = halves();
+        let id = ImageData::new(vec![0; 4], 1, 1).id;
+        let detail = doc
+            .render_detail(DetailRequest {
+                image_id: id,
+                bucket: 4,
+                rect: [3, 0, 5, 4],
+            })
+            .unwrap();
+        assert_eq!((detail.width, detail.height), (8, 16));
+        let px = |x: usize| &detail.pixels[x * 4..x * 4 + 4];
+        assert_eq!(px(0), [255, 0, 0, 255]);
+        assert_eq!(px(7), [0, 0, 255, 255]);
+        assert!(detail.covers(4, [3.0, 0.0, 5.0, 4.0]));
+        assert!(!detail.covers(2, [3.0, 0.0, 5.0, 4.0]));
+        assert!(!detail.covers(4, [2.0, 0.0, 5.0, 4.0]));
+    }
+
+    #[test]
+    fn render_scales_the_whole_document() {
+        let doc = halves();
+        let pixels = doc.render(16, 8).unwrap();
+        assert_eq!(pixels.len(), 16 * 8 * 4);
+        assert_eq!(&pixels[7 * 4..8 * 4], [255, 0, 0, 255]);
+        assert_eq!(&pixels[8 * 4..9 * 4], [0, 0, 255, 255]);
+    }
+}
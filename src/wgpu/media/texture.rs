@@ -0,0 +1,706 @@
+//! GPU texture containers: DDS and KTX2.
+//!
+//! A texture file is rarely one picture. It holds a chain of mip levels, each
+//! half the size of the one before, for every array layer and, in a cubemap,
+//! for each of six faces; a volume texture has depth slices too. The view shows
+//! one of those surfaces at a time, chosen by level, layer, face and slice, and
+//! the info panel reports the block-compression format, supercompression and
+//! alpha mode that texture authors check before shipping.
+//!
+//! Both containers store their surfaces back to back in a fixed order, so a
+//! surface is found by arithmetic rather than by walking the file. DDS data is
+//! layer by layer, face by face, then the mip chain, with a volume's slices
+//! inside each level; the sizes come from the pixel format. KTX2 stores level
+//! by level, each level holding every layer, face and slice, and gives each
+//! level's length, so an image's size falls out of a division.
+//!
+//! Decoding goes through the dds crate either way. A DDS surface other than
+//! the first is cut out and given a header of its own, and a BC-compressed KTX2
+//! image is wrapped in a DX10 header the same way. Uncompressed 8-bit KTX2
+//! formats are swizzled directly. UASTC images, Basis Universal's high
+//! quality mode, are inflated like any other level and then transcoded block
+//! by block, at whichever level, layer, face and slice is asked for. ETC1S
+//! (BasisLZ) keeps its codebooks in the file's global data, which the
+//! transcoder here cannot take apart, so it is listed but not decoded, as are
+//! ETC2 and ASTC.
+
+use std::io::{Cursor, Error, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use basis_universal::transcoding::{
+    DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat,
+};
+use dds::{ColorFormat, Decoder as DdsDecoder, ImageViewMut};
+use image::ImageError;
+use ktx2::{ColorModel, DfdBlockBasic, Format, Reader as Ktx2Reader, SupercompressionScheme};
+
+use super::image_data::{ImageData, Source};
+
+/// Cubemap faces in storage order, which both containers share.
+pub const FACES: [(u32, &str); 6] = [
+    (0, "+X"),
+    (1, "-X"),
+    (2, "+Y"),
+    (3, "-Y"),
+    (4, "+Z"),
+    (5, "-Z"),
+];
+
+const DDS_HEADER: usize = 128;
+const DX10_HEADER: usize = 148;
+
+// Header flags, pixel format flags and caps from the DDS specification.
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDSD_DEPTH: u32 = 0x800000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DX10_TEXTURE2D: u32 = 3;
+const DX10_TEXTURE3D: u32 = 4;
+const DX10_TEXTURECUBE: u32 = 0x4;
+
+/// How much storage a surface takes per pixel or per 4x4 block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Footprint {
+    Bits(u32),
+    Block(u32),
+}
+
+impl Footprint {
+    fn surface_len(self, width: u32, height: u32) -> usize {
+        match self {
+            Footprint::Bits(bits) => (width as usize * bits as usize).div_ceil(8) * height as usize,
+            Footprint::Block(bytes) => {
+                width.div_ceil(4) as usize * height.div_ceil(4) as usize * bytes as usize
+            }
+        }
+    }
+
+    fn pitch(self, width: u32) -> usize {
+        match self {
+            Footprint::Bits(bits) => (width as usize * bits as usize).div_ceil(8),
+            Footprint::Block(_) => self.surface_len(width, 4),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Container {
+    Dds {
+        /// Header length, 148 with a DX10 extension and 128 without.
+        data_offset: usize,
+        /// None for a pixel format whose size is not known here, which leaves
+        /// only the first surface readable.
+        footprint: Option<Footprint>,
+    },
+    Ktx2 {
+        format: Option<Format>,
+        supercompression: Option<SupercompressionScheme>,
+        basis: Option<Basis>,
+    },
+}
+
+/// The Basis Universal mode of a KTX2 that has no GPU format of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Basis {
+    Etc1s,
+    Uastc,
+}
+
+/// One surface of a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextureView {
+    pub level: u32,
+    pub layer: u32,
+    pub face: u32,
+    pub slice: u32,
+}
+
+#[derive(Debug)]
+pub struct TextureDocument {
+    path: PathBuf,
+    container: Container,
+    pub kind: &'static str,
+    pub format: String,
+    pub supercompression: String,
+    pub alpha: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub levels: u32,
+    pub layers: u32,
+    pub faces: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextureSelection {
+    pub document: Arc<TextureDocument>,
+    pub view: TextureView,
+}
+
+fn texture_error(msg: impl Into<String>) -> ImageError {
+    ImageError::IoError(Error::other(msg.into()))
+}
+
+fn le32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn put32(bytes: &mut [u8], at: usize, value: u32) {
+    bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+impl TextureDocument {
+    pub fn dds(path: &Path) -> Result<Self, ImageError> {
+        let mut header = [0u8; DX10_HEADER];
+        let mut file = std::fs::File::open(path).map_err(ImageError::IoError)?;
+        let read = file.read(&mut header).map_err(ImageError::IoError)?;
+        if read < DDS_HEADER || &header[..4] != b"DDS " {
+            return Err(texture_error("not a DDS file"));
+        }
+        let flags = le32(&header, 8);
+        let pf_flags = le32(&header, 80);
+        let fourcc = &header[84..88];
+        let caps2 = le32(&header, 112);
+        let dx10 = pf_flags & DDPF_FOURCC != 0 && fourcc == b"DX10";
+        if dx10 && read < DX10_HEADER {
+            return Err(texture_error("truncated DX10 header"));
+        }
+
+        let (format, footprint, alpha, layers, cube, volume) = if dx10 {
+            let dxgi = le32(&header, 128);
+            let (name, footprint) = match dxgi_format(dxgi) {
+                Some((name, footprint)) => (name.to_string(), Some(footprint)),
+                None => (format!("DXGI {dxgi}"), None),
+            };
+            let alpha = match le32(&header, 144) & 0x7 {
+                1 => "Straight",
+                2 => "Premultiplied",
+                3 => "Opaque",
+                4 => "Custom",
+                _ => "Unspecified",
+            };
+            let cube = le32(&header, 136) & DX10_TEXTURECUBE != 0;
+            let volume = le32(&header, 132) == DX10_TEXTURE3D;
+            (
+                name,
+                footprint,
+                alpha,
+                le32(&header, 140).max(1),
+                cube,
+                volume,
+            )
+        } else {
+            let (name, footprint) = if pf_flags & DDPF_FOURCC != 0 {
+                match fourcc_format(fourcc) {
+                    Some((name, footprint)) => (name.to_string(), Some(footprint)),
+                    None => (format!("FourCC {}", String::from_utf8_lossy(fourcc)), None),
+                }
+            } else {
+                let bits = le32(&header, 88);
+                let channels = if pf_flags & DDPF_ALPHAPIXELS != 0 {
+                    "RGBA"
+                } else {
+                    "RGB"
+                };
+                (
+                    format!("{bits}-bit {channels}"),
+                    Some(Footprint::Bits(bits)),
+                )
+            };
+            let alpha = match fourcc {
+                _ if pf_flags & DDPF_FOURCC == 0 && pf_flags & DDPF_ALPHAPIXELS == 0 => "Opaque",
+                b"DXT2" | b"DXT4" => "Premultiplied",
+                b"DXT3" | b"DXT5" => "Straight",
+                _ if pf_flags & DDPF_ALPHAPIXELS != 0 => "Straight",
+                _ => "Unspecified",
+            };
+            let cube = caps2 & DDSCAPS2_CUBEMAP != 0;
+            let volume = caps2 & DDSCAPS2_VOLUME != 0 && flags & DDSD_DEPTH != 0;
+            (name, footprint, alpha, 1, cube, volume)
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            container: Container::Dds {
+                data_offset: if dx10 { DX10_HEADER } else { DDS_HEADER },
+                footprint,
+            },
+            kind: "DDS",
+            format,
+            supercompression: "None".to_string(),
+            alpha,
+            width: le32(&header, 16).max(1),
+            height: le32(&header, 12).max(1),
+            depth: if volume { le32(&header, 24).max(1) } else { 1 },
+            levels: le32(&header, 28).max(1),
+            layers,
+            faces: if cube { 6 } else { 1 },
+        })
+    }
+
+    pub fn ktx2(path: &Path) -> Result<Self, ImageError> {
+        let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
+        let reader = Ktx2Reader::new(&bytes).map_err(|e| texture_error(format!("{e:?}")))?;
+        let header = reader.header();
+        let dfd = reader
+            .dfd_blocks()
+            .find_map(|block| DfdBlockBasic::parse(block.data).ok());
+        let model = dfd.as_ref().and_then(|d| d.header.color_model);
+        let basis = if header.supercompression_scheme == Some(SupercompressionScheme::BasisLZ) {
+            Some(Basis::Etc1s)
+        } else if header.format.is_some() {
+            None
+        } else if model == Some(ColorModel::UASTC) {
+            Some(Basis::Uastc)
+        } else if model == Some(ColorModel::ETC1S) {
+            Some(Basis::Etc1s)
+        } else {
+            None
+        };
+
+        let format = match header.format {
+            Some(f) => {
+                let name = format!("{f:?}");
+                name.strip_suffix("_BLOCK").unwrap_or(&name).to_string()
+            }
+            None if model == Some(ColorModel::ETC1S) => "ETC1S (Basis)".to_string(),
+            None if model == Some(ColorModel::UASTC) => "UASTC (Basis)".to_string(),
+            None => "Unknown".to_string(),
+        };
+        let alpha = match &dfd {
+            Some(d)
+                if d.header
+                    .flags
+                    .contains(ktx2::DataFormatFlags::ALPHA_PREMULTIPLIED) =>
+            {
+                "Premultiplied"
+            }
+            Some(d) => {
+                // ETC1S and ordinary formats mark alpha with channel 15; UASTC
+                // uses its own ids for RGBA and RRRG.
+                let uastc = d.header.color_model == Some(ColorModel::UASTC);
+                let alpha = d
+                    .sample_information()
+                    .any(|s| s.channel_type == 15 || (uastc && matches!(s.channel_type, 3 | 5)));
+                if alpha { "Straight" } else { "Opaque" }
+            }
+            None => "Unspecified",
+        };
+        let supercompression = header
+            .supercompression_scheme
+            .map_or_else(|| "None".to_string(), |s| format!("{s:?}"));
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            container: Container::Ktx2 {
+                format: header.format,
+                supercompression: header.supercompression_scheme,
+                basis,
+            },
+            kind: "KTX2",
+            format,
+            supercompression,
+            alpha,
+            width: header.pixel_width.max(1),
+            height: header.pixel_height.max(1),
+            depth: header.pixel_depth.max(1),
+            levels: header.level_count.max(1),
+            layers: header.layer_count.max(1),
+            faces: header.face_count.max(1),
+        })
+    }
+
+    /// Width, height and depth of a mip level.
+    pub fn level_size(&self, level: u32) -> (u32, u32, u32) {
+        let shrink = |v: u32| (v >> level.min(31)).max(1);
+        (shrink(self.width), shrink(self.height), shrink(self.depth))
+    }
+
+    pub fn open(self: &Arc<Self>) -> Result<ImageData, ImageError> {
+        self.render(TextureView::default())
+    }
+
+    pub fn render(self: &Arc<Self>, view: TextureView) -> Result<ImageData, ImageError> {
+        let slices = self.level_size(view.level).2;
+        if view.level >= self.levels
+            || view.layer >= self.layers
+            || view.face >= self.faces
+            || view.slice >= slices
+        {
+            return Err(texture_error("no such surface"));
+        }
+        let mut data = match &self.container {
+            Container::Dds { .. } if view == TextureView::default() => {
+                let file = std::fs::File::open(&self.path).map_err(ImageError::IoError)?;
+                decode_dds(std::io::BufReader::new(file))?
+            }
+            Container::Dds {
+                data_offset,
+                footprint,
+            } => {
+                let footprint = footprint.ok_or_else(|| {
+                    texture_error(format!(
+                        "{}: only the first surface is readable",
+                        self.format
+                    ))
+                })?;
+                self.dds_surface(*data_offset, footprint, view)?
+            }
+            Container::Ktx2 {
+                format,
+                supercompression,
+                basis,
+            } => self.ktx2_image(*format, *supercompression, *basis, view)?,
+        };
+        data.source = Source::Texture(TextureSelection {
+            document: Arc::clone(self),
+            view,
+        });
+        Ok(data)
+    }
+
+    /// Cuts one surface out of the file and decodes it as a DDS of its own.
+    fn dds_surface(
+        &self,
+        data_offset: usize,
+        footprint: Footprint,
+        view: TextureView,
+    ) -> Result<ImageData, ImageError> {
+        let bytes = std::fs::read(&self.path).map_err(ImageError::IoError)?;
+        let chain: usize = (0..self.levels)
+            .map(|level| {
+                let (w, h, d) = self.level_size(level);
+                footprint.surface_len(w, h) * d as usize
+            })
+            .sum();
+        let before: usize = (0..view.level)
+            .map(|level| {
+                let (w, h, d) = self.level_size(level);
+                footprint.surface_len(w, h) * d as usize
+            })
+            .sum();
+        let (width, height, _) = self.level_size(view.level);
+        let len = footprint.surface_len(width, height);
+        let start = data_offset
+            + (view.layer as usize * self.faces as usize + view.face as usize) * chain
+            + before
+            + view.slice as usize * len;
+        let surface = bytes
+            .get(start..start + len)
+            .ok_or_else(|| texture_error("surface lies past the end of the file"))?;
+
+        let mut single = bytes[..data_offset].to_vec();
+        let flags = le32(&single, 8) & !(DDSD_MIPMAPCOUNT | DDSD_DEPTH);
+        put32(&mut single, 8, flags);
+        put32(&mut single, 12, height);
+        put32(&mut single, 16, width);
+        if flags & DDSD_LINEARSIZE != 0 {
+            put32(&mut single, 20, len as u32);
+        } else if flags & DDSD_PITCH != 0 {
+            put32(&mut single, 20, footprint.pitch(width) as u32);
+        }
+        put32(&mut single, 24, 0);
+        put32(&mut single, 28, 1);
+        put32(&mut single, 108, DDSCAPS_TEXTURE);
+        put32(&mut single, 112, 0);
+        if data_offset == DX10_HEADER {
+            put32(&mut single, 132, DX10_TEXTURE2D);
+            let misc = le32(&single, 136) & !DX10_TEXTURECUBE;
+            put32(&mut single, 136, misc);
+            put32(&mut single, 140, 1);
+        }
+        single.extend_from_slice(surface);
+        decode_dds(Cursor::new(single))
+    }
+
+    fn ktx2_image(
+        &self,
+        format: Option<Format>,
+        supercompression: Option<SupercompressionScheme>,
+        basis: Option<Basis>,
+        view: TextureView,
+    ) -> Result<ImageData, ImageError> {
+        let bytes = std::fs::read(&self.path).map_err(ImageError::IoError)?;
+        let reader = Ktx2Reader::new(&bytes).map_err(|e| texture_error(format!("{e:?}")))?;
+        let level = reader
+            .levels()
+            .nth(view.level as usize)
+            .ok_or_else(|| texture_error("KTX2 is missing a mip level"))?;
+
+        if basis == Some(Basis::Etc1s) {
+            return Err(texture_error(format!(
+                "KTX2: {} is not decoded",
+                self.format
+            )));
+        }
+
+        let raw: Vec<u8> = match supercompression {
+            Some(s) if s == SupercompressionScheme::Zstandard => {
+                zstd::decode_all(level.data).map_err(|e| texture_error(e.to_string()))?
+            }
+            Some(s) if s == SupercompressionScheme::ZLIB => {
+                let mut out = Vec::new();
+                flate2::read::ZlibDecoder::new(level.data)
+                    .read_to_end(&mut out)
+                    .map_err(ImageError::IoError)?;
+                out
+            }
+            _ => level.data.to_vec(),
+        };
+        let (width, height, slices) = self.level_size(view.level);
+        let images = (self.layers * self.faces * slices) as usize;
+        let len = raw.len() / images.max(1);
+        let index = ((view.layer * self.faces + view.face) * slices + view.slice) as usize;
+        let image = raw
+            .get(index * len..(index + 1) * len)
+            .ok_or_else(|| texture_error("KTX2 image lies past the end of its level"))?;
+
+        if basis == Some(Basis::Uastc) {
+            let pixels = transcode_uastc(image, width, height, self.alpha != "Opaque")?;
+            return Ok(ImageData::new(pixels, width, height));
+        }
+        let fmt = format.ok_or_else(|| texture_error("KTX2: missing format field"))?;
+        if let Some(dxgi) = vulkan_bc_to_dxgi(fmt) {
+            return decode_dds(Cursor::new(dx10_dds(dxgi, width, height, image)));
+        }
+        let pixels: Vec<u8> = match fmt {
+            f if f == Format::R8G8B8A8_UNORM || f == Format::R8G8B8A8_SRGB => image.to_vec(),
+            f if f == Format::B8G8R8A8_UNORM || f == Format::B8G8R8A8_SRGB => image
+                .chunks_exact(4)
+                .flat_map(|p| [p[2], p[1], p[0], p[3]])
+                .collect(),
+            f if f == Format::R8G8B8_UNORM || f == Format::R8G8B8_SRGB => image
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            f if f == Format::B8G8R8_UNORM || f == Format::B8G8R8_SRGB => image
+                .chunks_exact(3)
+                .flat_map(|p| [p[2], p[1], p[0], 255])
+                .collect(),
+            _ => {
+                return Err(texture_error(format!(
+                    "KTX2: {} is not decoded",
+                    self.format
+                )));
+            }
+        };
+        Ok(ImageData::new(pixels, width, height))
+    }
+}
+
+fn decode_dds(reader: impl Read) -> Result<ImageData, ImageError> {
+    let mut decoder = DdsDecoder::new(reader).map_err(|e| texture_error(e.to_string()))?;
+    let size = decoder.main_size();
+    let buf_len = ColorFormat::RGBA_U8
+        .buffer_size(size)
+        .ok_or_else(|| texture_error("DDS dimensions overflow"))?;
+    let mut pixels = vec![0u8; buf_len];
+    let view = ImageViewMut::new(&mut pixels, size, ColorFormat::RGBA_U8)
+        .ok_or_else(|| texture_error("DDS buffer size mismatch"))?;
+    decoder
+        .read_surface(view)
+        .map_err(|e| texture_error(e.to_string()))?;
+    Ok(ImageData::new(pixels, size.width, size.height))
+}
+
+/// One UASTC image, 16 bytes per 4x4 block, as RGBA8.
+fn transcode_uastc(
+    image: &[u8],
+    width: u32,
+    height: u32,
+    has_alpha: bool,
+) -> Result<Vec<u8>, ImageError> {
+    let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+    if image.len() < Footprint::Block(16).surface_len(width, height) {
+        return Err(texture_error("KTX2 UASTC image is shorter than its blocks"));
+    }
+    let params = SliceParametersUastc {
+        num_blocks_x: blocks_x,
+        num_blocks_y: blocks_y,
+        has_alpha,
+        original_width: width,
+        original_height: height,
+    };
+    let pixels = LowLevelUastcTranscoder::new()
+        .transcode_slice(
+            image,
+            params,
+            DecodeFlags::HIGH_QUALITY,
+            TranscoderBlockFormat::RGBA32,
+        )
+        .map_err(|e| texture_error(format!("KTX2 UASTC transcode failed: {e:?}")))?;
+    if pixels.len() < width as usize * height as usize * 4 {
+        return Err(texture_error("KTX2 UASTC transcode came back short"));
+    }
+    Ok(pixels)
+}
+
+/// A bare DX10 DDS around one 2D surface.
+fn dx10_dds(dxgi: u32, width: u32, height: u32, surface: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; DX10_HEADER];
+    out[..4].copy_from_slice(b"DDS ");
+    put32(&mut out, 4, 124);
+    // CAPS | HEIGHT | WIDTH | PIXELFORMAT
+    put32(&mut out, 8, 0x1 | 0x2 | 0x4 | 0x1000);
+    put32(&mut out, 12, height);
+    put32(&mut out, 16, width);
+    put32(&mut out, 76, 32);
+    put32(&mut out, 80, DDPF_FOURCC);
+    out[84..88].copy_from_slice(b"DX10");
+    put32(&mut out, 108, DDSCAPS_TEXTURE);
+    put32(&mut out, 128, dxgi);
+    put32(&mut out, 132, DX10_TEXTURE2D);
+    put32(&mut out, 140, 1);
+    out.extend_from_slice(surface);
+    out
+}
+
+fn vulkan_bc_to_dxgi(format: Format) -> Option<u32> {
+    Some(match format.value() {
+        131 | 133 => 71,
+        132 | 134 => 72,
+        135 => 74,
+        136 => 75,
+        137 => 77,
+        138 => 78,
+        139 => 80,
+        140 => 81,
+        141 => 83,
+        142 => 84,
+        143 => 95,
+        144 => 96,
+        145 => 98,
+        146 => 99,
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: u32) -> Option<(&'static str, Footprint)> {
+    use Footprint::{Bits, Block};
+    Some(match format {
+        2 => ("RGBA32 float", Bits(128)),
+        10 => ("RGBA16 float", Bits(64)),
+        11 => ("RGBA16", Bits(64)),
+        24 => ("RGB10A2", Bits(32)),
+        26 => ("RG11B10 float", Bits(32)),
+        28 => ("RGBA8", Bits(32)),
+        29 => ("RGBA8 sRGB", Bits(32)),
+        34 => ("RG16 float", Bits(32)),
+        35 => ("RG16", Bits(32)),
+        41 => ("R32 float", Bits(32)),
+        49 => ("RG8", Bits(16)),
+        54 => ("R16 float", Bits(16)),
+        56 => ("R16", Bits(16)),
+        61 => ("R8", Bits(8)),
+        65 => ("A8", Bits(8)),
+        67 => ("RGB9E5", Bits(32)),
+        71 => ("BC1", Block(8)),
+        72 => ("BC1 sRGB", Block(8)),
+        74 => ("BC2", Block(16)),
+        75 => ("BC2 sRGB", Block(16)),
+        77 => ("BC3", Block(16)),
+        78 => ("BC3 sRGB", Block(16)),
+        80 => ("BC4", Block(8)),
+        81 => ("BC4 signed", Block(8)),
+        83 => ("BC5", Block(16)),
+        84 => ("BC5 signed", Block(16)),
+        85 => ("B5G6R5", Bits(16)),
+        86 => ("BGR5A1", Bits(16)),
+        87 => ("BGRA8", Bits(32)),
+        88 => ("BGRX8", Bits(32)),
+        91 => ("BGRA8 sRGB", Bits(32)),
+        93 => ("BGRX8 sRGB", Bits(32)),
+        95 => ("BC6H", Block(16)),
+        96 => ("BC6H signed", Block(16)),
+        98 => ("BC7", Block(16)),
+        99 => ("BC7 sRGB", Block(16)),
+        115 => ("BGRA4", Bits(16)),
+        _ => return None,
+    })
+}
+
+/// Legacy FourCC codes, and the D3DFORMAT numbers some writers put there.
+fn fourcc_format(code: &[u8]) -> Option<(&'static str, Footprint)> {
+    use Footprint::{Bits, Block};
+    Some(match code {
+        b"DXT1" => ("BC1 (DXT1)", Block(8)),
+        b"DXT2" => ("BC2 (DXT2)", Block(16)),
+        b"DXT3" => ("BC2 (DXT3)", Block(16)),
+        b"DXT4" => ("BC3 (DXT4)", Block(16)),
+        b"DXT5" => ("BC3 (DXT5)", Block(16)),
+        b"ATI1" | b"BC4U" => ("BC4", Block(8)),
+        b"BC4S" => ("BC4 signed", Block(8)),
+        b"ATI2" | b"BC5U" => ("BC5", Block(16)),
+        b"BC5S" => ("BC5 signed", Block(16)),
+        _ => match le32(code, 0) {
+            36 => ("RGBA16", Bits(64)),
+            111 => ("R16 float", Bits(16)),
+            112 => ("RG16 float", Bits(32)),
+            113 => ("RGBA16 float", Bits(64)),
+            114 => ("R32 float", Bits(32)),
+            115 => ("RG32 float", Bits(64)),
+            116 => ("RGBA32 float", Bits(128)),
+            _ => return None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_and_pixel_surfaces_round_up() {
+        assert_eq!(Footprint::Block(8).surface_len(1, 1), 8);
+        assert_eq!(Footprint::Block(16).surface_len(5, 4), 32);
+        assert_eq!(Footprint::Bits(32).surface_len(3, 2), 24);
+        assert_eq!(Footprint::Bits(4).surface_len(3, 1), 2);
+    }
+
+    #[test]
+    fn a_mip_surface_of_a_cubemap_array_is_found_by_offset() {
+        // Two cubes of RGBA8 with a 4x4, 2x2, 1x1 chain, every texel its
+        // surface's number.
+        let mut header = dx10_dds(28, 4, 4, &[]);
+        put32(&mut header, 8, le32(&header, 8) | DDSD_MIPMAPCOUNT);
+        put32(&mut header, 28, 3);
+        put32(&mut header, 136, DX10_TEXTURECUBE);
+        put32(&mut header, 140, 2);
+        let mut file = header;
+        let mut surface = 0u8;
+        for _ in 0..2 * 6 {
+            for side in [4usize, 2, 1] {
+                file.extend(std::iter::repeat_n(surface, side * side * 4));
+                surface += 1;
+            }
+        }
+        let path =
+            std::env::temp_dir().join(format!("bloom-test-texture-{}.dds", std::process::id()));
+        std::fs::write(&path, &file).unwrap();
+
+        let doc = Arc::new(TextureDocument::dds(&path).unwrap());
+        assert_eq!(
+            (doc.levels, doc.layers, doc.faces, doc.format.as_str()),
+            (3, 2, 6, "RGBA8")
+        );
+        let view = TextureView {
+            level: 1,
+            layer: 1,
+            face: 2,
+            slice: 0,
+        };
+        let data = doc.render(view);
+        let _ = std::fs::remove_file(&path);
+        let data = data.unwrap();
+        assert_eq!((data.width, data.height), (2, 2));
+        // Layer 1, face 2 is the ninth cube face, so its chain starts at 24.
+        assert_eq!(data.pixels_snapshot()[0], 25);
+        assert_eq!(data.texture().map(|t| t.view), Some(view));
+    }
+}
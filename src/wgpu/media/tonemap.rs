@@ -0,0 +1,333 @@
+//! Tonemapping: turning scene-linear HDR samples into display values.
+//!
+//! Radiance HDR and OpenEXR images are kept as the linear floats they decode
+//! to, with nothing above 1.0 thrown away. The curve that brings them into the
+//! displayable range runs at the very end instead: in the display shader for
+//! the view, and in export right before quantizing. Both go through the same
+//! operators, written twice (here and in display.wgsl), so the file a user
+//! exports is the picture they were looking at.
+//!
+//! Exposure is an EV offset applied to the linear values before the curve, so
+//! +1 doubles every sample. It is part of the view, not the image: flipping
+//! through a sequence of renders keeps the exposure the user settled on.
+//!
+//! Each operator returns display-linear values in 0..1, which are then sRGB
+//! encoded, so Clamp is a plain linear-to-sRGB conversion of whatever the
+//! exposure leaves below 1.0. ACES is Narkowicz's fit of the RRT and ODT, Hable
+//! is the Uncharted 2 curve with its usual white point of 11.2, and AgX is the
+//! minimal polynomial approximation of Sobotka's default look.
+//!
+//! An image's RGBA8 buffer (cursor readout, histogram, clipboard) is tonemapped
+//! once at load with the default settings, since those readouts have no way to
+//! follow the view's exposure.
+//!
+//! Raw measurements such as a CT's Hounsfield units take the same route with a
+//! window in place of the curve: a straight ramp from black at the bottom of
+//! the window to white at the top, with no sRGB encoding, since the ramp is
+//! already meant as display values. Tone is the choice between the two.
+
+use glam::{Mat3, Vec3};
+use rayon::prelude::*;
+
+use super::icc::srgb_encode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneOperator {
+    Aces,
+    Agx,
+    #[default]
+    Reinhard,
+    Hable,
+    Clamp,
+}
+
+impl ToneOperator {
+    pub const ALL: &[(ToneOperator, &str)] = &[
+        (ToneOperator::Aces, "ACES"),
+        (ToneOperator::Agx, "AgX"),
+        (ToneOperator::Reinhard, "Reinhard"),
+        (ToneOperator::Hable, "Hable"),
+        (ToneOperator::Clamp, "Clamp"),
+    ];
+
+    pub fn name(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(op, _)| *op == self)
+            .map_or("", |(_, name)| name)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(op, _)| *op)
+    }
+
+    /// The operator's number in display.wgsl. Zero there means no tonemapping.
+    pub fn shader_id(self) -> u32 {
+        match self {
+            ToneOperator::Aces => 1,
+            ToneOperator::Agx => 2,
+            ToneOperator::Reinhard => 3,
+            ToneOperator::Hable => 4,
+            ToneOperator::Clamp => 5,
+        }
+    }
+}
+
+pub const EXPOSURE_MIN: f32 = -10.0;
+pub const EXPOSURE_MAX: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Tonemap {
+    pub operator: ToneOperator,
+    /// EV offset applied before the operator.
+    pub exposure: f32,
+}
+
+impl Tonemap {
+    pub fn scale(&self) -> f32 {
+        self.exposure.exp2()
+    }
+
+    /// Maps one scene-linear color to sRGB-encoded display values in 0..1.
+    pub fn map(&self, rgb: Vec3) -> Vec3 {
+        let c = (rgb * self.scale()).max(Vec3::ZERO);
+        let display = match self.operator {
+            ToneOperator::Aces => aces(c),
+            ToneOperator::Agx => agx(c),
+            ToneOperator::Reinhard => reinhard(c),
+            ToneOperator::Hable => hable(c),
+            ToneOperator::Clamp => c,
+        };
+        display.clamp(Vec3::ZERO, Vec3::ONE).map(srgb_encode)
+    }
+
+    /// Tonemaps RGBA samples in place. Alpha is only clamped.
+    pub fn apply(&self, samples: &mut [f32]) {
+        samples.par_chunks_mut(4).for_each(|p| {
+            let c = self.map(Vec3::new(p[0], p[1], p[2]));
+            p[..3].copy_from_slice(&c.to_array());
+            p[3] = p[3].clamp(0.0, 1.0);
+        });
+    }
+
+    pub fn to_rgba8(&self, samples: &[f32]) -> Vec<u8> {
+        Tone::Map(*self).to_rgba8(samples)
+    }
+}
+
+/// A DICOM-style VOI window over data values, `width` wide around `center`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub center: f32,
+    pub width: f32,
+    /// Low values are white, as in a MONOCHROME1 image.
+    pub invert: bool,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self {
+            center: 0.5,
+            width: 1.0,
+            invert: false,
+        }
+    }
+}
+
+impl Window {
+    pub const MIN_WIDTH: f32 = 1e-3;
+
+    /// The window as `v * scale + offset`, before clamping.
+    pub fn linear(&self) -> (f32, f32) {
+        let scale = 1.0 / self.width.max(Self::MIN_WIDTH);
+        let offset = 0.5 - self.center * scale;
+        if self.invert {
+            (-scale, 1.0 - offset)
+        } else {
+            (scale, offset)
+        }
+    }
+
+    pub fn map(&self, v: f32) -> f32 {
+        let (scale, offset) = self.linear();
+        (v * scale + offset).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tone {
+    Map(Tonemap),
+    Window(Window),
+}
+
+impl Tone {
+    /// Brings RGBA samples into display values in place. Alpha is only
+    /// clamped.
+    pub fn apply(&self, samples: &mut [f32]) {
+        match self {
+            Tone::Map(tone) => tone.apply(samples),
+            Tone::Window(window) => samples.par_chunks_mut(4).for_each(|p| {
+                for c in &mut p[..3] {
+                    *c = window.map(*c);
+                }
+                p[3] = p[3].clamp(0.0, 1.0);
+            }),
+        }
+    }
+
+    pub fn to_rgba8(&self, samples: &[f32]) -> Vec<u8> {
+        let mut mapped = samples.to_vec();
+        self.apply(&mut mapped);
+        mapped
+            .into_par_iter()
+            .map(|v| (v * 255.0).round() as u8)
+            .collect()
+    }
+}
+
+fn reinhard(c: Vec3) -> Vec3 {
+    let luma = c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+    if luma <= 0.0 {
+        return c;
+    }
+    c * (1.0 / (1.0 + luma))
+}
+
+fn aces(c: Vec3) -> Vec3 {
+    let x = c * 0.6;
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn hable_partial(x: Vec3) -> Vec3 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+fn hable(c: Vec3) -> Vec3 {
+    const WHITE: f32 = 11.2;
+    hable_partial(c * 2.0) / hable_partial(Vec3::splat(WHITE))
+}
+
+const AGX_IN: Mat3 = Mat3::from_cols_array(&[
+    0.8424791, 0.04232824, 0.04237565, 0.0784336, 0.8784686, 0.0784336, 0.07922375, 0.07916613,
+    0.879143,
+]);
+
+const AGX_OUT: Mat3 = Mat3::from_cols_array(&[
+    1.196879,
+    -0.05289685,
+    -0.05297164,
+    -0.09802088,
+    1.151903,
+    -0.09804345,
+    -0.09902974,
+    -0.09896118,
+    1.151074,
+]);
+
+const AGX_MIN_EV: f32 = -12.473_93;
+const AGX_MAX_EV: f32 = 4.026_069;
+
+fn agx(c: Vec3) -> Vec3 {
+    let v = (AGX_IN * c).max(Vec3::splat(1e-10));
+    let v = v
+        .map(f32::log2)
+        .clamp(Vec3::splat(AGX_MIN_EV), Vec3::splat(AGX_MAX_EV));
+    let x = (v - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+    (AGX_OUT * curve).max(Vec3::ZERO).powf(2.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every() -> impl Iterator<Item = Tonemap> {
+        ToneOperator::ALL.iter().map(|&(operator, _)| Tonemap {
+            operator,
+            exposure: 0.0,
+        })
+    }
+
+    #[test]
+    fn operators_stay_in_range_and_rise_with_light() {
+        for t in every() {
+            let mut last = -1.0;
+            for i in 0..200 {
+                let v = t.map(Vec3::splat(i as f32 * 0.25)).x;
+                assert!(
+                    (0.0..=1.0).contains(&v),
+                    "{:?} left 0..1 at {i}",
+                    t.operator
+                );
+                assert!(v >= last - 1e-4, "{:?} is not monotonic at {i}", t.operator);
+                last = v;
+            }
+        }
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for t in every() {
+            let v = t.map(Vec3::ZERO);
+            assert!(
+                v.max_element() < 0.01,
+                "{:?} lifted black to {v}",
+                t.operator
+            );
+        }
+    }
+
+    #[test]
+    fn highlights_above_one_keep_their_detail() {
+        for t in every().filter(|t| t.operator != ToneOperator::Clamp) {
+            let a = t.map(Vec3::splat(2.0)).x;
+            let b = t.map(Vec3::splat(4.0)).x;
+            assert!(b > a, "{:?} flattened highlights", t.operator);
+        }
+    }
+
+    #[test]
+    fn one_ev_is_a_doubling() {
+        let base = Tonemap {
+            operator: ToneOperator::Clamp,
+            exposure: 0.0,
+        };
+        let up = Tonemap {
+            exposure: 1.0,
+            ..base
+        };
+        assert_eq!(up.map(Vec3::splat(0.1)), base.map(Vec3::splat(0.2)));
+    }
+
+    #[test]
+    fn a_window_spans_black_to_white_and_inverts() {
+        let brain = Window {
+            center: 40.0,
+            width: 80.0,
+            invert: false,
+        };
+        assert_eq!(brain.map(-1000.0), 0.0);
+        assert_eq!(brain.map(0.0), 0.0);
+        assert_eq!(brain.map(40.0), 0.5);
+        assert_eq!(brain.map(80.0), 1.0);
+        let inverted = Window {
+            invert: true,
+            ..brain
+        };
+        assert_eq!(inverted.map(0.0), 1.0);
+        assert_eq!(inverted.map(60.0), 1.0 - brain.map(60.0));
+    }
+}
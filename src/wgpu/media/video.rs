@@ -45,6 +45,9 @@ const FRAME_BUFFER_MIN: usize = 8;
 const FRAME_BUFFER_MAX: usize = 48;
 const FRAME_BUFFER_BUDGET_BYTES: usize = 384 * 1024 * 1024;
 
+const SEQUENCE_BUDGET_BYTES: usize = 1024 * 1024 * 1024;
+const SEQUENCE_DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
 fn frame_buffer_len(width: u32, height: u32) -> usize {
     let frame_bytes = (width as usize * height as usize * 4).max(1);
     let by_budget = FRAME_BUFFER_BUDGET_BYTES / frame_bytes;
@@ -554,6 +557,139 @@ pub fn probe_video(path: &Path) -> Result<VideoInfo, ImageError> {
     })
 }
 
+/// Every frame of a short clip, decoded up front, with how long each shows.
+/// For image sequences (AVIF, HEIF) rather than video: stops once the frames
+/// reach SEQUENCE_BUDGET_BYTES.
+pub fn decode_sequence(path: &Path) -> Result<Vec<(ImageData, Duration)>, ImageError> {
+    init_ffmpeg()?;
+    let mut ictx = ffmpeg::format::input(path).map_err(err)?;
+
+    let stream = ictx
+        .streams()
+        .best(ffmpeg::media::Type::Video)
+        .ok_or_else(|| err("no video stream"))?;
+    let video_index = stream.index();
+    let time_base = stream.time_base();
+    let tb = if time_base.denominator() != 0 {
+        time_base.numerator() as f64 / time_base.denominator() as f64
+    } else {
+        0.0
+    };
+
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())
+        .map_err(err)?
+        .decoder()
+        .video()
+        .map_err(err)?;
+    let mut scaler = Scaler::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGBA,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )
+    .map_err(err)?;
+
+    let frame_bytes = (decoder.width() as usize * decoder.height() as usize * 4).max(1);
+    let max_frames = (SEQUENCE_BUDGET_BYTES / frame_bytes).max(1);
+    let mut decoded = ffmpeg::frame::Video::empty();
+    let mut rgba = ffmpeg::frame::Video::empty();
+    let mut frames: Vec<(ImageData, Duration)> = Vec::new();
+
+    let mut receive = |decoder: &mut ffmpeg::decoder::Video,
+                       frames: &mut Vec<(ImageData, Duration)>|
+     -> Result<(), ImageError> {
+        while frames.len() < max_frames && decoder.receive_frame(&mut decoded).is_ok() {
+            let pts = ts_to_duration(decoded.pts().or_else(|| decoded.timestamp()), tb);
+            frames.push((frame_to_image(&mut scaler, &decoded, &mut rgba)?, pts));
+        }
+        Ok(())
+    };
+    for (stream, packet) in ictx.packets() {
+        if frames.len() >= max_frames {
+            break;
+        }
+        if stream.index() == video_index {
+            decoder.send_packet(&packet).map_err(err)?;
+            receive(&mut decoder, &mut frames)?;
+        }
+    }
+    let _ = decoder.send_eof();
+    receive(&mut decoder, &mut frames)?;
+
+    // Each frame shows until the next one's timestamp. The last, or any
+    // without a usable gap, gets SEQUENCE_DEFAULT_DELAY.
+    let starts: Vec<Duration> = frames.iter().map(|(_, pts)| *pts).collect();
+    for (i, (_, delay)) in frames.iter_mut().enumerate() {
+        *delay = starts
+            .get(i + 1)
+            .map(|next| next.saturating_sub(starts[i]))
+            .filter(|d| !d.is_zero())
+            .unwrap_or(SEQUENCE_DEFAULT_DELAY);
+    }
+    Ok(frames)
+}
+
+/// The first frame of a video, scaled to fit a `max_side` square and carrying
+/// the stream's rotation as an EXIF orientation.
+pub fn poster_frame(path: &Path, max_side: u32) -> Result<ImageData, ImageError> {
+    init_ffmpeg()?;
+    let mut ictx = ffmpeg::format::input(path).map_err(err)?;
+
+    let stream = ictx
+        .streams()
+        .best(ffmpeg::media::Type::Video)
+        .ok_or_else(|| err("no video stream"))?;
+    let video_index = stream.index();
+    let rotation = stream_rotation(&stream);
+
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())
+        .map_err(err)?
+        .decoder()
+        .video()
+        .map_err(err)?;
+    let (width, height) = (decoder.width().max(1), decoder.height().max(1));
+    let scale = (max_side as f64 / width.max(height) as f64).min(1.0);
+    let mut scaler = Scaler::get(
+        decoder.format(),
+        width,
+        height,
+        Pixel::RGBA,
+        ((width as f64 * scale) as u32).max(1),
+        ((height as f64 * scale) as u32).max(1),
+        Flags::AREA,
+    )
+    .map_err(err)?;
+
+    let mut decoded = ffmpeg::frame::Video::empty();
+    let mut rgba = ffmpeg::frame::Video::empty();
+    let mut got = false;
+    for (stream, packet) in ictx.packets() {
+        if stream.index() == video_index {
+            decoder.send_packet(&packet).map_err(err)?;
+            if decoder.receive_frame(&mut decoded).is_ok() {
+                got = true;
+                break;
+            }
+        }
+    }
+    if !got {
+        let _ = decoder.send_eof();
+        decoder.receive_frame(&mut decoded).map_err(err)?;
+    }
+
+    let mut image = frame_to_image(&mut scaler, &decoded, &mut rgba)?;
+    image.exif.orientation = Some(match rotation {
+        1 => 6,
+        2 => 3,
+        3 => 8,
+        _ => 1,
+    });
+    Ok(image)
+}
+
 fn codec_label(params: &ffmpeg::codec::Parameters) -> Option<String> {
     let name = params.id().name();
     if name.is_empty() {
@@ -982,7 +1118,7 @@ fn build_frame(
     pts: Duration,
     epoch: u64,
 ) -> Result<VideoFrame, ImageError> {
-    let data = frame_to_image(scaler, decoded, rgba)?;
+    let data = Arc::new(frame_to_image(scaler, decoded, rgba)?);
     Ok(VideoFrame { data, pts, epoch })
 }
 
@@ -990,7 +1126,7 @@ fn frame_to_image(
     scaler: &mut Scaler,
     frame: &ffmpeg::frame::Video,
     rgba: &mut ffmpeg::frame::Video,
-) -> Result<Arc<ImageData>, ImageError> {
+) -> Result<ImageData, ImageError> {
     scaler.run(frame, rgba).map_err(err)?;
 
     let width = rgba.width();
@@ -1010,5 +1146,5 @@ fn frame_to_image(
         pixels
     };
 
-    Ok(Arc::new(ImageData::new(pixels, width, height)))
+    Ok(ImageData::new(pixels, width, height))
 }
@@ -929,6 +929,7 @@ fn golden_drawing_multi_tile() {
         source: ExportSource::Frames {
             frames: vec![ExportFrame {
                 pixels: std::sync::Arc::new(pixels),
+                deep: None,
                 delay: std::time::Duration::ZERO,
             }],
             still_index: 0,
@@ -937,6 +938,9 @@ fn golden_drawing_multi_tile() {
         height: GOLDEN_H,
         modifiers: chain,
         rotation: 0,
+        mirror: false,
+        profile: None,
+        tone: None,
         trim: None,
     };
     let (_, _, cpu_img) = render_still_rgba(&data).expect("render");
@@ -378,6 +378,10 @@ impl ModifierPipeline {
         self.reprocess_pending
     }
 
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
     pub fn doc_size(&self) -> (u32, u32) {
         self.doc_size
     }
@@ -1,13 +1,52 @@
 use iced::wgpu::{
-    BindGroup, BindGroupLayout, BlendState, Buffer, Device, PrimitiveTopology, RenderPass,
-    RenderPipeline, Sampler, ShaderStages, TextureFormat, TextureView,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferBindingType, Device,
+    PrimitiveTopology, Queue, RenderPass, RenderPipeline, Sampler, ShaderStages, TextureFormat,
+    TextureView,
 };
 
 use crate::wgpu::gpu;
+use crate::wgpu::media::tonemap::Tone;
+
+/// The tonemap every tile is drawn with, shared across tiles in its own bind
+/// group so changing exposure is one buffer write rather than one per tile.
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct ToneUniforms {
+    pub operator: u32,
+    pub scale: f32,
+    pub offset: f32,
+    pub _pad: f32,
+}
+
+/// display.wgsl's operator number for a window.
+const WINDOW_OPERATOR: u32 = 6;
+
+impl ToneUniforms {
+    pub fn of(tone: Option<Tone>) -> Self {
+        let (operator, scale, offset) = match tone {
+            None => (0, 1.0, 0.0),
+            Some(Tone::Map(t)) => (t.operator.shader_id(), t.scale(), 0.0),
+            Some(Tone::Window(w)) => {
+                let (scale, offset) = w.linear();
+                (WINDOW_OPERATOR, scale, offset)
+            }
+        };
+        Self {
+            operator,
+            scale,
+            offset,
+            _pad: 0.0,
+        }
+    }
+}
 
 pub struct DisplayPass {
     pipeline: RenderPipeline,
     bind_group_layout: BindGroupLayout,
+    tone_buffer: Buffer,
+    tone_bind_group: BindGroup,
+    last_tone: Option<ToneUniforms>,
 }
 
 impl DisplayPass {
@@ -17,19 +56,45 @@ impl DisplayPass {
             ShaderStages::VERTEX_FRAGMENT,
             Some("display-bgl"),
         );
-        let pipeline = gpu::fullscreen_pipeline(
+        let tone_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("display-tone-bgl"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline = gpu::fullscreen_pipeline_layouts(
             device,
             include_str!("../shaders/display.wgsl"),
             Some("display-pipeline"),
             PrimitiveTopology::TriangleStrip,
             format,
             BlendState::ALPHA_BLENDING,
-            &bind_group_layout,
+            &[&bind_group_layout, &tone_layout],
         );
 
+        let tone_buffer = gpu::uniform_buffer::<ToneUniforms>(device, Some("display-tone"));
+        let tone_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("display-tone-bg"),
+            layout: &tone_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: tone_buffer.as_entire_binding(),
+            }],
+        });
+
         Self {
             pipeline,
             bind_group_layout,
+            tone_buffer,
+            tone_bind_group,
+            last_tone: None,
         }
     }
 
@@ -51,9 +116,20 @@ impl DisplayPass {
         )
     }
 
+    /// None draws samples as they are, which is right for anything that is
+    /// neither scene-linear nor raw data.
+    pub fn set_tone(&mut self, queue: &Queue, tone: Option<Tone>) {
+        let uniforms = ToneUniforms::of(tone);
+        if self.last_tone != Some(uniforms) {
+            gpu::write_uniform(queue, &self.tone_buffer, &uniforms);
+            self.last_tone = Some(uniforms);
+        }
+    }
+
     pub fn draw<'a>(&'a self, pass: &mut RenderPass<'a>, bind_group: &'a BindGroup) {
         pass.set_pipeline(&self.pipeline);
         pass.set_bind_group(0, bind_group, &[]);
+        pass.set_bind_group(1, &self.tone_bind_group, &[]);
         pass.draw(0..4, 0..1);
     }
 }
@@ -247,5 +247,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         c = apply_entry(u.entries[i], full_uv, c);
     }
 
-    return clamp(c, vec4<f32>(0.0), vec4<f32>(1.0));
+    // Color is left unclamped so a float target keeps HDR values for the
+    // display pass's tonemap; an 8-bit target clamps on write anyway.
+    return vec4<f32>(c.rgb, clamp(c.a, 0.0, 1.0));
 }
@@ -8,9 +8,19 @@ struct VertexOutput {
     @location(0) uv: vec2<f32>,
 };
 
+// Mirrors tonemap.rs. operator 0 passes samples through untouched, and 6 is a
+// window: a clamped ramp with offset, already in display values.
+struct ToneUniforms {
+    operator: u32,
+    scale: f32,
+    offset: f32,
+    _pad: f32,
+};
+
 @group(0) @binding(0) var<uniform> u: DisplayUniforms;
 @group(0) @binding(1) var t_image: texture_2d<f32>;
 @group(0) @binding(2) var s_image: sampler;
+@group(1) @binding(0) var<uniform> tone: ToneUniforms;
 
 @vertex
 fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
@@ -27,8 +37,82 @@ fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
     return out;
 }
 
+fn srgb_encode(c: vec3<f32>) -> vec3<f32> {
+    let v = clamp(c, vec3<f32>(0.0), vec3<f32>(1.0));
+    let lo = v * 12.92;
+    let hi = 1.055 * pow(v, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(hi, lo, v <= vec3<f32>(0.0031308));
+}
+
+fn reinhard(c: vec3<f32>) -> vec3<f32> {
+    let luma = dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return c / (1.0 + max(luma, 0.0));
+}
+
+fn aces(c: vec3<f32>) -> vec3<f32> {
+    let x = c * 0.6;
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+fn hable_partial(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+fn hable(c: vec3<f32>) -> vec3<f32> {
+    return hable_partial(c * 2.0) / hable_partial(vec3<f32>(11.2));
+}
+
+fn agx(c: vec3<f32>) -> vec3<f32> {
+    let agx_in = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104
+    );
+    let agx_out = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let v = clamp(log2(max(agx_in * c, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    let x = (v - min_ev) / (max_ev - min_ev);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+        + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    return pow(max(agx_out * curve, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn tonemap(c: vec4<f32>) -> vec4<f32> {
+    if tone.operator == 0u {
+        return c;
+    }
+    if tone.operator == 6u {
+        let v = clamp(c.rgb * tone.scale + tone.offset, vec3<f32>(0.0), vec3<f32>(1.0));
+        return vec4<f32>(v, clamp(c.a, 0.0, 1.0));
+    }
+    let lin = max(c.rgb * tone.scale, vec3<f32>(0.0));
+    var mapped = lin;
+    switch tone.operator {
+        case 1u: { mapped = aces(lin); }
+        case 2u: { mapped = agx(lin); }
+        case 3u: { mapped = reinhard(lin); }
+        case 4u: { mapped = hable(lin); }
+        default: {}
+    }
+    return vec4<f32>(srgb_encode(mapped), clamp(c.a, 0.0, 1.0));
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let uv = u.crop_uv.xy + in.uv * (u.crop_uv.zw - u.crop_uv.xy);
-    return textureSample(t_image, s_image, uv);
+    return tonemap(textureSample(t_image, s_image, uv));
 }
\ No newline at end of file
@@ -10,22 +10,50 @@
 //! when mips_dirty is set. Level count is derived from the tile's own
 //! dimensions, since asking for more levels than a tile can hold is a crash.
 //!
+//! A source with 16-bit or float samples is uploaded at that precision when the
+//! device can sample it (Rgba16Unorm and a filterable Rgba32Float are both
+//! optional features), and as Rgba16Float otherwise. That format needs its own
+//! mip blit pipeline, so such a source carries one instead of borrowing the
+//! shared RGBA8 pipeline.
+//!
 //! TileGeom is the part of a tile the geometry path reads: its position, its
 //! extent, and the region worth processing. A Tile also owns a texture, three
 //! bind groups and a buffer, so it can only exist with a live device, which put
 //! the rect math out of reach of any test. Splitting the plain data out is what
 //! lets the reuse decision in modifier_pipeline::geom be driven without one.
+//!
+//! A vector source can also hold detail rasters: part of the image rendered
+//! finer than its native size, drawn over the tiles when zoomed in (see
+//! media/svg.rs). They are cached one per zoom bucket, so zooming back to a
+//! bucket already seen shows its raster again without another upload. Only
+//! DETAIL_BUCKETS are kept, the least recently shown dropped first.
+//!
+//! A streamed image (see media/stream.rs) has no pixels to upload up front.
+//! Its tiles start out empty and take the decoded bands as they arrive, a few
+//! per frame, each band written straight into every tile it crosses. A tile's
+//! mipmaps are built when its last row lands, so zoomed out the picture fills
+//! in a row of tiles at a time.
+
+use std::borrow::Cow;
+use std::sync::mpsc::{Receiver, TryRecvError};
 
+use bytemuck::cast_slice;
 use glam::{Mat4, Vec2};
 use iced::wgpu::{
-    BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, Extent3d, Queue, RenderPipeline,
-    Sampler, TexelCopyBufferLayout, Texture, TextureFormat, TextureUsages, TextureView,
+    BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, Extent3d, Features, Origin3d,
+    Queue, RenderPipeline, Sampler, TexelCopyBufferLayout, TexelCopyTextureInfo, Texture,
+    TextureFormat, TextureUsages, TextureView,
 };
 
 use crate::wgpu::{
     error::ViewError,
     gpu,
-    media::image_data::{ImageData, ImageId},
+    media::{
+        image_data::{ImageData, ImageId},
+        samples::{DeepPixels, SampleFormat},
+        stream::Band,
+        svg::{DETAIL_BUCKETS, DetailRequest, SvgDetail},
+    },
     passes::display::DisplayPass,
     view_pipeline::DisplayUniforms,
 };
@@ -89,14 +117,82 @@ impl Tile {
     }
 }
 
+pub struct DetailTile {
+    pub request: DetailRequest,
+    _texture: Texture,
+    pub uniform_buffer: Buffer,
+    pub bind_group: BindGroup,
+    pub last_transform: Option<Mat4>,
+    pub last_ndc_rect: Option<(Vec2, Vec2)>,
+}
+
+impl DetailTile {
+    /// The region this raster covers, in source pixels.
+    pub fn rect(&self) -> [f32; 4] {
+        self.request.rect.map(|v| v as f32)
+    }
+}
+
 pub struct TiledSource {
     pub tiles: Vec<Tile>,
+    /// Most recently shown last. The last one is drawn when detail_shown is set.
+    pub details: Vec<DetailTile>,
+    pub detail_shown: bool,
     pub image_id: ImageId,
     pub full_width: u32,
     pub full_height: u32,
     pub physical_scale: f32,
     pub has_mipmaps: bool,
     pub mips_dirty: bool,
+    pub format: TextureFormat,
+    samples: Option<SampleFormat>,
+    deep_blit: Option<(RenderPipeline, BindGroupLayout)>,
+    /// Bands of a streamed image still to come.
+    inflow: Option<Receiver<Result<Band, String>>>,
+    /// How far down the bands have reached.
+    streamed_to: u32,
+}
+
+/// Bands of a streamed image written per frame at most, so a fast decoder
+/// cannot stall the frame it lands in.
+const BANDS_PER_FRAME: usize = 2;
+
+/// The texture format a source is uploaded in.
+pub fn upload_format(samples: Option<SampleFormat>, features: Features) -> TextureFormat {
+    match samples {
+        None => TextureFormat::Rgba8Unorm,
+        Some(SampleFormat::U16) if features.contains(Features::TEXTURE_FORMAT_16BIT_NORM) => {
+            TextureFormat::Rgba16Unorm
+        }
+        Some(SampleFormat::F32) if features.contains(Features::FLOAT32_FILTERABLE) => {
+            TextureFormat::Rgba32Float
+        }
+        Some(_) => TextureFormat::Rgba16Float,
+    }
+}
+
+fn upload_bytes<'a>(
+    image: &ImageData,
+    pixels: &'a [u8],
+    deep: Option<&'a DeepPixels>,
+    format: TextureFormat,
+) -> Result<Cow<'a, [u8]>, ViewError> {
+    let bytes: Cow<'a, [u8]> = match (deep, format) {
+        (None, _) => Cow::Borrowed(pixels),
+        (Some(DeepPixels::U16(s)), TextureFormat::Rgba16Unorm) => Cow::Borrowed(cast_slice(s)),
+        (Some(DeepPixels::F16(s)), TextureFormat::Rgba16Float) => Cow::Borrowed(cast_slice(s)),
+        (Some(DeepPixels::F32(s)), TextureFormat::Rgba32Float) => Cow::Borrowed(cast_slice(s)),
+        (Some(d), _) => Cow::Owned(cast_slice(&d.to_f16()).to_vec()),
+    };
+    let bpp = format.block_copy_size(None).unwrap_or(4) as usize;
+    let expected = image.size_bytes() / 4 * bpp;
+    if bytes.len() < expected {
+        return Err(ViewError::ImageDataMismatch {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+    Ok(bytes)
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -108,17 +204,18 @@ fn write_tile_texture(
     width: u32,
     height: u32,
     full_width: u32,
+    bpp: u32,
     image_pixels: &[u8],
     scratch: &mut Vec<u8>,
 ) {
-    let src_stride = (full_width * 4) as usize;
+    let src_stride = (full_width * bpp) as usize;
     if width == full_width {
         queue.write_texture(
             texture.as_image_copy(),
             image_pixels,
             TexelCopyBufferLayout {
                 offset: (y as usize * src_stride) as u64,
-                bytes_per_row: Some(width * 4),
+                bytes_per_row: Some(width * bpp),
                 rows_per_image: None,
             },
             Extent3d {
@@ -128,10 +225,10 @@ fn write_tile_texture(
             },
         );
     } else {
-        let row_bytes = (width * 4) as usize;
+        let row_bytes = (width * bpp) as usize;
         scratch.clear();
         for r in 0..height {
-            let row_start = (y + r) as usize * src_stride + x as usize * 4;
+            let row_start = (y + r) as usize * src_stride + (x * bpp) as usize;
             scratch.extend_from_slice(&image_pixels[row_start..row_start + row_bytes]);
         }
         queue.write_texture(
@@ -139,7 +236,7 @@ fn write_tile_texture(
             scratch,
             TexelCopyBufferLayout {
                 offset: 0,
-                bytes_per_row: Some(width * 4),
+                bytes_per_row: Some(width * bpp),
                 rows_per_image: None,
             },
             Extent3d {
@@ -167,6 +264,7 @@ fn regen_tile_mipmaps(
     queue: &Queue,
     texture: &Texture,
     mip_count: u32,
+    format: TextureFormat,
     blit_pipeline: &RenderPipeline,
     blit_bgl: &BindGroupLayout,
     linear_sampler: &Sampler,
@@ -179,7 +277,7 @@ fn regen_tile_mipmaps(
         device,
         texture,
         mip_count,
-        TextureFormat::Rgba8Unorm,
+        format,
         blit_pipeline,
         blit_bgl,
         linear_sampler,
@@ -203,20 +301,27 @@ impl TiledSource {
         tile_dim: Option<u32>,
     ) -> Result<Self, ViewError> {
         let image_pixels = image.pixels_snapshot();
-
-        if image_pixels.len() < image.size_bytes() {
-            return Err(ViewError::ImageDataMismatch {
-                expected: image.size_bytes(),
-                actual: image_pixels.len(),
-            });
-        }
+        let deep = image.deep_snapshot();
+        let samples = deep.as_ref().map(|d| d.format());
+        let format = upload_format(samples, device.features());
+        let upload = match image.stream() {
+            Some(_) => None,
+            None => Some(upload_bytes(image, &image_pixels, deep.as_deref(), format)?),
+        };
+        let bpp = format.block_copy_size(None).unwrap_or(4);
+
+        let deep_blit = (mipmap_zoom_out && format != TextureFormat::Rgba8Unorm)
+            .then(|| gpu::blit_pipeline(device, format));
+        let (blit_pipeline, blit_bgl) = deep_blit
+            .as_ref()
+            .map_or((blit_pipeline, blit_bgl), |(p, l)| (p, l));
 
         let limit = device.limits().max_texture_dimension_2d;
         let max_dim = tile_dim.map_or(limit, |d| d.clamp(1, limit));
         let cols = image.width.div_ceil(max_dim);
         let rows = image.height.div_ceil(max_dim);
 
-        let max_tile_bytes = (max_dim * max_dim * 4) as usize;
+        let max_tile_bytes = (max_dim * max_dim * bpp) as usize;
         let mut tile_pixels = Vec::with_capacity(max_tile_bytes);
 
         let mut tiles = Vec::with_capacity((cols * rows) as usize);
@@ -240,7 +345,7 @@ impl TiledSource {
                     tw,
                     th,
                     mip_count,
-                    TextureFormat::Rgba8Unorm,
+                    format,
                     if mipmap_zoom_out {
                         TextureUsages::TEXTURE_BINDING
                             | TextureUsages::COPY_DST
@@ -254,24 +359,28 @@ impl TiledSource {
                     Some(&format!("{label}:source")),
                 );
 
-                write_tile_texture(
-                    queue,
-                    &source_texture,
-                    tx,
-                    ty,
-                    tw,
-                    th,
-                    image.width,
-                    image_pixels.as_slice(),
-                    &mut tile_pixels,
-                );
+                if let Some(upload) = &upload {
+                    write_tile_texture(
+                        queue,
+                        &source_texture,
+                        tx,
+                        ty,
+                        tw,
+                        th,
+                        image.width,
+                        bpp,
+                        upload,
+                        &mut tile_pixels,
+                    );
+                }
 
-                if mipmap_zoom_out && mip_count > 1 {
+                if upload.is_some() && mipmap_zoom_out && mip_count > 1 {
                     regen_tile_mipmaps(
                         device,
                         queue,
                         &source_texture,
                         mip_count,
+                        format,
                         blit_pipeline,
                         blit_bgl,
                         linear_sampler,
@@ -334,12 +443,19 @@ impl TiledSource {
 
         Ok(TiledSource {
             tiles,
+            details: Vec::new(),
+            detail_shown: false,
             image_id: image.id,
             full_width: image.width,
             full_height: image.height,
             physical_scale: 1.0,
             has_mipmaps: mipmap_zoom_out,
             mips_dirty: false,
+            format,
+            samples,
+            deep_blit,
+            inflow: image.stream().map(|stream| stream.bands()),
+            streamed_to: 0,
         })
     }
 
@@ -347,6 +463,137 @@ impl TiledSource {
         self.full_width == image.width
             && self.full_height == image.height
             && self.has_mipmaps == mipmap_zoom_out
+            && self.samples == image.sample_format()
+            && image.stream().is_none()
+    }
+
+    pub fn streaming(&self) -> bool {
+        self.inflow.is_some()
+    }
+
+    /// Writes the bands a streamed image has decoded since the last call,
+    /// returning whether any arrived.
+    pub fn receive(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        blit_pipeline: &RenderPipeline,
+        blit_bgl: &BindGroupLayout,
+        linear_sampler: &Sampler,
+    ) -> Result<bool, String> {
+        let Some(bands) = &self.inflow else {
+            return Ok(false);
+        };
+        let (blit_pipeline, blit_bgl) = self.blit(blit_pipeline, blit_bgl);
+        let mut outcome = Ok(false);
+        let mut finished = false;
+        let mut streamed_to = self.streamed_to;
+        for _ in 0..BANDS_PER_FRAME {
+            match bands.try_recv() {
+                Ok(Ok(band)) => {
+                    self.write_band(
+                        device,
+                        queue,
+                        &band,
+                        blit_pipeline,
+                        blit_bgl,
+                        linear_sampler,
+                    );
+                    streamed_to = streamed_to.max(band.y + band.rows);
+                    outcome = Ok(true);
+                }
+                Ok(Err(e)) => {
+                    outcome = Err(e);
+                    finished = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+                // The decoder only hangs up early when it died.
+                Err(TryRecvError::Disconnected) => {
+                    if streamed_to < self.full_height {
+                        outcome = Err("the image ended early".to_string());
+                    }
+                    finished = true;
+                    break;
+                }
+            }
+        }
+        self.streamed_to = streamed_to;
+        if finished {
+            self.inflow = None;
+        }
+        if outcome == Ok(true) {
+            // Queued writes sit in staging memory until submitted and done
+            // with, which would add up to the whole image.
+            queue.submit([]);
+            let _ = device.poll(iced::wgpu::PollType::Wait {
+                submission_index: None,
+                timeout: None,
+            });
+        }
+        outcome
+    }
+
+    fn write_band(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        band: &Band,
+        blit_pipeline: &RenderPipeline,
+        blit_bgl: &BindGroupLayout,
+        linear_sampler: &Sampler,
+    ) {
+        let stride = self.full_width as usize * 4;
+        for tile in &self.tiles {
+            let top = tile.y.max(band.y);
+            let bottom = (tile.y + tile.height).min(band.y + band.rows);
+            if top >= bottom {
+                continue;
+            }
+            queue.write_texture(
+                TexelCopyTextureInfo {
+                    origin: Origin3d {
+                        x: 0,
+                        y: top - tile.y,
+                        z: 0,
+                    },
+                    ..tile._source_texture.as_image_copy()
+                },
+                &band.pixels,
+                TexelCopyBufferLayout {
+                    offset: ((top - band.y) as usize * stride + tile.x as usize * 4) as u64,
+                    bytes_per_row: Some(self.full_width * 4),
+                    rows_per_image: None,
+                },
+                Extent3d {
+                    width: tile.width,
+                    height: bottom - top,
+                    depth_or_array_layers: 1,
+                },
+            );
+            if bottom == tile.y + tile.height && self.has_mipmaps && tile.mip_count > 1 {
+                regen_tile_mipmaps(
+                    device,
+                    queue,
+                    &tile._source_texture,
+                    tile.mip_count,
+                    self.format,
+                    blit_pipeline,
+                    blit_bgl,
+                    linear_sampler,
+                );
+            }
+        }
+    }
+
+    fn blit<'a>(
+        &'a self,
+        pipeline: &'a RenderPipeline,
+        bgl: &'a BindGroupLayout,
+    ) -> (&'a RenderPipeline, &'a BindGroupLayout) {
+        self.deep_blit
+            .as_ref()
+            .map_or((pipeline, bgl), |(p, l)| (p, l))
     }
 
     pub fn write_frame(
@@ -359,12 +606,11 @@ impl TiledSource {
         linear_sampler: &Sampler,
     ) -> Result<(), ViewError> {
         let image_pixels = image.pixels_snapshot();
-        if image_pixels.len() < image.size_bytes() {
-            return Err(ViewError::ImageDataMismatch {
-                expected: image.size_bytes(),
-                actual: image_pixels.len(),
-            });
-        }
+        let deep = image.deep_snapshot();
+        let upload = upload_bytes(image, &image_pixels, deep.as_deref(), self.format)?;
+        let bpp = self.format.block_copy_size(None).unwrap_or(4);
+        self.inflow = None;
+        let (blit_pipeline, blit_bgl) = self.blit(blit_pipeline, blit_bgl);
 
         let full_width = self.full_width;
         let needs_mips = self.has_mipmaps && self.physical_scale < 1.0 - 1e-6;
@@ -379,7 +625,8 @@ impl TiledSource {
                 tile.width,
                 tile.height,
                 full_width,
-                image_pixels.as_slice(),
+                bpp,
+                &upload,
                 &mut scratch,
             );
 
@@ -389,6 +636,7 @@ impl TiledSource {
                     queue,
                     &tile._source_texture,
                     tile.mip_count,
+                    self.format,
                     blit_pipeline,
                     blit_bgl,
                     linear_sampler,
@@ -410,6 +658,7 @@ impl TiledSource {
         linear_sampler: &Sampler,
     ) {
         let mut encoder: Option<CommandEncoder> = None;
+        let (blit_pipeline, blit_bgl) = self.blit(blit_pipeline, blit_bgl);
 
         for tile in &self.tiles {
             if tile.mip_count > 1 {
@@ -418,7 +667,7 @@ impl TiledSource {
                     device,
                     &tile._source_texture,
                     tile.mip_count,
-                    TextureFormat::Rgba8Unorm,
+                    self.format,
                     blit_pipeline,
                     blit_bgl,
                     linear_sampler,
@@ -432,4 +681,88 @@ impl TiledSource {
 
         self.mips_dirty = false;
     }
+
+    pub fn shown_detail(&self) -> Option<&DetailTile> {
+        self.details.last().filter(|_| self.detail_shown)
+    }
+
+    pub fn shown_detail_mut(&mut self) -> Option<&mut DetailTile> {
+        self.details.last_mut().filter(|_| self.detail_shown)
+    }
+
+    /// Draws `detail` over the tiles from now on, or nothing over them for
+    /// None. A raster already held for the same request is reused.
+    pub fn show_detail(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        detail: Option<&SvgDetail>,
+        display_pass: &DisplayPass,
+        linear_sampler: &Sampler,
+    ) {
+        let Some(detail) = detail.filter(|d| d.request.image_id == self.image_id) else {
+            self.detail_shown = false;
+            return;
+        };
+        self.detail_shown = true;
+        if let Some(i) = self
+            .details
+            .iter()
+            .position(|d| d.request == detail.request)
+        {
+            let held = self.details.remove(i);
+            self.details.push(held);
+            return;
+        }
+
+        self.details
+            .retain(|d| d.request.bucket != detail.request.bucket);
+        if self.details.len() >= DETAIL_BUCKETS {
+            self.details.remove(0);
+        }
+
+        let label = format!("detail[{}x]", detail.request.bucket);
+        let texture = gpu::texture_2d(
+            device,
+            detail.width,
+            detail.height,
+            TextureFormat::Rgba8Unorm,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            Some(&format!("{label}:source")),
+        );
+        queue.write_texture(
+            texture.as_image_copy(),
+            &detail.pixels,
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(detail.width * 4),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: detail.width,
+                height: detail.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        let view = texture.create_view(&Default::default());
+        let uniform_buffer = gpu::uniform_buffer::<DisplayUniforms>(
+            device,
+            Some(&format!("{label}:display-uniform")),
+        );
+        let bind_group = display_pass.create_bind_group(
+            device,
+            &uniform_buffer,
+            &view,
+            linear_sampler,
+            Some(&format!("{label}:linear-bg")),
+        );
+        self.details.push(DetailTile {
+            request: detail.request,
+            _texture: texture,
+            uniform_buffer,
+            bind_group,
+            last_transform: None,
+            last_ndc_rect: None,
+        });
+    }
 }
@@ -44,6 +44,15 @@
 //! a resize the ratio is 1 and nothing changes, which is why only a resized
 //! document showed it.
 //!
+//! A source uploaded above 8 bits runs its chain in Rgba16Float intermediates so
+//! the precision survives to the display pass. Pixel sort is the exception: it
+//! copies intermediates through buffers as four bytes a pixel, so a chain that
+//! contains one stays in the surface format.
+//!
+//! An SVG's detail raster (see media/svg.rs) is laid out by the same
+//! place_tile, as a tile over the part of the source it covers, and drawn
+//! last. A tile it hides entirely is skipped rather than drawn underneath.
+//!
 //! place_tile is that layout as a pure function, so the display_harness module
 //! can drive a real multi-tile grid through pan and zoom. Every crop bug that
 //! reached a user lived in this arithmetic, and none were visible to a test
@@ -63,14 +72,18 @@ use iced::{
 };
 
 use crate::{
-    modifiers::Modifier,
     modifiers::plan::{
         ImageSpec, chain_doc_offset, chain_output_spec, infer_specs, plan_modifiers,
     },
+    modifiers::{Modifier, ModifierKind},
     wgpu::{
         error::ViewError,
         gpu,
-        media::image_data::{ImageData, ImageId},
+        media::{
+            image_data::{ImageData, ImageId},
+            svg::SvgDetail,
+            tonemap::Tone,
+        },
         modifier_pipeline::ModifierPipeline,
         passes::{
             checkerboard::{CheckerboardPass, CheckerboardUniforms},
@@ -110,6 +123,9 @@ pub(crate) struct ViewGeometry {
     pub scale: f32,
     pub pan_ndc: Vec2,
     pub rotation: u8,
+    /// Flip the document horizontally before rotating it, for mirrored EXIF
+    /// orientations.
+    pub mirror: bool,
 }
 
 impl ViewGeometry {
@@ -164,6 +180,21 @@ pub(crate) fn doc_changed(
     have.is_some_and(|(size, offset)| size != want_size || offset != want_offset)
 }
 
+pub(crate) fn chain_format(
+    source: TextureFormat,
+    surface: TextureFormat,
+    modifiers: &[Modifier],
+) -> TextureFormat {
+    let sorts = modifiers
+        .iter()
+        .any(|m| matches!(m.kind, ModifierKind::PixelSort(_)));
+    if source == TextureFormat::Rgba8Unorm || sorts {
+        surface
+    } else {
+        TextureFormat::Rgba16Float
+    }
+}
+
 pub(crate) fn place_tile(tile: [f32; 4], g: ViewGeometry) -> Option<TilePlacement> {
     let isec = tile_doc_intersection(tile, g.doc_region);
     if isec[0] >= isec[2] || isec[1] >= isec[3] {
@@ -178,10 +209,12 @@ pub(crate) fn place_tile(tile: [f32; 4], g: ViewGeometry) -> Option<TilePlacemen
     let offset = 2.0 * vec2(isec_c.x - doc_c.x, doc_c.y - isec_c.y) * inv;
     let aspect = vec2(isec[2] - isec[0], isec[3] - isec[1]) * inv;
     let angle = -(g.rotation as f32) * std::f32::consts::FRAC_PI_2;
+    let flip = if g.mirror { -1.0 } else { 1.0 };
 
     let transform = Mat4::from_scale(vec3(g.scale, g.scale, 1.0))
         * Mat4::from_translation(vec3(g.pan_ndc.x, g.pan_ndc.y, 0.0))
         * Mat4::from_rotation_z(angle)
+        * Mat4::from_scale(vec3(flip, 1.0, 1.0))
         * Mat4::from_translation(vec3(offset.x, offset.y, 0.0))
         * Mat4::from_scale(vec3(aspect.x, aspect.y, 1.0));
 
@@ -208,6 +241,17 @@ pub(crate) fn tile_ndc_culled(rect: Option<(Vec2, Vec2)>) -> bool {
     )
 }
 
+/// Whether `over` hides the on-screen part of `rect`, both NDC rects.
+pub(crate) fn ndc_covers(over: (Vec2, Vec2), rect: (Vec2, Vec2)) -> bool {
+    const EPS: f32 = 1e-4;
+    let min = rect.0.max(Vec2::NEG_ONE);
+    let max = rect.1.min(Vec2::ONE);
+    min.x >= over.0.x - EPS
+        && min.y >= over.0.y - EPS
+        && max.x <= over.1.x + EPS
+        && max.y <= over.1.y + EPS
+}
+
 fn roi_from_ndc_clip((ndc_min, ndc_max): (Vec2, Vec2), rect: [f32; 4]) -> Option<[f32; 4]> {
     let [left, top, right, bottom] = rect;
     let nw = ndc_max.x - ndc_min.x;
@@ -288,7 +332,7 @@ impl ViewPipeline {
         queue: &Queue,
         image: &ImageData,
     ) -> Result<(), ViewError> {
-        if !image.pixels_available() {
+        if !image.pixels_available() && image.stream().is_none() {
             return Ok(());
         }
 
@@ -341,6 +385,7 @@ impl ViewPipeline {
         viewport: Vec2,
         pan_ndc: Vec2,
         rotation: u8,
+        mirror: bool,
         doc_region: [f32; 4],
         doc_size: Vec2,
     ) {
@@ -368,6 +413,36 @@ impl ViewPipeline {
             );
         }
 
+        let geom = ViewGeometry {
+            doc_region,
+            doc_size,
+            viewport,
+            scale,
+            pan_ndc,
+            rotation,
+            mirror,
+        };
+
+        if let Some(detail) = source.shown_detail_mut() {
+            match place_tile(detail.rect(), geom) {
+                Some(p) => {
+                    if detail.last_transform != Some(p.transform) {
+                        queue.write_buffer(
+                            &detail.uniform_buffer,
+                            0,
+                            bytes_of(&DisplayUniforms {
+                                transform: p.transform,
+                                crop_uv: p.crop_uv,
+                            }),
+                        );
+                        detail.last_transform = Some(p.transform);
+                    }
+                    detail.last_ndc_rect = Some(p.ndc);
+                }
+                None => detail.last_ndc_rect = None,
+            }
+        }
+
         if source.tiles.len() == 1 {
             let tile = &mut source.tiles[0];
             if tile.last_transform != Some(uniforms.transform)
@@ -384,15 +459,6 @@ impl ViewPipeline {
         let full_w = source.full_width as f32;
         let full_h = source.full_height as f32;
 
-        let geom = ViewGeometry {
-            doc_region,
-            doc_size,
-            viewport,
-            scale,
-            pan_ndc,
-            rotation,
-        };
-
         for tile in &mut source.tiles {
             let tx = tile.x as f32;
             let ty = tile.y as f32;
@@ -410,7 +476,7 @@ impl ViewPipeline {
             let (transform, ndc) = (p.transform, p.ndc);
             let [isec_left, isec_top, isec_right, isec_bottom] = p.isec;
 
-            let roi = if rotation == 0 {
+            let roi = if rotation == 0 && !mirror {
                 roi_from_ndc_clip(ndc, p.isec)
             } else {
                 None
@@ -440,6 +506,19 @@ impl ViewPipeline {
         }
     }
 
+    /// The detail raster to draw over the tiles, for a vector source viewed
+    /// past its native size. Only the plain path draws it: a modifier chain
+    /// works from the native raster.
+    pub fn show_detail(&mut self, device: &Device, queue: &Queue, detail: Option<&SvgDetail>) {
+        if let Some(source) = &mut self.source {
+            source.show_detail(device, queue, detail, &self.display, &self.linear_sampler);
+        }
+    }
+
+    pub fn set_tone(&mut self, queue: &Queue, tone: Option<Tone>) {
+        self.display.set_tone(queue, tone);
+    }
+
     pub fn update_checkerboard(&mut self, queue: &Queue, uniforms: CheckerboardUniforms) {
         if self.last_checker_uniforms != Some(uniforms) {
             self.checkerboard.update_colors(queue, &uniforms);
@@ -456,7 +535,7 @@ impl ViewPipeline {
     }
 
     pub fn reprocess_pending(&self) -> bool {
-        if self.interacting() {
+        if self.interacting() || self.source.as_ref().is_some_and(TiledSource::streaming) {
             return true;
         }
         self.modifier_pipeline
@@ -509,14 +588,15 @@ impl ViewPipeline {
         }
 
         let (w, h) = (source.full_width, source.full_height);
+        let format = chain_format(source.format, self.format, modifiers);
 
         let needs_create = self
             .modifier_pipeline
             .as_ref()
-            .is_none_or(|mp| mp.width != w || mp.height != h);
+            .is_none_or(|mp| mp.width != w || mp.height != h || mp.format() != format);
 
         if needs_create {
-            let mut mp = ModifierPipeline::new(device, self.format, w, h);
+            let mut mp = ModifierPipeline::new(device, format, w, h);
             mp.prepare(device, queue, source, modifiers, false);
             self.modifier_pipeline = Some(mp);
         } else if let Some(mp) = &mut self.modifier_pipeline {
@@ -569,10 +649,22 @@ impl ViewPipeline {
                     }
                 }
             } else {
+                // Blending the detail over a tile it hides would let the
+                // tile's soft edges show through wherever the detail is
+                // transparent, so a hidden tile is not drawn at all.
+                let detail = source
+                    .shown_detail()
+                    .filter(|_| !zoomed_out)
+                    .and_then(|d| Some((d, d.last_ndc_rect?)));
                 for tile in &source.tiles {
                     if tile_ndc_culled(tile.last_ndc_rect) {
                         continue;
                     }
+                    if let (Some((_, over)), Some(rect)) = (detail, tile.last_ndc_rect)
+                        && ndc_covers(over, rect)
+                    {
+                        continue;
+                    }
                     bind_groups.push(if zoomed_out {
                         &tile.zoom_out_bind_group
                     } else if smooth_zoom_in {
@@ -581,6 +673,11 @@ impl ViewPipeline {
                         &tile.nearest_bind_group
                     });
                 }
+                if let Some((detail, over)) = detail
+                    && !tile_ndc_culled(Some(over))
+                {
+                    bind_groups.push(&detail.bind_group);
+                }
             }
         } else {
             bind_groups.push(&self.placeholder_bind_group);
@@ -627,6 +724,24 @@ impl ViewPipeline {
         }
     }
 
+    /// Uploads the bands a streamed image has decoded since the last frame.
+    pub fn receive_bands(&mut self, device: &Device, queue: &Queue) {
+        let Some(source) = &mut self.source else {
+            return;
+        };
+        match source.receive(
+            device,
+            queue,
+            &self.blit_pipeline,
+            &self.blit_bgl,
+            &self.linear_sampler,
+        ) {
+            Ok(true) => self.pending_source_dirty = true,
+            Ok(false) => {}
+            Err(e) => eprintln!("streamed decode failed: {e}"),
+        }
+    }
+
     pub fn needs_upload(&self, image_id: ImageId) -> bool {
         match &self.source {
             Some(s) => s.image_id != image_id,
@@ -734,7 +849,7 @@ impl Pipeline for ViewPipeline {
 
 #[cfg(test)]
 mod display_harness {
-    use super::{TilePlacement, ViewGeometry, place_tile};
+    use super::{TilePlacement, ViewGeometry, ndc_covers, place_tile};
     use glam::{Vec2, vec2, vec4};
 
     pub(super) const SRC: f32 = 30000.0;
@@ -772,6 +887,7 @@ mod display_harness {
             scale,
             pan_ndc: pan,
             rotation: 0,
+            mirror: false,
         }
     }
 
@@ -1108,6 +1224,25 @@ mod display_harness {
             );
         }
     }
+
+    #[test]
+    fn a_detail_over_the_visible_part_hides_the_tile_beneath() {
+        let image = [0.0, 0.0, 800.0, 450.0];
+        let zoomed = geometry(image, 8.0, Vec2::ZERO);
+        let tile = place_tile(image, zoomed).unwrap().ndc;
+        let over = |rect| ndc_covers(place_tile(rect, zoomed).unwrap().ndc, tile);
+        assert!(over([250.0, 140.0, 550.0, 310.0]));
+        assert!(
+            !over([350.0, 140.0, 550.0, 310.0]),
+            "the left of the view still shows the tile"
+        );
+
+        let whole = geometry(image, 1.0, Vec2::ZERO);
+        let tile = place_tile(image, whole).unwrap().ndc;
+        assert!(ndc_covers(tile, tile));
+        let part = place_tile([0.0, 0.0, 400.0, 450.0], whole).unwrap().ndc;
+        assert!(!ndc_covers(part, tile));
+    }
 }
 
 #[cfg(test)]
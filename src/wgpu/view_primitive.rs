@@ -18,7 +18,7 @@ use iced::{
 use crate::{
     modifiers::Modifier,
     wgpu::{
-        media::image_data::ImageData,
+        media::{image_data::ImageData, svg::SvgDetail, tonemap::Tone},
         passes::{checkerboard::CheckerboardUniforms, pixel_grid::PixelGridUniforms},
         view_pipeline::{DisplayUniforms, ViewPipeline},
     },
@@ -31,15 +31,19 @@ pub struct ViewPrimitive {
     pub scale: f32,
     pub pan_ndc: Vec2,
     pub rotation: u8,
+    pub mirror: bool,
     pub bounds: Rectangle,
     pub show_checkerboard: bool,
     pub checker_uniforms: CheckerboardUniforms,
     pub grid: Option<PixelGridUniforms>,
     pub mipmap_zoom_out: bool,
     pub smooth_zoom_in: bool,
+    pub tone: Option<Tone>,
     pub modifiers: Arc<Vec<Modifier>>,
     pub doc_region: [f32; 4],
     pub doc_size: Vec2,
+    /// A finer raster of the visible part of a vector image, drawn over it.
+    pub svg_detail: Option<Arc<SvgDetail>>,
     pub dirty: bool,
     pub pre_clear_gpu: Arc<std::sync::atomic::AtomicBool>,
     pub reprocess_pending: Arc<std::sync::atomic::AtomicBool>,
@@ -72,6 +76,8 @@ impl Primitive for ViewPrimitive {
             eprintln!("upload_image failed: {e}");
             return;
         }
+        pipeline.receive_bands(device, queue);
+        pipeline.show_detail(device, queue, self.svg_detail.as_deref());
         pipeline.update(
             device,
             queue,
@@ -81,6 +87,7 @@ impl Primitive for ViewPrimitive {
             vec2(self.bounds.width, self.bounds.height),
             self.pan_ndc,
             self.rotation,
+            self.mirror,
             self.doc_region,
             self.doc_size,
         );
@@ -92,6 +99,7 @@ impl Primitive for ViewPrimitive {
             grid.viewport = grid.viewport.map(|v| v * sf);
             pipeline.update_pixel_grid(queue, &grid);
         }
+        pipeline.set_tone(queue, self.tone);
         pipeline.prepare_modifiers(device, queue, &self.modifiers, self.dirty);
         self.reprocess_pending
             .store(pipeline.reprocess_pending(), Ordering::Release);
@@ -52,6 +52,11 @@
 //! interpolates between pixels already present and moves the distribution
 //! little, while costing a full resample per slider tick; a downscale averages
 //! neighbors and measurably narrows the distribution, so it is kept.
+//!
+//! An SVG keeps the detail rasters it has been sent, one per zoom bucket (see
+//! media/svg.rs), and hands the renderer the one for the current bucket. They
+//! stand for the source, so an edit stack that changes anything turns them off
+//! and the chain works from the native raster as it does for any image.
 
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
@@ -78,8 +83,17 @@ use crate::{
     },
     wgpu::{
         media::animation::Animation,
+        media::dicom::DicomInfo,
         media::exif_data::ExifData,
+        media::exr::ExrSelection,
+        media::fits::FitsSelection,
         media::image_data::ImageData,
+        media::layers::LayerSelection,
+        media::pages::Pages,
+        media::raw::RawSelection,
+        media::svg::{DETAIL_BUCKETS, DetailRequest, SvgDetail, SvgDocument},
+        media::texture::TextureSelection,
+        media::tonemap::{Tone, Tonemap, Window},
         passes::{checkerboard::CheckerboardUniforms, pixel_grid::PixelGridUniforms},
         scale::Scale,
         view_pipeline::DisplayUniforms,
@@ -144,6 +158,18 @@ pub(crate) fn wheel_scale_msg(
     }
 }
 
+/// Where the view stood on a file, to come back to once it is read again.
+#[derive(Clone)]
+pub struct ViewSnapshot {
+    offset: Vec2,
+    scale: f32,
+    fit_active: bool,
+    rotation: u8,
+    mirror: bool,
+    image_size: Vec2,
+    modifiers: Arc<Vec<Modifier>>,
+}
+
 #[derive(Clone)]
 pub struct ViewProgram {
     offset: Vec2,
@@ -159,12 +185,19 @@ pub struct ViewProgram {
     pub mipmap_zoom_out: bool,
     pub smooth_zoom_in: bool,
     pub loop_animations: bool,
+    pub keep_color_profile: bool,
+    pub tone: Tonemap,
+    /// The window over a DICOM's values. Reset to the file's own on load.
+    pub window: Window,
     uploaded_mipmap_zoom_out: bool,
     cursor_image_pos: Option<Vec2>,
     panning: bool,
     rotation: u8,
+    mirror: bool,
     pub modifiers: Arc<Vec<Modifier>>,
     pub crop_tool_active: bool,
+    svg_details: Vec<Arc<SvgDetail>>,
+    svg_bucket: u32,
     dirty: Arc<std::sync::atomic::AtomicBool>,
     pre_clear_gpu: Arc<std::sync::atomic::AtomicBool>,
     reprocess_pending: Arc<std::sync::atomic::AtomicBool>,
@@ -215,12 +248,18 @@ impl Default for ViewProgram {
             cursor_image_pos: None,
             panning: false,
             rotation: 0,
+            mirror: false,
             mipmap_zoom_out: true,
             smooth_zoom_in: false,
             loop_animations: true,
+            keep_color_profile: false,
+            tone: Tonemap::default(),
+            window: Window::default(),
             uploaded_mipmap_zoom_out: true,
             modifiers: Arc::new(Vec::new()),
             crop_tool_active: false,
+            svg_details: Vec::new(),
+            svg_bucket: 1,
             dirty: Arc::new(std::sync::atomic::AtomicBool::new(false)),
             pre_clear_gpu: Arc::new(std::sync::atomic::AtomicBool::new(false)),
             reprocess_pending: Arc::new(std::sync::atomic::AtomicBool::new(false)),
@@ -293,6 +332,12 @@ impl ViewProgram {
         self.fit();
     }
 
+    /// Flip the image horizontally underneath the rotation. Only mirrored EXIF
+    /// orientations set this; the rotate actions leave it alone.
+    pub fn set_mirror(&mut self, mirror: bool) {
+        self.mirror = mirror;
+    }
+
     pub fn rotate(&mut self) {
         self.rotation = (self.rotation + 1) % 4;
         self.fit();
@@ -356,10 +401,11 @@ impl ViewProgram {
         let aspect = self.aspect(viewport);
         let pan_ndc = self.offset / viewport;
         let angle = -(self.rotation as f32) * std::f32::consts::FRAC_PI_2;
+        let flip = if self.mirror { -1.0 } else { 1.0 };
         Mat4::from_scale(vec3(s, s, 1.0))
             * Mat4::from_translation(vec3(pan_ndc.x, pan_ndc.y, 0.0))
             * Mat4::from_rotation_z(angle)
-            * Mat4::from_scale(vec3(aspect.x, aspect.y, 1.0))
+            * Mat4::from_scale(vec3(flip * aspect.x, aspect.y, 1.0))
     }
 
     fn grid_uniforms(&self, bounds: Rectangle) -> Option<PixelGridUniforms> {
@@ -397,12 +443,16 @@ impl ViewProgram {
 
     fn set_display_image(&mut self, data: Arc<ImageData>) {
         self.image_size = vec2(data.width as f32, data.height as f32);
+        self.open_window(&data);
         self.image = Some(data);
         self.animation = None;
         self.cursor_image_pos = Some(self.image_size / 2.0);
         self.panning = false;
         self.rotation = 0;
+        self.mirror = false;
         self.uploaded_mipmap_zoom_out = self.mipmap_zoom_out;
+        self.svg_details.clear();
+        self.svg_bucket = 1;
         self.reset_crop_to_image();
     }
 
@@ -415,35 +465,184 @@ impl ViewProgram {
         }
     }
 
+    /// Swaps in another rendering of the same source, such as a different EXR
+    /// layer, keeping zoom, pan, orientation and metadata as they are.
+    pub fn replace_image(&mut self, mut data: ImageData) {
+        let Some(current) = self.image.as_deref() else {
+            return self.set_image(data);
+        };
+        data.exif = current.exif.clone();
+        if (data.width, data.height) == (current.width, current.height) {
+            self.image = Some(Arc::new(data));
+        } else {
+            let (rotation, mirror) = (self.rotation, self.mirror);
+            self.set_display_image(Arc::new(data));
+            self.rotation = rotation;
+            self.mirror = mirror;
+            self.fit();
+        }
+    }
+
+    pub fn snapshot(&self) -> ViewSnapshot {
+        ViewSnapshot {
+            offset: self.offset,
+            scale: self.scale.value(),
+            fit_active: self.fit_active,
+            rotation: self.rotation,
+            mirror: self.mirror,
+            image_size: self.image_size,
+            modifiers: Arc::clone(&self.modifiers),
+        }
+    }
+
+    /// Puts back the zoom, pan, orientation and modifiers of `snapshot` over
+    /// a fresh load of the same file. One that came back a different size
+    /// keeps its orientation and modifiers but is fitted and its crops cover
+    /// the whole image, since the old zoom, pan and crops no longer line up.
+    pub fn restore(&mut self, snapshot: ViewSnapshot) {
+        self.rotation = snapshot.rotation;
+        self.mirror = snapshot.mirror;
+        self.modifiers = snapshot.modifiers;
+        if snapshot.image_size != self.image_size {
+            self.reset_crop_to_image();
+            self.fit();
+        } else if snapshot.fit_active {
+            self.fit();
+        } else {
+            self.fit_active = false;
+            self.scale.custom(snapshot.scale);
+            self.offset = snapshot.offset;
+            self.clamp_offset();
+        }
+    }
+
+    /// Puts back the view of `snapshot`, taken over a stand-in for this image
+    /// such as its embedded preview. Zoom, pan and crops are scaled from the
+    /// stand-in's size to this one's, so the picture stays where it was on
+    /// screen.
+    pub fn carry_over(&mut self, snapshot: ViewSnapshot) {
+        if snapshot.image_size.min_element() <= 0.0 || self.image_size == Vec2::ZERO {
+            return;
+        }
+        let ratio = self.image_size / snapshot.image_size;
+        let size = self.image_size;
+        self.rotation = snapshot.rotation;
+        self.mirror = snapshot.mirror;
+        self.modifiers = snapshot.modifiers;
+        for m in self.modifiers_mut() {
+            if let Some(crop) = m.kind.as_crop_mut() {
+                crop.x = (crop.x * ratio.x).min(size.x);
+                crop.y = (crop.y * ratio.y).min(size.y);
+                crop.width = (crop.width * ratio.x).min(size.x - crop.x);
+                crop.height = (crop.height * ratio.y).min(size.y - crop.y);
+            }
+        }
+        if snapshot.fit_active {
+            self.fit();
+        } else {
+            self.fit_active = false;
+            self.scale.custom(snapshot.scale / ratio.x);
+            self.offset = snapshot.offset * ratio;
+            self.clamp_offset();
+        }
+    }
+
+    pub fn exr(&self) -> Option<&ExrSelection> {
+        self.image.as_deref().and_then(|d| d.exr())
+    }
+
+    pub fn fits(&self) -> Option<&FitsSelection> {
+        self.image.as_deref().and_then(|d| d.fits())
+    }
+
+    pub fn layers(&self) -> Option<&LayerSelection> {
+        self.image.as_deref().and_then(|d| d.layers())
+    }
+
+    pub fn texture(&self) -> Option<&TextureSelection> {
+        self.image.as_deref().and_then(|d| d.texture())
+    }
+
+    pub fn raw(&self) -> Option<&RawSelection> {
+        self.image.as_deref().and_then(|d| d.raw())
+    }
+
+    pub fn svg(&self) -> Option<&Arc<SvgDocument>> {
+        self.image.as_deref().and_then(|d| d.svg())
+    }
+
+    pub fn pages(&self) -> Option<&Pages> {
+        self.image.as_deref().and_then(|d| d.pages())
+    }
+
+    pub fn dicom(&self) -> Option<&DicomInfo> {
+        self.image.as_deref().and_then(|d| d.dicom())
+    }
+
+    #[cfg(feature = "heif")]
+    pub fn heif(&self) -> Option<&crate::wgpu::media::heif::HeifInfo> {
+        self.image.as_deref().and_then(|d| d.heif())
+    }
+
     pub fn current_image(&self) -> Option<Arc<ImageData>> {
         self.image.clone()
     }
 
+    fn open_window(&mut self, data: &ImageData) {
+        if let Some(window) = data.dicom().and_then(|d| d.window) {
+            self.window = window;
+        }
+    }
+
+    /// The tonemap or window the current image is displayed and exported
+    /// with, if it needs one.
+    pub fn display_tone(&self) -> Option<Tone> {
+        let image = self.image.as_deref()?;
+        if image.scene_linear {
+            Some(Tone::Map(self.tone))
+        } else {
+            image
+                .dicom()
+                .and_then(|d| d.window)
+                .map(|_| Tone::Window(self.window))
+        }
+    }
+
     pub fn exif(&self) -> Option<&ExifData> {
         self.image.as_deref().map(|d| &d.exif)
     }
 
+    /// Whether the image is a preview embedded in the file rather than its
+    /// full decode.
+    pub fn embedded_preview(&self) -> bool {
+        self.image.as_deref().is_some_and(|d| d.embedded_preview())
+    }
+
+    pub fn streamed(&self) -> bool {
+        self.image.as_deref().is_some_and(|d| d.stream().is_some())
+    }
+
     pub fn bit_depth(&self) -> Option<u8> {
         self.image.as_deref().map(|d| d.bit_depth)
     }
 
     pub fn color_space(&self) -> Option<&str> {
-        self.image.as_deref().and_then(|d| {
-            d.color_space
-                .map(|s| s as &str)
-                .or(d.exif.color_space.as_deref())
-        })
+        self.image
+            .as_deref()
+            .and_then(|d| d.color_space.as_deref().or(d.exif.color_space.as_deref()))
     }
 
     pub fn set_animation(&mut self, mut anim: Animation) {
         anim.set_looping(self.loop_animations);
         let first = Arc::clone(anim.current_image());
         self.image_size = vec2(first.width as f32, first.height as f32);
+        self.open_window(&first);
         self.image = Some(first);
         self.animation = Some(anim);
         self.cursor_image_pos = Some(self.image_size / 2.0);
         self.panning = false;
         self.rotation = 0;
+        self.mirror = false;
         self.uploaded_mipmap_zoom_out = self.mipmap_zoom_out;
         self.reset_crop_to_image();
     }
@@ -521,6 +720,10 @@ impl ViewProgram {
         self.rotation
     }
 
+    pub fn mirror(&self) -> bool {
+        self.mirror
+    }
+
     pub fn image_size(&self) -> Option<(u32, u32)> {
         if self.image_size == Vec2::ZERO {
             return None;
@@ -614,9 +817,7 @@ impl ViewProgram {
     }
 
     pub fn animation_delays(&self) -> impl Iterator<Item = Duration> + '_ {
-        self.animation
-            .iter()
-            .flat_map(|a| a.frames().iter().map(|f| f.delay))
+        self.animation.iter().flat_map(|a| a.delays())
     }
 
     pub fn animation_timestamp(&self) -> Option<Duration> {
@@ -636,6 +837,69 @@ impl ViewProgram {
         })
     }
 
+    fn svg_detail_shown(&self) -> bool {
+        !self.modifiers.iter().any(|m| m.has_visible_effect())
+    }
+
+    /// The detail raster an SVG needs for the current view, or None when one
+    /// on hand already covers it. `density` is device pixels per view pixel.
+    pub fn svg_detail_request(
+        &mut self,
+        density: f32,
+    ) -> Option<(Arc<SvgDocument>, DetailRequest)> {
+        let image = self.image.as_deref()?;
+        let svg = image.svg()?;
+        let viewport = vec2(self.bounds.width, self.bounds.height);
+        let corners = [
+            Vec2::ZERO,
+            vec2(viewport.x, 0.0),
+            vec2(0.0, viewport.y),
+            viewport,
+        ];
+        let mut min = Vec2::INFINITY;
+        let mut max = Vec2::NEG_INFINITY;
+        for corner in corners {
+            let uv = self.screen_to_image_uv(corner)?;
+            min = min.min(uv * self.image_size);
+            max = max.max(uv * self.image_size);
+        }
+        let visible = [min.x, min.y, max.x, max.y];
+
+        let request = svg.detail_request(image.id, self.scale.value() * density, visible);
+        self.svg_bucket = request.map_or(1, |r| r.bucket);
+        let request = request.filter(|_| self.svg_detail_shown())?;
+        if self
+            .svg_details
+            .iter()
+            .any(|d| d.covers(request.bucket, visible))
+        {
+            return None;
+        }
+        Some((Arc::clone(svg), request))
+    }
+
+    pub fn add_svg_detail(&mut self, detail: Arc<SvgDetail>) {
+        if self.image.as_ref().map(|i| i.id) != Some(detail.request.image_id) {
+            return;
+        }
+        self.svg_details
+            .retain(|d| d.request.bucket != detail.request.bucket);
+        if self.svg_details.len() >= DETAIL_BUCKETS {
+            self.svg_details.remove(0);
+        }
+        self.svg_details.push(detail);
+    }
+
+    fn shown_svg_detail(&self) -> Option<Arc<SvgDetail>> {
+        if !self.svg_detail_shown() {
+            return None;
+        }
+        self.svg_details
+            .iter()
+            .find(|d| d.request.bucket == self.svg_bucket)
+            .cloned()
+    }
+
     pub fn screen_to_image_uv(&self, screen_pos: Vec2) -> Option<Vec2> {
         let viewport = vec2(self.bounds.width, self.bounds.height);
         if self.image_size == Vec2::ZERO || viewport.x < 1.0 || viewport.y < 1.0 {
@@ -937,28 +1201,42 @@ impl ViewProgram {
             None => return self.export_frame_data(),
         };
 
-        let frames = anim
-            .frames()
+        let (width, height) = (anim.current_image().width, anim.current_image().height);
+        let Some(frames) = anim.frames() else {
+            let sequence = Arc::clone(anim.sequence()?);
+            let duration = anim.total_duration();
+            let source = ExportSource::Sequence {
+                sequence,
+                still_index: anim.current_index(),
+            };
+            return Some(self.build_export(source, duration, width, height));
+        };
+        let frames = frames
             .iter()
             .map(|f| ExportFrame {
                 pixels: f.data.pixels_snapshot(),
+                deep: f.data.deep_snapshot(),
                 delay: f.delay,
             })
             .collect();
-        let first = &anim.frames()[0].data;
-        Some(self.build_export(frames, anim.current_index(), first.width, first.height))
+        Some(self.build_frames_export(frames, anim.current_index(), width, height))
     }
 
     pub fn export_frame_data(&self) -> Option<ExportData> {
         let image = self.image.as_ref()?;
+        if let Some(stream) = image.stream() {
+            let source = ExportSource::Stream(Arc::clone(stream));
+            return Some(self.build_export(source, Duration::ZERO, image.width, image.height));
+        }
         let frames = vec![ExportFrame {
             pixels: image.pixels_snapshot(),
+            deep: image.deep_snapshot(),
             delay: Duration::ZERO,
         }];
-        Some(self.build_export(frames, 0, image.width, image.height))
+        Some(self.build_frames_export(frames, 0, image.width, image.height))
     }
 
-    fn build_export(
+    fn build_frames_export(
         &self,
         frames: Vec<ExportFrame>,
         still_index: usize,
@@ -966,15 +1244,33 @@ impl ViewProgram {
         height: u32,
     ) -> ExportData {
         let duration = frames.iter().map(|f| f.delay).sum();
+        let source = ExportSource::Frames {
+            frames,
+            still_index,
+        };
+        self.build_export(source, duration, width, height)
+    }
+
+    fn build_export(
+        &self,
+        source: ExportSource,
+        duration: Duration,
+        width: u32,
+        height: u32,
+    ) -> ExportData {
         ExportData {
-            source: ExportSource::Frames {
-                frames,
-                still_index,
-            },
+            source,
             width,
             height,
             modifiers: self.modifiers.as_ref().clone(),
             rotation: self.rotation,
+            mirror: self.mirror,
+            profile: self
+                .image
+                .as_ref()
+                .and_then(|i| i.icc_profile.clone())
+                .filter(|_| self.keep_color_profile),
+            tone: self.display_tone(),
             trim: self.active_trim(duration),
         }
     }
@@ -991,6 +1287,9 @@ impl ViewProgram {
             height: info.height,
             modifiers: self.modifiers.as_ref().clone(),
             rotation: self.rotation,
+            mirror: self.mirror,
+            profile: None,
+            tone: None,
             trim: self.active_trim(info.duration),
         }
     }
@@ -1003,6 +1302,7 @@ impl ViewProgram {
         (img.y as i64).hash(&mut hasher);
         image.id.hash(&mut hasher);
         self.rotation.hash(&mut hasher);
+        self.mirror.hash(&mut hasher);
         hash_modifiers(&self.modifiers).hash(&mut hasher);
         Some(hasher.finish())
     }
@@ -1076,13 +1376,8 @@ impl ViewProgram {
         }
 
         let coord = |row: i64, col: i64| -> (i64, i64) {
-            match self.rotation {
-                0 => (cx - half + col, cy - half + row),
-                1 => (cx - half + row, cy + half - col),
-                2 => (cx + half - col, cy + half - row),
-                3 => (cx + half - row, cy - half + col),
-                _ => unreachable!(),
-            }
+            let (dx, dy) = self.loupe_offset(row, col, half);
+            (cx + dx, cy + dy)
         };
 
         let mut pixels = vec![0u8; (size * size * 4) as usize];
@@ -1112,13 +1407,8 @@ impl ViewProgram {
         let ocy = if oh == h { cy } else { cy * oh / h.max(1) };
 
         let ocoord = |row: i64, col: i64| -> (i64, i64) {
-            match self.rotation {
-                0 => (ocx - half + col, ocy - half + row),
-                1 => (ocx - half + row, ocy + half - col),
-                2 => (ocx + half - col, ocy + half - row),
-                3 => (ocx + half - row, ocy - half + col),
-                _ => unreachable!(),
-            }
+            let (dx, dy) = self.loupe_offset(row, col, half);
+            (ocx + dx, ocy + dy)
         };
 
         let (y0, y1) = {
@@ -1206,13 +1496,8 @@ impl ViewProgram {
                 };
                 for row in 0..size as i64 {
                     for col in 0..size as i64 {
-                        let (sx, sy) = match self.rotation {
-                            0 => (ocx - half + col, ocy - half + row),
-                            1 => (ocx - half + row, ocy + half - col),
-                            2 => (ocx + half - col, ocy + half - row),
-                            3 => (ocx + half - row, ocy - half + col),
-                            _ => unreachable!(),
-                        };
+                        let (dx, dy) = self.loupe_offset(row, col, half);
+                        let (sx, sy) = (ocx + dx, ocy + dy);
                         if sx < 0 || sy < 0 || sx >= sw as i64 || sy >= sh as i64 {
                             continue;
                         }
@@ -1229,6 +1514,19 @@ impl ViewProgram {
         Some(pixels)
     }
 
+    /// Source-pixel offset from the cursor for a loupe cell, undoing the
+    /// display rotation and then the mirror.
+    fn loupe_offset(&self, row: i64, col: i64, half: i64) -> (i64, i64) {
+        let (dx, dy) = match self.rotation {
+            0 => (col - half, row - half),
+            1 => (row - half, half - col),
+            2 => (half - col, half - row),
+            3 => (half - row, col - half),
+            _ => unreachable!(),
+        };
+        if self.mirror { (-dx, dy) } else { (dx, dy) }
+    }
+
     fn store_cursor_pixels(&self, key: Option<u64>, size: u32, pixels: &[u8]) {
         let Some(key) = key else { return };
         let Ok(mut guard) = self.eyedropper_cache.lock() else {
@@ -1277,14 +1575,17 @@ impl Program<Message> for ViewProgram {
             scale: s,
             pan_ndc,
             rotation: self.rotation,
+            mirror: self.mirror,
             bounds,
             show_checkerboard: self.show_checkerboard,
             checker_uniforms: self.checker_uniforms,
             grid: self.grid_uniforms(bounds),
             mipmap_zoom_out: self.mipmap_zoom_out,
             smooth_zoom_in: self.smooth_zoom_in,
+            tone: self.display_tone(),
             doc_region: self.doc_region(),
             doc_size: self.effective_display_size(),
+            svg_detail: self.shown_svg_detail(),
             modifiers: if self.crop_tool_active {
                 Arc::new(Self::widen_crops(&self.modifiers))
             } else {
@@ -2844,6 +3145,55 @@ mod document_size_tests {
         );
     }
 
+    #[test]
+    fn a_view_carries_over_from_a_preview_to_the_full_image() {
+        let mut preview = program(vec![crop_of(10.0, 5.0, 50.0, 25.0)], 100, 50);
+        preview.set_bounds(Rectangle {
+            x: 0.0,
+            y: 0.0,
+            width: 400.0,
+            height: 300.0,
+        });
+        preview.rotate();
+        preview.scale.custom(2.0);
+        preview.fit_active = false;
+        preview.offset = vec2(4.0, -2.0);
+        let snapshot = preview.snapshot();
+
+        let mut full = preview.clone();
+        full.set_image(ImageData::new(vec![255u8; 400 * 200 * 4], 400, 200));
+        full.carry_over(snapshot);
+        assert_eq!(full.rotation, 1);
+        assert_eq!(full.scale(), 0.5, "the picture keeps its size on screen");
+        assert_eq!(full.offset, vec2(16.0, -8.0));
+        assert_eq!(full.effective_display_size(), vec2(200.0, 100.0));
+    }
+
+    #[test]
+    fn a_reload_at_a_new_size_keeps_its_modifiers_but_not_its_crop() {
+        let mut before = program(
+            vec![resize_pct(50.0), crop_of(10.0, 5.0, 50.0, 25.0)],
+            100,
+            50,
+        );
+        before.rotate();
+        before.scale.custom(2.0);
+        before.fit_active = false;
+        let snapshot = before.snapshot();
+
+        let mut after = before.clone();
+        after.set_image(ImageData::new(vec![255u8; 400 * 200 * 4], 400, 200));
+        after.restore(snapshot);
+        assert_eq!(after.rotation, 1);
+        assert_eq!(after.modifiers.len(), 2, "the resize is kept");
+        let crop = after.modifiers[1].kind.as_crop().unwrap();
+        assert_eq!(
+            (crop.x, crop.y, crop.width, crop.height),
+            (0.0, 0.0, 400.0, 200.0)
+        );
+        assert!(after.fit_active);
+    }
+
     #[test]
     fn a_disabled_resize_does_not_change_the_document() {
         let mut m = resize_pct(50.0);